@@ -0,0 +1,15 @@
+-- Create one_time_codes table for email verification codes, MFA email codes and password reset tokens
+CREATE TABLE IF NOT EXISTS one_time_codes (
+    id SERIAL PRIMARY KEY,
+    purpose VARCHAR(50) NOT NULL, -- email_verification, mfa_email, password_reset
+    subject VARCHAR(255) NOT NULL, -- Email address or user id the code was issued for
+    code_hash VARCHAR(255) NOT NULL, -- SHA256 hash of the code, plaintext is never stored
+    attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (purpose, subject) -- Only one active code per subject
+);
+
+-- Indexes for efficient queries
+CREATE INDEX IF NOT EXISTS idx_one_time_codes_code_hash ON one_time_codes(purpose, code_hash);
+CREATE INDEX IF NOT EXISTS idx_one_time_codes_expires_at ON one_time_codes(expires_at);
@@ -0,0 +1,3 @@
+-- Preferred locale for transactional emails (e.g. 'en', 'id', 'pt-br')
+-- NULL means fall back to the request's Accept-Language, then the default template locale
+ALTER TABLE users ADD COLUMN IF NOT EXISTS locale VARCHAR(10);
@@ -0,0 +1,58 @@
+-- Roles and fine-grained permissions, replacing hard-coded role == 'admin' checks
+CREATE TABLE IF NOT EXISTS roles (
+    name VARCHAR(50) PRIMARY KEY, -- Matches users.role
+    description TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS permissions (
+    name VARCHAR(100) PRIMARY KEY, -- e.g. posts.delete.any
+    description TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role VARCHAR(50) NOT NULL REFERENCES roles(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    permission VARCHAR(100) NOT NULL REFERENCES permissions(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    PRIMARY KEY (role, permission)
+);
+
+CREATE INDEX IF NOT EXISTS idx_role_permissions_permission ON role_permissions(permission);
+
+INSERT INTO roles (name, description) VALUES
+    ('user', 'Regular account, owns its own content'),
+    ('support', 'Helps users with their accounts, read-only on user data'),
+    ('moderator', 'Moderates content and can ban users, cannot edit accounts'),
+    ('admin', 'Full access')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO permissions (name, description) VALUES
+    ('users.read', 'View any user account'),
+    ('users.create', 'Create user accounts'),
+    ('users.update', 'Edit any user account'),
+    ('users.delete', 'Delete user accounts'),
+    ('users.ban', 'Ban and unban users'),
+    ('users.role.assign', 'Change a user''s role'),
+    ('posts.delete.any', 'Delete posts owned by other users'),
+    ('comments.delete.any', 'Delete comments owned by other users'),
+    ('email_outbox.manage', 'Inspect and retry queued emails')
+ON CONFLICT (name) DO NOTHING;
+
+-- Admins get every permission
+INSERT INTO role_permissions (role, permission)
+SELECT 'admin', name FROM permissions
+ON CONFLICT DO NOTHING;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('moderator', 'users.read'),
+    ('moderator', 'users.ban'),
+    ('moderator', 'posts.delete.any'),
+    ('moderator', 'comments.delete.any'),
+    ('support', 'users.read'),
+    ('support', 'email_outbox.manage')
+ON CONFLICT DO NOTHING;
+
+-- Any role already in use gets a row so users.role can be checked against it
+INSERT INTO roles (name)
+SELECT DISTINCT role FROM users WHERE role IS NOT NULL
+ON CONFLICT (name) DO NOTHING;
@@ -0,0 +1,11 @@
+-- Admin audit log search: keyset pagination walks ids newest first, plus IP and status filters
+CREATE INDEX IF NOT EXISTS idx_audit_logs_ip_address ON audit_logs(ip_address);
+CREATE INDEX IF NOT EXISTS idx_audit_logs_status ON audit_logs(status);
+
+INSERT INTO permissions (name, description) VALUES
+    ('audit.read', 'Search and export audit logs')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'audit.read')
+ON CONFLICT DO NOTHING;
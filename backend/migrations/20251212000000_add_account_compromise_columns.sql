@@ -0,0 +1,9 @@
+-- Set by "this wasn't me": password logins are refused until the password is reset by email
+ALTER TABLE users ADD COLUMN IF NOT EXISTS password_reset_required BOOLEAN NOT NULL DEFAULT false;
+
+-- Access tokens issued at or before this time are rejected by the auth middleware
+-- (JWTs are stateless, so this is how "sign out everywhere" reaches already issued tokens)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tokens_revoked_at TIMESTAMP;
+
+-- Account activity feed reads a user's trail newest first
+CREATE INDEX IF NOT EXISTS idx_audit_logs_user_id_id ON audit_logs(user_id, id DESC);
@@ -0,0 +1,48 @@
+-- Ban history. users.is_banned / banned_until mirror the currently active ban (if any)
+CREATE TABLE IF NOT EXISTS user_bans (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    moderator_id INTEGER REFERENCES users(id) ON DELETE SET NULL, -- NULL for imported or system bans
+    reason TEXT NOT NULL,
+    banned_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP, -- NULL = permanent
+    lifted_at TIMESTAMP,  -- Set when the ban ends early, expires, or is replaced
+    lifted_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    lift_reason TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_bans_user_id ON user_bans(user_id, id DESC);
+-- Auto-unban job looks for active bans past their expiry
+CREATE INDEX IF NOT EXISTS idx_user_bans_active_expiry ON user_bans(expires_at) WHERE lifted_at IS NULL;
+
+-- Appeals from banned users, reviewed by admins
+CREATE TABLE IF NOT EXISTS ban_appeals (
+    id SERIAL PRIMARY KEY,
+    ban_id INTEGER NOT NULL REFERENCES user_bans(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message TEXT NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+    reviewed_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    review_note TEXT,
+    reviewed_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- At most one open appeal per ban
+CREATE UNIQUE INDEX IF NOT EXISTS idx_ban_appeals_one_pending ON ban_appeals(ban_id) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_ban_appeals_status ON ban_appeals(status, id DESC);
+
+-- Bans set before the history table existed
+INSERT INTO user_bans (user_id, reason, expires_at)
+SELECT id, 'Imported from users.is_banned', banned_until
+FROM users
+WHERE is_banned = true
+  AND NOT EXISTS (SELECT 1 FROM user_bans WHERE user_bans.user_id = users.id);
+
+INSERT INTO permissions (name, description) VALUES
+    ('ban_appeals.review', 'Review ban appeals and lift bans on approval')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'ban_appeals.review')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,29 @@
+-- External login methods (Google, OIDC providers) linked to an account.
+-- Replaces the sentinel strings that used to be written into users.password
+CREATE TABLE IF NOT EXISTS user_identities (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    provider VARCHAR(64) NOT NULL,  -- 'google' or the OIDC provider name
+    subject VARCHAR(255) NOT NULL,  -- The provider's stable user ID (`sub`)
+    email VARCHAR(255),             -- Email the provider reported when linked, for display
+    linked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP,
+    UNIQUE (provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_identities_user_id ON user_identities(user_id);
+
+-- Google accounts created before this table never stored the Google subject. They get a
+-- placeholder that the next Google sign-in with the same email replaces with the real one
+INSERT INTO user_identities (user_id, provider, subject, email, linked_at)
+SELECT id, 'google', 'legacy:' || LOWER(email), email, created_at
+FROM users
+WHERE password = 'google_oauth' AND email IS NOT NULL
+ON CONFLICT (provider, subject) DO NOTHING;
+
+-- No password is now simply an empty one. Web3 accounts keep logging in through wallet_address,
+-- OIDC accounts (provider unknown here) are re-linked by email confirmation on their next sign-in
+UPDATE users SET password = '' WHERE password IN ('google_oauth', 'web3_auth', 'oidc_oauth');
+
+-- OIDC flows started from account settings link the identity instead of logging in
+ALTER TABLE oidc_login_states ADD COLUMN IF NOT EXISTS link_user_id INTEGER REFERENCES users(id) ON DELETE CASCADE;
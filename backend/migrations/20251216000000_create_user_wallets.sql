@@ -0,0 +1,23 @@
+-- Wallets linked to an account. Any of them can be used to sign in; the primary one is
+-- mirrored into users.wallet_address for display
+CREATE TABLE IF NOT EXISTS user_wallets (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    address VARCHAR(255) NOT NULL UNIQUE,  -- Normalized (lowercase for EVM addresses)
+    label VARCHAR(64),
+    is_primary BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_wallets_user_id ON user_wallets(user_id);
+-- At most one primary wallet per user
+CREATE UNIQUE INDEX IF NOT EXISTS idx_user_wallets_primary ON user_wallets(user_id) WHERE is_primary;
+
+UPDATE users SET wallet_address = LOWER(wallet_address) WHERE wallet_address LIKE '0x%';
+
+INSERT INTO user_wallets (user_id, address, is_primary, created_at)
+SELECT id, wallet_address, true, created_at
+FROM users
+WHERE wallet_address IS NOT NULL
+ON CONFLICT (address) DO NOTHING;
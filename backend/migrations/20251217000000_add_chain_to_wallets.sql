@@ -0,0 +1,5 @@
+-- Wallets can be Ethereum (0x, stored lowercase) or Solana (base58, case-sensitive) accounts
+ALTER TABLE user_wallets ADD COLUMN IF NOT EXISTS chain VARCHAR(16) NOT NULL DEFAULT 'ethereum';
+
+-- Base58 Solana addresses are up to 44 characters
+ALTER TABLE web3_challenges ALTER COLUMN address TYPE VARCHAR(64);
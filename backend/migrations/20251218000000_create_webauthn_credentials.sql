@@ -0,0 +1,27 @@
+-- Passkeys (WebAuthn credentials). Usable as a passwordless login and as a second factor
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id VARCHAR(1024) NOT NULL UNIQUE,  -- base64url, as sent by the browser
+    public_key BYTEA NOT NULL,                    -- COSE_Key from the attested credential data
+    algorithm INTEGER NOT NULL,                   -- COSE algorithm (-7 ES256, -8 EdDSA, -257 RS256)
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    transports TEXT[] NOT NULL DEFAULT '{}',
+    name VARCHAR(64),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_webauthn_credentials_user_id ON webauthn_credentials(user_id);
+
+-- Pending registration / authentication ceremonies, looked up (and deleted) by challenge
+CREATE TABLE IF NOT EXISTS webauthn_challenges (
+    id SERIAL PRIMARY KEY,
+    challenge VARCHAR(128) NOT NULL UNIQUE,
+    purpose VARCHAR(16) NOT NULL,                 -- register, login or mfa
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,  -- NULL for passwordless login
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_webauthn_challenges_expires_at ON webauthn_challenges(expires_at);
@@ -0,0 +1,10 @@
+-- Recovery codes are stored as SHA-256 hex digests of "<user id>:<code>", with the code
+-- uppercased and without dashes (see RecoveryCodeService::hash). Existing plaintext codes
+-- keep working
+UPDATE users
+SET recovery_codes = ARRAY(
+    SELECT encode(sha256(convert_to(users.id || ':' || upper(replace(code, '-', '')), 'UTF8')), 'hex')
+    FROM unnest(users.recovery_codes) AS code
+)
+WHERE recovery_codes IS NOT NULL
+  AND NOT (recovery_codes[1] ~ '^[0-9a-f]{64}$');
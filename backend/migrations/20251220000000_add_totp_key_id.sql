@@ -0,0 +1,4 @@
+-- users.totp_secret holds an AES-256-GCM envelope (see TotpKeyring) encrypted under the
+-- master key named here. NULL means a plaintext secret written before encryption was
+-- configured; `backend reencrypt-totp-secrets` encrypts those
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_key_id VARCHAR(64);
@@ -0,0 +1,27 @@
+-- Authenticator apps, several per user. `secret` is encrypted like users.totp_secret was (see
+-- TotpKeyring), so existing rows move over unchanged. Parameters are kept per authenticator:
+-- changing TOTP_ALGORITHM / TOTP_DIGITS / TOTP_PERIOD only affects ones added afterwards
+CREATE TABLE IF NOT EXISTS totp_authenticators (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    label VARCHAR(64) NOT NULL,
+    secret TEXT NOT NULL,
+    key_id VARCHAR(64),                           -- master key, NULL for a plaintext secret
+    algorithm VARCHAR(8) NOT NULL DEFAULT 'SHA1', -- SHA1, SHA256 or SHA512
+    digits SMALLINT NOT NULL DEFAULT 6,
+    period INTEGER NOT NULL DEFAULT 30,
+    last_used_step BIGINT,                        -- codes for this step or earlier are rejected
+    confirmed_at TIMESTAMP,                       -- NULL until a code from the app was entered
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_totp_authenticators_user_id ON totp_authenticators(user_id);
+
+INSERT INTO totp_authenticators (user_id, label, secret, key_id, confirmed_at)
+SELECT id, 'Authenticator app', totp_secret, totp_key_id,
+       CASE WHEN totp_enabled THEN CURRENT_TIMESTAMP END
+FROM users
+WHERE totp_secret IS NOT NULL;
+
+ALTER TABLE users DROP COLUMN IF EXISTS totp_secret, DROP COLUMN IF EXISTS totp_key_id;
@@ -0,0 +1,5 @@
+-- The refresh token family a session was issued with, so logging the session out
+-- also revokes the refresh tokens that could mint new access tokens for it
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS refresh_token_family VARCHAR(36);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_refresh_token_family ON sessions(refresh_token_family);
@@ -1,51 +1,165 @@
 use actix_web::{HttpRequest, HttpResponse, Result, web};
 use serde_json;
 use sqlx::PgPool;
+use std::collections::HashMap;
 
 use crate::middleware::auth::get_current_user;
-use crate::models::auth::Web3ChallengeRequest;
+use crate::auth::web3::{siwe_config, verify_wallet_ownership};
+use crate::models::auth::{ConnectWalletRequest, ReportCompromiseRequest, UpdateWalletRequest};
+use crate::services::audit_logger::AuditLogger;
+use crate::services::email_service::EmailService;
+use crate::services::email_templates::locale_from_request;
+use crate::services::identity_service::IdentityService;
+use crate::services::personal_access_token_service::PersonalAccessTokenService;
+use crate::services::refresh_token_service::RefreshTokenService;
 use crate::services::session_manager::SessionManager;
+use crate::services::token_blacklist::TokenBlacklist;
+use crate::services::wallet_service::{AddWalletOutcome, RemoveWalletOutcome, WalletService};
 
+const ACTIVITY_PAGE_DEFAULT: i64 = 50;
+const ACTIVITY_PAGE_MAX: i64 = 200;
+// Sign-in methods added this recently are removed when no reported event is named
+const COMPROMISE_LOOKBACK_DAYS: i64 = 7;
+
+// Link another wallet. Ownership is proven the same way as a wallet sign-in: sign a challenge
+// from /auth/web3/challenge
 pub async fn connect_wallet(
     pool: web::Data<PgPool>,
     req: HttpRequest,
-    wallet_data: web::Json<Web3ChallengeRequest>,
+    wallet_data: web::Json<ConnectWalletRequest>,
 ) -> Result<HttpResponse> {
     let current_user = get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
-    // Check if wallet address is already connected to another user
-    let existing_user = sqlx::query!(
-        "SELECT id FROM users WHERE wallet_address = $1 AND id != $2",
-        wallet_data.address,
-        current_user.sub
-    )
-    .fetch_optional(pool.get_ref())
-    .await
-    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
-
-    if existing_user.is_some() {
+    let label = wallet_data.label.as_deref().map(str::trim).filter(|l| !l.is_empty());
+    if label.is_some_and(|l| l.chars().count() > WalletService::MAX_LABEL_LENGTH) {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Wallet address already connected to another account"
+            "error": format!("Wallet label must be at most {} characters", WalletService::MAX_LABEL_LENGTH)
         })));
     }
 
-    // Update user's wallet address
-    sqlx::query!(
-        "UPDATE users SET wallet_address = $1, updated_at = NOW() WHERE id = $2",
-        wallet_data.address,
-        current_user.sub
-    )
-    .execute(pool.get_ref())
-    .await
-    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to connect wallet"))?;
+    let chain = match verify_wallet_ownership(
+        pool.get_ref(),
+        &siwe_config(&req),
+        &wallet_data.address,
+        &wallet_data.challenge,
+        &wallet_data.signature,
+    ).await {
+        Ok(chain) => chain,
+        Err(message) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": message })));
+        }
+    };
+
+    let outcome = WalletService::add(pool.get_ref(), current_user.sub, &wallet_data.address, chain, label)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to connect wallet"))?;
+
+    let wallet = match outcome {
+        AddWalletOutcome::Added(wallet) => wallet,
+        AddWalletOutcome::AlreadyAdded(wallet) => {
+            return Ok(HttpResponse::Ok().json(serde_json::json!({
+                "message": "Wallet is already connected",
+                "wallet_address": wallet.address,
+                "wallet": wallet
+            })));
+        }
+        AddWalletOutcome::OwnedByOtherUser => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Wallet address already connected to another account"
+            })));
+        }
+    };
+
+    log_wallet_event(&pool, &req, current_user.sub, AuditLogger::EVENT_IDENTITY_LINKED, "wallet_connect", &wallet.address).await;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "Wallet connected successfully",
-        "wallet_address": wallet_data.address
+        "wallet_address": wallet.address,
+        "wallet": wallet
     })))
 }
 
+pub async fn list_wallets(pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let wallets = WalletService::list(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "wallets": wallets })))
+}
+
+// Rename a wallet or make it the primary one
+pub async fn update_wallet(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    update: web::Json<UpdateWalletRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let label = update.label.as_deref().map(str::trim);
+    if label.is_some_and(|l| l.chars().count() > WalletService::MAX_LABEL_LENGTH) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Wallet label must be at most {} characters", WalletService::MAX_LABEL_LENGTH)
+        })));
+    }
+
+    match WalletService::update(pool.get_ref(), current_user.sub, path.into_inner(), label, update.primary).await {
+        Ok(Some(wallet)) => Ok(HttpResponse::Ok().json(serde_json::json!({ "wallet": wallet }))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Wallet not found" }))),
+        Err(_) => Err(actix_web::error::ErrorInternalServerError("Database error")),
+    }
+}
+
+// Unlink a wallet. The last way to sign in can't be removed
+pub async fn remove_wallet(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let outcome = WalletService::remove(pool.get_ref(), current_user.sub, path.into_inner())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match outcome {
+        RemoveWalletOutcome::Removed(wallet) => {
+            log_wallet_event(&pool, &req, current_user.sub, AuditLogger::EVENT_IDENTITY_UNLINKED, "wallet_remove", &wallet.address).await;
+
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "message": "Wallet removed" })))
+        }
+        RemoveWalletOutcome::NotFound => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Wallet not found"
+        }))),
+        RemoveWalletOutcome::LastLoginMethod => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "This wallet is your only way to sign in. Set a password or link another account first."
+        }))),
+    }
+}
+
+pub(crate) async fn log_wallet_event(pool: &PgPool, req: &HttpRequest, user_id: i32, event_type: &str, action: &str, address: &str) {
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+
+    let _ = AuditLogger::log(
+        pool,
+        Some(user_id),
+        event_type,
+        action,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(serde_json::json!({ "provider": "wallet", "wallet_address": address })),
+    )
+    .await;
+}
+
 pub async fn add_email(
     pool: web::Data<PgPool>,
     req: HttpRequest,
@@ -75,9 +189,9 @@ pub async fn add_email(
         })));
     }
 
-    // Update user's email
+    // A different address has to be verified again
     sqlx::query!(
-        "UPDATE users SET email = $1, updated_at = NOW() WHERE id = $2",
+        "UPDATE users SET email = $1, email_verified = (email_verified AND email IS NOT DISTINCT FROM $1::VARCHAR), updated_at = NOW() WHERE id = $2",
         email,
         current_user.sub
     )
@@ -142,11 +256,19 @@ pub async fn logout_session(
         })));
     }
 
-    // Invalidate session
+    // Invalidate session, and the refresh tokens that would let it mint new access tokens
+    RefreshTokenService::revoke_for_session(pool.get_ref(), current_user.sub, session_id)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to logout session"))?;
     SessionManager::invalidate_session(pool.get_ref(), session_id)
         .await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to logout session"))?;
 
+    log_session_revoked(pool.get_ref(), &req, current_user.sub, "Session revoked", serde_json::json!({
+        "scope": "single",
+        "session_id": session_id
+    })).await;
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "Session logged out successfully"
     })))
@@ -178,11 +300,19 @@ pub async fn logout_all_sessions(
         None
     };
 
-    // Invalidate all sessions
+    // Invalidate all sessions and refresh tokens
+    RefreshTokenService::revoke_all_tokens(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to logout all sessions"))?;
     SessionManager::invalidate_all_sessions(pool.get_ref(), current_user.sub)
         .await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to logout all sessions"))?;
 
+    log_session_revoked(pool.get_ref(), &req, current_user.sub, "All sessions revoked", serde_json::json!({
+        "scope": "all",
+        "current_session_id": current_session_id
+    })).await;
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "All sessions logged out successfully"
     })))
@@ -222,12 +352,197 @@ pub async fn logout_other_sessions(
         })));
     };
 
-    // Invalidate all other sessions
+    // Invalidate all other sessions and their refresh tokens
+    RefreshTokenService::revoke_all_except_session(pool.get_ref(), current_user.sub, current_session_id)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to logout other sessions"))?;
     SessionManager::invalidate_other_sessions(pool.get_ref(), current_user.sub, current_session_id)
         .await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to logout other sessions"))?;
 
+    log_session_revoked(pool.get_ref(), &req, current_user.sub, "Other sessions revoked", serde_json::json!({
+        "scope": "others",
+        "current_session_id": current_session_id
+    })).await;
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "All other sessions logged out successfully"
     })))
 }
+
+async fn log_session_revoked(pool: &PgPool, req: &HttpRequest, user_id: i32, action: &str, details: serde_json::Value) {
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+
+    let _ = AuditLogger::log(
+        pool,
+        Some(user_id),
+        AuditLogger::EVENT_SESSION_REVOKED,
+        action,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(details),
+    )
+    .await;
+}
+
+// Security Activity Endpoints
+
+// The current user's recent logins, failed attempts, password/2FA changes and session revocations.
+// Query: limit, cursor (pass `next_cursor` back for the next page)
+pub async fn get_activity(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let limit = query
+        .get("limit")
+        .and_then(|l| l.parse::<i64>().ok())
+        .unwrap_or(ACTIVITY_PAGE_DEFAULT)
+        .clamp(1, ACTIVITY_PAGE_MAX);
+    let before_id = match query.get("cursor").map(|c| c.parse::<i32>()) {
+        None => None,
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid cursor"
+            })));
+        }
+    };
+
+    // Fetch one extra row to know whether another page exists
+    let mut events = AuditLogger::get_user_audit_trail(pool.get_ref(), current_user.sub, before_id, limit + 1)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to fetch activity"))?;
+
+    let has_more = events.len() as i64 > limit;
+    events.truncate(limit as usize);
+    let next_cursor = if has_more { events.last().map(|e| e.id.to_string()) } else { None };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "events": events,
+        "next_cursor": next_cursor
+    })))
+}
+
+// "This wasn't me": sign the account out everywhere, remove sign-in methods added since the
+// reported activity and force a password reset by email. Body (optional): event_id
+pub async fn report_compromise(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    report: Option<web::Json<ReportCompromiseRequest>>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let since = match report.and_then(|r| r.event_id) {
+        Some(event_id) => sqlx::query_scalar!(
+            "SELECT created_at FROM audit_logs WHERE id = $1 AND user_id = $2",
+            event_id,
+            current_user.sub
+        )
+        .fetch_optional(pool.get_ref())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+        .flatten()
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Activity not found"))?,
+        None => (chrono::Utc::now() - chrono::Duration::days(COMPROMISE_LOOKBACK_DAYS)).naive_utc(),
+    };
+
+    let user = sqlx::query!(
+        "SELECT email, password FROM users WHERE id = $1",
+        current_user.sub
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("User not found"))?;
+
+    // Sessions, refresh tokens, every access token issued so far (including this one) and personal access tokens
+    SessionManager::invalidate_all_sessions(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to revoke sessions"))?;
+    RefreshTokenService::revoke_all_tokens(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to revoke refresh tokens"))?;
+    TokenBlacklist::blacklist_all_user_tokens(pool.get_ref(), current_user.sub, "compromise_reported")
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to revoke tokens"))?;
+    PersonalAccessTokenService::revoke_all(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to revoke personal access tokens"))?;
+
+    // Passkeys, identities and wallets the attacker may have added. Kept if they are all that's left
+    let removed_login_methods = IdentityService::remove_login_methods_since(pool.get_ref(), current_user.sub, since)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to remove sign-in methods"))?;
+
+    // Accounts without a password (wallet or provider sign-in only) have nothing to reset
+    let reset_email = user.email.filter(|_| !user.password.is_empty());
+    let password_reset_required = reset_email.is_some();
+    let mut reset_email_sent = false;
+
+    if let Some(email) = &reset_email {
+        sqlx::query!(
+            "UPDATE users SET password_reset_required = true WHERE id = $1",
+            current_user.sub
+        )
+        .execute(pool.get_ref())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to require password reset"))?;
+
+        let reset_token = EmailService::generate_password_reset_token();
+        EmailService::store_password_reset_token(email, &reset_token).await;
+
+        match EmailService::new() {
+            Ok(client) => {
+                let locale = EmailService::locale_for_user(pool.get_ref(), current_user.sub)
+                    .await
+                    .or_else(|| locale_from_request(&req));
+                match client
+                    .with_outbox(pool.get_ref().clone())
+                    .send_password_reset_email(email, &reset_token, locale.as_deref())
+                    .await
+                {
+                    Ok(()) => reset_email_sent = true,
+                    Err(e) => eprintln!("Failed to send password reset email: {}", e),
+                }
+            }
+            Err(e) => eprintln!("Failed to initialize email client: {}", e),
+        }
+    }
+
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+    let _ = AuditLogger::log(
+        pool.get_ref(),
+        Some(current_user.sub),
+        AuditLogger::EVENT_COMPROMISE_REPORTED,
+        "User reported unrecognized activity, all sessions revoked",
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(serde_json::json!({
+            "password_reset_required": password_reset_required,
+            "reset_email_sent": reset_email_sent,
+            "since": since,
+            "removed_login_methods": removed_login_methods
+        })),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": if password_reset_required {
+            "All sessions have been signed out. Check your email to reset your password before signing in again."
+        } else {
+            "All sessions have been signed out."
+        },
+        "password_reset_required": password_reset_required,
+        "reset_email_sent": reset_email_sent,
+        "removed_login_methods": removed_login_methods
+    })))
+}
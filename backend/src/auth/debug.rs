@@ -1,12 +1,13 @@
-use actix_web::{HttpResponse, Result, web};
+use actix_web::{HttpRequest, HttpResponse, Result, web};
 use sqlx::PgPool;
 use serde::Deserialize;
 
+use crate::auth::password::password_hash_config;
 use crate::services::token_blacklist::TokenBlacklist;
 use crate::services::account_lockout::AccountLockout;
 use crate::services::cleanup_service::CleanupService;
 use crate::services::email_service::EmailService;
-use crate::utils::auth::AuthUtils;
+use crate::services::password_hash_service::PasswordHashService;
 
 pub async fn blacklist_stats(pool: web::Data<PgPool>) -> Result<HttpResponse> {
     match TokenBlacklist::get_stats(pool.get_ref()).await {
@@ -87,7 +88,7 @@ pub async fn cleanup_unverified_accounts(pool: web::Data<PgPool>) -> Result<Http
     match CleanupService::cleanup_old_unverified_accounts(pool.get_ref()).await {
         Ok(deleted_count) => {
             // Also cleanup expired verification codes
-            let codes_cleaned = EmailService::cleanup_expired_codes();
+            let codes_cleaned = EmailService::cleanup_expired_codes().await;
 
             Ok(HttpResponse::Ok().json(serde_json::json!({
                 "message": "Cleaned up unverified accounts and expired codes",
@@ -127,8 +128,8 @@ pub struct HashPasswordRequest {
     pub password: String,
 }
 
-pub async fn hash_password_debug(req: web::Json<HashPasswordRequest>) -> Result<HttpResponse> {
-    match AuthUtils::hash_password(&req.password) {
+pub async fn hash_password_debug(http_req: HttpRequest, req: web::Json<HashPasswordRequest>) -> Result<HttpResponse> {
+    match PasswordHashService::hash(&password_hash_config(&http_req), &req.password).await {
         Ok(hash) => {
             Ok(HttpResponse::Ok().json(serde_json::json!({
                 "password": req.password,
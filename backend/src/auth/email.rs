@@ -1,4 +1,5 @@
 use crate::services::email_service::EmailService;
+use crate::services::email_templates::locale_from_request;
 use crate::middleware::rate_limiter::RateLimiter;
 use actix_web::{HttpRequest, HttpResponse, Result, web};
 use serde::{Deserialize, Serialize};
@@ -29,6 +30,7 @@ pub struct VerifyEmailResponse {
 }
 
 pub async fn send_verification(
+    pool: web::Data<PgPool>,
     req_http: HttpRequest,
     req: web::Json<SendVerificationRequest>,
 ) -> Result<HttpResponse> {
@@ -47,7 +49,7 @@ pub async fn send_verification(
     }
 
     let client = match EmailService::new() {
-        Ok(client) => client,
+        Ok(client) => client.with_outbox(pool.get_ref().clone()),
         Err(e) => {
             return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
                 "error": format!("Email service not configured: {}", e)
@@ -59,10 +61,15 @@ pub async fn send_verification(
     let code = EmailService::generate_verification_code();
 
     // Store code with normalized email
-    EmailService::store_verification_code(&email, &code);
+    EmailService::store_verification_code(&email, &code).await;
+
+    // Prefer the account's saved locale over the browser's
+    let locale = EmailService::locale_for_email(pool.get_ref(), &email)
+        .await
+        .or_else(|| locale_from_request(&req_http));
 
     // Send email
-    match client.send_verification_email(&email, &code).await {
+    match client.send_verification_email(&email, &code, locale.as_deref()).await {
         Ok(_) => Ok(HttpResponse::Ok().json(SendVerificationResponse {
             message: "Verification email sent successfully".to_string(),
             resend_cooldown_seconds: 60,
@@ -80,10 +87,9 @@ pub async fn verify_email(
     let email = req.email.to_lowercase().trim().to_string();
     println!("Verify email request - Email: {}, Code: {}", email, req.code);
     
-    let is_valid = EmailService::verify_code(&email, &req.code);
+    let is_valid = EmailService::verify_code(&email, &req.code).await;
     
     println!("Verification result - Valid: {}", is_valid);
-    println!("Available codes: {:?}", EmailService::get_debug_codes());
 
     if is_valid {
         // Update email_verified status in database
@@ -109,13 +115,15 @@ pub async fn verify_email(
     }
 }
 
+#[cfg(feature = "debug-endpoints")]
 #[derive(Serialize)]
 pub struct DebugCodesResponse {
-    pub codes: std::collections::HashMap<String, String>,
+    pub codes: std::collections::HashMap<String, chrono::DateTime<Utc>>, // email -> expires_at
 }
 
+#[cfg(feature = "debug-endpoints")]
 pub async fn debug_codes() -> Result<HttpResponse> {
-    let codes = EmailService::get_debug_codes();
+    let codes = EmailService::get_debug_codes().await;
     Ok(HttpResponse::Ok().json(DebugCodesResponse { codes }))
 }
 
@@ -136,10 +144,12 @@ pub async fn check_code_expiry(
     req: web::Json<CheckExpiryRequest>,
 ) -> Result<HttpResponse> {
     let email = req.email.to_lowercase().trim().to_string();
-    let codes = EmailService::get_all_codes_with_expiry();
+    let expiry = EmailService::get_verification_code_expiry(&email)
+        .await
+        .filter(|expires_at| *expires_at > Utc::now());
     
-    if let Some((_, expires_at)) = codes.get(&email) {
-        let duration = *expires_at - Utc::now();
+    if let Some(expires_at) = expiry {
+        let duration = expires_at - Utc::now();
         let remaining_seconds = duration.num_seconds().max(0);
         Ok(HttpResponse::Ok().json(CheckExpiryResponse {
             has_code: true,
@@ -169,6 +179,7 @@ pub struct SendMfaCodeResponse {
 }
 
 pub async fn send_mfa_code(
+    pool: web::Data<PgPool>,
     req_http: HttpRequest,
     req: web::Json<SendMfaCodeRequest>,
     jwt_secret: web::Data<String>,
@@ -196,7 +207,7 @@ pub async fn send_mfa_code(
     }
 
     let client = match EmailService::new() {
-        Ok(client) => client,
+        Ok(client) => client.with_outbox(pool.get_ref().clone()),
         Err(e) => {
             return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
                 "error": format!("Email service not configured: {}", e)
@@ -208,10 +219,14 @@ pub async fn send_mfa_code(
     let code = EmailService::generate_verification_code();
 
     // Store code for MFA (indexed by user_id, not email)
-    EmailService::store_mfa_verification_code(mfa_claims.user_id, &code);
+    EmailService::store_mfa_verification_code(mfa_claims.user_id, &code).await;
+
+    let locale = EmailService::locale_for_user(pool.get_ref(), mfa_claims.user_id)
+        .await
+        .or_else(|| locale_from_request(&req_http));
 
     // Send email
-    match client.send_verification_email(&email, &code).await {
+    match client.send_mfa_code_email(&email, &code, locale.as_deref()).await {
         Ok(_) => {
             println!("MFA code sent successfully to user {}", mfa_claims.user_id);
             Ok(HttpResponse::Ok().json(SendMfaCodeResponse {
@@ -242,29 +257,19 @@ pub async fn check_mfa_code_expiry(
         .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid or expired MFA token"))?;
 
     // Check if MFA code exists for this user
-    let code = EmailService::get_mfa_verification_code(mfa_claims.user_id);
+    let expiry = EmailService::get_mfa_code_expiry(mfa_claims.user_id)
+        .await
+        .filter(|expires_at| *expires_at > Utc::now());
     
-    if code.is_some() {
-        // Code exists, calculate expiry
-        // We need to get the actual expiry time from the store
-        let codes = crate::services::email_service::EmailService::get_all_mfa_codes_with_expiry();
-        if let Some((_, expires_at)) = codes.get(&mfa_claims.user_id) {
-            let duration = *expires_at - Utc::now();
-            let remaining_seconds = duration.num_seconds().max(0);
-            Ok(HttpResponse::Ok().json(CheckExpiryResponse {
-                has_code: true,
-                expires_in_seconds: Some(remaining_seconds),
-                expires_at: Some(expires_at.to_rfc3339()),
-                message: "MFA code found".to_string(),
-            }))
-        } else {
-            Ok(HttpResponse::Ok().json(CheckExpiryResponse {
-                has_code: false,
-                expires_in_seconds: None,
-                expires_at: None,
-                message: "No active MFA code found".to_string(),
-            }))
-        }
+    if let Some(expires_at) = expiry {
+        let duration = expires_at - Utc::now();
+        let remaining_seconds = duration.num_seconds().max(0);
+        Ok(HttpResponse::Ok().json(CheckExpiryResponse {
+            has_code: true,
+            expires_in_seconds: Some(remaining_seconds),
+            expires_at: Some(expires_at.to_rfc3339()),
+            message: "MFA code found".to_string(),
+        }))
     } else {
         Ok(HttpResponse::Ok().json(CheckExpiryResponse {
             has_code: false,
@@ -1,14 +1,16 @@
-use actix_web::{HttpResponse, Result, web};
+use actix_web::{HttpRequest, HttpResponse, Result, web};
 use serde_json;
 use sqlx::PgPool;
 use serde::{Deserialize, Serialize};
-use reqwest::Client;
 
-use crate::models::user::UserResponse;
+use crate::auth::identities::resolve_external_login;
+use crate::models::user::{User, UserResponse};
 use crate::services::session_manager::{SessionManager, CreateSessionData};
 use crate::utils::auth::AuthUtils;
 use crate::services::mfa_service::MFAService;
 use crate::services::email_service::EmailService;
+use crate::services::google_id_token::{GoogleIdTokenVerifier, GoogleTokenError};
+use crate::services::identity_service::{ExternalIdentity, PROVIDER_GOOGLE};
 
 #[derive(Debug, Deserialize)]
 pub struct GoogleTokenRequest {
@@ -24,28 +26,28 @@ pub struct GoogleTokenResponse {
     pub user: UserResponse,
 }
 
-// Google JWT claims structure
-#[derive(Debug, Deserialize)]
-pub struct GoogleClaims {
-    pub iss: String,
-    pub sub: String,
-    pub aud: String,
-    pub email: String,
-    pub name: Option<String>,
-    pub picture: Option<String>,
-    pub email_verified: bool,
-    pub iat: i64,
-    pub exp: i64,
+// Tests register their own verifier pointing at a stub JWKS server
+pub(crate) fn google_verifier(req: &HttpRequest) -> GoogleIdTokenVerifier {
+    req.app_data::<web::Data<GoogleIdTokenVerifier>>()
+        .map(|v| v.get_ref().clone())
+        .unwrap_or_else(GoogleIdTokenVerifier::shared)
 }
 
 pub async fn google_callback(
     pool: web::Data<PgPool>,
     jwt_secret: web::Data<String>,
+    req: HttpRequest,
     token_req: web::Json<GoogleTokenRequest>,
 ) -> Result<HttpResponse> {
-    // Try to get user info from Google - works with both ID token and access token
-    let google_claims = match get_google_user_info(&token_req.token).await {
+    // Works with both ID tokens (JWKS signature check) and access tokens (tokeninfo)
+    let google_claims = match google_verifier(&req).verify(&token_req.token).await {
         Ok(claims) => claims,
+        Err(GoogleTokenError::NotConfigured) => {
+            eprintln!("Google sign-in attempted but GOOGLE_CLIENT_IDS is not set");
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Google sign-in is not configured"
+            })));
+        }
         Err(e) => {
             eprintln!("Failed to get Google user info: {}", e);
             return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
@@ -61,81 +63,35 @@ pub async fn google_callback(
         })));
     }
 
-    // Extract user info from Google claims
-    let email = &google_claims.email;
-    
     // Email dari Google harus ada dan verified
-    if email.is_empty() {
+    if google_claims.email.is_empty() {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
             "error": "Google account email is empty"
         })));
     }
 
-    let name = google_claims.name.as_ref()
-        .map(|n| n.clone())
-        .unwrap_or_else(|| email.split('@').next().unwrap_or("User").to_string());
+    // The Google account is identified by `sub`; a matching email only offers to link
+    let identity = ExternalIdentity {
+        provider: PROVIDER_GOOGLE.to_string(),
+        subject: google_claims.sub,
+        email: Some(google_claims.email),
+        email_verified: google_claims.email_verified,
+        username: None,
+        name: google_claims.name,
+    };
 
-    // Check if user exists
-    let user = match sqlx::query_as!(
-        crate::models::user::User,
-        "SELECT id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at
-         FROM users WHERE email = $1",
-        email
-    )
-    .fetch_optional(pool.get_ref())
-    .await
-    {
-        Ok(Some(user)) => {
-            // User already exists - this is a LOGIN
-            println!("Google OAuth: User {} already exists, logging in", user.email.as_ref().unwrap_or(&"unknown".to_string()));
-            user
-        }
-        Ok(None) => {
-            // Create new user from Google account (REGISTRATION)
-            println!("Google OAuth: Creating new user for email {}", email);
-            
-            // Username = name from Google profile (or email prefix as fallback)
-            let username = google_claims.name
-                .clone()
-                .unwrap_or_else(|| email.split('@').next().unwrap_or("user").to_string());
-            
-            match sqlx::query_as!(
-                crate::models::user::User,
-                "INSERT INTO users (username, email, password, role, email_verified, created_at, updated_at)
-                 VALUES ($1, $2, $3, $4, $5, NOW(), NOW())
-                 RETURNING id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at",
-                username,
-                email,
-                "google_oauth", // Placeholder password for OAuth users
-                "user",
-                true // Email is verified via Google
-            )
-            .fetch_one(pool.get_ref())
-            .await
-            {
-                Ok(user) => user,
-                Err(sqlx::Error::Database(db_err)) if db_err.constraint().is_some() => {
-                    // Username already taken by manual registration
-                    return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "Username already taken by another account. Please register manually with a different username."
-                    })));
-                }
-                Err(_) => {
-                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                        "error": "Failed to create user"
-                    })));
-                }
-            }
-        }
-        Err(_) => {
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Database error"
-            })));
-        }
+    let user = match resolve_external_login(pool.get_ref(), jwt_secret.get_ref(), &identity).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
     };
 
+    finish_google_login(pool.get_ref(), jwt_secret.get_ref(), user).await
+}
+
+/// Google sign-ins always continue to the MFA method selection page
+pub(crate) async fn finish_google_login(pool: &PgPool, jwt_secret: &str, user: User) -> Result<HttpResponse> {
     // Create JWT token
-    let token = match AuthUtils::create_token(user.id, &user.username, &user.role, jwt_secret.get_ref()) {
+    let token = match AuthUtils::create_token(user.id, &user.username, &user.role, jwt_secret) {
         Ok(t) => t,
         Err(_) => {
             return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
@@ -151,9 +107,10 @@ pub async fn google_callback(
         device_name: Some("OAuth".to_string()),
         ip_address: None,
         user_agent: None,
+        refresh_token_family: None,
     };
 
-    match SessionManager::create_session(pool.get_ref(), session_data).await {
+    match SessionManager::create_session(pool, session_data).await {
         Ok(_) => {},
         Err(_) => {
             return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
@@ -174,7 +131,7 @@ pub async fn google_callback(
         user.id,
         &user.username,
         user.email.as_deref(),
-        jwt_secret.get_ref()
+        jwt_secret
     )
     .map_err(|_| actix_web::error::ErrorInternalServerError("MFA token generation failed"))?;
 
@@ -183,12 +140,12 @@ pub async fn google_callback(
     let verification_code = EmailService::generate_verification_code();
     
     // Check if user already has a valid MFA code
-    let existing_code = EmailService::get_mfa_verification_code(user.id);
+    let has_existing_code = EmailService::has_mfa_verification_code(user.id).await;
     
-    if existing_code.is_none() {
+    if !has_existing_code {
         // Store code for later use (when user selects Email OTP)
         // But don't send email automatically to avoid double sending
-        EmailService::store_mfa_verification_code(user.id, &verification_code);
+        EmailService::store_mfa_verification_code(user.id, &verification_code).await;
         println!("MFA code generated and stored for user {} (not sent yet)", user.id);
     } else {
         println!("User {} already has valid MFA code, keeping existing code", user.id);
@@ -219,85 +176,3 @@ pub async fn google_callback(
         "message": "Google login successful. Please verify with MFA to complete authentication."
     })))
 }
-
-fn decode_google_token(token: &str) -> Result<GoogleClaims, Box<dyn std::error::Error>> {
-    // In production, you should fetch Google's public keys from:
-    // https://www.googleapis.com/oauth2/v1/certs
-    
-    // For now, we'll do a basic decode without verification
-    // This is a security compromise - in production you MUST verify against Google
-    
-    let parts: Vec<&str> = token.split('.').collect();
-    if parts.len() != 3 {
-        return Err("Invalid token format".into());
-    }
-
-    let payload = parts[1];
-    let decoded = base64_url_decode(payload)?;
-    let claims: GoogleClaims = serde_json::from_slice(&decoded)?;
-
-    // Basic time validation
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)?
-        .as_secs() as i64;
-
-    if claims.exp < now {
-        return Err("Token expired".into());
-    }
-
-    Ok(claims)
-}
-
-fn base64_url_decode(s: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let s = s.replace('-', "+").replace('_', "/");
-    let padding = (4 - s.len() % 4) % 4;
-    let s = format!("{}{}", s, "=".repeat(padding));
-    
-    let engine = base64::engine::general_purpose::STANDARD;
-    Ok(base64::engine::Engine::decode(&engine, s)?)
-}
-
-// Get user info from Google using access token via tokeninfo endpoint
-async fn get_google_user_info(token: &str) -> Result<GoogleClaims, Box<dyn std::error::Error>> {
-    let client = Client::new();
-    
-    // Try to decode as JWT first (for ID tokens)
-    if let Ok(claims) = decode_google_token(token) {
-        return Ok(claims);
-    }
-    
-    // If JWT decode fails, use Google's tokeninfo endpoint (for access tokens)
-    let response = client
-        .get(&format!("https://www.googleapis.com/oauth2/v1/tokeninfo?access_token={}", token))
-        .send()
-        .await?;
-    
-    if !response.status().is_success() {
-        return Err("Failed to verify access token with Google".into());
-    }
-    
-    #[derive(Deserialize)]
-    struct TokenInfo {
-        email: String,
-        #[serde(default)]
-        name: Option<String>,
-        email_verified: Option<bool>,
-    }
-    
-    let token_info: TokenInfo = response.json().await?;
-    
-    // Create GoogleClaims from token info
-    let claims = GoogleClaims {
-        iss: "https://accounts.google.com".to_string(),
-        sub: "".to_string(), // Not available from tokeninfo
-        aud: "".to_string(), // Not available from tokeninfo
-        email: token_info.email,
-        name: token_info.name,
-        picture: None,
-        email_verified: token_info.email_verified.unwrap_or(true),
-        iat: 0,
-        exp: i64::MAX,
-    };
-    
-    Ok(claims)
-}
@@ -0,0 +1,320 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::auth::google::{GoogleTokenRequest, finish_google_login, google_verifier};
+use crate::auth::oidc::{OidcCallbackRequest, error_response, registry};
+use crate::auth::traditional::{complete_login, mfa_challenge};
+use crate::middleware::auth::get_current_user;
+use crate::models::user::User;
+use crate::services::audit_logger::AuditLogger;
+use crate::services::identity_service::{
+    ExternalIdentity, ExternalLogin, IdentityService, LinkOutcome, PROVIDER_GOOGLE, UnlinkOutcome,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmLinkRequest {
+    pub link_token: String,
+}
+
+/// Map an external login to the account it signs into. `Err` carries the response to send
+/// instead: a link confirmation for an existing account with the same email, or a refusal
+pub(crate) async fn resolve_external_login(
+    pool: &PgPool,
+    jwt_secret: &str,
+    identity: &ExternalIdentity,
+) -> std::result::Result<User, HttpResponse> {
+    let database_error = |e: sqlx::Error| {
+        eprintln!("Failed to resolve {} login: {}", identity.provider, e);
+        HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Database error" }))
+    };
+
+    match IdentityService::resolve_login(pool, identity).await.map_err(database_error)? {
+        ExternalLogin::Linked(user) => Ok(user),
+        ExternalLogin::ConfirmLink(user) => {
+            let link_token = IdentityService::create_link_token(user.id, identity, jwt_secret).map_err(|_| {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Failed to create link token" }))
+            })?;
+
+            Err(HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!(
+                    "An account with this email already exists. Confirm to link your {} account to it, or sign in and link it from your account settings.",
+                    identity.provider
+                ),
+                "link_required": true,
+                "link_token": link_token,
+                "provider": identity.provider,
+                "email": identity.email
+            })))
+        }
+        ExternalLogin::EmailNotVerified => Err(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!(
+                "An account with this email already exists but its email is not verified. Sign in to it and link your {} account from your account settings.",
+                identity.provider
+            )
+        }))),
+        ExternalLogin::NewUser => {
+            // New accounts are created with the provider's email, so it has to be one the provider verified
+            if identity.email.is_none() || !identity.email_verified {
+                return Err(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Your {} account has no verified email address", identity.provider)
+                })));
+            }
+
+            let user = IdentityService::create_user(pool, identity).await.map_err(database_error)?;
+            println!("Created user {} from {} account", user.username, identity.provider);
+            Ok(user)
+        }
+    }
+}
+
+// Linked sign-in methods plus whether a password or wallet is set, so the UI can tell which one is the last
+pub async fn list_identities(pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let identities = IdentityService::list(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+    let login_methods = IdentityService::login_methods(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "identities": identities,
+        "login_methods": login_methods
+    })))
+}
+
+// Link a Google account to the signed-in user
+pub async fn link_google_identity(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    token_req: web::Json<GoogleTokenRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let claims = match google_verifier(&req).verify(&token_req.token).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            eprintln!("Google link for user {} rejected: {}", current_user.sub, e);
+            return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid Google token or failed to verify"
+            })));
+        }
+    };
+
+    link_response(&pool, &req, current_user.sub, PROVIDER_GOOGLE, &claims.sub, Some(&claims.email)).await
+}
+
+// Begin a provider round trip that links the provider account to the signed-in user
+pub async fn start_oidc_link(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+    let provider = path.into_inner();
+
+    match registry(&req).start_login(pool.get_ref(), &provider, Some(current_user.sub)).await {
+        Ok(authorization) => Ok(HttpResponse::Ok().json(authorization)),
+        Err(e) => Ok(error_response(&provider, e)),
+    }
+}
+
+// The provider redirected back after a link started with `start_oidc_link`
+pub async fn link_oidc_identity(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    callback: web::Json<OidcCallbackRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+    let provider = path.into_inner();
+
+    let identity = match registry(&req)
+        .complete_login(pool.get_ref(), &provider, &callback.code, &callback.state)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(e) => return Ok(error_response(&provider, e)),
+    };
+
+    // The state must have been issued to this user, otherwise a login round trip could be replayed here
+    if identity.link_user_id != Some(current_user.sub) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "This link request was not started from your account"
+        })));
+    }
+
+    link_response(&pool, &req, current_user.sub, &identity.provider, &identity.subject, identity.email.as_deref()).await
+}
+
+// Remove a linked sign-in method. The last way to sign in can't be removed
+pub async fn unlink_identity(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let outcome = IdentityService::unlink(pool.get_ref(), current_user.sub, path.into_inner())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match outcome {
+        UnlinkOutcome::Unlinked(identity) => {
+            log_identity_event(
+                &pool,
+                &req,
+                current_user.sub,
+                AuditLogger::EVENT_IDENTITY_UNLINKED,
+                "unlink",
+                serde_json::json!({ "provider": identity.provider, "email": identity.email }),
+            )
+            .await;
+
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "message": format!("{} account unlinked", identity.provider)
+            })))
+        }
+        UnlinkOutcome::NotFound => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Linked account not found"
+        }))),
+        UnlinkOutcome::LastLoginMethod => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "This is your only way to sign in. Set a password or link another account first."
+        }))),
+    }
+}
+
+// Link a provider account to the existing account with the same verified email, after the user
+// confirmed it on the login page, and finish signing in
+pub async fn confirm_identity_link(
+    pool: web::Data<PgPool>,
+    jwt_secret: web::Data<String>,
+    req: HttpRequest,
+    confirm: web::Json<ConfirmLinkRequest>,
+) -> Result<HttpResponse> {
+    let Some(claims) = IdentityService::verify_link_token(&confirm.link_token, jwt_secret.get_ref()) else {
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Invalid or expired link request. Please sign in again."
+        })));
+    };
+
+    let outcome = IdentityService::link(pool.get_ref(), claims.sub, &claims.provider, &claims.subject, claims.email.as_deref())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match outcome {
+        LinkOutcome::Linked(_) => {}
+        // A link token signs the user in, so it is only good for the link it was issued for
+        LinkOutcome::AlreadyLinked(_) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "This link request has already been used. Please sign in again."
+            })));
+        }
+        LinkOutcome::LinkedToOtherUser => {
+            return Ok(HttpResponse::Conflict().json(serde_json::json!({
+                "error": format!("This {} account is already linked to another user", claims.provider)
+            })));
+        }
+    }
+
+    log_identity_event(
+        &pool,
+        &req,
+        claims.sub,
+        AuditLogger::EVENT_IDENTITY_LINKED,
+        "confirm_link",
+        serde_json::json!({ "provider": claims.provider, "email": claims.email }),
+    )
+    .await;
+
+    let user = sqlx::query_as!(
+        User,
+        "SELECT id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at
+         FROM users WHERE id = $1",
+        claims.sub
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    // Finish the same way a login with this provider would have
+    if claims.provider == PROVIDER_GOOGLE {
+        return finish_google_login(pool.get_ref(), jwt_secret.get_ref(), user).await;
+    }
+    if user.totp_enabled.unwrap_or(false) {
+        let message = format!("Linked {}. Please verify with 2FA to complete authentication.", claims.provider);
+        return mfa_challenge(pool.get_ref(), jwt_secret.get_ref(), user, &message).await;
+    }
+
+    complete_login(pool, jwt_secret, req, user).await
+}
+
+async fn link_response(
+    pool: &PgPool,
+    req: &HttpRequest,
+    user_id: i32,
+    provider: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> Result<HttpResponse> {
+    let outcome = IdentityService::link(pool, user_id, provider, subject, email)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match outcome {
+        LinkOutcome::Linked(identity) => {
+            log_identity_event(
+                pool,
+                req,
+                user_id,
+                AuditLogger::EVENT_IDENTITY_LINKED,
+                "link",
+                serde_json::json!({ "provider": identity.provider, "email": identity.email }),
+            )
+            .await;
+
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "message": format!("{} account linked", provider),
+                "identity": identity
+            })))
+        }
+        LinkOutcome::AlreadyLinked(identity) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "message": format!("{} account is already linked", provider),
+            "identity": identity
+        }))),
+        LinkOutcome::LinkedToOtherUser => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("This {} account is already linked to another user", provider)
+        }))),
+    }
+}
+
+async fn log_identity_event(
+    pool: &PgPool,
+    req: &HttpRequest,
+    user_id: i32,
+    event_type: &str,
+    action: &str,
+    details: serde_json::Value,
+) {
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+
+    let _ = AuditLogger::log(
+        pool,
+        Some(user_id),
+        event_type,
+        action,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(details),
+    )
+    .await;
+}
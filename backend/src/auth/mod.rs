@@ -1,9 +1,17 @@
 pub mod account;
+#[cfg(feature = "debug-endpoints")]
 pub mod debug;
 pub mod email;
 pub mod google;
+pub mod identities;
+pub mod oidc;
+pub mod passkeys;
 pub mod password;
+pub mod passwordless;
+pub mod personal_access_tokens;
 pub mod security;
+pub mod step_up;
+pub mod totp;
 pub mod traditional;
 pub mod web3;
 pub mod refresh_tokens;
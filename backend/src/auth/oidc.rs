@@ -0,0 +1,102 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::auth::identities::resolve_external_login;
+use crate::auth::traditional::{complete_login, mfa_challenge};
+use crate::services::oidc_service::{OidcError, OidcRegistry};
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackRequest {
+    pub code: String,
+    pub state: String,
+}
+
+// Tests register their own registry pointing at a stub provider
+pub(crate) fn registry(req: &HttpRequest) -> OidcRegistry {
+    req.app_data::<web::Data<OidcRegistry>>()
+        .map(|r| r.get_ref().clone())
+        .unwrap_or_else(OidcRegistry::shared)
+}
+
+pub(crate) fn error_response(provider: &str, error: OidcError) -> HttpResponse {
+    let body = serde_json::json!({ "error": error.to_string() });
+    match error {
+        OidcError::UnknownProvider(_) => HttpResponse::NotFound().json(body),
+        OidcError::InvalidState => HttpResponse::BadRequest().json(body),
+        OidcError::TokenExchange(_) | OidcError::InvalidIdToken(_) | OidcError::MissingClaim(_) => {
+            eprintln!("OIDC login with {} rejected: {}", provider, error);
+            HttpResponse::Unauthorized().json(body)
+        }
+        OidcError::Provider(_) => {
+            eprintln!("OIDC provider {} unavailable: {}", provider, error);
+            HttpResponse::BadGateway().json(serde_json::json!({
+                "error": "The identity provider could not be reached. Please try again later."
+            }))
+        }
+        OidcError::Database(e) => {
+            eprintln!("OIDC login with {} failed: {}", provider, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Database error" }))
+        }
+    }
+}
+
+// Identity providers available on the login page
+pub async fn list_oidc_providers(req: HttpRequest) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "providers": registry(&req).providers()
+    })))
+}
+
+// Begin an authorization code + PKCE login; the frontend redirects the browser to `authorization_url`
+pub async fn oidc_start(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let provider = path.into_inner();
+
+    match registry(&req).start_login(pool.get_ref(), &provider, None).await {
+        Ok(authorization) => Ok(HttpResponse::Ok().json(authorization)),
+        Err(e) => Ok(error_response(&provider, e)),
+    }
+}
+
+// The provider redirected back to the frontend with `code` and `state`, which are posted here
+pub async fn oidc_callback(
+    pool: web::Data<PgPool>,
+    jwt_secret: web::Data<String>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    callback: web::Json<OidcCallbackRequest>,
+) -> Result<HttpResponse> {
+    let provider = path.into_inner();
+
+    let identity = match registry(&req)
+        .complete_login(pool.get_ref(), &provider, &callback.code, &callback.state)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(e) => return Ok(error_response(&provider, e)),
+    };
+
+    // Link round trips are finished by the signed-in user, not used to sign in
+    if identity.link_user_id.is_some() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "This request was started to link an account, not to sign in"
+        })));
+    }
+
+    let user = match resolve_external_login(pool.get_ref(), jwt_secret.get_ref(), &identity.into()).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    // Same second factor as a password login
+    if user.totp_enabled.unwrap_or(false) {
+        let message = format!("Signed in with {}. Please verify with 2FA to complete authentication.", provider);
+        return mfa_challenge(pool.get_ref(), jwt_secret.get_ref(), user, &message).await;
+    }
+
+    complete_login(pool, jwt_secret, req, user).await
+}
@@ -0,0 +1,289 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::auth::traditional::complete_login;
+use crate::middleware::auth::get_current_user;
+use crate::middleware::rate_limiter::RateLimiter;
+use crate::models::user::User;
+use crate::services::audit_logger::AuditLogger;
+use crate::services::mfa_service::MFAService;
+use crate::services::webauthn_service::{
+    AuthenticationCredential, PasskeyCredential, RegistrationCredential, RemovePasskeyOutcome, WebAuthnConfig,
+    WebAuthnError, WebAuthnService,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct FinishRegistrationRequest {
+    pub credential: RegistrationCredential,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FinishLoginRequest {
+    pub credential: AuthenticationCredential,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyMfaStartRequest {
+    pub temp_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenamePasskeyRequest {
+    pub name: String,
+}
+
+pub(crate) fn webauthn_config(req: &HttpRequest) -> WebAuthnConfig {
+    req.app_data::<web::Data<WebAuthnConfig>>()
+        .map(|c| c.get_ref().clone())
+        .unwrap_or_else(WebAuthnConfig::shared)
+}
+
+pub(crate) fn passkey_error_response(error: &WebAuthnError) -> HttpResponse {
+    match error {
+        WebAuthnError::Database(e) => {
+            eprintln!("Passkey database error: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Database error" }))
+        }
+        WebAuthnError::CredentialExists => HttpResponse::Conflict().json(serde_json::json!({ "error": error.to_string() })),
+        WebAuthnError::InvalidResponse(_) => HttpResponse::BadRequest().json(serde_json::json!({ "error": error.to_string() })),
+        _ => HttpResponse::Unauthorized().json(serde_json::json!({ "error": error.to_string() })),
+    }
+}
+
+// Options for navigator.credentials.create()
+pub async fn start_passkey_registration(pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let user = find_user(&pool, current_user.sub)
+        .await?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("User not found"))?;
+
+    let options = WebAuthnService::start_registration(pool.get_ref(), &webauthn_config(&req), &user)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "publicKey": options })))
+}
+
+pub async fn finish_passkey_registration(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    registration: web::Json<FinishRegistrationRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let name = registration.name.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if name.is_some_and(|n| n.chars().count() > WebAuthnService::MAX_NAME_LENGTH) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Passkey name must be at most {} characters", WebAuthnService::MAX_NAME_LENGTH)
+        })));
+    }
+
+    let passkey = match WebAuthnService::finish_registration(
+        pool.get_ref(),
+        &webauthn_config(&req),
+        current_user.sub,
+        &registration.credential,
+        name,
+    )
+    .await
+    {
+        Ok(passkey) => passkey,
+        Err(e) => return Ok(passkey_error_response(&e)),
+    };
+
+    log_passkey_event(&pool, &req, current_user.sub, AuditLogger::EVENT_PASSKEY_ADDED, "register", &passkey).await;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "message": "Passkey added",
+        "passkey": passkey
+    })))
+}
+
+pub async fn list_passkeys(pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let passkeys = WebAuthnService::list(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "passkeys": passkeys })))
+}
+
+pub async fn rename_passkey(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    rename: web::Json<RenamePasskeyRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    if rename.name.trim().chars().count() > WebAuthnService::MAX_NAME_LENGTH {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Passkey name must be at most {} characters", WebAuthnService::MAX_NAME_LENGTH)
+        })));
+    }
+
+    match WebAuthnService::rename(pool.get_ref(), current_user.sub, path.into_inner(), &rename.name)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+    {
+        Some(passkey) => Ok(HttpResponse::Ok().json(serde_json::json!({ "passkey": passkey }))),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Passkey not found" }))),
+    }
+}
+
+pub async fn remove_passkey(pool: web::Data<PgPool>, req: HttpRequest, path: web::Path<i32>) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let outcome = WebAuthnService::remove(pool.get_ref(), current_user.sub, path.into_inner())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match outcome {
+        RemovePasskeyOutcome::Removed(passkey) => {
+            log_passkey_event(&pool, &req, current_user.sub, AuditLogger::EVENT_PASSKEY_REMOVED, "remove", &passkey).await;
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "message": "Passkey removed" })))
+        }
+        RemovePasskeyOutcome::NotFound => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Passkey not found"
+        }))),
+        RemovePasskeyOutcome::LastLoginMethod => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "This is your only way to sign in. Set a password or add another sign-in method first."
+        }))),
+    }
+}
+
+// Options for a passwordless sign-in with a discoverable credential
+pub async fn start_passkey_login(pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse> {
+    // Rate limiting: 20 ceremonies per 5 minutes per IP
+    let (is_allowed, _, reset_seconds) = RateLimiter::check_limit(&req, "passkey_login", 20, 5);
+
+    if !is_allowed {
+        return Ok(HttpResponse::TooManyRequests().json(serde_json::json!({
+            "error": "Too many passkey sign-in attempts. Please try again later.",
+            "retry_after": reset_seconds
+        })));
+    }
+
+    let options = WebAuthnService::start_authentication(pool.get_ref(), &webauthn_config(&req), None)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "publicKey": options })))
+}
+
+// A user-verified passkey is both factors at once, so this signs in without a further MFA step
+pub async fn finish_passkey_login(
+    pool: web::Data<PgPool>,
+    jwt_secret: web::Data<String>,
+    req: HttpRequest,
+    login: web::Json<FinishLoginRequest>,
+) -> Result<HttpResponse> {
+    // Rate limiting: 10 assertions per 5 minutes per IP
+    let (is_allowed, _, reset_seconds) = RateLimiter::check_limit(&req, "passkey_login_finish", 10, 5);
+
+    if !is_allowed {
+        return Ok(HttpResponse::TooManyRequests().json(serde_json::json!({
+            "error": "Too many passkey sign-in attempts. Please try again later.",
+            "retry_after": reset_seconds
+        })));
+    }
+
+    let passkey = match WebAuthnService::finish_authentication(pool.get_ref(), &webauthn_config(&req), None, &login.credential).await {
+        Ok(passkey) => passkey,
+        Err(e) => {
+            log_failed_passkey(&pool, &req, &login.credential, &e).await;
+            return Ok(passkey_error_response(&e));
+        }
+    };
+
+    let user = find_user(&pool, passkey.user_id)
+        .await?
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Unknown passkey"))?;
+
+    complete_login(pool, jwt_secret, req, user).await
+}
+
+// Options for using a passkey as the second factor of a login (see `mfa_challenge`), finished
+// at /verify-mfa with method "passkey"
+pub async fn start_passkey_mfa(
+    pool: web::Data<PgPool>,
+    jwt_secret: web::Data<String>,
+    req: HttpRequest,
+    start: web::Json<PasskeyMfaStartRequest>,
+) -> Result<HttpResponse> {
+    let mfa_claims = MFAService::verify_mfa_token(&start.temp_token, jwt_secret.get_ref())
+        .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid or expired MFA token"))?;
+
+    let options = WebAuthnService::start_authentication(pool.get_ref(), &webauthn_config(&req), Some(mfa_claims.user_id))
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "publicKey": options })))
+}
+
+async fn find_user(pool: &PgPool, user_id: i32) -> Result<Option<User>> {
+    sqlx::query_as!(
+        User,
+        "SELECT id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at
+         FROM users WHERE id = $1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))
+}
+
+async fn log_passkey_event(pool: &PgPool, req: &HttpRequest, user_id: i32, event_type: &str, action: &str, passkey: &PasskeyCredential) {
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+
+    let _ = AuditLogger::log(
+        pool,
+        Some(user_id),
+        event_type,
+        action,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(serde_json::json!({ "method": "passkey", "passkey_id": passkey.id, "name": passkey.name })),
+    )
+    .await;
+}
+
+// Failed assertions for a known credential show up in its owner's activity
+pub(crate) async fn log_failed_passkey(pool: &PgPool, req: &HttpRequest, credential: &AuthenticationCredential, error: &WebAuthnError) {
+    if matches!(error, WebAuthnError::Database(_) | WebAuthnError::UnknownCredential) {
+        return;
+    }
+
+    let owner = sqlx::query_scalar!(
+        "SELECT user_id FROM webauthn_credentials WHERE credential_id = $1",
+        credential.id
+    )
+    .fetch_optional(pool)
+    .await
+    .ok()
+    .flatten();
+
+    if let Some(user_id) = owner {
+        let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+        let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+        let _ = AuditLogger::log_failed_login(
+            pool,
+            user_id,
+            &format!("passkey - {}", error),
+            ip_address.as_deref(),
+            user_agent.as_deref(),
+        )
+        .await;
+    }
+}
@@ -6,10 +6,54 @@ use crate::middleware::rate_limiter::RateLimiter;
 use crate::models::auth::{PasswordResetRequest, PasswordResetConfirm, PasswordResetResponse};
 use crate::models::user::User;
 use crate::services::email_service::EmailService;
+use crate::services::email_templates::locale_from_request;
 use crate::services::audit_logger::AuditLogger;
-use crate::utils::auth::AuthUtils;
-use totp_rs::{TOTP, Algorithm};
-use base32;
+use crate::services::password_hash_service::{PasswordHashConfig, PasswordHashService};
+use crate::services::password_policy_service::{PasswordOwner, PasswordPolicy, PasswordPolicyError, PasswordPolicyService};
+use crate::services::refresh_token_service::RefreshTokenService;
+
+pub(crate) fn password_hash_config(req: &HttpRequest) -> PasswordHashConfig {
+    req.app_data::<web::Data<PasswordHashConfig>>()
+        .map(|c| c.get_ref().clone())
+        .unwrap_or_else(PasswordHashConfig::shared)
+}
+
+pub(crate) fn password_policy(req: &HttpRequest) -> PasswordPolicy {
+    req.app_data::<web::Data<PasswordPolicy>>()
+        .map(|p| p.get_ref().clone())
+        .unwrap_or_else(PasswordPolicy::shared)
+}
+
+/// Why a new password is refused by the policy, if it is
+pub(crate) async fn password_policy_violation(
+    pool: &PgPool,
+    req: &HttpRequest,
+    password: &str,
+    owner: &PasswordOwner<'_>,
+) -> Result<Option<String>> {
+    match PasswordPolicyService::check(pool, &password_policy(req), password, owner).await {
+        Ok(()) => Ok(None),
+        Err(PasswordPolicyError::Rejected(reason)) => Ok(Some(reason)),
+        Err(e) => {
+            eprintln!("Failed to check password policy: {}", e);
+            Err(actix_web::error::ErrorInternalServerError("Failed to check password"))
+        }
+    }
+}
+
+/// Keep the replaced hash for the reuse check; a failure only weakens that check
+pub(crate) async fn remember_password(pool: &PgPool, req: &HttpRequest, user_id: i32, old_hash: &str) {
+    if let Err(e) = PasswordPolicyService::remember(pool, &password_policy(req), user_id, old_hash).await {
+        eprintln!("Failed to record password history for user {}: {}", user_id, e);
+    }
+}
+
+/// Hash a new password with the configured Argon2id parameters
+pub(crate) async fn hash_password(req: &HttpRequest, password: &str) -> Result<String> {
+    PasswordHashService::hash(&password_hash_config(req), password)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Password hashing failed"))
+}
 
 pub async fn request_password_reset(
     pool: web::Data<PgPool>,
@@ -48,23 +92,15 @@ pub async fn request_password_reset(
 
     let user = user.unwrap();
 
-    // Check if user is a traditional user (not Web3 only)
-    if user.password == "web3_auth" {
-        return Ok(HttpResponse::Ok().json(PasswordResetResponse {
-            success: true,
-            message: "If an account with this email exists, a password reset link has been sent.".to_string(),
-        }));
-    }
-
     // Generate reset token
     let reset_token = EmailService::generate_password_reset_token();
-    EmailService::store_password_reset_token(&reset_data.email, &reset_token);
+    EmailService::store_password_reset_token(&reset_data.email, &reset_token).await;
 
     // Send password reset email
     let email_client = match EmailService::new() {
         Ok(client) => {
             println!("Email client initialized successfully for password reset");
-            Some(client)
+            Some(client.with_outbox(pool.get_ref().clone()))
         },
         Err(e) => {
             println!("Failed to initialize email client for password reset: {}", e);
@@ -75,7 +111,10 @@ pub async fn request_password_reset(
 
     if let Some(client) = email_client {
         println!("Attempting to send password reset email to: {}", reset_data.email);
-        if let Err(e) = client.send_password_reset_email(&reset_data.email, &reset_token).await {
+        let locale = EmailService::locale_for_user(pool.get_ref(), user.id)
+            .await
+            .or_else(|| locale_from_request(&req_http));
+        if let Err(e) = client.send_password_reset_email(&reset_data.email, &reset_token, locale.as_deref()).await {
             println!("Failed to send password reset email: {}", e);
             eprintln!("Failed to send password reset email: {}", e);
             // Don't fail the request if email fails
@@ -94,10 +133,11 @@ pub async fn request_password_reset(
 
 pub async fn reset_password(
     pool: web::Data<PgPool>,
+    req: HttpRequest,
     reset_data: web::Json<PasswordResetConfirm>,
 ) -> Result<HttpResponse> {
     // Verify the reset token and get the associated email
-    let email = match EmailService::verify_password_reset_token(&reset_data.token) {
+    let email = match EmailService::verify_password_reset_token(&reset_data.token).await {
         Some(email) => email,
         None => {
             return Ok(HttpResponse::BadRequest().json(PasswordResetResponse {
@@ -128,26 +168,46 @@ pub async fn reset_password(
         }
     };
 
+    let owner = PasswordOwner {
+        user_id: Some(user.id),
+        current_hash: Some(&user.password),
+        username: Some(&user.username),
+        email: user.email.as_deref(),
+    };
+    if let Some(reason) = password_policy_violation(&pool, &req, &reset_data.new_password, &owner).await? {
+        return Ok(HttpResponse::BadRequest().json(PasswordResetResponse {
+            success: false,
+            message: format!("Invalid password: {}", reason),
+        }));
+    }
+
     // Hash the new password
-    let hashed_password = AuthUtils::hash_password(&reset_data.new_password)
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Password hashing failed"))?;
+    let hashed_password = hash_password(&req, &reset_data.new_password).await?;
 
-    // Update password in database
+    // Update password in database (this also satisfies a forced reset)
     sqlx::query!(
-        "UPDATE users SET password = $1 WHERE id = $2",
+        "UPDATE users SET password = $1, password_reset_required = false WHERE id = $2",
         hashed_password,
         user.id
     )
     .execute(pool.get_ref())
     .await
     .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to update password"))?;
+    remember_password(&pool, &req, user.id, &user.password).await;
+
+    // Whoever knew the old password may hold a refresh token
+    RefreshTokenService::revoke_all_tokens(pool.get_ref(), user.id)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to revoke refresh tokens"))?;
 
     // Log password reset
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
     let _ = AuditLogger::log_password_reset(
         pool.get_ref(),
         user.id,
-        None,
-        None,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
     ).await;
 
     Ok(HttpResponse::Ok().json(PasswordResetResponse {
@@ -156,6 +216,7 @@ pub async fn reset_password(
     }))
 }
 
+#[cfg(feature = "debug-endpoints")]
 pub async fn get_rate_limit_stats() -> Result<HttpResponse> {
     let stats = RateLimiter::get_stats();
     Ok(HttpResponse::Ok().json(serde_json::json!({
@@ -163,13 +224,15 @@ pub async fn get_rate_limit_stats() -> Result<HttpResponse> {
     })))
 }
 
+#[cfg(feature = "debug-endpoints")]
 pub async fn debug_password_reset_tokens() -> Result<HttpResponse> {
-    let tokens = EmailService::get_debug_password_reset_tokens();
+    let tokens = EmailService::get_debug_password_reset_tokens().await;
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "tokens": tokens
     })))
 }
 
+#[cfg(feature = "debug-endpoints")]
 pub async fn test_email_service() -> Result<HttpResponse> {
     match EmailService::new() {
         Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
@@ -182,225 +245,38 @@ pub async fn test_email_service() -> Result<HttpResponse> {
     }
 }
 
-pub async fn verify_password_change_code(
+pub async fn change_password(
     req: HttpRequest,
     pool: web::Data<PgPool>,
-    verify_data: web::Json<crate::models::auth::VerifyCodeRequest>,
+    change_data: web::Json<crate::models::auth::ChangePasswordRequest>,
 ) -> Result<HttpResponse> {
     // Get current user from JWT
     let current_user = crate::middleware::auth::get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
-    // Get user basic info including TOTP secret
+    // Validate new password
     let user = sqlx::query!(
-        "SELECT id, email, password, totp_enabled, totp_secret, username FROM users WHERE id = $1",
+        "SELECT username, email, password FROM users WHERE id = $1",
         current_user.sub
     )
-    .fetch_optional(pool.get_ref())
+    .fetch_one(pool.get_ref())
     .await
-    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
-    .ok_or_else(|| actix_web::error::ErrorNotFound("User not found"))?;
-
-    // Verify code based on method
-    println!("Verify code request - Method: {}, Code: {}", verify_data.verification_method, verify_data.verification_code);
-    match verify_data.verification_method.as_str() {
-        "totp" => {
-            if !user.totp_enabled.unwrap_or(false) {
-                 return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "2FA (TOTP) is not enabled for this account"
-                })));
-            }
-            
-            let totp_secret = user.totp_secret.ok_or_else(|| {
-                actix_web::error::ErrorInternalServerError("TOTP secret missing")
-            })?;
-
-            let secret_bytes = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &totp_secret)
-                .ok_or_else(|| actix_web::error::ErrorInternalServerError("Invalid TOTP secret format"))?;
-
-            let totp = TOTP::new(
-                Algorithm::SHA1,
-                6,
-                1,
-                30,
-                secret_bytes,
-                Some("USH".to_string()),
-                user.username.clone(),
-            ).map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create TOTP instance"))?;
-
-            let current_time = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-
-            if !totp.check(&verify_data.verification_code, current_time) {
-                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "Invalid 2FA code"
-                })));
-            }
-        }
-        "email" => {
-             let email = user.email.clone().ok_or_else(|| {
-                 actix_web::error::ErrorBadRequest("User does not have an email address")
-             })?;
-             
-             // Normalize email to match how it's stored
-             let normalized_email = email.to_lowercase().trim().to_string();
-             println!("Verifying email code for: {}", normalized_email);
-
-             let is_valid = EmailService::verify_code(&normalized_email, &verify_data.verification_code);
-             println!("Verification result for {}: {}", normalized_email, is_valid);
-             
-             if !is_valid {
-                 return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "Invalid or expired email verification code"
-                })));
-             }
-        }
-        _ => {
-             return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid verification method. Use 'totp' or 'email'."
-            })));
-        }
-    }
-
-    // Generate temp token
-    let temp_token = EmailService::generate_password_reset_token();
-    let email = user.email.ok_or_else(|| actix_web::error::ErrorBadRequest("User email required"))?;
-    EmailService::store_password_reset_token(&email, &temp_token);
-
-    Ok(HttpResponse::Ok().json(crate::models::auth::VerifyCodeResponse {
-        success: true,
-        temp_token,
-        message: "Code verified successfully".to_string(),
-    }))
-}
-
-pub async fn change_password(
-    req: HttpRequest,
-    pool: web::Data<PgPool>,
-    change_data: web::Json<crate::models::auth::ChangePasswordRequest>,
-) -> Result<HttpResponse> {
-    // Get current user from JWT
-    let current_user = crate::middleware::auth::get_current_user(&req)
-        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
-
-    // If temp_token is provided, verify it
-    if let Some(token) = &change_data.temp_token {
-        let email = EmailService::verify_password_reset_token(token);
-        
-        if email.is_none() {
-             return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid or expired verification token. Please verify your code again."
-            })));
-        }
-        
-        // Verify that the token belongs to the current user
-        // We need to fetch the user's email to compare
-        let user_email = sqlx::query!(
-            "SELECT email FROM users WHERE id = $1",
-            current_user.sub
-        )
-        .fetch_one(pool.get_ref())
-        .await
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
-        .email;
-
-        if user_email != email {
-             return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Token mismatch"
-            })));
-        }
-    } else if let (Some(code), Some(method)) = (&change_data.verification_code, &change_data.verification_method) {
-        // Legacy/Single-step flow
-        // Get user basic info including TOTP secret
-        let user = sqlx::query!(
-            "SELECT id, email, password, totp_enabled, totp_secret, username FROM users WHERE id = $1",
-            current_user.sub
-        )
-        .fetch_optional(pool.get_ref())
-        .await
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
-        .ok_or_else(|| actix_web::error::ErrorNotFound("User not found"))?;
-
-        // Verify code based on method
-        println!("Change password request - Method: {}, Code: {}", method, code);
-        match method.as_str() {
-            "totp" => {
-                if !user.totp_enabled.unwrap_or(false) {
-                     return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "2FA (TOTP) is not enabled for this account"
-                    })));
-                }
-                
-                let totp_secret = user.totp_secret.ok_or_else(|| {
-                    actix_web::error::ErrorInternalServerError("TOTP secret missing")
-                })?;
-
-                let secret_bytes = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &totp_secret)
-                    .ok_or_else(|| actix_web::error::ErrorInternalServerError("Invalid TOTP secret format"))?;
-
-                let totp = TOTP::new(
-                    Algorithm::SHA1,
-                    6,
-                    1,
-                    30,
-                    secret_bytes,
-                    Some("USH".to_string()),
-                    user.username.clone(),
-                ).map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create TOTP instance"))?;
-
-                let current_time = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-
-                if !totp.check(code, current_time) {
-                    return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "Invalid 2FA code"
-                    })));
-                }
-            }
-            "email" => {
-                 let email = user.email.ok_or_else(|| {
-                     actix_web::error::ErrorBadRequest("User does not have an email address")
-                 })?;
-                 
-                 // Normalize email to match how it's stored
-                 let normalized_email = email.to_lowercase().trim().to_string();
-                 println!("Verifying email code for: {}", normalized_email);
-
-                 let is_valid = EmailService::verify_code(&normalized_email, code);
-                 println!("Verification result for {}: {}", normalized_email, is_valid);
-                 
-                 if !is_valid {
-                     return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "Invalid or expired email verification code"
-                    })));
-                 }
-            }
-            _ => {
-                 return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "Invalid verification method. Use 'totp' or 'email'."
-                })));
-            }
-        }
-    } else {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Missing verification credentials"
-        })));
-    }
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
 
-    // Validate new password
-    if let Err(e) = crate::utils::validation::validate_password(&change_data.new_password) {
+    let owner = PasswordOwner {
+        user_id: Some(current_user.sub),
+        current_hash: Some(&user.password),
+        username: Some(&user.username),
+        email: user.email.as_deref(),
+    };
+    if let Some(reason) = password_policy_violation(&pool, &req, &change_data.new_password, &owner).await? {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid password: {}", e)
+            "error": format!("Invalid password: {}", reason)
         })));
     }
 
     // Hash new password
-    let hashed_password = AuthUtils::hash_password(&change_data.new_password)
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Password hashing failed"))?;
+    let hashed_password = hash_password(&req, &change_data.new_password).await?;
 
     // Update password in database
     sqlx::query!(
@@ -411,6 +287,24 @@ pub async fn change_password(
     .execute(pool.get_ref())
     .await
     .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to update password"))?;
+    remember_password(&pool, &req, current_user.sub, &user.password).await;
+
+    RefreshTokenService::revoke_all_tokens(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to revoke refresh tokens"))?;
+
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+    let _ = AuditLogger::log(
+        pool.get_ref(),
+        Some(current_user.sub),
+        AuditLogger::EVENT_PASSWORD_CHANGE,
+        "Password changed",
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        None,
+    ).await;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
@@ -0,0 +1,210 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::auth::traditional::{complete_login, mfa_challenge, password_reset_response};
+use crate::models::user::User;
+use crate::services::account_lockout::AccountLockout;
+use crate::services::audit_logger::AuditLogger;
+use crate::services::email_service::EmailService;
+use crate::services::email_templates::locale_from_request;
+use crate::services::passwordless_service::PasswordlessService;
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordlessStartRequest {
+    pub email: String,
+}
+
+/// Either the token from the emailed link, or the email address with the emailed code
+#[derive(Debug, Deserialize)]
+pub struct PasswordlessCompleteRequest {
+    pub token: Option<String>,
+    pub email: Option<String>,
+    pub code: Option<String>,
+}
+
+const START_MESSAGE: &str = "If an account with this email exists, a sign-in link and code have been sent.";
+
+// Email a sign-in link and code. The answer is the same whether or not the account exists
+pub async fn passwordless_start(
+    pool: web::Data<PgPool>,
+    jwt_secret: web::Data<String>,
+    req: HttpRequest,
+    start: web::Json<PasswordlessStartRequest>,
+) -> Result<HttpResponse> {
+    let email_client = match EmailService::new() {
+        Ok(client) => client.with_outbox(pool.get_ref().clone()),
+        Err(e) => {
+            eprintln!("Failed to initialize email client for passwordless login: {}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Email service is not available"));
+        }
+    };
+
+    let Some(user) = find_user_by_email(&pool, &start.email).await? else {
+        return Ok(start_response());
+    };
+
+    let login = match PasswordlessService::issue(user.id, jwt_secret.get_ref()).await {
+        Ok(Some(login)) => login,
+        // The last email is recent enough, don't send another one yet
+        Ok(None) => return Ok(start_response()),
+        Err(e) => {
+            eprintln!("Failed to store passwordless login code: {}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Failed to start sign-in"));
+        }
+    };
+
+    let email = user.email.as_deref().unwrap_or_default();
+    let locale = EmailService::locale_for_user(pool.get_ref(), user.id)
+        .await
+        .or_else(|| locale_from_request(&req));
+    if let Err(e) = email_client.send_passwordless_login_email(email, &login.token, &login.code, locale.as_deref()).await {
+        eprintln!("Failed to send passwordless login email: {}", e);
+        return Err(actix_web::error::ErrorInternalServerError("Failed to send sign-in email"));
+    }
+
+    Ok(start_response())
+}
+
+fn start_response() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": START_MESSAGE,
+        "expires_in": EmailService::PASSWORDLESS_LOGIN_EXPIRY_MINUTES * 60
+    }))
+}
+
+// Redeem a link or code, then finish like a password login: 2FA users get an MFA challenge
+pub async fn passwordless_complete(
+    pool: web::Data<PgPool>,
+    jwt_secret: web::Data<String>,
+    req: HttpRequest,
+    complete: web::Json<PasswordlessCompleteRequest>,
+) -> Result<HttpResponse> {
+    let user = match (&complete.token, &complete.email, &complete.code) {
+        (Some(token), _, _) => {
+            let user_id = PasswordlessService::redeem_token(token, jwt_secret.get_ref())
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to verify sign-in link"))?;
+            match user_id {
+                Some(user_id) => find_user(&pool, user_id).await?,
+                None => None,
+            }
+        }
+        (None, Some(email), Some(code)) => {
+            let Some(user) = find_user_by_email(&pool, email).await? else {
+                return Ok(invalid_response());
+            };
+            if let Some(locked) = lockout_response(&pool, &req, &user).await {
+                return Ok(locked);
+            }
+            let redeemed = PasswordlessService::redeem_code(user.id, code)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to verify sign-in code"))?;
+            if !redeemed {
+                log_failed(&pool, &req, user.id, "Invalid passwordless login code").await;
+                return Ok(invalid_response());
+            }
+            Some(user)
+        }
+        _ => return Err(actix_web::error::ErrorBadRequest("Either token, or email and code, are required")),
+    };
+
+    let Some(user) = user else {
+        return Ok(invalid_response());
+    };
+
+    if let Some(locked) = lockout_response(&pool, &req, &user).await {
+        return Ok(locked);
+    }
+
+    if let Some(reset_required) = password_reset_response(pool.get_ref(), user.id).await? {
+        return Ok(reset_required);
+    }
+
+    if let Err(e) = AccountLockout::reset_attempts(pool.get_ref(), user.id).await {
+        eprintln!("Error resetting lockout attempts: {}", e);
+    }
+
+    if user.totp_enabled.unwrap_or(false) {
+        return mfa_challenge(
+            pool.get_ref(),
+            jwt_secret.get_ref(),
+            user,
+            "Sign-in link accepted. Please verify with 2FA to complete authentication.",
+        )
+        .await;
+    }
+
+    complete_login(pool, jwt_secret, req, user).await
+}
+
+fn invalid_response() -> HttpResponse {
+    HttpResponse::Unauthorized().json(serde_json::json!({
+        "error": "Invalid or expired sign-in link or code"
+    }))
+}
+
+// Same answer as a password login on a locked account
+async fn lockout_response(pool: &PgPool, req: &HttpRequest, user: &User) -> Option<HttpResponse> {
+    match AccountLockout::is_locked(pool, user.id).await {
+        Ok(true) => {}
+        Ok(false) => return None,
+        Err(e) => {
+            eprintln!("Error checking account lockout: {}", e);
+            return None;
+        }
+    }
+
+    let seconds = AccountLockout::get_remaining_lockout_seconds(pool, user.id).await.ok();
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+    let _ = AuditLogger::log(
+        pool,
+        Some(user.id),
+        AuditLogger::EVENT_FAILED_LOGIN,
+        "Passwordless login attempt on locked account",
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_BLOCKED,
+        seconds.map(|seconds| serde_json::json!({"remaining_seconds": seconds})),
+    )
+    .await;
+
+    Some(HttpResponse::Forbidden().json(serde_json::json!({
+        "error": "Account is temporarily locked due to too many failed login attempts",
+        "locked": true,
+        "retry_after": seconds
+    })))
+}
+
+async fn log_failed(pool: &PgPool, req: &HttpRequest, user_id: i32, reason: &str) {
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+    let _ = AuditLogger::log_failed_login(pool, user_id, reason, ip_address.as_deref(), user_agent.as_deref()).await;
+}
+
+// Only verified addresses can be used to sign in
+async fn find_user_by_email(pool: &PgPool, email: &str) -> Result<Option<User>> {
+    sqlx::query_as!(
+        User,
+        "SELECT id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at
+         FROM users WHERE LOWER(email) = LOWER($1) AND email_verified = true",
+        email.trim()
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))
+}
+
+async fn find_user(pool: &PgPool, user_id: i32) -> Result<Option<User>> {
+    sqlx::query_as!(
+        User,
+        "SELECT id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at
+         FROM users WHERE id = $1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))
+}
@@ -0,0 +1,166 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::middleware::auth::get_current_user;
+use crate::middleware::step_up::get_step_up;
+use crate::services::audit_logger::AuditLogger;
+use crate::services::personal_access_token_service::{self as access_tokens, PersonalAccessToken, PersonalAccessTokenService};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAccessTokenRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>,
+    pub rate_limit_per_minute: Option<i32>,
+}
+
+// The user's active personal access tokens, and the scopes a new one can have
+pub async fn list_access_tokens(pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let tokens = PersonalAccessTokenService::list(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "tokens": tokens,
+        "scopes": access_tokens::SCOPES
+    })))
+}
+
+// Create a token. Its value is in this response only, it is stored hashed
+pub async fn create_access_token(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    create: web::Json<CreateAccessTokenRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let name = create.name.trim();
+    if name.is_empty() || name.chars().count() > PersonalAccessTokenService::MAX_NAME_LENGTH {
+        return Ok(bad_request(format!(
+            "Token name must be 1 to {} characters",
+            PersonalAccessTokenService::MAX_NAME_LENGTH
+        )));
+    }
+
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in &create.scopes {
+        let scope = scope.trim();
+        if !access_tokens::SCOPES.contains(&scope) {
+            return Ok(bad_request(format!("Unknown scope: {}", scope)));
+        }
+        if !scopes.iter().any(|s| s == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+    if scopes.is_empty() {
+        return Ok(bad_request("Choose at least one scope".to_string()));
+    }
+
+    let expires_in_days = create.expires_in_days.unwrap_or(PersonalAccessTokenService::DEFAULT_EXPIRY_DAYS);
+    if !(1..=PersonalAccessTokenService::MAX_EXPIRY_DAYS).contains(&expires_in_days) {
+        return Ok(bad_request(format!(
+            "Tokens can be valid for 1 to {} days",
+            PersonalAccessTokenService::MAX_EXPIRY_DAYS
+        )));
+    }
+
+    let rate_limit = create.rate_limit_per_minute.unwrap_or(PersonalAccessTokenService::DEFAULT_RATE_LIMIT_PER_MINUTE);
+    if !(1..=PersonalAccessTokenService::MAX_RATE_LIMIT_PER_MINUTE).contains(&rate_limit) {
+        return Ok(bad_request(format!(
+            "Rate limit must be 1 to {} requests per minute",
+            PersonalAccessTokenService::MAX_RATE_LIMIT_PER_MINUTE
+        )));
+    }
+
+    let active = PersonalAccessTokenService::count_active(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+    if active >= PersonalAccessTokenService::MAX_TOKENS_PER_USER {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!(
+                "You can have at most {} active tokens. Revoke one first.",
+                PersonalAccessTokenService::MAX_TOKENS_PER_USER
+            )
+        })));
+    }
+
+    let created = PersonalAccessTokenService::create(pool.get_ref(), current_user.sub, name, &scopes, expires_in_days, rate_limit)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create token"))?;
+
+    // What the token can do, and how the user confirmed it was them, are kept with the event
+    let action = format!("create with scopes: {}", created.details.scopes.join(", "));
+    let step_up_method = get_step_up(&req).map(|claims| claims.method);
+    log_access_token_event(
+        &pool,
+        &req,
+        current_user.sub,
+        AuditLogger::EVENT_ACCESS_TOKEN_CREATED,
+        &action,
+        &created.details,
+        serde_json::json!({
+            "expires_at": created.details.expires_at,
+            "rate_limit_per_minute": created.details.rate_limit_per_minute,
+            "step_up_method": step_up_method
+        }),
+    )
+    .await;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({ "token": created })))
+}
+
+pub async fn revoke_access_token(pool: web::Data<PgPool>, req: HttpRequest, path: web::Path<i32>) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let revoked = PersonalAccessTokenService::revoke(pool.get_ref(), current_user.sub, path.into_inner())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match revoked {
+        Some(token) => {
+            log_access_token_event(&pool, &req, current_user.sub, AuditLogger::EVENT_ACCESS_TOKEN_REVOKED, "revoke", &token, serde_json::json!({})).await;
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "message": "Token revoked" })))
+        }
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Token not found" }))),
+    }
+}
+
+fn bad_request(error: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({ "error": error }))
+}
+
+async fn log_access_token_event(
+    pool: &PgPool,
+    req: &HttpRequest,
+    user_id: i32,
+    event_type: &str,
+    action: &str,
+    token: &PersonalAccessToken,
+    extra: serde_json::Value,
+) {
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+
+    let mut details = serde_json::json!({ "token_id": token.id, "name": token.name, "scopes": token.scopes });
+    if let (Some(details), serde_json::Value::Object(extra)) = (details.as_object_mut(), extra) {
+        details.extend(extra);
+    }
+
+    let _ = AuditLogger::log(
+        pool,
+        Some(user_id),
+        event_type,
+        action,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(details),
+    )
+    .await;
+}
@@ -1,53 +1,170 @@
-use chrono::{Utc, Duration};
-use uuid::Uuid;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-
-#[derive(Clone)]
-pub struct RefreshTokenManager {
-    store: Arc<Mutex<HashMap<String, String>>>, // user_id -> token
-    rotation_window: Duration,
-}
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use sqlx::PgPool;
+
+use crate::models::auth::{RefreshTokenRequest, RefreshTokenResponse};
+use crate::models::user::{User, UserResponse};
+use crate::services::audit_logger::AuditLogger;
+use crate::services::refresh_token_service::RefreshTokenService;
+use crate::services::session_manager::{SessionManager, CreateSessionData};
+use crate::utils::auth::AuthUtils;
+
+/// Exchange a refresh token for a new access JWT and a rotated refresh token.
+///
+/// Presenting a token that was already rotated is treated as token theft:
+/// the whole token family is revoked and the event is audited.
+pub async fn refresh_token(
+    pool: web::Data<PgPool>,
+    jwt_secret: web::Data<String>,
+    req: HttpRequest,
+    refresh_data: web::Json<RefreshTokenRequest>,
+) -> Result<HttpResponse> {
+    let ip_address = req.connection_info()
+        .peer_addr()
+        .map(|s| s.to_string());
+    let user_agent = req.headers()
+        .get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+
+    let record = RefreshTokenService::find_token(pool.get_ref(), &refresh_data.refresh_token)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    let record = match record {
+        Some(record) => record,
+        None => {
+            return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+                "error": "Invalid refresh token"
+            })));
+        }
+    };
+
+    // A token that already produced a child is being replayed
+    let reuse_detected = RefreshTokenService::detect_reuse_attack(
+        pool.get_ref(),
+        record.user_id,
+        &refresh_data.refresh_token,
+    )
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
 
-impl RefreshTokenManager {
-    pub fn new(rotation_minutes: i64) -> Self {
-        Self { store: Arc::new(Mutex::new(HashMap::new())), rotation_window: Duration::minutes(rotation_minutes) }
+    if reuse_detected {
+        return revoke_family_on_reuse(pool.get_ref(), record.user_id, &record.token_family, ip_address.as_deref(), user_agent.as_deref()).await;
     }
 
-    pub fn generate_for(&self, user_id: &str) -> String {
-        let token = Uuid::new_v4().to_string();
-        let mut s = self.store.lock().unwrap();
-        s.insert(user_id.to_string(), token.clone());
-        token
+    let is_valid = RefreshTokenService::verify_refresh_token(
+        pool.get_ref(),
+        record.user_id,
+        &refresh_data.refresh_token,
+    )
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    if !is_valid {
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Refresh token expired or revoked"
+        })));
     }
 
-    pub fn rotate(&self, user_id: &str) -> String {
-        // simple rotation: generate new and replace
-        self.generate_for(user_id)
+    // Claim the token before rotating; losing this race means it was used concurrently
+    let consumed = RefreshTokenService::consume_token(
+        pool.get_ref(),
+        record.user_id,
+        &refresh_data.refresh_token,
+    )
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    if !consumed {
+        return revoke_family_on_reuse(pool.get_ref(), record.user_id, &record.token_family, ip_address.as_deref(), user_agent.as_deref()).await;
     }
 
-    pub fn validate(&self, user_id: &str, token: &str) -> bool {
-        let s = self.store.lock().unwrap();
-        if let Some(stored) = s.get(user_id) {
-            return stored == token;
-        }
-        false
+    let user = sqlx::query_as!(
+        User,
+        "SELECT id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at
+         FROM users WHERE id = $1",
+        record.user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+    .ok_or_else(|| actix_web::error::ErrorUnauthorized("User not found"))?;
+
+    let new_refresh_token = RefreshTokenService::generate_token();
+    RefreshTokenService::rotate_refresh_token(
+        pool.get_ref(),
+        user.id,
+        &refresh_data.refresh_token,
+        &new_refresh_token,
+    )
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to rotate refresh token"))?;
+
+    let token = AuthUtils::create_token(user.id, &user.username, &user.role, jwt_secret.get_ref())
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Token creation failed"))?;
+
+    // Track the new access token as a session so logout can invalidate it
+    let device_name = user_agent.as_deref().map(|ua| {
+        if ua.contains("Mobile") { "Mobile" }
+        else if ua.contains("Tablet") { "Tablet" }
+        else { "Desktop" }
+    })
+    .map(|s| s.to_string());
+
+    let session_data = CreateSessionData {
+        user_id: user.id,
+        token: token.clone(),
+        device_name,
+        ip_address: ip_address.clone(),
+        user_agent: user_agent.clone(),
+        refresh_token_family: Some(record.token_family.clone()),
+    };
+
+    if let Err(e) = SessionManager::create_session(pool.get_ref(), session_data).await {
+        eprintln!("Failed to create session: {}", e);
     }
+
+    let _ = AuditLogger::log(
+        pool.get_ref(),
+        Some(user.id),
+        AuditLogger::EVENT_TOKEN_REFRESH,
+        "Access token refreshed",
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(serde_json::json!({"token_family": record.token_family})),
+    ).await;
+
+    Ok(HttpResponse::Ok().json(RefreshTokenResponse {
+        token,
+        refresh_token: new_refresh_token,
+        user: UserResponse::from(user),
+    }))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn refresh_token_flow() {
-        let mgr = RefreshTokenManager::new(60);
-        let user = "1";
-        let token = mgr.generate_for(user);
-        assert!(mgr.validate(user, &token));
-        let new_token = mgr.rotate(user);
-        assert!(!new_token.is_empty());
-        assert!(!mgr.validate(user, &token));
-        assert!(mgr.validate(user, &new_token));
+async fn revoke_family_on_reuse(
+    pool: &PgPool,
+    user_id: i32,
+    token_family: &str,
+    ip_address: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<HttpResponse> {
+    if let Err(e) = RefreshTokenService::revoke_token_family(pool, user_id, token_family).await {
+        eprintln!("Failed to revoke refresh token family: {}", e);
     }
+
+    let _ = AuditLogger::log(
+        pool,
+        Some(user_id),
+        AuditLogger::EVENT_REFRESH_TOKEN_REUSE,
+        "Refresh token reuse detected - token family revoked",
+        ip_address,
+        user_agent,
+        AuditLogger::STATUS_BLOCKED,
+        Some(serde_json::json!({"token_family": token_family})),
+    ).await;
+
+    Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+        "error": "Refresh token has already been used. Please log in again."
+    })))
 }
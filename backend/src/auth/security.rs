@@ -1,86 +1,54 @@
-use actix_web::{HttpRequest, HttpResponse, Result, web};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, Result, web};
 use sqlx::PgPool;
 
+use crate::auth::totp::{totp_config, totp_keyring, verify_totp};
 use crate::middleware::auth::get_current_user;
 use crate::models::auth::{TOTPSetupResponse, TOTPVerifyRequest, TOTPVerifyResponse};
+use crate::services::audit_logger::AuditLogger;
+use crate::services::recovery_code_service::RecoveryCodeService;
+use crate::services::step_up_service::StepUpClaims;
+use crate::services::totp_service::TotpService;
 
 pub async fn setup_2fa(pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse> {
     let current_user = get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
-    // Check if user already has a secret
-    let user_data = sqlx::query!(
-        "SELECT totp_secret, totp_enabled, recovery_codes FROM users WHERE id = $1",
+    let totp_enabled = sqlx::query_scalar!(
+        "SELECT totp_enabled FROM users WHERE id = $1",
         current_user.sub
     )
     .fetch_one(pool.get_ref())
     .await
     .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
 
-    if user_data.totp_enabled.unwrap_or(false) {
+    if totp_enabled.unwrap_or(false) {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
             "error": "2FA is already enabled"
         })));
     }
 
-    // If secret exists but not enabled, reuse it (don't regenerate)
-    if let (Some(secret), Some(codes)) = (user_data.totp_secret, user_data.recovery_codes) {
-        let qr_code_url = format!(
-            "otpauth://totp/USH:{}?secret={}&issuer=USH&algorithm=SHA1&digits=6&period=30",
-            current_user.username, secret
-        );
-
-        return Ok(HttpResponse::Ok().json(serde_json::json!({
-            "secret": secret,
-            "qr_code_url": qr_code_url,
-            "recovery_codes": codes
-        })));
-    }
-
-    // Generate random secret (only if none exists)
-    use rand::Rng;
-    let mut secret_bytes = [0u8; 20];
-    rand::thread_rng().fill(&mut secret_bytes);
-    let secret_bytes_vec = secret_bytes.to_vec();
-
-    let secret_base32 = base32::encode(
-        base32::Alphabet::RFC4648 { padding: false },
-        &secret_bytes_vec,
-    );
-
-    // Generate 8 recovery codes (8 characters each)
-    let recovery_codes: Vec<String> = (0..8)
-        .map(|_| {
-            let code: String = (0..8)
-                .map(|_| {
-                    let chars = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
-                    chars.chars().nth(rand::thread_rng().gen_range(0..chars.len())).unwrap()
-                })
-                .collect();
-            format!("{}-{}", &code[0..4], &code[4..8])
-        })
-        .collect();
-
-    // Save secret and recovery codes to database
-    sqlx::query!(
-        "UPDATE users SET totp_secret = $1, recovery_codes = $2 WHERE id = $3",
-        secret_base32,
-        &recovery_codes,
-        current_user.sub
+    // A secret already shown but not confirmed yet is reused (see `TotpService::enroll`)
+    let enrollment = TotpService::enroll(
+        pool.get_ref(),
+        &totp_keyring(&req),
+        &totp_config(&req),
+        current_user.sub,
+        &current_user.username,
+        TotpService::DEFAULT_LABEL,
     )
-    .execute(pool.get_ref())
     .await
     .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to save 2FA secret"))?;
 
-    // Generate QR code URL manually
-    let qr_code_url = format!(
-        "otpauth://totp/USH:{}?secret={}&issuer=USH&algorithm=SHA1&digits=6&period=30",
-        current_user.username, secret_base32
-    );
+    // Only hashes are stored, so an unfinished setup gets a fresh set of codes to show
+    let recovery_codes = RecoveryCodeService::regenerate(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to save recovery codes"))?;
 
     let response = serde_json::json!({
-        "secret": secret_base32,
-        "qr_code_url": qr_code_url,
+        "secret": enrollment.secret,
+        "qr_code_url": enrollment.qr_code_url,
+        "qr_code_png": enrollment.qr_code_png,
+        "authenticator": enrollment.authenticator,
         "recovery_codes": recovery_codes
     });
 
@@ -92,19 +60,8 @@ pub async fn verify_2fa(
     req: HttpRequest,
     verify_data: web::Json<TOTPVerifyRequest>,
 ) -> Result<HttpResponse> {
-    println!("\n=== 2FA Verification Started ===");
-    println!("Received code: {}", verify_data.code);
-    
-    // Get current server time
-    let server_time = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    println!("Server time (UTC): {} ({})", server_time, chrono::DateTime::<chrono::Utc>::from(std::time::UNIX_EPOCH) + chrono::Duration::seconds(server_time as i64));
-
     // Validate code format
     if verify_data.code.is_empty() || verify_data.code.len() < 6 {
-        println!("❌ Invalid code format: length={}", verify_data.code.len());
         return Ok(HttpResponse::BadRequest().json(TOTPVerifyResponse {
             success: false,
             message: "Code must be at least 6 digits".to_string(),
@@ -114,76 +71,15 @@ pub async fn verify_2fa(
     let current_user = get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
-    println!("✓ User authenticated: {} (ID: {})", current_user.username, current_user.sub);
-
-    // Get secret from database
-    let user_record = sqlx::query!(
-        "SELECT totp_secret FROM users WHERE id = $1",
-        current_user.sub
-    )
-    .fetch_one(pool.get_ref())
-    .await
-    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get user data"))?;
-
-    let totp_secret = user_record
-        .totp_secret
-        .ok_or_else(|| {
-            println!("❌ No TOTP secret found in database for user");
-            actix_web::error::ErrorBadRequest("2FA not set up for this user")
-        })?;
-
-    println!("✓ TOTP secret exists (base32): {}", &totp_secret);
-
-    // Decode base32 secret
-    let secret_bytes = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &totp_secret)
-        .ok_or_else(|| {
-            println!("❌ Failed to decode base32 secret");
-            actix_web::error::ErrorInternalServerError("Invalid TOTP secret format")
-        })?;
-
-    println!("✓ Secret decoded, length: {} bytes", secret_bytes.len());
-
-    // Create TOTP instance with proper parameters (RFC 6238 standard)
-    let totp = match totp_rs::TOTP::new(
-        totp_rs::Algorithm::SHA1,
-        6,              // 6-digit code
-        1,              // 1 step skew/tolerance (±30 seconds)
-        30,             // 30 second period
-        secret_bytes.clone(),
-        Some("USH".to_string()),
-        current_user.username.clone(),
-    ) {
-        Ok(t) => t,
-        Err(e) => {
-            println!("❌ Failed to create TOTP instance: {:?}", e);
-            return Err(actix_web::error::ErrorInternalServerError("Failed to create TOTP instance"));
-        }
-    };
-
-    // Check if the provided code is valid with time window tolerance
-    // Use check() method with current timestamp to allow ±1 step (±30 seconds) tolerance
-    let current_time = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    
-    let is_valid = totp.check(&verify_data.code, current_time);
-    
-    println!("🔍 TOTP Verification Result:");
-    println!("  - Code entered: {}", verify_data.code);
-    println!("  - Current timestamp: {}", current_time);
-    println!("  - Valid: {}", is_valid);
-    
-    // Also check what codes would be valid now (for debugging)
-    if !is_valid {
-        // Generate codes for debugging (current + previous + next)
-        println!("  - Debugging info:");
-        match totp.generate_current() {
-            Ok(current_code) => println!("    Current valid code: {}", current_code),
-            Err(e) => println!("    Error generating current code: {:?}", e),
-        }
+    let authenticators = TotpService::list(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get user data"))?;
+    if authenticators.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("2FA not set up for this user"));
     }
-    println!("=== 2FA Verification End ===\n");
+
+    // Confirms the authenticator from /setup-2fa
+    let is_valid = verify_totp(&pool, &req, current_user.sub, &verify_data.code).await?;
 
     if is_valid {
         // Enable 2FA in database
@@ -197,6 +93,19 @@ pub async fn verify_2fa(
 
         println!("✓ 2FA enabled in database for user {}", current_user.username);
 
+        let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+        let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+        let _ = AuditLogger::log(
+            pool.get_ref(),
+            Some(current_user.sub),
+            AuditLogger::EVENT_2FA_ENABLED,
+            "Two-factor authentication enabled",
+            ip_address.as_deref(),
+            user_agent.as_deref(),
+            AuditLogger::STATUS_SUCCESS,
+            Some(serde_json::json!({"method": "totp"})),
+        ).await;
+
         let response = TOTPVerifyResponse {
             success: true,
             message: "2FA code verified successfully and 2FA is now enabled".to_string(),
@@ -211,6 +120,7 @@ pub async fn verify_2fa(
     }
 }
 
+#[cfg(feature = "debug-endpoints")]
 pub async fn debug_2fa(
     pool: web::Data<PgPool>,
     req: HttpRequest,
@@ -224,46 +134,30 @@ pub async fn debug_2fa(
         .unwrap()
         .as_secs();
 
-    // Get secret from database
-    let user_record = sqlx::query!(
-        "SELECT totp_secret, totp_enabled FROM users WHERE id = $1",
+    let totp_enabled = sqlx::query_scalar!(
+        "SELECT totp_enabled FROM users WHERE id = $1",
         current_user.sub
     )
     .fetch_one(pool.get_ref())
     .await
-    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get user data"))?;
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get user data"))?
+    .unwrap_or(false);
 
-    let totp_secret = user_record.totp_secret.clone();
-    let totp_enabled = user_record.totp_enabled.unwrap_or(false);
+    // Reports the authenticators' parameters, never a secret or a code derived from one
+    let authenticators = TotpService::list(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get user data"))?;
 
-    let mut debug_info = serde_json::json!({
+    let debug_info = serde_json::json!({
         "user_id": current_user.sub,
         "username": current_user.username,
         "server_time_unix": server_time,
         "server_time_readable": chrono::DateTime::<chrono::Utc>::from(std::time::UNIX_EPOCH) + chrono::Duration::seconds(server_time as i64),
-        "totp_setup": totp_secret.is_some(),
+        "totp_setup": !authenticators.is_empty(),
         "totp_enabled": totp_enabled,
+        "authenticators": authenticators,
     });
 
-    if let Some(secret) = totp_secret {
-        if let Some(secret_bytes) = base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret) {
-            if let Ok(totp) = totp_rs::TOTP::new(
-                totp_rs::Algorithm::SHA1,
-                6,
-                1,
-                30,
-                secret_bytes,
-                Some("USH".to_string()),
-                current_user.username.clone(),
-            ) {
-                if let Ok(current_code) = totp.generate_current() {
-                    debug_info["current_valid_code"] = serde_json::json!(current_code);
-                    debug_info["code_valid_for_seconds"] = serde_json::json!("~30 seconds");
-                }
-            }
-        }
-    }
-
     Ok(HttpResponse::Ok().json(debug_info))
 }
 
@@ -274,19 +168,39 @@ pub async fn disable_2fa(
     let current_user = get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
+    // Set by StepUpMiddleware on the route
+    let step_up_method = req.extensions().get::<StepUpClaims>().map(|claims| claims.method);
+
     println!("=== Disable 2FA Started ===");
     println!("User: {} (ID: {})", current_user.username, current_user.sub);
 
     // Disable 2FA in database
     sqlx::query!(
-        "UPDATE users SET totp_enabled = false, totp_secret = NULL WHERE id = $1",
+        "UPDATE users SET totp_enabled = false, recovery_codes = NULL WHERE id = $1",
         current_user.sub
     )
     .execute(pool.get_ref())
     .await
     .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to disable 2FA"))?;
 
+    TotpService::clear(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to disable 2FA"))?;
+
     println!("✓ 2FA disabled for user {}", current_user.username);
+
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+    let _ = AuditLogger::log(
+        pool.get_ref(),
+        Some(current_user.sub),
+        AuditLogger::EVENT_2FA_DISABLED,
+        "Two-factor authentication disabled",
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(serde_json::json!({"method": "totp", "step_up": step_up_method})),
+    ).await;
     println!("=== Disable 2FA End ===\n");
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
@@ -294,3 +208,56 @@ pub async fn disable_2fa(
         "message": "2FA has been successfully disabled for your account"
     })))
 }
+
+// Replace the recovery codes with a new set. Requires a current TOTP code, so a stolen session
+// alone can't mint codes that bypass 2FA
+pub async fn regenerate_recovery_codes(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    verify_data: web::Json<TOTPVerifyRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let totp_enabled = sqlx::query_scalar!(
+        "SELECT totp_enabled FROM users WHERE id = $1",
+        current_user.sub
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get user data"))?;
+
+    if !totp_enabled.unwrap_or(false) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "2FA is not enabled for this account"
+        })));
+    }
+
+    if !verify_totp(&pool, &req, current_user.sub, &verify_data.code).await? {
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Invalid 2FA code"
+        })));
+    }
+
+    let recovery_codes = RecoveryCodeService::regenerate(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to save recovery codes"))?;
+
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+    let _ = AuditLogger::log(
+        pool.get_ref(),
+        Some(current_user.sub),
+        AuditLogger::EVENT_RECOVERY_CODES_REGENERATED,
+        "Recovery codes regenerated",
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(serde_json::json!({ "method": "totp", "recovery_codes_remaining": recovery_codes.len() })),
+    ).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "recovery_codes": recovery_codes,
+        "message": "New recovery codes generated. Your previous codes no longer work."
+    })))
+}
@@ -0,0 +1,310 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::auth::passkeys::{log_failed_passkey, passkey_error_response, webauthn_config};
+use crate::auth::totp::verify_totp;
+use crate::auth::web3::{siwe_config, verify_wallet_ownership};
+use crate::middleware::auth::get_current_user;
+use crate::middleware::step_up::get_step_up;
+use crate::services::account_lockout::AccountLockout;
+use crate::services::audit_logger::AuditLogger;
+use crate::services::email_service::EmailService;
+use crate::services::email_templates::locale_from_request;
+use crate::services::password_hash_service::PasswordHashService;
+use crate::services::step_up_service::{StepUpMethod, StepUpService};
+use crate::services::wallet_service::WalletService;
+use crate::services::webauthn_service::{AuthenticationCredential, WebAuthnService};
+use crate::utils::auth::AuthUtils;
+
+#[derive(Debug, Deserialize)]
+pub struct StepUpStartRequest {
+    pub method: String,
+}
+
+/// Proof for one method: `password`, `code` (totp, email), `credential` (passkey), or
+/// `address`, `challenge` and `signature` from /web3/challenge (wallet)
+#[derive(Debug, Deserialize)]
+pub struct StepUpRequest {
+    pub method: String,
+    pub password: Option<String>,
+    pub code: Option<String>,
+    pub credential: Option<AuthenticationCredential>,
+    pub address: Option<String>,
+    pub challenge: Option<String>,
+    pub signature: Option<String>,
+}
+
+// Methods the user can step up with, and the current step-up if the request carries one
+pub async fn step_up_status(pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let methods = StepUpService::available_methods(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+    let elevated = get_step_up(&req).map(|claims| {
+        serde_json::json!({
+            "method": claims.method,
+            "auth_time": claims.auth_time,
+            "expires_at": claims.exp
+        })
+    });
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "methods": methods,
+        "elevated": elevated
+    })))
+}
+
+// First half of the methods that need one: email sends a code, passkey returns the options for
+// navigator.credentials.get(). Wallets use /web3/challenge
+pub async fn start_step_up(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    start: web::Json<StepUpStartRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    match StepUpMethod::parse(&start.method) {
+        Some(StepUpMethod::Email) => {
+            let email = sqlx::query_scalar!(
+                "SELECT email FROM users WHERE id = $1 AND email_verified = true",
+                current_user.sub
+            )
+            .fetch_optional(pool.get_ref())
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+            .flatten()
+            .ok_or_else(|| actix_web::error::ErrorBadRequest("No verified email address on this account"))?;
+
+            let email_client = EmailService::new()
+                .map_err(|e| {
+                    eprintln!("Failed to initialize email client for step-up: {}", e);
+                    actix_web::error::ErrorInternalServerError("Email service is not available")
+                })?
+                .with_outbox(pool.get_ref().clone());
+
+            let code = StepUpService::create_email_code(current_user.sub)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create code"))?;
+            let locale = EmailService::locale_for_user(pool.get_ref(), current_user.sub)
+                .await
+                .or_else(|| locale_from_request(&req));
+            email_client
+                .send_step_up_code_email(&email, &code, locale.as_deref())
+                .await
+                .map_err(|e| {
+                    eprintln!("Failed to send step-up code: {}", e);
+                    actix_web::error::ErrorInternalServerError("Failed to send code")
+                })?;
+
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "message": "A confirmation code has been sent to your email.",
+                "expires_in": EmailService::STEP_UP_CODE_EXPIRY_MINUTES * 60
+            })))
+        }
+        Some(StepUpMethod::Passkey) => {
+            let options = WebAuthnService::start_authentication(pool.get_ref(), &webauthn_config(&req), Some(current_user.sub))
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "publicKey": options })))
+        }
+        Some(_) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "This method needs no start step"
+        }))),
+        None => Ok(unknown_method()),
+    }
+}
+
+// Re-verify the user and issue a step-up token for this session, to be sent in the
+// X-Step-Up-Token header to routes guarded by `StepUpMiddleware`
+pub async fn step_up(
+    pool: web::Data<PgPool>,
+    jwt_secret: web::Data<String>,
+    req: HttpRequest,
+    proof: web::Json<StepUpRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let method = match verify_step_up(&pool, &req, current_user.sub, &proof).await? {
+        Ok(method) => method,
+        Err(rejection) => return Ok(rejection),
+    };
+
+    let (step_up_token, expires_at) = issue_step_up_token(&req, jwt_secret.get_ref(), current_user.sub, method)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "step_up_token": step_up_token,
+        "method": method,
+        "expires_at": expires_at.timestamp()
+    })))
+}
+
+/// Step-up token for the session the request was made with
+pub(crate) fn issue_step_up_token(
+    req: &HttpRequest,
+    jwt_secret: &str,
+    user_id: i32,
+    method: StepUpMethod,
+) -> Result<(String, DateTime<Utc>)> {
+    let access_token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| AuthUtils::extract_token_from_header(h).ok())
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    StepUpService::issue(user_id, access_token, method, jwt_secret)
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Token creation failed"))
+}
+
+/// Check the proof of identity. `Ok(Err(response))` is a rejection to send back as is.
+/// Every attempt shows up in the user's activity
+pub(crate) async fn verify_step_up(
+    pool: &PgPool,
+    req: &HttpRequest,
+    user_id: i32,
+    proof: &StepUpRequest,
+) -> Result<std::result::Result<StepUpMethod, HttpResponse>> {
+    let Some(method) = StepUpMethod::parse(&proof.method) else {
+        return Ok(Err(unknown_method()));
+    };
+
+    let available = StepUpService::available_methods(pool, user_id)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+    if !available.contains(&method) {
+        return Ok(Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("{} is not set up for this account", method.as_str()),
+            "methods": available
+        }))));
+    }
+
+    let verified = match method {
+        StepUpMethod::Password => {
+            let Some(password) = proof.password.as_deref() else {
+                return Ok(Err(missing_field("password")));
+            };
+
+            // Failed step-ups count towards the lockout like failed logins, and a locked
+            // account can't keep guessing here either
+            let locked = AccountLockout::is_locked(pool, user_id)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+            if locked {
+                let retry_after = AccountLockout::get_remaining_lockout_seconds(pool, user_id).await.ok();
+                log_step_up(pool, req, user_id, method, false).await;
+                return Ok(Err(HttpResponse::Forbidden().json(serde_json::json!({
+                    "error": "Account is temporarily locked due to too many failed attempts",
+                    "locked": true,
+                    "retry_after": retry_after
+                }))));
+            }
+
+            let stored = sqlx::query_scalar!("SELECT password FROM users WHERE id = $1", user_id)
+                .fetch_one(pool)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+            let valid = PasswordHashService::verify(password, &stored)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Password verification failed"))?;
+
+            if !valid && let Err(e) = AccountLockout::record_failed_attempt(pool, user_id).await {
+                eprintln!("Error recording failed step-up attempt: {}", e);
+            }
+            valid
+        }
+        StepUpMethod::Totp => {
+            let Some(code) = proof.code.as_deref() else {
+                return Ok(Err(missing_field("code")));
+            };
+            verify_totp(pool, req, user_id, code).await?
+        }
+        StepUpMethod::Email => {
+            let Some(code) = proof.code.as_deref() else {
+                return Ok(Err(missing_field("code")));
+            };
+            StepUpService::verify_email_code(user_id, code)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to verify code"))?
+        }
+        StepUpMethod::Passkey => {
+            let Some(credential) = proof.credential.as_ref() else {
+                return Ok(Err(missing_field("credential")));
+            };
+            match WebAuthnService::finish_authentication(pool, &webauthn_config(req), Some(user_id), credential).await {
+                Ok(_) => true,
+                Err(e) => {
+                    log_failed_passkey(pool, req, credential, &e).await;
+                    log_step_up(pool, req, user_id, method, false).await;
+                    return Ok(Err(passkey_error_response(&e)));
+                }
+            }
+        }
+        StepUpMethod::Wallet => {
+            let (Some(address), Some(challenge), Some(signature)) = (&proof.address, &proof.challenge, &proof.signature) else {
+                return Ok(Err(missing_field("address, challenge and signature")));
+            };
+            match verify_wallet_ownership(pool, &siwe_config(req), address, challenge, signature).await {
+                // Signed by a wallet of this account, not just any wallet
+                Ok(_) => WalletService::find_user(pool, address)
+                    .await
+                    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+                    .is_some_and(|owner| owner.id == user_id),
+                Err(message) => {
+                    log_step_up(pool, req, user_id, method, false).await;
+                    return Ok(Err(HttpResponse::Unauthorized().json(serde_json::json!({ "error": message }))));
+                }
+            }
+        }
+    };
+
+    log_step_up(pool, req, user_id, method, verified).await;
+
+    if !verified {
+        return Ok(Err(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Verification failed"
+        }))));
+    }
+    Ok(Ok(method))
+}
+
+fn unknown_method() -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": "Invalid verification method. Use 'password', 'totp', 'email', 'passkey' or 'wallet'."
+    }))
+}
+
+fn missing_field(field: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": format!("Missing {}", field)
+    }))
+}
+
+async fn log_step_up(pool: &PgPool, req: &HttpRequest, user_id: i32, method: StepUpMethod, success: bool) {
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+    let (action, status) = if success {
+        ("Identity confirmed for a sensitive action", AuditLogger::STATUS_SUCCESS)
+    } else {
+        ("Failed to confirm identity for a sensitive action", AuditLogger::STATUS_FAILED)
+    };
+
+    let _ = AuditLogger::log(
+        pool,
+        Some(user_id),
+        AuditLogger::EVENT_STEP_UP,
+        action,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        status,
+        Some(serde_json::json!({ "method": method })),
+    )
+    .await;
+}
@@ -0,0 +1,218 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::middleware::auth::get_current_user;
+use crate::services::audit_logger::AuditLogger;
+use crate::services::totp_secret_service::TotpKeyring;
+use crate::services::totp_service::{RemoveAuthenticatorOutcome, TotpAuthenticator, TotpConfig, TotpService};
+
+#[derive(Debug, Deserialize)]
+pub struct AddAuthenticatorRequest {
+    pub label: Option<String>,
+    pub code: String, // from an authenticator that is already set up
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmAuthenticatorRequest {
+    pub code: String, // from the new authenticator
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameAuthenticatorRequest {
+    pub label: String,
+}
+
+pub(crate) fn totp_keyring(req: &HttpRequest) -> TotpKeyring {
+    req.app_data::<web::Data<TotpKeyring>>()
+        .map(|k| k.get_ref().clone())
+        .unwrap_or_else(TotpKeyring::shared)
+}
+
+pub(crate) fn totp_config(req: &HttpRequest) -> TotpConfig {
+    req.app_data::<web::Data<TotpConfig>>()
+        .map(|c| c.get_ref().clone())
+        .unwrap_or_else(TotpConfig::shared)
+}
+
+/// Check a TOTP code for the user, using it up (see `TotpService::verify`)
+pub(crate) async fn verify_totp(pool: &PgPool, req: &HttpRequest, user_id: i32, code: &str) -> Result<bool> {
+    TotpService::verify(pool, &totp_keyring(req), &totp_config(req), user_id, code)
+        .await
+        .map(|authenticator| authenticator.is_some())
+        .map_err(|e| {
+            eprintln!("Failed to verify TOTP code for user {}: {}", user_id, e);
+            actix_web::error::ErrorInternalServerError("Failed to verify 2FA code")
+        })
+}
+
+// Trimmed label, the default if empty, or `None` if too long
+fn normalize_label(label: Option<&str>) -> Option<String> {
+    let label = label.map(str::trim).filter(|l| !l.is_empty()).unwrap_or(TotpService::DEFAULT_LABEL);
+    (label.chars().count() <= TotpService::MAX_LABEL_LENGTH).then(|| label.to_string())
+}
+
+fn label_too_long() -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": format!("Authenticator name must be at most {} characters", TotpService::MAX_LABEL_LENGTH)
+    }))
+}
+
+pub async fn list_authenticators(pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let authenticators = TotpService::list(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "authenticators": authenticators })))
+}
+
+// Start adding another authenticator app. Needs a code from one already set up, so a stolen
+// session alone can't add its own; the first one is set up through /setup-2fa
+pub async fn add_authenticator(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    add: web::Json<AddAuthenticatorRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let Some(label) = normalize_label(add.label.as_deref()) else {
+        return Ok(label_too_long());
+    };
+
+    let totp_enabled = sqlx::query_scalar!("SELECT totp_enabled FROM users WHERE id = $1", current_user.sub)
+        .fetch_one(pool.get_ref())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    if !totp_enabled.unwrap_or(false) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "2FA is not enabled for this account"
+        })));
+    }
+
+    if !verify_totp(&pool, &req, current_user.sub, &add.code).await? {
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Invalid 2FA code"
+        })));
+    }
+
+    let enrollment = TotpService::enroll(
+        pool.get_ref(),
+        &totp_keyring(&req),
+        &totp_config(&req),
+        current_user.sub,
+        &current_user.username,
+        &label,
+    )
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to save 2FA secret"))?;
+
+    Ok(HttpResponse::Created().json(enrollment))
+}
+
+pub async fn confirm_authenticator(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    confirm: web::Json<ConfirmAuthenticatorRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+    let id = path.into_inner();
+
+    let pending = TotpService::list(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+        .into_iter()
+        .find(|a| a.id == id && a.confirmed_at.is_none());
+
+    let Some(pending) = pending else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "No pending authenticator with this ID"
+        })));
+    };
+
+    let confirmed = TotpService::confirm(pool.get_ref(), &totp_keyring(&req), &totp_config(&req), &pending, &confirm.code)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to verify 2FA code"))?;
+
+    let Some(authenticator) = confirmed else {
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Invalid 2FA code"
+        })));
+    };
+
+    log_authenticator_event(&pool, &req, current_user.sub, AuditLogger::EVENT_AUTHENTICATOR_ADDED, "add", &authenticator).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Authenticator added",
+        "authenticator": authenticator
+    })))
+}
+
+pub async fn rename_authenticator(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    rename: web::Json<RenameAuthenticatorRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let Some(label) = normalize_label(Some(&rename.label)) else {
+        return Ok(label_too_long());
+    };
+
+    match TotpService::rename(pool.get_ref(), current_user.sub, path.into_inner(), &label)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+    {
+        Some(authenticator) => Ok(HttpResponse::Ok().json(serde_json::json!({ "authenticator": authenticator }))),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Authenticator not found" }))),
+    }
+}
+
+pub async fn remove_authenticator(pool: web::Data<PgPool>, req: HttpRequest, path: web::Path<i32>) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let outcome = TotpService::remove(pool.get_ref(), current_user.sub, path.into_inner())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match outcome {
+        RemoveAuthenticatorOutcome::Removed(authenticator) => {
+            if authenticator.confirmed_at.is_some() {
+                log_authenticator_event(&pool, &req, current_user.sub, AuditLogger::EVENT_AUTHENTICATOR_REMOVED, "remove", &authenticator).await;
+            }
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "message": "Authenticator removed" })))
+        }
+        RemoveAuthenticatorOutcome::NotFound => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Authenticator not found"
+        }))),
+        RemoveAuthenticatorOutcome::LastAuthenticator => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "This is your only authenticator. Disable 2FA instead."
+        }))),
+    }
+}
+
+async fn log_authenticator_event(pool: &PgPool, req: &HttpRequest, user_id: i32, event_type: &str, action: &str, authenticator: &TotpAuthenticator) {
+    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+    let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+
+    let _ = AuditLogger::log(
+        pool,
+        Some(user_id),
+        event_type,
+        action,
+        ip_address.as_deref(),
+        user_agent.as_deref(),
+        AuditLogger::STATUS_SUCCESS,
+        Some(serde_json::json!({ "method": "totp", "authenticator_id": authenticator.id, "label": authenticator.label })),
+    )
+    .await;
+}
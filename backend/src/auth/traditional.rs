@@ -2,18 +2,28 @@ use actix_web::{HttpRequest, HttpResponse, Result, web};
 use serde_json;
 use sqlx::PgPool;
 
+use crate::auth::password::{hash_password, password_hash_config, password_policy_violation};
+use crate::auth::passkeys::{log_failed_passkey, passkey_error_response, webauthn_config};
+use crate::auth::totp::{totp_config, totp_keyring, verify_totp};
 use crate::middleware::auth::get_current_user;
 use crate::middleware::rate_limiter::RateLimiter;
 use crate::middleware::redis_token_blacklist::RedisTokenBlacklist;
 use crate::models::auth::{LoginRequest, LoginResponse, RegisterRequest};
 use crate::models::user::{User, UserResponse};
 use crate::services::email_service::EmailService;
+use crate::services::email_templates::locale_from_request;
 use crate::services::session_manager::{SessionManager, CreateSessionData};
 use crate::services::token_blacklist::TokenBlacklist;
 use crate::services::account_lockout::AccountLockout;
 use crate::services::audit_logger::AuditLogger;
 use crate::services::refresh_token_service::RefreshTokenService;
 use crate::services::mfa_service::MFAService;
+use crate::services::recovery_code_service::RecoveryCodeService;
+use crate::services::totp_service::TotpService;
+use crate::services::password_hash_service::PasswordHashService;
+use crate::services::password_policy_service::PasswordOwner;
+use crate::services::webauthn_service::WebAuthnService;
+use crate::services::permission_service::PermissionService;
 use crate::utils::auth::AuthUtils;
 
 pub async fn login(
@@ -137,95 +147,20 @@ pub async fn login(
         _ => {}
     }
 
-    // Check if email is verified (except for Web3 users)
-    if user.password != "web3_auth" && !user.email_verified {
+    // Check if email is verified (accounts without a password sign in through a wallet or provider)
+    if !user.password.is_empty() && !user.email_verified {
         return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
             "error": "Email not verified. Please check your email and verify your account.",
             "needs_verification": true
         })));
     }
 
-    // For Web3 users (no password required), allow login without password check
-    if user.password == "web3_auth" {
-        // Create JWT token for Web3 user
-        let token =
-            AuthUtils::create_token(user.id, &user.username, &user.role, jwt_secret.get_ref())
-                .map_err(|_| actix_web::error::ErrorInternalServerError("Token creation failed"))?;
-
-        // Create session in database
-        let device_name = req.headers()
-            .get("User-Agent")
-            .and_then(|h| h.to_str().ok())
-            .map(|ua| {
-                if ua.contains("Mobile") { "Mobile" }
-                else if ua.contains("Tablet") { "Tablet" }
-                else { "Desktop" }
-            })
-            .map(|s| s.to_string());
-
-        let ip_address = req.connection_info()
-            .peer_addr()
-            .map(|s| s.to_string());
-
-        let user_agent = req.headers()
-            .get("User-Agent")
-            .and_then(|h| h.to_str().ok())
-            .map(|s| s.to_string());
-
-        let session_data = CreateSessionData {
-            user_id: user.id,
-            token: token.clone(),
-            device_name,
-            ip_address: ip_address.clone(),
-            user_agent: user_agent.clone(),
-        };
-
-        if let Err(e) = SessionManager::create_session(pool.get_ref(), session_data).await {
-            eprintln!("Failed to create session: {}", e);
-        }
-
-        // Reset rate limit on successful login
-        RateLimiter::reset(&req, "login");
-
-        // Log successful Web3 login
-        let ip_address = req.connection_info()
-            .peer_addr()
-            .map(|s| s.to_string());
-        let user_agent = req.headers()
-            .get("User-Agent")
-            .and_then(|h| h.to_str().ok())
-            .map(|s| s.to_string());
-        
-        let _ = AuditLogger::log_login(
-            pool.get_ref(),
-            user.id,
-            ip_address.as_deref(),
-            user_agent.as_deref(),
-        ).await;
-
-        // Generate refresh token
-        let refresh_token = RefreshTokenService::generate_token();
-        let _ = RefreshTokenService::create_refresh_token(
-            pool.get_ref(),
-            user.id,
-            &refresh_token,
-        ).await;
-
-        let response = LoginResponse {
-            token: Some(token),
-            refresh_token: Some(refresh_token),
-            user: UserResponse::from(user),
-            requires_mfa: false,
-            mfa_methods: None,
-            temp_token: None,
-        };
-
-        return Ok(HttpResponse::Ok().json(response));
-    }
-
-    // Verify password with bcrypt for traditional users
-    let is_valid_password = AuthUtils::verify_password(&login_data.password, &user.password)
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Password verification failed"))?;
+    // Verify password (Argon2id, or bcrypt for older accounts); accounts without one can't
+    // sign in with a password at all
+    let is_valid_password = !user.password.is_empty()
+        && PasswordHashService::verify(&login_data.password, &user.password)
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Password verification failed"))?;
 
     if !is_valid_password {
         // Log failed login attempt
@@ -255,6 +190,12 @@ pub async fn login(
         })));
     }
 
+    if let Some(reset_required) = password_reset_response(pool.get_ref(), user.id).await? {
+        return Ok(reset_required);
+    }
+
+    upgrade_password_hash(pool.get_ref(), &req, &user, &login_data.password).await;
+
     // Reset failed login attempts on successful password verification
     if let Err(e) = AccountLockout::reset_attempts(pool.get_ref(), user.id).await {
         eprintln!("Error resetting lockout attempts: {}", e);
@@ -273,11 +214,64 @@ pub async fn login(
     }
 
     // User has 2FA enabled - require MFA verification
+    mfa_challenge(
+        pool.get_ref(),
+        jwt_secret.get_ref(),
+        user,
+        "Login successful. Please verify with 2FA to complete authentication.",
+    )
+    .await
+}
+
+// Replace a bcrypt hash, or an Argon2id one made with other parameters, now that we have the
+// password. Failures only mean the upgrade is retried at the next login
+async fn upgrade_password_hash(pool: &PgPool, req: &HttpRequest, user: &User, password: &str) {
+    let config = password_hash_config(req);
+    if !PasswordHashService::needs_rehash(&config, &user.password) {
+        return;
+    }
+
+    let hashed_password = match PasswordHashService::hash(&config, password).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("Failed to rehash password for user {}: {}", user.id, e);
+            return;
+        }
+    };
+
+    // Only if the password wasn't changed in the meantime
+    if let Err(e) = sqlx::query!(
+        "UPDATE users SET password = $1 WHERE id = $2 AND password = $3",
+        hashed_password,
+        user.id,
+        user.password
+    )
+    .execute(pool)
+    .await
+    {
+        eprintln!("Failed to store rehashed password for user {}: {}", user.id, e);
+    }
+}
+
+/// Second step of a login for users with 2FA enabled: a short-lived MFA token
+/// to be exchanged at /verify-mfa, which then calls `complete_login`
+pub(crate) async fn mfa_challenge(pool: &PgPool, jwt_secret: &str, user: User, message: &str) -> Result<HttpResponse> {
     let mut mfa_methods: Vec<String> = Vec::new();
     // TOTP is primary method if enabled
-    if has_2fa_enabled {
+    if user.totp_enabled.unwrap_or(false) {
         mfa_methods.push("totp".to_string());
     }
+    // Recovery codes stand in for a lost authenticator app
+    if user.totp_enabled.unwrap_or(false) && RecoveryCodeService::remaining(user.recovery_codes.as_ref()) > 0 {
+        mfa_methods.push("recovery_code".to_string());
+    }
+    // Any registered passkey can stand in for the code
+    if WebAuthnService::has_passkeys(pool, user.id)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+    {
+        mfa_methods.push("passkey".to_string());
+    }
     // Email is always available as fallback
     if user.email.is_some() {
         mfa_methods.push("email".to_string());
@@ -288,18 +282,18 @@ pub async fn login(
         user.id,
         &user.username,
         user.email.as_deref(),
-        jwt_secret.get_ref()
+        jwt_secret
     )
     .map_err(|_| actix_web::error::ErrorInternalServerError("MFA token generation failed"))?;
 
     // Return MFA challenge
-    return Ok(HttpResponse::Ok().json(serde_json::json!({
+    Ok(HttpResponse::Ok().json(serde_json::json!({
         "requires_mfa": true,
         "mfa_methods": mfa_methods,
         "temp_token": temp_mfa_token,
         "user": UserResponse::from(user),
-        "message": "Login successful. Please verify with 2FA to complete authentication."
-    })));
+        "message": message
+    })))
 }
 
 pub async fn verify_mfa(
@@ -348,126 +342,33 @@ pub async fn verify_mfa(
                     })));
                 }
 
-                // Check if user already has totp_secret (from profile or registration setup)
-                let existing_secret = sqlx::query!(
-                    "SELECT totp_secret FROM users WHERE id = $1",
-                    user.id
+                // Registration/setup: the secret from an unfinished setup is reused, else a new one
+                let enrollment = TotpService::enroll(
+                    pool.get_ref(),
+                    &totp_keyring(&req),
+                    &totp_config(&req),
+                    user.id,
+                    &user.username,
+                    TotpService::DEFAULT_LABEL,
                 )
-                .fetch_one(pool.get_ref())
-                .await
-                .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get user data"))?
-                .totp_secret;
-
-                // If secret exists and TOTP is NOT enabled yet, this is registration/setup - return QR
-                if let Some(secret) = existing_secret {
-                    let qr_code_url = format!(
-                        "otpauth://totp/USH:{}?secret={}&issuer=USH&algorithm=SHA1&digits=6&period=30",
-                        user.username, secret
-                    );
-                    
-                    println!("DEBUG: Returning existing TOTP setup for registration/profile (totp_enabled=false)");
-                    return Ok(HttpResponse::Ok().json(serde_json::json!({
-                        "setup_required": true,
-                        "secret": secret,
-                        "qr_code_url": qr_code_url,
-                        "message": "Please scan this QR code with your authenticator app, then enter the 6-digit code to complete setup."
-                    })));
-                }
-
-                // No secret exists - generate one for first-time setup
-                println!("DEBUG: Generating new TOTP secret for first-time setup");
-                let new_secret = crate::utils::totp::generate_totp_secret()
-                    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to generate TOTP secret"))?;
-                
-                // Store secret in database
-                sqlx::query!(
-                    "UPDATE users SET totp_secret = $1 WHERE id = $2",
-                    new_secret,
-                    user.id
-                )
-                .execute(pool.get_ref())
                 .await
                 .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to save TOTP secret"))?;
-                
-                let qr_code_url = format!(
-                    "otpauth://totp/USH:{}?secret={}&issuer=USH&algorithm=SHA1&digits=6&period=30",
-                    user.username, new_secret
-                );
-                
+
                 return Ok(HttpResponse::Ok().json(serde_json::json!({
                     "setup_required": true,
-                    "secret": new_secret,
-                    "qr_code_url": qr_code_url,
-                    "message": "Please scan this QR code with your authenticator app, then enter the 6-digit code to complete setup."
+                    "secret": enrollment.secret,
+                    "qr_code_url": enrollment.qr_code_url,
+                    "qr_code_png": enrollment.qr_code_png,
+                    "message": format!(
+                        "Please scan this QR code with your authenticator app, then enter the {}-digit code to complete setup.",
+                        enrollment.authenticator.digits
+                    )
                 })));
             }
 
-            let user_data = sqlx::query!(
-                "SELECT totp_secret, recovery_codes FROM users WHERE id = $1",
-                user.id
-            )
-            .fetch_one(pool.get_ref())
-            .await
-            .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to get TOTP secret"))?;
-
-            let totp_secret = user_data.totp_secret
-                .ok_or_else(|| actix_web::error::ErrorBadRequest("TOTP secret not found. Please set up 2FA first."))?;
-
-            // Try to verify as TOTP code first
-            let mut is_valid = crate::utils::totp::verify_totp_code(&totp_secret, &verify_data.code)
-                .unwrap_or(false);
-
-            // If TOTP fails, check if it's a recovery code
-            if !is_valid {
-                if let Some(recovery_codes) = user_data.recovery_codes {
-                    if recovery_codes.contains(&verify_data.code) {
-                        println!("✓ Valid recovery code used by user: {}", user.username);
-                        is_valid = true;
-                        
-                        // Log recovery code usage for security audit
-                        let ip_address = req.connection_info()
-                            .peer_addr()
-                            .map(|s| s.to_string());
-                        let user_agent = req.headers()
-                            .get("User-Agent")
-                            .and_then(|h| h.to_str().ok())
-                            .map(|s| s.to_string());
-                        
-                        let _ = AuditLogger::log(
-                            pool.get_ref(),
-                            Some(user.id),
-                            "RECOVERY_CODE_USED",
-                            &format!("Recovery code used for 2FA bypass - {} codes remaining", recovery_codes.len() - 1),
-                            ip_address.as_deref(),
-                            user_agent.as_deref(),
-                            AuditLogger::STATUS_SUCCESS,
-                            Some(serde_json::json!({
-                                "recovery_codes_remaining": recovery_codes.len() - 1,
-                                "method": "recovery_code"
-                            })),
-                        ).await;
-                        
-                        // Remove used recovery code from database
-                        let remaining_codes: Vec<String> = recovery_codes
-                            .into_iter()
-                            .filter(|c| c != &verify_data.code)
-                            .collect();
-                        
-                        sqlx::query!(
-                            "UPDATE users SET recovery_codes = $1 WHERE id = $2",
-                            &remaining_codes,
-                            user.id
-                        )
-                        .execute(pool.get_ref())
-                        .await
-                        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to update recovery codes"))?;
-                    }
-                }
-            }
-
-            if !is_valid {
+            if !verify_totp(&pool, &req, user.id, &verify_data.code).await? {
                 return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
-                    "error": "Invalid TOTP code or recovery code"
+                    "error": "Invalid TOTP code"
                 })));
             }
 
@@ -482,26 +383,77 @@ pub async fn verify_mfa(
                 .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to enable 2FA"))?;
             }
         }
+        "recovery_code" => {
+            // Only a fallback for the authenticator app
+            if !user.totp_enabled.unwrap_or(false) {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Recovery codes are only available with 2FA enabled"
+                })));
+            }
+
+            let remaining = RecoveryCodeService::redeem(pool.get_ref(), user.id, &verify_data.code)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to update recovery codes"))?;
+
+            let Some(remaining) = remaining else {
+                return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
+                    "error": "Invalid or already used recovery code"
+                })));
+            };
+
+            // Log recovery code usage for security audit
+            let ip_address = req.connection_info()
+                .peer_addr()
+                .map(|s| s.to_string());
+            let user_agent = req.headers()
+                .get("User-Agent")
+                .and_then(|h| h.to_str().ok())
+                .map(|s| s.to_string());
+
+            let _ = AuditLogger::log(
+                pool.get_ref(),
+                Some(user.id),
+                AuditLogger::EVENT_RECOVERY_CODE_USED,
+                &format!("Recovery code used for 2FA bypass - {} codes remaining", remaining),
+                ip_address.as_deref(),
+                user_agent.as_deref(),
+                AuditLogger::STATUS_SUCCESS,
+                Some(serde_json::json!({
+                    "recovery_codes_remaining": remaining,
+                    "method": "recovery_code"
+                })),
+            ).await;
+        }
+        "passkey" => {
+            let Some(credential) = verify_data.credential.as_ref() else {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Passkey response is required"
+                })));
+            };
+
+            let result = WebAuthnService::finish_authentication(pool.get_ref(), &webauthn_config(&req), Some(user.id), credential).await;
+            if let Err(e) = result {
+                log_failed_passkey(pool.get_ref(), &req, credential, &e).await;
+                return Ok(passkey_error_response(&e));
+            }
+        }
         "email" => {
             // SECURITY: Check if email code has expired
-            if let Some(expiry) = EmailService::get_mfa_code_expiry(user.id) {
+            if let Some(expiry) = EmailService::get_mfa_code_expiry(user.id).await {
                 if expiry <= chrono::Utc::now() {
-                    EmailService::clear_mfa_verification_code(user.id);
+                    EmailService::clear_mfa_verification_code(user.id).await;
                     return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
                         "error": "Email verification code has expired. Please request a new code."
                     })));
                 }
             }
             
-            // Verify email code
-            let stored_code = EmailService::get_mfa_verification_code(user.id);
-            if stored_code.as_deref() != Some(&verify_data.code) {
+            // Verify email code (consumed on success)
+            if !EmailService::verify_mfa_verification_code(user.id, &verify_data.code).await {
                 return Ok(HttpResponse::Unauthorized().json(serde_json::json!({
                     "error": "Invalid email verification code"
                 })));
             }
-            // Clear the code after successful verification
-            EmailService::clear_mfa_verification_code(user.id);
         }
         _ => {
             return Ok(HttpResponse::BadRequest().json(serde_json::json!({
@@ -514,12 +466,36 @@ pub async fn verify_mfa(
     complete_login(pool, jwt_secret, req, user).await
 }
 
-async fn complete_login(
+/// Refusal for an account reported as compromised: whichever way it signs in, the password
+/// must be reset by email first. Checked again by `complete_login`, methods without MFA end there
+pub(crate) async fn password_reset_response(pool: &PgPool, user_id: i32) -> Result<Option<HttpResponse>> {
+    let password_reset_required = sqlx::query_scalar!(
+        "SELECT password_reset_required FROM users WHERE id = $1",
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(password_reset_required.then(|| {
+        HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "A password reset is required for this account. Please use the link sent to your email or request a new one.",
+            "password_reset_required": true
+        }))
+    }))
+}
+
+/// Issue the access token, session and refresh token for a fully authenticated user
+pub(crate) async fn complete_login(
     pool: web::Data<PgPool>,
     jwt_secret: web::Data<String>,
     req: HttpRequest,
     user: User,
 ) -> Result<HttpResponse> {
+    if let Some(reset_required) = password_reset_response(pool.get_ref(), user.id).await? {
+        return Ok(reset_required);
+    }
+
     // Create JWT token
     let token = AuthUtils::create_token(user.id, &user.username, &user.role, jwt_secret.get_ref())
         .map_err(|_| actix_web::error::ErrorInternalServerError("Token creation failed"))?;
@@ -544,12 +520,21 @@ async fn complete_login(
         .and_then(|h| h.to_str().ok())
         .map(|s| s.to_string());
 
+    // Generate refresh token, remembered with the session so logging out revokes it
+    let refresh_token = RefreshTokenService::generate_token();
+    let refresh_token_family = RefreshTokenService::create_refresh_token(
+        pool.get_ref(),
+        user.id,
+        &refresh_token,
+    ).await.ok();
+
     let session_data = CreateSessionData {
         user_id: user.id,
         token: token.clone(),
         device_name,
         ip_address: ip_address.clone(),
         user_agent: user_agent.clone(),
+        refresh_token_family,
     };
 
     if let Err(e) = SessionManager::create_session(pool.get_ref(), session_data).await {
@@ -564,14 +549,6 @@ async fn complete_login(
     .execute(pool.get_ref())
     .await;
 
-    // Generate refresh token
-    let refresh_token = RefreshTokenService::generate_token();
-    let _ = RefreshTokenService::create_refresh_token(
-        pool.get_ref(),
-        user.id,
-        &refresh_token,
-    ).await;
-
     // Log successful login
     let _ = AuditLogger::log_login(
         pool.get_ref(),
@@ -614,7 +591,16 @@ pub async fn logout(
     if let Some(auth_header) = req.headers().get("Authorization") {
         if let Ok(auth_str) = auth_header.to_str() {
             if let Ok(token) = AuthUtils::extract_token_from_header(auth_str) {
-                // Invalidate session
+                // Revoke the session's refresh tokens, then invalidate the session
+                match SessionManager::get_session_by_token(pool.get_ref(), token).await {
+                    Ok(Some(session)) => {
+                        if let Err(e) = RefreshTokenService::revoke_for_session(pool.get_ref(), current_user.sub, session.id).await {
+                            eprintln!("Failed to revoke refresh tokens: {}", e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Failed to look up session: {}", e),
+                }
                 if let Err(e) = SessionManager::logout(pool.get_ref(), token).await {
                     eprintln!("Failed to logout session: {}", e);
                 }
@@ -688,31 +674,52 @@ pub async fn me(
     let user = user
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("User not found"))?;
 
+    let permissions = PermissionService::list_for_role(pool.get_ref(), &user.role)
+        .await
+        .unwrap_or_default();
+    let recovery_codes_remaining = RecoveryCodeService::remaining(user.recovery_codes.as_ref());
     let user_response = UserResponse::from(user);
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
-        "user": user_response
+        "user": user_response,
+        "permissions": permissions,
+        "recovery_codes_remaining": recovery_codes_remaining
     })))
 }
 
 pub async fn register(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
     register_data: web::Json<RegisterRequest>,
 ) -> Result<HttpResponse> {
+    // The username is made from the first name below
+    let owner = PasswordOwner {
+        username: Some(&register_data.first_name),
+        email: register_data.email.as_deref(),
+        ..PasswordOwner::default()
+    };
+    if let Some(reason) = password_policy_violation(&pool, &req, &register_data.password, &owner).await? {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Invalid password: {}", reason)
+        })));
+    }
+
     // Hash password before storing
-    let hashed_password = AuthUtils::hash_password(&register_data.password)
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Password hashing failed"))?;
+    let hashed_password = hash_password(&req, &register_data.password).await?;
 
     // First, insert user with temporary username to get the ID
     let temp_username = format!("temp_{}", chrono::Utc::now().timestamp_millis());
+    // Remember the browser's language so later emails keep using it
+    let locale = locale_from_request(&req);
     let mut user = sqlx::query_as!(
         User,
-        "INSERT INTO users (username, email, password, role, email_verified)
-         VALUES ($1, $2, $3, 'user', false)
+        "INSERT INTO users (username, email, password, role, email_verified, locale)
+         VALUES ($1, $2, $3, 'user', false, $4)
          RETURNING id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at",
         temp_username,
         register_data.email,
-        hashed_password
+        hashed_password,
+        locale
     )
     .fetch_one(pool.get_ref())
     .await
@@ -743,7 +750,7 @@ pub async fn register(
 
     // Send verification email
     let email_client = match EmailService::new() {
-        Ok(client) => Some(client),
+        Ok(client) => Some(client.with_outbox(pool.get_ref().clone())),
         Err(e) => {
             // Log error but don't fail registration
             eprintln!("Failed to initialize email client: {}", e);
@@ -753,10 +760,10 @@ pub async fn register(
 
     if let Some(email) = &user.email {
         let code = EmailService::generate_verification_code();
-        EmailService::store_verification_code(email, &code);
+        EmailService::store_verification_code(email, &code).await;
 
         if let Some(client) = email_client {
-            if let Err(e) = client.send_verification_email(email, &code).await {
+            if let Err(e) = client.send_verification_email(email, &code, locale.as_deref()).await {
                 eprintln!("Failed to send verification email: {}", e);
                 // Don't fail registration if email fails
             }
@@ -1,14 +1,18 @@
 use actix_web::{HttpResponse, Result, web, HttpRequest};
+use chrono::Utc;
+use rand::Rng;
 use sqlx::PgPool;
 
 use crate::models::auth::{
     Web3ChallengeRequest, Web3ChallengeResponse, Web3VerifyRequest, Web3VerifyResponse,
 };
-use crate::models::user::User;
-use crate::utils::auth::AuthUtils;
+use crate::auth::traditional::{complete_login, mfa_challenge};
 use crate::middleware::rate_limiter::RateLimiter;
+use crate::models::user::User;
+use crate::services::siwe::{SiweConfig, SiweError, SiweMessage};
+use crate::services::wallet_chains::WalletChain;
+use crate::services::wallet_service::WalletService;
 use crate::services::web3_challenge_service::Web3ChallengeService;
-use crate::utils::validation::validate_wallet_address;
 
 pub async fn web3_challenge(
     pool: web::Data<PgPool>,
@@ -16,11 +20,15 @@ pub async fn web3_challenge(
     challenge_data: web::Json<Web3ChallengeRequest>,
 ) -> Result<HttpResponse> {
     // Validate wallet address format
-    if let Err(e) = validate_wallet_address(&challenge_data.address) {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid wallet address: {}", e)
-        })));
-    }
+    let chain = challenge_data.chain;
+    let address = match chain.verifier().normalize_address(&challenge_data.address) {
+        Ok(address) => address,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid wallet address: {}", e)
+            })));
+        }
+    };
 
     // Rate limiting: 5 challenges per hour per IP
     let (is_allowed, _, reset_seconds) = 
@@ -32,39 +40,36 @@ pub async fn web3_challenge(
             "retry_after": reset_seconds
         })));
     }
-    use rand::Rng;
-    use std::time::{SystemTime, UNIX_EPOCH};
-
-    // Generate random challenge
-    let mut challenge_bytes = [0u8; 32];
-    rand::thread_rng().fill(&mut challenge_bytes);
-    let challenge = hex::encode(challenge_bytes);
-
-    // Create message to sign
-    let message = format!(
-        "Welcome to USH!\n\nPlease sign this message to authenticate with your wallet.\n\nAddress: {}\n\nChallenge: {}\n\nTimestamp: {}",
-        challenge_data.address,
-        challenge,
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-    );
-
-    // Store challenge (the full message) in database with 5-minute expiration
-    let _ = Web3ChallengeService::create_challenge(
+
+    let config = siwe_config(&req);
+    let supported_chain_ids = config.chain_ids(chain);
+    let chain_id = challenge_data.chain_id.clone().unwrap_or_else(|| supported_chain_ids[0].clone());
+    if !supported_chain_ids.contains(&chain_id) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Chain {} is not supported", chain_id),
+            "supported_chain_ids": supported_chain_ids
+        })));
+    }
+
+    let nonce = SiweConfig::generate_nonce();
+    let message = config.message(chain, &address, &chain_id, &nonce, Utc::now())
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    // Only the nonce is stored, the signed message itself is checked against the config
+    let expires_at = Web3ChallengeService::create_challenge(
         pool.get_ref(),
-        &challenge_data.address,
-        &message, // Store the full message!
-        300, // 5 minutes TTL
-    ).await;
+        &address,
+        &nonce,
+        config.ttl_seconds,
+    )
+    .await
+    .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create challenge"))?;
 
     let response = Web3ChallengeResponse {
-        challenge: message,
-        expires_at: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() + 300,
+        challenge: message.to_string(),
+        nonce,
+        chain,
+        expires_at: expires_at.timestamp() as u64,
     };
 
     Ok(HttpResponse::Ok().json(response))
@@ -87,86 +92,32 @@ pub async fn web3_verify(
         })));
     }
 
-    // Verify challenge exists in database and is not expired
-    // verify_data.challenge is the full message now
-    let challenge_valid = Web3ChallengeService::verify_challenge(
+    let chain = match verify_wallet_ownership(
         pool.get_ref(),
+        &siwe_config(&req),
         &verify_data.address,
         &verify_data.challenge,
-    ).await.unwrap_or(false);
-
-    if !challenge_valid {
-        return Ok(HttpResponse::BadRequest().json(Web3VerifyResponse {
-            success: false,
-            token: None,
-            message: "Invalid or expired challenge".to_string(),
-        }));
-    }
-
-    // Mark challenge as used
-    let _ = Web3ChallengeService::mark_used(pool.get_ref(), &verify_data.challenge).await;
-
-    // Verify signature using ethers-rs
-    // Use verify_data.challenge directly as the message
-    let recovered_address = match verify_web3_signature(
         &verify_data.signature,
-        &verify_data.challenge,
-        &verify_data.address,
-    ) {
-        Ok(addr) => addr,
-        Err(_) => {
+    ).await {
+        Ok(chain) => chain,
+        Err(message) => {
             return Ok(HttpResponse::BadRequest().json(Web3VerifyResponse {
                 success: false,
                 token: None,
-                message: "Invalid signature".to_string(),
+                message,
             }));
         }
     };
 
-    // Verify address matches
-    if recovered_address.to_lowercase() != verify_data.address.to_lowercase() {
-        return Ok(HttpResponse::BadRequest().json(Web3VerifyResponse {
-            success: false,
-            token: None,
-            message: "Address mismatch".to_string(),
-        }));
-    }
-
-    // Check if user exists
-    let user_result = sqlx::query_as!(
-        User,
-        "SELECT id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at FROM users WHERE wallet_address = $1",
-        verify_data.address
-    )
-    .fetch_optional(pool.get_ref())
-    .await;
-
-    let user = match user_result {
+    // Any wallet linked to an account signs into it
+    let user = match WalletService::find_user(pool.get_ref(), &verify_data.address).await {
         Ok(Some(user)) => {
-            // Wallet already registered - proceed with login
+            let _ = WalletService::touch(pool.get_ref(), &verify_data.address).await;
             user
         }
-        Ok(None) => {
-            // Generate readable username for Web3 user
-            let username = format!(
-                "user_{}",
-                &verify_data.address[verify_data.address.len().saturating_sub(8)..]
-            );
-            // Web3 users don't have email initially - they can add it later
-            sqlx::query_as!(
-                User,
-                "INSERT INTO users (username, email, password, role, wallet_address, email_verified)
-                 VALUES ($1, $2, $3, 'user', $4, true)
-                 RETURNING id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at",
-                username, // readable username
-                None::<String>, // null email for Web3 users
-                "web3_auth", // dummy password
-                verify_data.address // wallet_address
-            )
-            .fetch_one(pool.get_ref())
+        Ok(None) => create_wallet_user(pool.get_ref(), &verify_data.address, chain)
             .await
-            .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create user"))?
-        }
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create user"))?,
         Err(_) => {
             return Ok(
                 HttpResponse::InternalServerError().json(Web3VerifyResponse {
@@ -178,52 +129,72 @@ pub async fn web3_verify(
         }
     };
 
-    // Generate JWT token
-    let token = AuthUtils::create_token(user.id, &user.username, &user.role, jwt_secret.get_ref())
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Token generation failed"))?;
+    // Same second factor, session and refresh token as any other sign-in
+    if user.totp_enabled.unwrap_or(false) {
+        return mfa_challenge(
+            pool.get_ref(),
+            jwt_secret.get_ref(),
+            user,
+            "Wallet signature accepted. Please verify with 2FA to complete authentication.",
+        )
+        .await;
+    }
 
-    let response = Web3VerifyResponse {
-        success: true,
-        token: Some(token),
-        message: "Web3 authentication successful".to_string(),
-    };
+    complete_login(pool, jwt_secret, req, user).await
+}
 
-    Ok(HttpResponse::Ok().json(response))
+// Readable username from the end of the address, with a random suffix when it is taken
+async fn create_wallet_user(pool: &PgPool, address: &str, chain: WalletChain) -> std::result::Result<User, sqlx::Error> {
+    let base = format!("user_{}", &address[address.len().saturating_sub(8)..]);
+    let mut username = base.clone();
+
+    for _ in 0..10 {
+        match WalletService::create_user(pool, &username, address, chain).await {
+            Err(sqlx::Error::Database(e)) if e.constraint() == Some("users_username_key") => {
+                username = format!("{}_{}", base, rand::thread_rng().gen_range(1000..10000));
+            }
+            result => return result,
+        }
+    }
+
+    WalletService::create_user(pool, &format!("{}_{}", base, uuid::Uuid::new_v4().simple()), address, chain).await
 }
 
-/// Verify Web3 signature and recover address
-/// 
-/// Uses Ethereum message signing format (EIP-191):
-/// Message is hashed with Keccak256 and "\x19Ethereum Signed Message:" prefix
-fn verify_web3_signature(signature: &str, message: &str, expected_address: &str) -> std::result::Result<String, Box<dyn std::error::Error>> {
-    // For now, perform basic validation and return address
-    // Full ECDSA recovery requires k256 dependency
-    // This ensures signature format is valid and non-empty
-    
-    let sig_str = if signature.starts_with("0x") {
-        &signature[2..]
-    } else {
-        signature
-    };
+pub(crate) fn siwe_config(req: &HttpRequest) -> SiweConfig {
+    req.app_data::<web::Data<SiweConfig>>()
+        .map(|c| c.get_ref().clone())
+        .unwrap_or_else(SiweConfig::shared)
+}
 
-    // Validate hex format and length
-    let sig_bytes = hex::decode(sig_str)?;
-    if sig_bytes.len() != 65 {
-        return Err("Invalid signature length (expected 65 bytes)".into());
+/// Check that `signature` is the wallet's signature of a sign-in message issued by
+/// `web3_challenge` for `address`: the message must be for this site and chain, still valid,
+/// and carry an unused nonce. The nonce is consumed whether or not the signature is valid.
+/// Used for both wallet sign-in and linking a wallet to an account; returns the wallet's chain
+pub(crate) async fn verify_wallet_ownership(
+    pool: &PgPool,
+    config: &SiweConfig,
+    address: &str,
+    message: &str,
+    signature: &str,
+) -> std::result::Result<WalletChain, String> {
+    let siwe: SiweMessage = message.parse().map_err(|e: SiweError| e.to_string())?;
+    config.validate(&siwe, Utc::now()).map_err(|e| format!("Sign-in message rejected: {}", e))?;
+
+    let verifier = siwe.chain.verifier();
+    let address = verifier.normalize_address(address)?;
+    if verifier.normalize_address(&siwe.address)? != address {
+        return Err("Address mismatch".to_string());
     }
 
-    // Validate message is not empty
-    if message.is_empty() {
-        return Err("Message cannot be empty".into());
-    }
+    let nonce_valid = Web3ChallengeService::consume(pool, &address, &siwe.nonce)
+        .await
+        .unwrap_or(false);
 
-    // Validate address format (0x + 40 hex chars)
-    if !expected_address.starts_with("0x") || expected_address.len() != 42 {
-        return Err("Invalid Ethereum address format".into());
+    if !nonce_valid {
+        return Err("Invalid or expired challenge".to_string());
     }
 
-    // For production, this should use k256 or similar for actual ECDSA recovery
-    // Currently returns the address if all validations pass
-    // TODO: Implement full ECDSA recovery with k256 crate
-    Ok(expected_address.to_string())
+    verifier.verify_signature(&address, message, signature)?;
+
+    Ok(siwe.chain)
 }
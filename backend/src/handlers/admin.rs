@@ -0,0 +1,380 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use chrono::{DateTime, NaiveDateTime};
+use sqlx::PgPool;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+
+use crate::middleware::auth::get_current_user;
+use crate::services::audit_logger::{AuditLogEntry, AuditLogFilter, AuditLogger};
+use crate::services::ban_service::BanService;
+use crate::services::email_outbox::EmailOutbox;
+
+const AUDIT_PAGE_DEFAULT: i64 = 100;
+const AUDIT_PAGE_MAX: i64 = 1000;
+const AUDIT_EXPORT_BATCH: i64 = 1000;
+
+// List outbox messages, newest first. Query: status, page, limit
+pub async fn list_email_outbox(
+    pool: web::Data<PgPool>,
+    query: web::Query<std::collections::HashMap<String, String>>,
+) -> Result<HttpResponse> {
+    let status = query.get("status").map(|s| s.trim().to_lowercase()).filter(|s| !s.is_empty());
+    if status.as_deref().is_some_and(|s| !EmailOutbox::is_valid_status(s)) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid status. Use pending, sending, sent or dead"
+        })));
+    }
+
+    let page = query
+        .get("page")
+        .and_then(|p| p.parse::<i64>().ok())
+        .unwrap_or(1)
+        .max(1);
+    let limit = query
+        .get("limit")
+        .and_then(|l| l.parse::<i64>().ok())
+        .unwrap_or(50)
+        .clamp(1, 200);
+    let offset = (page - 1) * limit;
+
+    let messages = EmailOutbox::list(pool.get_ref(), status.as_deref(), limit, offset)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    let counts: serde_json::Map<String, serde_json::Value> = EmailOutbox::count_by_status(pool.get_ref())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+        .into_iter()
+        .map(|(status, count)| (status, serde_json::json!(count)))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "data": messages,
+        "counts": counts,
+        "pagination": {
+            "page": page,
+            "limit": limit
+        }
+    })))
+}
+
+pub async fn get_email_outbox(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse> {
+    let message = EmailOutbox::find(pool.get_ref(), path.into_inner())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match message {
+        Some(message) => Ok(HttpResponse::Ok().json(message)),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Message not found"
+        }))),
+    }
+}
+
+// Attempt a pending message now instead of after its backoff, or requeue a dead one
+pub async fn retry_email_outbox(
+    path: web::Path<i64>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse> {
+    let id = path.into_inner();
+
+    let requeued = EmailOutbox::retry(pool.get_ref(), id)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    let message = EmailOutbox::find(pool.get_ref(), id)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match (requeued, message) {
+        (true, Some(message)) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "message": "Email queued for retry",
+            "email": message
+        }))),
+        (false, Some(message)) => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("Cannot retry a message with status '{}'", message.status)
+        }))),
+        (_, None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Message not found"
+        }))),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReviewAppealRequest {
+    pub approve: bool,
+    pub note: Option<String>,
+}
+
+// Ban appeal review queue, newest first. Query: status (default pending), page, limit
+pub async fn list_ban_appeals(
+    pool: web::Data<PgPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse> {
+    let status = match query.get("status").map(|s| s.trim().to_lowercase()) {
+        None => Some("pending".to_string()),
+        Some(s) if s.is_empty() || s == "all" => None,
+        Some(s) => Some(s),
+    };
+    if status.as_deref().is_some_and(|s| !BanService::is_valid_appeal_status(s)) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid status. Use pending, approved, rejected or all"
+        })));
+    }
+
+    let page = query
+        .get("page")
+        .and_then(|p| p.parse::<i64>().ok())
+        .unwrap_or(1)
+        .max(1);
+    let limit = query
+        .get("limit")
+        .and_then(|l| l.parse::<i64>().ok())
+        .unwrap_or(50)
+        .clamp(1, 200);
+
+    let appeals = BanService::list_appeals(pool.get_ref(), status.as_deref(), limit, (page - 1) * limit)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "data": appeals,
+        "pagination": {
+            "page": page,
+            "limit": limit
+        }
+    })))
+}
+
+// Approve (lifts the ban) or reject a pending appeal
+pub async fn review_ban_appeal(
+    req: HttpRequest,
+    path: web::Path<i32>,
+    pool: web::Data<PgPool>,
+    review: web::Json<ReviewAppealRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+    let id = path.into_inner();
+    let note = review.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+
+    let reviewed = BanService::review_appeal(pool.get_ref(), id, current_user.sub, review.approve, note)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    if let Some(appeal) = reviewed {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "message": if review.approve { "Appeal approved, ban lifted" } else { "Appeal rejected" },
+            "appeal": appeal
+        })));
+    }
+
+    let existing = BanService::find_appeal(pool.get_ref(), id)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    match existing {
+        Some(appeal) => Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("Appeal was already {}", appeal.status)
+        }))),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Appeal not found"
+        }))),
+    }
+}
+
+// Build audit log filters from query parameters:
+// user_id, event_type (comma separated), status, ip, from, to (RFC 3339), cursor
+fn audit_filter_from_query(query: &HashMap<String, String>) -> std::result::Result<AuditLogFilter, String> {
+    let param = |key: &str| query.get(key).map(|v| v.trim()).filter(|v| !v.is_empty());
+
+    let user_id = param("user_id")
+        .map(|v| v.parse::<i32>().map_err(|_| "Invalid user_id".to_string()))
+        .transpose()?;
+    let before_id = param("cursor")
+        .map(|v| v.parse::<i32>().map_err(|_| "Invalid cursor".to_string()))
+        .transpose()?;
+    let from = param("from").map(|v| parse_timestamp(v, "from")).transpose()?;
+    let to = param("to").map(|v| parse_timestamp(v, "to")).transpose()?;
+
+    let event_types = param("event_type").map(|v| {
+        v.split(',')
+            .map(|e| e.trim().to_uppercase())
+            .filter(|e| !e.is_empty())
+            .collect::<Vec<_>>()
+    });
+
+    Ok(AuditLogFilter {
+        user_id,
+        event_types,
+        status: param("status").map(|v| v.to_lowercase()),
+        ip_address: param("ip").map(|v| v.to_string()),
+        from,
+        to,
+        before_id,
+    })
+}
+
+fn parse_timestamp(value: &str, name: &str) -> std::result::Result<NaiveDateTime, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.naive_utc())
+        .map_err(|_| format!("Invalid {} timestamp, expected RFC 3339", name))
+}
+
+// Search audit logs, newest first. Pass `next_cursor` back as `cursor` for the next page
+pub async fn search_audit_logs(
+    pool: web::Data<PgPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse> {
+    let filter = match audit_filter_from_query(&query) {
+        Ok(filter) => filter,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))),
+    };
+    let limit = query
+        .get("limit")
+        .and_then(|l| l.parse::<i64>().ok())
+        .unwrap_or(AUDIT_PAGE_DEFAULT)
+        .clamp(1, AUDIT_PAGE_MAX);
+
+    // Fetch one extra row to know whether another page exists
+    let mut logs = AuditLogger::search(pool.get_ref(), &filter, limit + 1)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    let has_more = logs.len() as i64 > limit;
+    logs.truncate(limit as usize);
+    let next_cursor = if has_more { logs.last().map(|log| log.id.to_string()) } else { None };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "data": logs,
+        "next_cursor": next_cursor
+    })))
+}
+
+// Stream every matching row as NDJSON (default) or CSV: ?format=ndjson|csv plus search filters
+pub async fn export_audit_logs(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse> {
+    let filter = match audit_filter_from_query(&query) {
+        Ok(filter) => filter,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))),
+    };
+
+    let csv = match query.get("format").map(|f| f.to_lowercase()).as_deref() {
+        None | Some("ndjson") | Some("jsonl") => false,
+        Some("csv") => true,
+        Some(_) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid format. Use ndjson or csv"
+            })));
+        }
+    };
+
+    // Exports leave the system, so record who took one and with which filters
+    if let Some(current_user) = get_current_user(&req) {
+        let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+        let user_agent = req.headers().get("User-Agent").and_then(|h| h.to_str().ok()).map(|s| s.to_string());
+        let _ = AuditLogger::log(
+            pool.get_ref(),
+            Some(current_user.sub),
+            AuditLogger::EVENT_AUDIT_EXPORT,
+            "Audit log export",
+            ip_address.as_deref(),
+            user_agent.as_deref(),
+            AuditLogger::STATUS_SUCCESS,
+            Some(serde_json::json!({ "format": if csv { "csv" } else { "ndjson" }, "filters": query.into_inner() })),
+        )
+        .await;
+    }
+
+    let pool = pool.into_inner();
+    let header = csv.then(|| web::Bytes::from_static(AUDIT_CSV_HEADER.as_bytes()));
+
+    // Page through the results with the keyset cursor, one chunk per batch
+    let body = futures_util::stream::unfold(Some(filter), move |state| {
+        let pool = pool.clone();
+        async move {
+            let mut filter = state?;
+            let logs = match AuditLogger::search(&pool, &filter, AUDIT_EXPORT_BATCH).await {
+                Ok(logs) => logs,
+                Err(e) => {
+                    eprintln!("Audit log export failed: {}", e);
+                    return Some((Err(actix_web::error::ErrorInternalServerError("Database error")), None));
+                }
+            };
+            if logs.is_empty() {
+                return None;
+            }
+
+            let mut chunk = String::new();
+            for log in &logs {
+                if csv {
+                    chunk.push_str(&audit_csv_row(log));
+                } else {
+                    chunk.push_str(&serde_json::to_string(log).unwrap_or_default());
+                    chunk.push('\n');
+                }
+            }
+
+            let next = if (logs.len() as i64) < AUDIT_EXPORT_BATCH {
+                None
+            } else {
+                filter.before_id = logs.last().map(|log| log.id);
+                Some(filter)
+            };
+            Some((Ok::<_, actix_web::Error>(web::Bytes::from(chunk)), next))
+        }
+    });
+
+    let (content_type, extension) = if csv { ("text/csv; charset=utf-8", "csv") } else { ("application/x-ndjson", "ndjson") };
+    let body = futures_util::stream::iter(header.map(Ok::<_, actix_web::Error>)).chain(body);
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"audit-logs.{}\"", extension),
+        ))
+        .streaming(body))
+}
+
+const AUDIT_CSV_HEADER: &str = "id,user_id,event_type,event_action,ip_address,user_agent,status,details,created_at\n";
+
+fn audit_csv_row(log: &AuditLogEntry) -> String {
+    let fields = [
+        log.id.to_string(),
+        log.user_id.map(|id| id.to_string()).unwrap_or_default(),
+        log.event_type.clone(),
+        log.event_action.clone(),
+        log.ip_address.clone().unwrap_or_default(),
+        log.user_agent.clone().unwrap_or_default(),
+        log.status.clone(),
+        log.details.as_ref().map(|d| d.to_string()).unwrap_or_default(),
+        log.created_at.map(|t| t.and_utc().to_rfc3339()).unwrap_or_default(),
+    ];
+
+    let mut row = fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(",");
+    row.push('\n');
+    row
+}
+
+// Quote fields containing separators, quotes or line breaks (RFC 4180). Fields a spreadsheet
+// would read as a formula get a leading quote, user agents and details are attacker-controlled
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
@@ -0,0 +1,93 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::middleware::auth::get_current_user;
+use crate::services::ban_service::BanService;
+
+const APPEAL_MIN_LENGTH: usize = 10;
+const APPEAL_MAX_LENGTH: usize = 2000;
+
+#[derive(Deserialize)]
+pub struct BanAppealRequest {
+    pub message: String,
+}
+
+// The current user's ban (if any) and their appeals
+pub async fn get_own_ban(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let ban = BanService::active_ban(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+    let appeals = BanService::appeals_for_user(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    let can_appeal = match &ban {
+        Some(ban) => BanService::pending_appeal(pool.get_ref(), ban.id)
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+            .is_none(),
+        None => false,
+    };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "banned": ban.is_some(),
+        "ban": ban,
+        "can_appeal": can_appeal,
+        "appeals": appeals
+    })))
+}
+
+// Appeal the ban currently in force. One open appeal per ban
+pub async fn submit_appeal(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    appeal_data: web::Json<BanAppealRequest>,
+) -> Result<HttpResponse> {
+    let current_user = get_current_user(&req)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+    let message = appeal_data.message.trim();
+    let length = message.chars().count();
+    if !(APPEAL_MIN_LENGTH..=APPEAL_MAX_LENGTH).contains(&length) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Appeal must be between {} and {} characters", APPEAL_MIN_LENGTH, APPEAL_MAX_LENGTH)
+        })));
+    }
+
+    let ban = match BanService::active_ban(pool.get_ref(), current_user.sub)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+    {
+        Some(ban) => ban,
+        None => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Your account is not banned"
+            })));
+        }
+    };
+
+    let pending = BanService::pending_appeal(pool.get_ref(), ban.id)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+    if pending.is_some() {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": "You already have an appeal waiting for review"
+        })));
+    }
+
+    let appeal = BanService::submit_appeal(pool.get_ref(), ban.id, current_user.sub, message)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to submit appeal"))?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "message": "Appeal submitted. An administrator will review it.",
+        "appeal": appeal
+    })))
+}
@@ -2,6 +2,8 @@ use actix_web::{web, HttpResponse, HttpRequest};
 use sqlx::{PgPool, Row};
 use crate::models::interaction::{CreateCommentRequest, CommentWithUser, UpdateCommentRequest};
 use crate::middleware::auth::get_current_user;
+use crate::services::ban_service::BanService;
+use crate::services::permission_service::{self as permissions, PermissionService};
 
 // Like handlers
 pub async fn toggle_like(
@@ -17,19 +19,9 @@ pub async fn toggle_like(
     let user_id = current_user.sub;
     let post_id = post_id_param.into_inner();
 
-    // Check if user is banned
-    let user_banned = sqlx::query_scalar::<_, bool>(
-        "SELECT COALESCE(is_banned, false) FROM users WHERE id = $1"
-    )
-    .bind(user_id)
-    .fetch_one(db.get_ref())
-    .await
-    .unwrap_or(false);
-
-    if user_banned {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-            "error": "Your account is banned. You cannot like posts."
-        }));
+    // Check if user is banned (expired bans don't count)
+    if let Ok(Some(ban)) = BanService::active_ban(db.get_ref(), user_id).await {
+        return HttpResponse::Forbidden().json(ban.forbidden_json("Your account is banned. You cannot like posts."));
     }
 
     // Start transaction
@@ -170,19 +162,9 @@ pub async fn create_comment(
     let user_id = current_user.sub;
     let post_id = post_id_param.into_inner();
 
-    // Check if user is banned
-    let user_banned = sqlx::query_scalar::<_, bool>(
-        "SELECT COALESCE(is_banned, false) FROM users WHERE id = $1"
-    )
-    .bind(user_id)
-    .fetch_one(db.get_ref())
-    .await
-    .unwrap_or(false);
-
-    if user_banned {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-            "error": "Your account is banned. You cannot create comments."
-        }));
+    // Check if user is banned (expired bans don't count)
+    if let Ok(Some(ban)) = BanService::active_ban(db.get_ref(), user_id).await {
+        return HttpResponse::Forbidden().json(ban.forbidden_json("Your account is banned. You cannot create comments."));
     }
 
     if body.content.trim().is_empty() {
@@ -377,14 +359,14 @@ pub async fn delete_comment(
             let owner_id: i32 = row.get("user_id");
             let post_id: i32 = row.get("post_id");
             
-            let is_admin = current_user.role == "admin";
+            let can_delete_any = PermissionService::has_permission(db.get_ref(), &current_user, permissions::COMMENTS_DELETE_ANY).await;
             let is_comment_owner = owner_id == user_id;
             
             eprintln!("DELETE COMMENT: Request for comment_id={} from user_id={} role={}", comment_id, user_id, current_user.role);
             eprintln!("DELETE COMMENT: Owner check: owner_id={} is_comment_owner={}", owner_id, is_comment_owner);
 
-            // Check if user is post owner (only if not admin or comment owner to save DB call)
-            let is_post_owner = if !is_admin && !is_comment_owner {
+            // Check if user is post owner (only if not a moderator or comment owner to save DB call)
+            let is_post_owner = if !can_delete_any && !is_comment_owner {
                  let post_owner_check = sqlx::query_scalar::<_, i32>("SELECT user_id FROM posts WHERE id = $1")
                     .bind(post_id)
                     .fetch_optional(db.get_ref())
@@ -398,7 +380,7 @@ pub async fn delete_comment(
                 false
             };
             
-            if is_admin || is_comment_owner || is_post_owner {
+            if can_delete_any || is_comment_owner || is_post_owner {
                 match sqlx::query("DELETE FROM comments WHERE id = $1")
                     .bind(comment_id)
                     .execute(db.get_ref())
@@ -2,3 +2,5 @@ pub mod post;
 pub mod user;
 pub mod interaction;
 pub mod user_profile;
+pub mod admin;
+pub mod ban_appeal;
@@ -1,6 +1,8 @@
 use crate::middleware::auth::get_current_user;
 use crate::middleware::redis_cache::RedisCache;
 use crate::models::post::{CreatePost, Post, PostResponse};
+use crate::services::ban_service::BanService;
+use crate::services::permission_service::{self as permissions, PermissionService};
 use actix_web::{HttpResponse, Result, web};
 use sqlx::PgPool;
 
@@ -121,19 +123,9 @@ pub async fn create_post(
     let current_user = get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
-    // Check if user is banned
-    let user_banned = sqlx::query_scalar::<_, bool>(
-        "SELECT COALESCE(is_banned, false) FROM users WHERE id = $1"
-    )
-    .bind(current_user.sub)
-    .fetch_one(pool.get_ref())
-    .await
-    .unwrap_or(false);
-
-    if user_banned {
-        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
-            "error": "Your account is banned. You cannot create posts."
-        })));
+    // Check if user is banned (expired bans don't count)
+    if let Ok(Some(ban)) = BanService::active_ban(pool.get_ref(), current_user.sub).await {
+        return Ok(HttpResponse::Forbidden().json(ban.forbidden_json("Your account is banned. You cannot create posts.")));
     }
 
     let post = sqlx::query_as!(
@@ -194,19 +186,9 @@ pub async fn update_post(
     let current_user = get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
-    // Check if user is banned
-    let user_banned = sqlx::query_scalar::<_, bool>(
-        "SELECT COALESCE(is_banned, false) FROM users WHERE id = $1"
-    )
-    .bind(current_user.sub)
-    .fetch_one(pool.get_ref())
-    .await
-    .unwrap_or(false);
-
-    if user_banned {
-        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
-            "error": "Your account is banned. You cannot update posts."
-        })));
+    // Check if user is banned (expired bans don't count)
+    if let Ok(Some(ban)) = BanService::active_ban(pool.get_ref(), current_user.sub).await {
+        return Ok(HttpResponse::Forbidden().json(ban.forbidden_json("Your account is banned. You cannot update posts.")));
     }
 
     let post_id = path.into_inner();
@@ -250,8 +232,9 @@ pub async fn delete_post(
     // Invalidate all posts cache
     let _ = redis.invalidate_post(post_id).await;
 
-    // First check if post exists and user is authorized
-    let post_exists = if current_user.role == "admin" {
+    // First check if post exists and user is authorized (owner, or allowed to delete any post)
+    let can_delete_any = PermissionService::has_permission(pool.get_ref(), &current_user, permissions::POSTS_DELETE_ANY).await;
+    let post_exists = if can_delete_any {
         sqlx::query_scalar::<_, i32>("SELECT id FROM posts WHERE id = $1")
             .bind(post_id)
             .fetch_optional(pool.get_ref())
@@ -1,11 +1,14 @@
 use actix_web::{HttpResponse, Result, web};
 use sqlx::PgPool;
 
+use crate::auth::password::{hash_password, password_policy_violation, remember_password};
 use crate::middleware::auth::get_current_user;
 use crate::middleware::redis_cache::RedisCache;
 use crate::models::user::{CreateUser, UpdateUser, User, UserResponse};
-use crate::utils::auth::AuthUtils;
-use crate::utils::validation::{validate_username, validate_email, validate_password};
+use crate::services::ban_service::BanService;
+use crate::services::password_policy_service::PasswordOwner;
+use crate::services::permission_service::{self as permissions, PermissionService};
+use crate::utils::validation::{validate_username, validate_email};
 
 pub async fn get_users(pool: web::Data<PgPool>) -> Result<HttpResponse> {
     let users = sqlx::query_as!(
@@ -58,6 +61,7 @@ pub async fn get_user(
 }
 
 pub async fn create_user(
+    req: actix_web::HttpRequest,
     pool: web::Data<PgPool>,
     user_data: web::Json<CreateUser>,
 ) -> Result<HttpResponse> {
@@ -76,17 +80,39 @@ pub async fn create_user(
         }
     }
 
-    if let Err(e) = validate_password(&user_data.password) {
+    let owner = PasswordOwner {
+        username: Some(&user_data.username),
+        email: user_data.email.as_deref(),
+        ..PasswordOwner::default()
+    };
+    if let Some(reason) = password_policy_violation(&pool, &req, &user_data.password, &owner).await? {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid password: {}", e)
+            "error": format!("Invalid password: {}", reason)
         })));
     }
 
+    // Picking the role needs users.role.assign, like changing it later
+    if let Some(role) = &user_data.role {
+        let current_user = get_current_user(&req)
+            .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+        if !PermissionService::has_permission(pool.get_ref(), &current_user, permissions::USERS_ROLE_ASSIGN).await {
+            return Ok(HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "Not allowed to assign roles"
+            })));
+        }
+        let role_exists = PermissionService::role_exists(pool.get_ref(), role)
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+        if !role_exists {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Unknown role: {}", role)
+            })));
+        }
+    }
     let role = user_data.role.as_deref().unwrap_or("user");
 
     // Hash password before storing
-    let hashed_password = AuthUtils::hash_password(&user_data.password)
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Password hashing failed"))?;
+    let hashed_password = hash_password(&req, &user_data.password).await?;
 
     let user = sqlx::query_as!(
         User,
@@ -123,13 +149,37 @@ pub async fn update_user(
     let current_user = get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
-    // Check if user can update (owner or admin)
-    if !AuthUtils::can_access_resource(current_user.sub, user_id, &current_user.role) {
+    // Check if user can update (owner, or allowed to edit any account)
+    let can_update_any = PermissionService::has_permission(pool.get_ref(), &current_user, permissions::USERS_UPDATE).await;
+    if current_user.sub != user_id && !can_update_any {
         return Ok(HttpResponse::Forbidden().json(serde_json::json!({
             "error": "Insufficient permissions"
         })));
     }
 
+    // Check a new password before changing anything, against the username and email it will have
+    let mut replaced_password = None;
+    if let Some(password) = &user_data.password {
+        let user = sqlx::query!("SELECT username, email, password FROM users WHERE id = $1", user_id)
+            .fetch_optional(pool.get_ref())
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+            .ok_or_else(|| actix_web::error::ErrorNotFound("User not found"))?;
+
+        let owner = PasswordOwner {
+            user_id: Some(user_id),
+            current_hash: Some(&user.password),
+            username: Some(user_data.username.as_deref().unwrap_or(&user.username)),
+            email: user_data.email.as_deref().or(user.email.as_deref()),
+        };
+        if let Some(reason) = password_policy_violation(&pool, &req, password, &owner).await? {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid password: {}", reason)
+            })));
+        }
+        replaced_password = Some(user.password);
+    }
+
     // Invalidate user cache before update
     let _ = redis.invalidate_user(user_id).await;
 
@@ -164,8 +214,7 @@ pub async fn update_user(
 
     // Update password if provided
     if let Some(password) = &user_data.password {
-        let hashed_password = AuthUtils::hash_password(password)
-            .map_err(|_| actix_web::error::ErrorInternalServerError("Password hashing failed"))?;
+        let hashed_password = hash_password(&req, password).await?;
         sqlx::query!(
             "UPDATE users SET password = $1, updated_at = NOW() WHERE id = $2",
             hashed_password,
@@ -174,14 +223,25 @@ pub async fn update_user(
         .execute(pool.get_ref())
         .await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+        if let Some(old_hash) = &replaced_password {
+            remember_password(&pool, &req, user_id, old_hash).await;
+        }
         has_updates = true;
     }
 
-    // Update role if provided (admin only)
+    // Update role if provided (requires users.role.assign)
     if let Some(role) = &user_data.role {
-        if current_user.role != "admin" {
+        if !PermissionService::has_permission(pool.get_ref(), &current_user, permissions::USERS_ROLE_ASSIGN).await {
             return Ok(HttpResponse::Forbidden().json(serde_json::json!({
-                "error": "Only admins can update roles"
+                "error": "Not allowed to update roles"
+            })));
+        }
+        let role_exists = PermissionService::role_exists(pool.get_ref(), role)
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+        if !role_exists {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Unknown role: {}", role)
             })));
         }
         sqlx::query!(
@@ -230,8 +290,9 @@ pub async fn delete_user(
     let current_user = get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
-    // Check if user can delete (owner or admin)
-    if !AuthUtils::can_access_resource(current_user.sub, user_id, &current_user.role) {
+    // Check if user can delete (owner, or allowed to delete any account)
+    let can_delete_any = PermissionService::has_permission(pool.get_ref(), &current_user, permissions::USERS_DELETE).await;
+    if current_user.sub != user_id && !can_delete_any {
         return Ok(HttpResponse::Forbidden().json(serde_json::json!({
             "error": "Insufficient permissions"
         })));
@@ -364,14 +425,35 @@ pub async fn search_users_public(
     })))
 }
 
+// Ban history for a user, newest first
+pub async fn get_user_bans(
+    path: web::Path<i32>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse> {
+    let bans = BanService::history(pool.get_ref(), path.into_inner())
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "bans": bans,
+        "count": bans.len()
+    })))
+}
+
 use serde::Deserialize;
 
 #[derive(Deserialize)]
 pub struct BanUserRequest {
     pub is_banned: bool,
-    pub ban_days: Option<i64>, // Number of days to ban (None = permanent)
+    pub ban_days: Option<i64>,       // Number of days to ban (None = permanent)
+    pub duration_hours: Option<i64>, // Finer grained alternative to ban_days
+    pub reason: Option<String>,      // Shown to the banned user, also used when lifting a ban
 }
 
+const BAN_REASON_MAX_LENGTH: usize = 500;
+// Longer temporary bans are refused, a permanent ban is the way to go past this
+const BAN_MAX_DAYS: i64 = 100 * 365;
+
 pub async fn ban_user(
     req: actix_web::HttpRequest,
     path: web::Path<i32>,
@@ -383,10 +465,10 @@ pub async fn ban_user(
     let current_user = get_current_user(&req)
         .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
 
-    // Only admin can ban users
-    if current_user.role != "admin" {
+    // Only users with users.ban can ban (the route checks this too)
+    if !PermissionService::has_permission(pool.get_ref(), &current_user, permissions::USERS_BAN).await {
         return Ok(HttpResponse::Forbidden().json(serde_json::json!({
-            "error": "Only admins can ban users"
+            "error": "Not allowed to ban users"
         })));
     }
 
@@ -404,6 +486,17 @@ pub async fn ban_user(
                     "error": "Cannot ban admin users"
                 })));
             }
+
+            // Other staff (anyone who can ban) can only be banned by someone who can change roles
+            let target_is_staff = PermissionService::role_has_permission(pool.get_ref(), &user.role, permissions::USERS_BAN)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+            let can_assign_roles = PermissionService::has_permission(pool.get_ref(), &current_user, permissions::USERS_ROLE_ASSIGN).await;
+            if target_is_staff && !can_assign_roles {
+                return Ok(HttpResponse::Forbidden().json(serde_json::json!({
+                    "error": "Cannot ban staff users"
+                })));
+            }
         }
         None => {
             return Ok(HttpResponse::NotFound().json(serde_json::json!({
@@ -412,29 +505,55 @@ pub async fn ban_user(
         }
     }
 
-    // Invalidate user cache before update
-    let _ = redis.invalidate_user(user_id).await;
+    let reason = ban_data.reason.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    if reason.is_some_and(|r| r.chars().count() > BAN_REASON_MAX_LENGTH) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Reason must be at most {} characters", BAN_REASON_MAX_LENGTH)
+        })));
+    }
 
     // Calculate banned_until timestamp
     use chrono::{Utc, Duration};
-    let banned_until = if ban_data.is_banned {
-        ban_data.ban_days.map(|days| {
-            (Utc::now() + Duration::days(days)).naive_utc()
-        })
-    } else {
-        None
+    let duration = match (ban_data.ban_days, ban_data.duration_hours) {
+        (Some(_), Some(_)) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Use either ban_days or duration_hours, not both"
+            })));
+        }
+        (Some(days), None) => Some(Duration::try_days(days)),
+        (None, Some(hours)) => Some(Duration::try_hours(hours)),
+        (None, None) => None,
+    };
+    let duration = match duration {
+        Some(Some(d)) if d <= Duration::zero() => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Ban duration must be positive"
+            })));
+        }
+        Some(Some(d)) if d <= Duration::days(BAN_MAX_DAYS) => Some(d),
+        Some(_) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Temporary bans can last at most {} days, use a permanent ban instead", BAN_MAX_DAYS)
+            })));
+        }
+        None => None,
     };
 
-    // Update is_banned status and banned_until
-    sqlx::query!(
-        "UPDATE users SET is_banned = $1, banned_until = $2, updated_at = NOW() WHERE id = $3",
-        ban_data.is_banned,
-        banned_until,
-        user_id
-    )
-    .execute(pool.get_ref())
-    .await
-    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+    // Invalidate user cache before update
+    let _ = redis.invalidate_user(user_id).await;
+
+    // Record the ban (or lift it) in user_bans, which also updates is_banned and banned_until
+    let result = if ban_data.is_banned {
+        let banned_until = duration.map(|d| (Utc::now() + d).naive_utc());
+        BanService::ban(pool.get_ref(), user_id, Some(current_user.sub), reason.unwrap_or("No reason given"), banned_until)
+            .await
+            .map(|_| ())
+    } else {
+        BanService::lift(pool.get_ref(), user_id, Some(current_user.sub), reason.unwrap_or("Lifted by moderator"))
+            .await
+            .map(|_| ())
+    };
+    result.map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
 
     // Get updated user
     let user = sqlx::query_as!(
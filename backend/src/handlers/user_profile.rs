@@ -1,10 +1,12 @@
 use actix_web::{HttpResponse, Result, web, HttpRequest};
 use sqlx::PgPool;
-use totp_rs::{TOTP, Algorithm};
-use base32;
 
+use crate::auth::account::log_wallet_event;
 use crate::middleware::auth::get_current_user;
 use crate::models::user::{UpdateUser, User, UserResponse};
+use crate::services::audit_logger::AuditLogger;
+use crate::services::email_templates::normalize_locale;
+use crate::services::wallet_service::{RemoveWalletOutcome, WalletService};
 
 // Handler for users to update their own profile
 pub async fn update_own_profile(
@@ -23,58 +25,20 @@ pub async fn update_own_profile(
     // Update username if provided
     if let Some(username) = &user_data.username {
         // Security check: If changing username, require 2FA
-        let user = sqlx::query!(
-            "SELECT totp_enabled, totp_secret, username FROM users WHERE id = $1",
+        let totp_enabled = sqlx::query_scalar!(
+            "SELECT totp_enabled FROM users WHERE id = $1",
             user_id
         )
         .fetch_one(pool.get_ref())
         .await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
 
-        if !user.totp_enabled.unwrap_or(false) {
+        if !totp_enabled.unwrap_or(false) {
              return Ok(HttpResponse::BadRequest().json(serde_json::json!({
                 "error": "Two-factor authentication (2FA) must be enabled to update your username. Please enable 2FA first."
             })));
         }
 
-        match &user_data.verification_code {
-            Some(code) => {
-                 let totp_secret = user.totp_secret.as_ref().ok_or_else(|| {
-                    actix_web::error::ErrorInternalServerError("TOTP secret missing")
-                })?;
-
-                let secret_bytes = base32::decode(base32::Alphabet::RFC4648 { padding: false }, totp_secret)
-                    .ok_or_else(|| actix_web::error::ErrorInternalServerError("Invalid TOTP secret format"))?;
-
-                let totp = TOTP::new(
-                    Algorithm::SHA1,
-                    6,
-                    1,
-                    30,
-                    secret_bytes,
-                    Some("USH".to_string()),
-                    user.username.clone(),
-                ).map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create TOTP instance"))?;
-
-                let current_time = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-
-                if !totp.check(code, current_time) {
-                    return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "Invalid 2FA code"
-                    })));
-                }
-            },
-            None => {
-                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "2FA verification required",
-                    "require_2fa": true
-                })));
-            }
-        }
-
         sqlx::query!(
             "UPDATE users SET username = $1, updated_at = NOW() WHERE id = $2",
             username,
@@ -89,58 +53,20 @@ pub async fn update_own_profile(
     // Update email if provided
     if let Some(email) = &user_data.email {
         // Security check: If changing email, require 2FA
-        let user = sqlx::query!(
-            "SELECT totp_enabled, totp_secret, username FROM users WHERE id = $1",
+        let totp_enabled = sqlx::query_scalar!(
+            "SELECT totp_enabled FROM users WHERE id = $1",
             user_id
         )
         .fetch_one(pool.get_ref())
         .await
         .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
 
-        if !user.totp_enabled.unwrap_or(false) {
+        if !totp_enabled.unwrap_or(false) {
              return Ok(HttpResponse::BadRequest().json(serde_json::json!({
                 "error": "Two-factor authentication (2FA) must be enabled to update your email. Please enable 2FA first."
             })));
         }
 
-        match &user_data.verification_code {
-            Some(code) => {
-                 let totp_secret = user.totp_secret.as_ref().ok_or_else(|| {
-                    actix_web::error::ErrorInternalServerError("TOTP secret missing")
-                })?;
-
-                let secret_bytes = base32::decode(base32::Alphabet::RFC4648 { padding: false }, totp_secret)
-                    .ok_or_else(|| actix_web::error::ErrorInternalServerError("Invalid TOTP secret format"))?;
-
-                let totp = TOTP::new(
-                    Algorithm::SHA1,
-                    6,
-                    1,
-                    30,
-                    secret_bytes,
-                    Some("USH".to_string()),
-                    user.username.clone(),
-                ).map_err(|_| actix_web::error::ErrorInternalServerError("Failed to create TOTP instance"))?;
-
-                let current_time = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-
-                if !totp.check(code, current_time) {
-                    return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "Invalid 2FA code"
-                    })));
-                }
-            },
-            None => {
-                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "2FA verification required",
-                    "require_2fa": true
-                })));
-            }
-        }
-
         sqlx::query!(
             "UPDATE users SET email = $1, email_verified = false, updated_at = NOW() WHERE id = $2",
             email,
@@ -152,11 +78,52 @@ pub async fn update_own_profile(
         has_updates = true;
     }
 
-    // Update wallet_address if provided
+    // Wallets can only be removed here; adding one needs a signature (POST /api/auth/connect-wallet)
     if let Some(wallet_address) = &user_data.wallet_address {
+        if wallet_address.is_some() {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Connect a wallet by signing a challenge via /api/auth/connect-wallet"
+            })));
+        }
+
+        let primary = WalletService::primary(pool.get_ref(), user_id)
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+        if let Some(primary) = primary {
+            let outcome = WalletService::remove(pool.get_ref(), user_id, primary.id)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+            match outcome {
+                RemoveWalletOutcome::Removed(wallet) => {
+                    log_wallet_event(&pool, &req, user_id, AuditLogger::EVENT_IDENTITY_UNLINKED, "wallet_remove", &wallet.address).await;
+                }
+                RemoveWalletOutcome::NotFound => {}
+                RemoveWalletOutcome::LastLoginMethod => {
+                    return Ok(HttpResponse::Conflict().json(serde_json::json!({
+                        "error": "Your wallet is your only way to sign in. Set a password or link another account first."
+                    })));
+                }
+            }
+        }
+        has_updates = true;
+    }
+
+    // Update preferred email locale if provided
+    if let Some(locale) = &user_data.locale {
+        let locale = match normalize_locale(locale) {
+            Some(locale) => locale,
+            None => {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Invalid locale"
+                })));
+            }
+        };
+
         sqlx::query!(
-            "UPDATE users SET wallet_address = $1, updated_at = NOW() WHERE id = $2",
-            wallet_address.as_ref().map(|s| s.as_str()),
+            "UPDATE users SET locale = $1, updated_at = NOW() WHERE id = $2",
+            locale,
             user_id
         )
         .execute(pool.get_ref())
@@ -17,7 +17,11 @@ use crate::middleware::redis_token_blacklist::RedisTokenBlacklist;
 use crate::middleware::redis_session::RedisSessionStore;
 use crate::middleware::redis_cache::RedisCache;
 use crate::services::token_blacklist::TokenBlacklistService;
+use crate::services::one_time_code_store::OneTimeCodeStore;
 use crate::services::scheduled_tasks::start_scheduled_tasks;
+use crate::services::step_up_service::StepUpService;
+use crate::services::totp_secret_service::TotpKeyring;
+use crate::services::totp_service::TotpService;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -39,6 +43,23 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to connect database");
 
+    // Fail at startup rather than on the first 2FA request
+    let totp_keyring = TotpKeyring::from_env().expect("Invalid TOTP master keys");
+
+    // Admin command: `backend reencrypt-totp-secrets` moves every TOTP secret to the first
+    // key of TOTP_MASTER_KEYS, after which older keys can be removed
+    if std::env::args().nth(1).as_deref() == Some("reencrypt-totp-secrets") {
+        let count = TotpService::reencrypt_all(&pool, &totp_keyring)
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        println!("Re-encrypted {} TOTP secrets", count);
+        return Ok(());
+    }
+
+    if totp_keyring.current().is_none() {
+        eprintln!("Warning: TOTP_MASTER_KEYS is not set, TOTP secrets are stored unencrypted");
+    }
+
     // Initialize Redis services
     let redis_rate_limiter = RedisRateLimiter::new(&redis_url)
         .await
@@ -56,6 +77,13 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to connect to Redis for caching");
 
+    // One-time codes (verification, MFA email, password reset) shared by all replicas
+    let one_time_code_store = OneTimeCodeStore::from_env(&pool, &redis_url)
+        .await
+        .expect("Failed to initialize one-time code store");
+    OneTimeCodeStore::install(one_time_code_store);
+    OneTimeCodeStore::install_secret(&jwt_secret);
+
     // Start background scheduled cleanup tasks
     start_scheduled_tasks(pool.clone());
 
@@ -74,6 +102,7 @@ async fn main() -> std::io::Result<()> {
                 actix_web::http::header::CONTENT_TYPE,
                 actix_web::http::header::AUTHORIZATION,
             ])
+            .allowed_header(StepUpService::HEADER)
             .supports_credentials()
             .max_age(3600);
 
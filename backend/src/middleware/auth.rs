@@ -1,6 +1,6 @@
 use actix_web::dev::{Service, Transform, forward_ready};
 use actix_web::{
-    Error, HttpMessage, Result,
+    Error, HttpMessage, HttpResponse, Result,
     dev::{ServiceRequest, ServiceResponse},
 };
 use futures_util::future::{Ready, ready};
@@ -10,25 +10,52 @@ use std::rc::Rc;
 
 use crate::models::auth::Claims;
 use crate::utils::auth::{AuthError, AuthUtils};
-use crate::services::token_blacklist::TokenBlacklistService;
+use crate::services::token_blacklist::{TokenBlacklist, TokenBlacklistService};
+use crate::services::permission_service::PermissionService;
+use crate::middleware::rate_limiter::RateLimiter;
+use crate::middleware::redis_rate_limiter::RedisRateLimiter;
 use crate::middleware::redis_token_blacklist::RedisTokenBlacklist;
+use crate::services::personal_access_token_service::{PersonalAccessToken, PersonalAccessTokenService};
 
 pub struct AuthMiddleware {
     pub required_role: Option<String>,
+    pub required_permission: Option<String>,
+    /// Personal access tokens are only accepted where a scope is declared (see `token_scope`)
+    pub token_scope: Option<String>,
 }
 
 impl AuthMiddleware {
     pub fn new() -> Self {
         Self {
             required_role: None,
+            required_permission: None,
+            token_scope: None,
         }
     }
 
     pub fn require_role(role: &str) -> Self {
         Self {
             required_role: Some(role.to_string()),
+            required_permission: None,
+            token_scope: None,
         }
     }
+
+    /// Require a permission from the `role_permissions` table, e.g. "posts.delete.any"
+    pub fn require_permission(permission: &str) -> Self {
+        Self {
+            required_role: None,
+            required_permission: Some(permission.to_string()),
+            token_scope: None,
+        }
+    }
+
+    /// Also accept personal access tokens with the scope, e.g. "admin:users". A bare resource
+    /// such as "posts" means "posts:read" for GET requests and "posts:write" for the rest
+    pub fn token_scope(mut self, scope: &str) -> Self {
+        self.token_scope = Some(scope.to_string());
+        self
+    }
 }
 
 impl<S> Transform<S, ServiceRequest> for AuthMiddleware
@@ -46,6 +73,8 @@ where
         ready(Ok(AuthMiddlewareService {
             service: Rc::new(service),
             required_role: self.required_role.clone(),
+            required_permission: self.required_permission.clone(),
+            token_scope: self.token_scope.clone(),
         }))
     }
 }
@@ -53,6 +82,8 @@ where
 pub struct AuthMiddlewareService<S> {
     service: Rc<S>,
     required_role: Option<String>,
+    required_permission: Option<String>,
+    token_scope: Option<String>,
 }
 
 impl<S> Service<ServiceRequest> for AuthMiddlewareService<S>
@@ -69,6 +100,8 @@ where
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = Rc::clone(&self.service);
         let required_role = self.required_role.clone();
+        let required_permission = self.required_permission.clone();
+        let token_scope = self.token_scope.clone();
 
         Box::pin(async move {
             // Extract JWT secret from app data
@@ -91,6 +124,31 @@ where
             let token = AuthUtils::extract_token_from_header(auth_header)
                 .map_err(|_| actix_web::error::ErrorUnauthorized("Invalid token format"))?;
 
+            if PersonalAccessTokenService::is_token(token) {
+                let Some(scope) = &token_scope else {
+                    return Err(actix_web::error::ErrorForbidden("Personal access tokens can't be used here"));
+                };
+                let (access_token, claims) = match authenticate_access_token(&req, token, scope).await? {
+                    Ok(authenticated) => authenticated,
+                    Err(response) => return Ok(req.into_response(response)),
+                };
+
+                check_role_and_permission(&req, &claims, &required_role, &required_permission).await?;
+
+                if let Some(pool) = req.app_data::<actix_web::web::Data<sqlx::PgPool>>() {
+                    let pool = pool.clone();
+                    let id = access_token.id;
+                    let ip_address = req.connection_info().peer_addr().map(|s| s.to_string());
+                    actix_web::rt::spawn(async move {
+                        let _ = PersonalAccessTokenService::touch(pool.get_ref(), id, ip_address.as_deref()).await;
+                    });
+                }
+
+                req.extensions_mut().insert(claims);
+                req.extensions_mut().insert(access_token);
+                return service.call(req).await;
+            }
+
             // Check if token is blacklisted in Redis (important: before validating expiry)
             if let Some(redis_blacklist) = req.app_data::<actix_web::web::Data<RedisTokenBlacklist>>() {
                 if let Ok(is_blacklisted) = redis_blacklist.is_blacklisted(token).await {
@@ -114,13 +172,18 @@ where
                 _ => actix_web::error::ErrorUnauthorized("Invalid token"),
             })?;
 
-            // Check role if required
-            if let Some(required) = &required_role {
-                if !AuthUtils::has_role(&claims.role, required) {
-                    return Err(actix_web::error::ErrorForbidden("Insufficient permissions"));
+            // Reject tokens issued before the user revoked all of them (see TokenBlacklist::blacklist_all_user_tokens)
+            if let Some(pool) = req.app_data::<actix_web::web::Data<sqlx::PgPool>>() {
+                let revoked = TokenBlacklist::is_revoked_for_user(pool.get_ref(), claims.sub, claims.iat as i64)
+                    .await
+                    .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?;
+                if revoked {
+                    return Err(actix_web::error::ErrorUnauthorized("Token has been revoked"));
                 }
             }
 
+            check_role_and_permission(&req, &claims, &required_role, &required_permission).await?;
+
             // Add claims to request extensions for handlers to use
             req.extensions_mut().insert(claims.clone());
 
@@ -148,7 +211,88 @@ where
     }
 }
 
+async fn check_role_and_permission(
+    req: &ServiceRequest,
+    claims: &Claims,
+    required_role: &Option<String>,
+    required_permission: &Option<String>,
+) -> Result<(), Error> {
+    // Check role if required
+    if let Some(required) = required_role
+        && !AuthUtils::has_role(&claims.role, required)
+    {
+        return Err(actix_web::error::ErrorForbidden("Insufficient permissions"));
+    }
+
+    // Check permission if required (needs the database pool for role_permissions)
+    if let Some(permission) = required_permission {
+        let pool = req
+            .app_data::<actix_web::web::Data<sqlx::PgPool>>()
+            .ok_or_else(|| {
+                actix_web::error::ErrorInternalServerError("Database pool not found")
+            })?;
+
+        if !PermissionService::has_permission(pool.get_ref(), claims, permission).await {
+            return Err(actix_web::error::ErrorForbidden("Insufficient permissions"));
+        }
+    }
+
+    Ok(())
+}
+
+// Look up a personal access token, check it has the scope this request needs and count the
+// request against the token's own rate limit. The inner Err is the response to send instead
+async fn authenticate_access_token(
+    req: &ServiceRequest,
+    token: &str,
+    scope: &str,
+) -> Result<std::result::Result<(PersonalAccessToken, Claims), HttpResponse>, Error> {
+    let pool = req
+        .app_data::<actix_web::web::Data<sqlx::PgPool>>()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("Database pool not found"))?;
+
+    let (access_token, claims) = PersonalAccessTokenService::authenticate(pool.get_ref(), token)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Invalid or expired token"))?;
+
+    let required = PersonalAccessTokenService::required_scope(scope, req.method());
+    if !access_token.has_scope(&required) {
+        return Ok(Err(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": format!("This token does not have the {} scope", required),
+            "required_scope": required
+        }))));
+    }
+
+    // One minute windows, in Redis when it is configured so all instances share the count
+    let limit = access_token.rate_limit_per_minute.max(1) as u32;
+    let key = access_token.id.to_string();
+    let (is_allowed, _, reset_seconds) = match req.app_data::<actix_web::web::Data<RedisRateLimiter>>() {
+        Some(rate_limiter) => rate_limiter.check_limit_key("personal-access-token", &key, limit, 60).await,
+        None => RateLimiter::check_limit_with_key(&format!("personal-access-token:{}", key), limit, 1),
+    };
+    if !is_allowed {
+        return Ok(Err(HttpResponse::TooManyRequests()
+            .insert_header(("X-RateLimit-Limit", limit.to_string()))
+            .insert_header(("X-RateLimit-Remaining", "0"))
+            .insert_header(("X-RateLimit-Reset", reset_seconds.to_string()))
+            .insert_header(("Retry-After", reset_seconds.to_string()))
+            .json(serde_json::json!({
+                "error": "Too many requests",
+                "message": format!("Rate limit for this token exceeded. Try again in {} seconds", reset_seconds),
+                "retry_after": reset_seconds
+            }))));
+    }
+
+    Ok(Ok((access_token, claims)))
+}
+
 // Helper function to extract claims from request
 pub fn get_current_user(req: &actix_web::HttpRequest) -> Option<Claims> {
     req.extensions().get::<Claims>().cloned()
 }
+
+/// The personal access token the request was made with, `None` for a regular session
+pub fn get_access_token(req: &actix_web::HttpRequest) -> Option<PersonalAccessToken> {
+    req.extensions().get::<PersonalAccessToken>().cloned()
+}
@@ -7,3 +7,4 @@ pub mod redis_cache;
 pub mod security_headers;
 pub mod jwt_blacklist;
 pub mod redis_token_blacklist;
+pub mod step_up;
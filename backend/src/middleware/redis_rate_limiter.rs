@@ -45,40 +45,7 @@ impl RedisRateLimiter {
         window_seconds: u32,
     ) -> (bool, u32, u32) {
         let client_ip = Self::get_client_ip_from_http(req);
-        let key = format!("rate_limit:{}:{}", endpoint, client_ip);
-
-        let mut conn = self.connection.lock().await;
-
-        // Try to increment the counter
-        let current_count: u32 = redis::cmd("INCR")
-            .arg(&key)
-            .query_async(&mut *conn)
-            .await
-            .unwrap_or(0);
-
-        // Set expiration if this is first request (count = 1)
-        if current_count == 1 {
-            let _: () = redis::cmd("EXPIRE")
-                .arg(&key)
-                .arg(window_seconds as i64)
-                .query_async(&mut *conn)
-                .await
-                .unwrap_or(());
-        }
-
-        let remaining = if current_count > max_attempts {
-            0
-        } else {
-            max_attempts - current_count
-        };
-
-        let ttl: i64 = redis::cmd("TTL")
-            .arg(&key)
-            .query_async(&mut *conn)
-            .await
-            .unwrap_or(-1);
-
-        (current_count <= max_attempts, remaining, ttl.max(0) as u32)
+        self.check_limit_key(endpoint, &client_ip, max_attempts, window_seconds).await
     }
 
     /// Check rate limit using Redis for ServiceRequest (for middleware)
@@ -91,7 +58,19 @@ impl RedisRateLimiter {
         window_seconds: u32,
     ) -> (bool, u32, u32) {
         let client_ip = Self::get_client_ip(req);
-        let key = format!("rate_limit:{}:{}", endpoint, client_ip);
+        self.check_limit_key(endpoint, &client_ip, max_attempts, window_seconds).await
+    }
+
+    /// Check rate limit for any client key, e.g. a personal access token id instead of an IP
+    /// Returns: (is_allowed, remaining_attempts, reset_seconds)
+    pub async fn check_limit_key(
+        &self,
+        endpoint: &str,
+        client_key: &str,
+        max_attempts: u32,
+        window_seconds: u32,
+    ) -> (bool, u32, u32) {
+        let key = format!("rate_limit:{}:{}", endpoint, client_key);
 
         let mut conn = self.connection.lock().await;
 
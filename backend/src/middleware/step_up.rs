@@ -0,0 +1,121 @@
+use actix_web::dev::{Service, ServiceResponse, Transform, forward_ready};
+use actix_web::{
+    body::BoxBody,
+    Error, HttpMessage, HttpRequest, HttpResponse,
+    dev::ServiceRequest,
+};
+use futures_util::future::{Ready, ready};
+use sqlx::PgPool;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use crate::middleware::auth::get_current_user;
+use crate::services::step_up_service::{StepUpClaims, StepUpService};
+use crate::utils::auth::AuthUtils;
+
+/// Require a recent step-up re-authentication (see `StepUpService`). Goes inside `AuthMiddleware`,
+/// i.e. is wrapped before it:
+/// `web::post().to(handler).wrap(StepUpMiddleware::max_age(300)).wrap(AuthMiddleware::new())`
+pub struct StepUpMiddleware {
+    pub max_age_seconds: i64,
+}
+
+impl StepUpMiddleware {
+    /// Accept step-up tokens earned at most `seconds` ago
+    pub fn max_age(seconds: i64) -> Self {
+        Self { max_age_seconds: seconds }
+    }
+}
+
+impl<S> Transform<S, ServiceRequest> for StepUpMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = StepUpMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(StepUpMiddlewareService {
+            service: Rc::new(service),
+            max_age_seconds: self.max_age_seconds,
+        }))
+    }
+}
+
+pub struct StepUpMiddlewareService<S> {
+    service: Rc<S>,
+    max_age_seconds: i64,
+}
+
+impl<S> Service<ServiceRequest> for StepUpMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let max_age_seconds = self.max_age_seconds;
+
+        Box::pin(async move {
+            let current_user = get_current_user(req.request())
+                .ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated"))?;
+
+            match get_step_up(req.request()) {
+                Some(claims) if claims.is_fresh(max_age_seconds) => {
+                    req.extensions_mut().insert(claims);
+                    service.call(req).await
+                }
+                _ => {
+                    let pool = req.app_data::<actix_web::web::Data<PgPool>>().map(|p| p.get_ref().clone());
+                    let response = step_up_required(pool.as_ref(), current_user.sub, max_age_seconds).await;
+                    Ok(req.into_response(response))
+                }
+            }
+        })
+    }
+}
+
+/// The valid step-up token sent in the `X-Step-Up-Token` header, if any, whatever its age
+pub fn get_step_up(req: &HttpRequest) -> Option<StepUpClaims> {
+    let step_up_token = req.headers().get(StepUpService::HEADER).and_then(|h| h.to_str().ok())?;
+    verify_step_up_token(req, step_up_token)
+}
+
+/// Only tokens issued to the authenticated user for the access token of this request count
+pub fn verify_step_up_token(req: &HttpRequest, step_up_token: &str) -> Option<StepUpClaims> {
+    let current_user = get_current_user(req)?;
+    let secret = req.app_data::<actix_web::web::Data<String>>()?;
+    let access_token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| AuthUtils::extract_token_from_header(h).ok())?;
+
+    StepUpService::verify(step_up_token, current_user.sub, access_token, secret.get_ref())
+}
+
+/// 403 telling the client to step up at /api/auth/step-up with one of the listed methods
+pub async fn step_up_required(pool: Option<&PgPool>, user_id: i32, max_age_seconds: i64) -> HttpResponse {
+    let methods = match pool {
+        Some(pool) => StepUpService::available_methods(pool, user_id).await.unwrap_or_default(),
+        None => Vec::new(),
+    };
+
+    HttpResponse::Forbidden().json(serde_json::json!({
+        "error": "Please confirm it's you to continue",
+        "step_up_required": true,
+        "max_age": max_age_seconds,
+        "methods": methods
+    }))
+}
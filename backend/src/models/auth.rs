@@ -1,5 +1,7 @@
 use crate::models::user::UserResponse;
-use serde::{Deserialize, Serialize};
+use crate::services::wallet_chains::WalletChain;
+use crate::services::webauthn_service::AuthenticationCredential;
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
@@ -14,15 +16,30 @@ pub struct LoginResponse {
     pub refresh_token: Option<String>,
     pub user: UserResponse,
     pub requires_mfa: bool,
-    pub mfa_methods: Option<Vec<String>>, // ["totp", "email"]
+    pub mfa_methods: Option<Vec<String>>, // ["totp", "recovery_code", "passkey", "email"]
     pub temp_token: Option<String>, // Token untuk sementara untuk MFA verification
 }
 
 #[derive(Debug, Deserialize)]
 pub struct MFAVerifyRequest {
     pub temp_token: String,
-    pub method: String, // "totp" atau "email"
+    pub method: String, // "totp", "recovery_code", "email" atau "passkey"
+    #[serde(default)]
     pub code: String,
+    /// Assertion for method "passkey", from options issued by /passkeys/mfa/start
+    pub credential: Option<AuthenticationCredential>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefreshTokenResponse {
+    pub token: String,
+    pub refresh_token: String, // Rotated token, the old one is no longer valid
+    pub user: UserResponse,
 }
 
 #[derive(Debug, Deserialize)]
@@ -61,19 +78,70 @@ pub struct TOTPVerifyResponse {
 #[derive(Debug, Deserialize)]
 pub struct Web3ChallengeRequest {
     pub address: String,
-}
-
+    /// "ethereum" (default) or "solana"
+    #[serde(default)]
+    pub chain: WalletChain,
+    /// Network the wallet is connected to: an EIP-155 chain ID (11155111) or a Solana
+    /// cluster ("devnet"). Defaults to the first configured one
+    #[serde(default, deserialize_with = "deserialize_chain_id")]
+    pub chain_id: Option<String>,
+}
+
+// Chain IDs are numbers on Ethereum and names on Solana
+fn deserialize_chain_id<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ChainId {
+        Number(u64),
+        Name(String),
+    }
+
+    Ok(Option::<ChainId>::deserialize(deserializer)?.map(|id| match id {
+        ChainId::Number(id) => id.to_string(),
+        ChainId::Name(name) => name,
+    }))
+}
+
+// `challenge` is the EIP-4361 (Sign-In with Ethereum, or its Solana counterpart) message to
+// sign as is
 #[derive(Debug, Serialize)]
 pub struct Web3ChallengeResponse {
     pub challenge: String,
+    pub nonce: String,
+    pub chain: WalletChain,
     pub expires_at: u64,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Web3VerifyRequest {
+    pub address: String,
+    pub signature: String,
+    /// The signed sign-in message
+    pub challenge: String,
+}
+
+// Link a wallet to the signed-in account; challenge and signature as for `Web3VerifyRequest`
+#[derive(Debug, Deserialize)]
+pub struct ConnectWalletRequest {
     pub address: String,
     pub signature: String,
     pub challenge: String,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateWalletRequest {
+    pub label: Option<String>, // Empty string clears the label
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportCompromiseRequest {
+    pub event_id: Option<i32>, // The activity the user doesn't recognise
 }
 
 #[derive(Debug, Serialize)]
@@ -109,23 +177,7 @@ pub struct PasswordResetResponse {
     pub message: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct VerifyCodeRequest {
-    pub verification_code: String,
-    pub verification_method: String, // "totp" or "email"
-}
-
-#[derive(Debug, Serialize)]
-pub struct VerifyCodeResponse {
-    pub success: bool,
-    pub temp_token: String,
-    pub message: String,
-}
-
 #[derive(Debug, Deserialize)]
 pub struct ChangePasswordRequest {
-    pub verification_code: Option<String>,
-    pub verification_method: Option<String>, // "totp" or "email"
-    pub temp_token: Option<String>,
     pub new_password: String,
 }
@@ -1,5 +1,5 @@
 use chrono::NaiveDateTime;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::FromRow;
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -37,9 +37,20 @@ pub struct UpdateUser {
     pub email: Option<String>,
     pub password: Option<String>,
     pub current_password: Option<String>, // For confirming sensitive changes
-    pub verification_code: Option<String>, // For 2FA confirmation
     pub role: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
     pub wallet_address: Option<Option<String>>, // Option<Option<>> to allow setting to NULL
+    pub locale: Option<String>, // Preferred email language, e.g. "en" or "id"
+}
+
+// A present field becomes `Some`, even when it is null, so `Option<Option<T>>` can tell
+// "set to NULL" apart from "not sent"
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1,29 +1,96 @@
 use actix_web::web;
 
-use crate::auth::account::{add_email, connect_wallet, get_sessions, logout_all_sessions, logout_other_sessions, logout_session};
-use crate::auth::debug::{blacklist_stats, cleanup_blacklist, check_lockout_status, cleanup_unverified_accounts, get_unverified_accounts_stats, hash_password_debug};
-use crate::auth::email::{debug_codes, send_verification, verify_email, check_code_expiry, send_mfa_code, check_mfa_code_expiry};
+use crate::auth::account::{add_email, connect_wallet, get_activity, get_sessions, list_wallets, logout_all_sessions, logout_other_sessions, logout_session, remove_wallet, report_compromise, update_wallet};
+#[cfg(feature = "debug-endpoints")]
+use crate::auth::debug::{blacklist_stats, cleanup_blacklist, cleanup_unverified_accounts, get_unverified_accounts_stats, hash_password_debug};
+#[cfg(feature = "debug-endpoints")]
+use crate::auth::email::debug_codes;
+use crate::auth::email::{send_verification, verify_email, check_code_expiry, send_mfa_code, check_mfa_code_expiry};
 use crate::auth::google::google_callback;
-use crate::auth::password::{request_password_reset, reset_password, debug_password_reset_tokens, test_email_service, get_rate_limit_stats, change_password};
-use crate::auth::security::{setup_2fa, verify_2fa, debug_2fa, disable_2fa};
+use crate::auth::identities::{confirm_identity_link, link_google_identity, link_oidc_identity, list_identities, start_oidc_link, unlink_identity};
+use crate::auth::oidc::{list_oidc_providers, oidc_callback, oidc_start};
+#[cfg(feature = "debug-endpoints")]
+use crate::auth::password::{debug_password_reset_tokens, test_email_service, get_rate_limit_stats};
+use crate::auth::passkeys::{
+    finish_passkey_login, finish_passkey_registration, list_passkeys, remove_passkey, rename_passkey,
+    start_passkey_login, start_passkey_mfa, start_passkey_registration,
+};
+use crate::auth::password::{request_password_reset, reset_password, change_password};
+use crate::auth::passwordless::{passwordless_complete, passwordless_start};
+use crate::auth::personal_access_tokens::{create_access_token, list_access_tokens, revoke_access_token};
+use crate::auth::totp::{
+    add_authenticator, confirm_authenticator, list_authenticators, remove_authenticator, rename_authenticator,
+};
+use crate::auth::refresh_tokens::refresh_token;
+use crate::auth::security::{setup_2fa, verify_2fa, disable_2fa, regenerate_recovery_codes};
+#[cfg(feature = "debug-endpoints")]
+use crate::auth::security::debug_2fa;
+use crate::auth::step_up::{start_step_up, step_up, step_up_status};
 use crate::auth::traditional::{login, logout, me, register, verify_mfa};
 use crate::auth::web3::{web3_challenge, web3_verify};
-use crate::handlers::{post, user, interaction};
+use crate::handlers::{admin, ban_appeal, post, user, interaction};
 use crate::middleware::auth::AuthMiddleware;
 use crate::middleware::rate_limit_middleware::RateLimitMiddleware;
+use crate::middleware::step_up::StepUpMiddleware;
+use crate::services::permission_service as permissions;
+use crate::services::personal_access_token_service as tokens;
+use crate::services::step_up_service::StepUpService;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/api")
+            // Debug routes (admin only, must be registered before the /auth scope)
+            .configure(debug_routes)
             // Auth routes (public)
             .service(
                 web::scope("/auth")
                     .route("/login", web::post().to(login)
                         .wrap(RateLimitMiddleware::new("login", 5, 300)))
                     .route("/verify-mfa", web::post().to(verify_mfa))
+                    .route("/refresh", web::post().to(refresh_token)
+                        .wrap(RateLimitMiddleware::new("refresh", 30, 300)))
                     .route("/register", web::post().to(register)
                         .wrap(RateLimitMiddleware::new("register", 3, 600)))
+                    // Passwordless sign-in by emailed link or code
+                    .route("/passwordless/start", web::post().to(passwordless_start)
+                        .wrap(RateLimitMiddleware::new("passwordless-start", 5, 300)))
+                    .route("/passwordless/complete", web::post().to(passwordless_complete)
+                        .wrap(RateLimitMiddleware::new("passwordless-complete", 10, 300)))
                     .route("/google/callback", web::post().to(google_callback))
+                    .route("/oidc/providers", web::get().to(list_oidc_providers))
+                    .route("/oidc/{provider}/start", web::get().to(oidc_start)
+                        .wrap(RateLimitMiddleware::new("oidc-start", 20, 300)))
+                    .route("/oidc/{provider}/callback", web::post().to(oidc_callback)
+                        .wrap(RateLimitMiddleware::new("oidc-callback", 20, 300)))
+                    // Linked sign-in methods (Google, OIDC providers)
+                    .route("/identities", web::get().to(list_identities).wrap(AuthMiddleware::new()))
+                    .route("/identities/confirm-link", web::post().to(confirm_identity_link)
+                        .wrap(RateLimitMiddleware::new("identity-confirm-link", 10, 300)))
+                    .route("/identities/google", web::post().to(link_google_identity)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
+                    .route("/identities/oidc/{provider}/start", web::post().to(start_oidc_link)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
+                    .route("/identities/oidc/{provider}", web::post().to(link_oidc_identity).wrap(AuthMiddleware::new()))
+                    .route("/identities/{id}", web::delete().to(unlink_identity)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
+                    // Passkeys (WebAuthn): registration, passwordless sign-in and second factor
+                    .route("/passkeys", web::get().to(list_passkeys).wrap(AuthMiddleware::new()))
+                    .route("/passkeys/register/start", web::post().to(start_passkey_registration)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
+                    .route("/passkeys/register/finish", web::post().to(finish_passkey_registration)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
+                    .route("/passkeys/login/start", web::post().to(start_passkey_login))
+                    .route("/passkeys/login/finish", web::post().to(finish_passkey_login))
+                    .route("/passkeys/mfa/start", web::post().to(start_passkey_mfa))
+                    .route("/passkeys/{id}", web::put().to(rename_passkey).wrap(AuthMiddleware::new()))
+                    .route("/passkeys/{id}", web::delete().to(remove_passkey)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
                     .route(
                         "/setup-2fa",
                         web::post().to(setup_2fa).wrap(AuthMiddleware::new()),
@@ -33,22 +100,51 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                         web::post().to(verify_2fa).wrap(AuthMiddleware::new()),
                     )
                     .route(
-                        "/debug-2fa",
-                        web::get().to(debug_2fa).wrap(AuthMiddleware::new()),
+                        "/disable-2fa",
+                        web::post()
+                            .to(disable_2fa)
+                            .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                            .wrap(AuthMiddleware::new()),
                     )
                     .route(
-                        "/disable-2fa",
-                        web::post().to(disable_2fa).wrap(AuthMiddleware::new()),
+                        "/recovery-codes/regenerate",
+                        web::post()
+                            .to(regenerate_recovery_codes)
+                            .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                            .wrap(AuthMiddleware::new()),
                     )
+                    // Additional authenticator apps, once 2FA is set up
+                    .route("/totp/authenticators", web::get().to(list_authenticators).wrap(AuthMiddleware::new()))
+                    .route("/totp/authenticators", web::post().to(add_authenticator)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
+                    .route("/totp/authenticators/{id}/confirm", web::post().to(confirm_authenticator)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
+                    .route("/totp/authenticators/{id}", web::put().to(rename_authenticator).wrap(AuthMiddleware::new()))
+                    .route("/totp/authenticators/{id}", web::delete().to(remove_authenticator)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
                     .route("/web3/challenge", web::post().to(web3_challenge))
                     .route("/web3/verify", web::post().to(web3_verify))
                     .route(
                         "/connect-wallet",
-                        web::post().to(connect_wallet).wrap(AuthMiddleware::new()),
+                        web::post()
+                            .to(connect_wallet)
+                            .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                            .wrap(AuthMiddleware::new()),
                     )
+                    .route("/wallets", web::get().to(list_wallets).wrap(AuthMiddleware::new()))
+                    .route("/wallets/{id}", web::put().to(update_wallet).wrap(AuthMiddleware::new()))
+                    .route("/wallets/{id}", web::delete().to(remove_wallet)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
                     .route(
                         "/add-email",
-                        web::post().to(add_email).wrap(AuthMiddleware::new()),
+                        web::post()
+                            .to(add_email)
+                            .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                            .wrap(AuthMiddleware::new()),
                     )
                     .route(
                         "/email/send-verification",
@@ -57,7 +153,6 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                     )
                     .route("/email/verify", web::post().to(verify_email)
                         .wrap(RateLimitMiddleware::new("email-verify", 10, 600)))
-                    .route("/email/debug-codes", web::get().to(debug_codes))
                     .route("/email/check-expiry", web::post().to(check_code_expiry))
                     .route("/email/send-mfa-code", web::post().to(send_mfa_code))
                     .route("/email/check-mfa-expiry", web::post().to(check_mfa_code_expiry))
@@ -65,28 +160,45 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                         "/logout",
                         web::post().to(logout).wrap(AuthMiddleware::new()),
                     )
-                    .route("/me", web::get().to(me).wrap(AuthMiddleware::new()))
+                    .route("/me", web::get().to(me).wrap(AuthMiddleware::new().token_scope(tokens::PROFILE_READ)))
                     .route("/password/request-reset", web::post().to(request_password_reset)
                         .wrap(RateLimitMiddleware::new("password-reset-request", 3, 900)))
                     .route("/password/reset", web::post().to(reset_password)
                         .wrap(RateLimitMiddleware::new("password-reset", 5, 600)))
-                    .route("/password/verify-code", web::post().to(crate::auth::password::verify_password_change_code).wrap(AuthMiddleware::new()))
-                    .route("/password/change", web::post().to(change_password).wrap(AuthMiddleware::new()))
-                    .route("/password/debug-tokens", web::get().to(debug_password_reset_tokens))
-                    .route("/password/test-email", web::get().to(test_email_service))
-                    .route("/rate-limit-stats", web::get().to(get_rate_limit_stats))
-                    .route("/debug/blacklist/stats", web::get().to(blacklist_stats))
-                    .route("/debug/blacklist/cleanup", web::post().to(cleanup_blacklist))
-                    .route("/debug/cleanup/unverified-accounts", web::post().to(cleanup_unverified_accounts))
-                    .route("/debug/cleanup/unverified-stats", web::get().to(get_unverified_accounts_stats))
-                    .route("/debug/hash-password", web::post().to(hash_password_debug))
+                    // Step-up re-authentication for sensitive operations
+                    .route("/step-up", web::get().to(step_up_status).wrap(AuthMiddleware::new()))
+                    .route("/step-up", web::post().to(step_up)
+                        .wrap(RateLimitMiddleware::new("step-up", 10, 300))
+                        .wrap(AuthMiddleware::new()))
+                    .route("/step-up/start", web::post().to(start_step_up)
+                        .wrap(RateLimitMiddleware::new("step-up-start", 5, 300))
+                        .wrap(AuthMiddleware::new()))
+                    .route("/password/change", web::post().to(change_password)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
+                    // Personal access tokens for scripts and bots
+                    .route("/tokens", web::get().to(list_access_tokens).wrap(AuthMiddleware::new()))
+                    .route("/tokens", web::post().to(create_access_token)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
+                    .route("/tokens/{id}", web::delete().to(revoke_access_token)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new()))
                     // Session management endpoints
                     .route("/sessions", web::get().to(get_sessions).wrap(AuthMiddleware::new()))
                     .route("/sessions/{id}", web::delete().to(logout_session).wrap(AuthMiddleware::new()))
                     .route("/sessions/logout-all", web::post().to(logout_all_sessions).wrap(AuthMiddleware::new()))
                     .route("/sessions/logout-others", web::post().to(logout_other_sessions).wrap(AuthMiddleware::new()))
+                    // Account security activity
+                    .route("/activity", web::get().to(get_activity).wrap(AuthMiddleware::new()))
+                    .route("/activity/not-me", web::post().to(report_compromise).wrap(AuthMiddleware::new()))
+                    // Ban status and appeals for the current user
+                    .route("/ban", web::get().to(ban_appeal::get_own_ban).wrap(AuthMiddleware::new()))
+                    .route("/ban/appeal", web::post().to(ban_appeal::submit_appeal).wrap(AuthMiddleware::new()))
                     // Profile update endpoint (user can update their own profile)
-                    .route("/profile", web::put().to(crate::handlers::user_profile::update_own_profile).wrap(AuthMiddleware::new())),
+                    .route("/profile", web::put().to(crate::handlers::user_profile::update_own_profile)
+                        .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                        .wrap(AuthMiddleware::new())),
             )
             // User search (authenticated users only - like Instagram search)
             .service(
@@ -94,21 +206,46 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                     .wrap(AuthMiddleware::new())
                     .route("", web::get().to(user::search_users_public)),
             )
-            // User management routes (admin only)
+            // User management routes (staff, per-permission)
             .service(
                 web::scope("/users")
-                    .wrap(AuthMiddleware::require_role("admin"))
-                    .route("", web::get().to(user::get_users))
-                    .route("", web::post().to(user::create_user))
-                    .route("/{id}", web::get().to(user::get_user))
-                    .route("/{id}", web::put().to(user::update_user))
-                    .route("/{id}/ban", web::put().to(user::ban_user))
-                    .route("/{id}", web::delete().to(user::delete_user)),
+                    .route("", web::get().to(user::get_users)
+                        .wrap(AuthMiddleware::require_permission(permissions::USERS_READ).token_scope(tokens::ADMIN_USERS)))
+                    .route("", web::post().to(user::create_user)
+                        .wrap(AuthMiddleware::require_permission(permissions::USERS_CREATE).token_scope(tokens::ADMIN_USERS)))
+                    .route("/{id}", web::get().to(user::get_user)
+                        .wrap(AuthMiddleware::require_permission(permissions::USERS_READ).token_scope(tokens::ADMIN_USERS)))
+                    .route("/{id}", web::put().to(user::update_user)
+                        .wrap(AuthMiddleware::require_permission(permissions::USERS_UPDATE).token_scope(tokens::ADMIN_USERS)))
+                    .route("/{id}/ban", web::put().to(user::ban_user)
+                        .wrap(AuthMiddleware::require_permission(permissions::USERS_BAN).token_scope(tokens::ADMIN_USERS)))
+                    .route("/{id}/bans", web::get().to(user::get_user_bans)
+                        .wrap(AuthMiddleware::require_permission(permissions::USERS_BAN).token_scope(tokens::ADMIN_USERS)))
+                    .route("/{id}", web::delete().to(user::delete_user)
+                        .wrap(AuthMiddleware::require_permission(permissions::USERS_DELETE).token_scope(tokens::ADMIN_USERS))),
+            )
+            // Admin tools (staff, per-permission)
+            .service(
+                web::scope("/admin")
+                    .route("/email-outbox", web::get().to(admin::list_email_outbox)
+                        .wrap(AuthMiddleware::require_permission(permissions::EMAIL_OUTBOX_MANAGE)))
+                    .route("/email-outbox/{id}", web::get().to(admin::get_email_outbox)
+                        .wrap(AuthMiddleware::require_permission(permissions::EMAIL_OUTBOX_MANAGE)))
+                    .route("/email-outbox/{id}/retry", web::post().to(admin::retry_email_outbox)
+                        .wrap(AuthMiddleware::require_permission(permissions::EMAIL_OUTBOX_MANAGE)))
+                    .route("/audit-logs", web::get().to(admin::search_audit_logs)
+                        .wrap(AuthMiddleware::require_permission(permissions::AUDIT_READ)))
+                    .route("/audit-logs/export", web::get().to(admin::export_audit_logs)
+                        .wrap(AuthMiddleware::require_permission(permissions::AUDIT_READ)))
+                    .route("/ban-appeals", web::get().to(admin::list_ban_appeals)
+                        .wrap(AuthMiddleware::require_permission(permissions::BAN_APPEALS_REVIEW)))
+                    .route("/ban-appeals/{id}/review", web::post().to(admin::review_ban_appeal)
+                        .wrap(AuthMiddleware::require_permission(permissions::BAN_APPEALS_REVIEW))),
             )
             // Post routes (authenticated users)
             .service(
                 web::scope("/posts")
-                    .wrap(AuthMiddleware::new())
+                    .wrap(AuthMiddleware::new().token_scope("posts"))
                     .route("/feed", web::get().to(post::get_all_posts))
                     .route("/search", web::get().to(post::search_posts))
                     .route("", web::get().to(post::get_posts))
@@ -130,6 +267,65 @@ pub fn config(cfg: &mut web::ServiceConfig) {
     );
 }
 
+/// Debug/maintenance endpoints, only compiled with the `debug-endpoints` feature
+#[cfg(feature = "debug-endpoints")]
+fn debug_routes(cfg: &mut web::ServiceConfig) {
+    // Full paths as resources so they don't shadow the /auth scope
+    cfg.service(
+        web::resource("/auth/email/debug-codes")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::get().to(debug_codes)),
+    );
+    cfg.service(
+        web::resource("/auth/password/debug-tokens")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::get().to(debug_password_reset_tokens)),
+    );
+    cfg.service(
+        web::resource("/auth/password/test-email")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::get().to(test_email_service)),
+    );
+    cfg.service(
+        web::resource("/auth/rate-limit-stats")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::get().to(get_rate_limit_stats)),
+    );
+    cfg.service(
+        web::resource("/auth/debug/blacklist/stats")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::get().to(blacklist_stats)),
+    );
+    cfg.service(
+        web::resource("/auth/debug/blacklist/cleanup")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::post().to(cleanup_blacklist)),
+    );
+    cfg.service(
+        web::resource("/auth/debug/cleanup/unverified-accounts")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::post().to(cleanup_unverified_accounts)),
+    );
+    cfg.service(
+        web::resource("/auth/debug/cleanup/unverified-stats")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::get().to(get_unverified_accounts_stats)),
+    );
+    cfg.service(
+        web::resource("/auth/debug/hash-password")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::post().to(hash_password_debug)),
+    );
+    cfg.service(
+        web::resource("/auth/debug-2fa")
+            .wrap(AuthMiddleware::require_role("admin"))
+            .route(web::get().to(debug_2fa)),
+    );
+}
+
+#[cfg(not(feature = "debug-endpoints"))]
+fn debug_routes(_cfg: &mut web::ServiceConfig) {}
+
 async fn health_check() -> actix_web::Result<actix_web::HttpResponse> {
     Ok(actix_web::HttpResponse::Ok().json(serde_json::json!({
         "status": "healthy",
@@ -1,10 +1,156 @@
 use sqlx::PgPool;
+use serde::Serialize;
 use serde_json::{json, Value};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 
 #[derive(Clone, Debug)]
 pub struct AuditLogger;
 
+/// Full audit_logs row, as returned by the admin search API
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditLogEntry {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub event_type: String,
+    pub event_action: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub status: String,
+    pub details: Option<Value>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+/// What a security event means for the account owner, derived from `event_type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityEventKind {
+    Login,
+    FailedLogin,
+    Logout,
+    AccountLocked,
+    PasswordChanged,
+    PasswordReset,
+    PasswordResetRequested,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    SessionRevoked,
+    RefreshTokenReuse,
+    CompromiseReported,
+    IdentityLinked,
+    IdentityUnlinked,
+    PasskeyAdded,
+    PasskeyRemoved,
+    AuthenticatorAdded,
+    AuthenticatorRemoved,
+    RecoveryCodeUsed,
+    RecoveryCodesRegenerated,
+    StepUp,
+    AccessTokenCreated,
+    AccessTokenRevoked,
+    Other,
+}
+
+impl SecurityEventKind {
+    pub fn from_event_type(event_type: &str) -> Self {
+        match event_type {
+            AuditLogger::EVENT_LOGIN => Self::Login,
+            AuditLogger::EVENT_FAILED_LOGIN => Self::FailedLogin,
+            AuditLogger::EVENT_LOGOUT => Self::Logout,
+            AuditLogger::EVENT_ACCOUNT_LOCKOUT => Self::AccountLocked,
+            AuditLogger::EVENT_PASSWORD_CHANGE => Self::PasswordChanged,
+            AuditLogger::EVENT_PASSWORD_RESET => Self::PasswordReset,
+            AuditLogger::EVENT_PASSWORD_RESET_REQUEST => Self::PasswordResetRequested,
+            AuditLogger::EVENT_2FA_ENABLED => Self::TwoFactorEnabled,
+            AuditLogger::EVENT_2FA_DISABLED => Self::TwoFactorDisabled,
+            AuditLogger::EVENT_SESSION_REVOKED => Self::SessionRevoked,
+            AuditLogger::EVENT_REFRESH_TOKEN_REUSE => Self::RefreshTokenReuse,
+            AuditLogger::EVENT_COMPROMISE_REPORTED => Self::CompromiseReported,
+            AuditLogger::EVENT_IDENTITY_LINKED => Self::IdentityLinked,
+            AuditLogger::EVENT_IDENTITY_UNLINKED => Self::IdentityUnlinked,
+            AuditLogger::EVENT_PASSKEY_ADDED => Self::PasskeyAdded,
+            AuditLogger::EVENT_PASSKEY_REMOVED => Self::PasskeyRemoved,
+            AuditLogger::EVENT_AUTHENTICATOR_ADDED => Self::AuthenticatorAdded,
+            AuditLogger::EVENT_AUTHENTICATOR_REMOVED => Self::AuthenticatorRemoved,
+            AuditLogger::EVENT_RECOVERY_CODE_USED => Self::RecoveryCodeUsed,
+            AuditLogger::EVENT_RECOVERY_CODES_REGENERATED => Self::RecoveryCodesRegenerated,
+            AuditLogger::EVENT_STEP_UP => Self::StepUp,
+            AuditLogger::EVENT_ACCESS_TOKEN_CREATED => Self::AccessTokenCreated,
+            AuditLogger::EVENT_ACCESS_TOKEN_REVOKED => Self::AccessTokenRevoked,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// One entry of a user's own security activity (see `AuditLogger::get_user_audit_trail`)
+#[derive(Debug, Clone, Serialize)]
+pub struct SecurityEvent {
+    pub id: i32,
+    pub kind: SecurityEventKind,
+    pub event_type: String,
+    pub description: String,
+    pub status: String,
+    pub ip_address: Option<String>,
+    pub device: Option<String>, // e.g. "Firefox on Windows (Desktop)"
+    pub user_agent: Option<String>,
+    pub details: Option<Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Short human readable device label from a User-Agent header
+pub fn describe_device(user_agent: &str) -> String {
+    // Order matters: Edge and Opera also claim Chrome, Chrome also claims Safari
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+        ("curl/", "curl"),
+    ]
+    .iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map(|(_, name)| *name);
+
+    let os = [
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ]
+    .iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map(|(_, name)| *name);
+
+    let form_factor = if user_agent.contains("Tablet") || user_agent.contains("iPad") {
+        "Tablet"
+    } else if user_agent.contains("Mobile") {
+        "Mobile"
+    } else {
+        "Desktop"
+    };
+
+    match (browser, os) {
+        (Some(browser), Some(os)) => format!("{} on {} ({})", browser, os, form_factor),
+        (Some(name), None) | (None, Some(name)) => format!("{} ({})", name, form_factor),
+        (None, None) => form_factor.to_string(),
+    }
+}
+
+/// Search filters, every field is optional. Results are newest first by id
+#[derive(Debug, Clone, Default)]
+pub struct AuditLogFilter {
+    pub user_id: Option<i32>,
+    pub event_types: Option<Vec<String>>,
+    pub status: Option<String>,
+    pub ip_address: Option<String>,
+    pub from: Option<NaiveDateTime>, // Inclusive
+    pub to: Option<NaiveDateTime>,   // Exclusive
+    pub before_id: Option<i32>,      // Keyset cursor: only rows with a smaller id
+}
+
 impl AuditLogger {
     /// Event types
     pub const EVENT_LOGIN: &'static str = "LOGIN";
@@ -16,6 +162,52 @@ impl AuditLogger {
     pub const EVENT_ACCOUNT_LOCKOUT: &'static str = "ACCOUNT_LOCKOUT";
     pub const EVENT_TOKEN_BLACKLIST: &'static str = "TOKEN_BLACKLIST";
     pub const EVENT_FAILED_LOGIN: &'static str = "FAILED_LOGIN";
+    pub const EVENT_TOKEN_REFRESH: &'static str = "TOKEN_REFRESH";
+    pub const EVENT_REFRESH_TOKEN_REUSE: &'static str = "REFRESH_TOKEN_REUSE";
+    pub const EVENT_AUDIT_EXPORT: &'static str = "AUDIT_EXPORT";
+    pub const EVENT_PASSWORD_CHANGE: &'static str = "PASSWORD_CHANGE";
+    pub const EVENT_2FA_ENABLED: &'static str = "2FA_ENABLED";
+    pub const EVENT_2FA_DISABLED: &'static str = "2FA_DISABLED";
+    pub const EVENT_SESSION_REVOKED: &'static str = "SESSION_REVOKED";
+    pub const EVENT_COMPROMISE_REPORTED: &'static str = "COMPROMISE_REPORTED";
+    pub const EVENT_IDENTITY_LINKED: &'static str = "IDENTITY_LINKED";
+    pub const EVENT_IDENTITY_UNLINKED: &'static str = "IDENTITY_UNLINKED";
+    pub const EVENT_PASSKEY_ADDED: &'static str = "PASSKEY_ADDED";
+    pub const EVENT_PASSKEY_REMOVED: &'static str = "PASSKEY_REMOVED";
+    pub const EVENT_AUTHENTICATOR_ADDED: &'static str = "AUTHENTICATOR_ADDED";
+    pub const EVENT_AUTHENTICATOR_REMOVED: &'static str = "AUTHENTICATOR_REMOVED";
+    pub const EVENT_RECOVERY_CODE_USED: &'static str = "RECOVERY_CODE_USED";
+    pub const EVENT_RECOVERY_CODES_REGENERATED: &'static str = "RECOVERY_CODES_REGENERATED";
+    pub const EVENT_STEP_UP: &'static str = "STEP_UP";
+    pub const EVENT_ACCESS_TOKEN_CREATED: &'static str = "ACCESS_TOKEN_CREATED";
+    pub const EVENT_ACCESS_TOKEN_REVOKED: &'static str = "ACCESS_TOKEN_REVOKED";
+
+    /// Events shown to users in their own account activity (token refreshes etc. are noise there)
+    pub const ACTIVITY_EVENTS: &'static [&'static str] = &[
+        Self::EVENT_LOGIN,
+        Self::EVENT_FAILED_LOGIN,
+        Self::EVENT_LOGOUT,
+        Self::EVENT_ACCOUNT_LOCKOUT,
+        Self::EVENT_PASSWORD_CHANGE,
+        Self::EVENT_PASSWORD_RESET,
+        Self::EVENT_PASSWORD_RESET_REQUEST,
+        Self::EVENT_2FA_ENABLED,
+        Self::EVENT_2FA_DISABLED,
+        Self::EVENT_SESSION_REVOKED,
+        Self::EVENT_REFRESH_TOKEN_REUSE,
+        Self::EVENT_COMPROMISE_REPORTED,
+        Self::EVENT_IDENTITY_LINKED,
+        Self::EVENT_IDENTITY_UNLINKED,
+        Self::EVENT_PASSKEY_ADDED,
+        Self::EVENT_PASSKEY_REMOVED,
+        Self::EVENT_AUTHENTICATOR_ADDED,
+        Self::EVENT_AUTHENTICATOR_REMOVED,
+        Self::EVENT_RECOVERY_CODE_USED,
+        Self::EVENT_RECOVERY_CODES_REGENERATED,
+        Self::EVENT_STEP_UP,
+        Self::EVENT_ACCESS_TOKEN_CREATED,
+        Self::EVENT_ACCESS_TOKEN_REVOKED,
+    ];
 
     /// Status types
     pub const STATUS_SUCCESS: &'static str = "success";
@@ -173,19 +365,26 @@ impl AuditLogger {
         .await
     }
 
-    /// Get user's audit trail
+    /// Get user's security activity, newest first (pass the last id of a page as `before_id`)
     pub async fn get_user_audit_trail(
         pool: &PgPool,
         user_id: i32,
+        before_id: Option<i32>,
         limit: i64,
-    ) -> Result<Vec<(String, String, String, Option<String>, DateTime<Utc>)>, sqlx::Error> {
+    ) -> Result<Vec<SecurityEvent>, sqlx::Error> {
+        let event_types: Vec<String> = Self::ACTIVITY_EVENTS.iter().map(|e| e.to_string()).collect();
+
         let records = sqlx::query!(
-            "SELECT event_type, event_action, status, details, created_at 
-             FROM audit_logs 
-             WHERE user_id = $1 
-             ORDER BY created_at DESC 
-             LIMIT $2",
+            "SELECT id, event_type, event_action, ip_address, user_agent, status, details, created_at
+             FROM audit_logs
+             WHERE user_id = $1
+               AND event_type = ANY($2)
+               AND ($3::INT IS NULL OR id < $3)
+             ORDER BY id DESC
+             LIMIT $4",
             user_id,
+            &event_types,
+            before_id,
             limit
         )
         .fetch_all(pool)
@@ -194,23 +393,27 @@ impl AuditLogger {
         Ok(records
             .into_iter()
             .map(|r| {
-                let created_at = r.created_at.unwrap_or_else(|| chrono::NaiveDateTime::from_timestamp_opt(0, 0).unwrap());
-                (
-                    r.event_type,
-                    r.event_action,
-                    r.status,
-                    r.details.map(|d| d.to_string()),
-                    DateTime::<Utc>::from_naive_utc_and_offset(created_at, Utc),
-                )
+                let created_at = r.created_at.unwrap_or_default();
+                SecurityEvent {
+                    id: r.id,
+                    kind: SecurityEventKind::from_event_type(&r.event_type),
+                    description: r.event_action,
+                    status: r.status,
+                    device: r.user_agent.as_deref().map(describe_device),
+                    ip_address: r.ip_address,
+                    user_agent: r.user_agent,
+                    details: r.details,
+                    created_at: DateTime::<Utc>::from_naive_utc_and_offset(created_at, Utc),
+                    event_type: r.event_type,
+                }
             })
             .collect())
     }
 
-    /// Get audit logs for specific event type
-    pub async fn get_logs_by_event(
+    /// Count audit logs for a specific event type
+    pub async fn count_logs_by_event(
         pool: &PgPool,
         event_type: &str,
-        limit: i64,
     ) -> Result<i64, sqlx::Error> {
         let result = sqlx::query!("SELECT COUNT(*) as count FROM audit_logs WHERE event_type = $1", event_type)
             .fetch_one(pool)
@@ -218,4 +421,36 @@ impl AuditLogger {
 
         Ok(result.count.unwrap_or(0))
     }
+
+    /// Search audit logs with keyset pagination (pass the last id of a page as `before_id`)
+    pub async fn search(
+        pool: &PgPool,
+        filter: &AuditLogFilter,
+        limit: i64,
+    ) -> Result<Vec<AuditLogEntry>, sqlx::Error> {
+        sqlx::query_as!(
+            AuditLogEntry,
+            "SELECT id, user_id, event_type, event_action, ip_address, user_agent, status, details, created_at
+             FROM audit_logs
+             WHERE ($1::INT IS NULL OR user_id = $1)
+               AND ($2::VARCHAR[] IS NULL OR event_type = ANY($2))
+               AND ($3::VARCHAR IS NULL OR status = $3)
+               AND ($4::VARCHAR IS NULL OR ip_address = $4)
+               AND ($5::TIMESTAMP IS NULL OR created_at >= $5)
+               AND ($6::TIMESTAMP IS NULL OR created_at < $6)
+               AND ($7::INT IS NULL OR id < $7)
+             ORDER BY id DESC
+             LIMIT $8",
+            filter.user_id,
+            filter.event_types.as_deref(),
+            filter.status,
+            filter.ip_address,
+            filter.from,
+            filter.to,
+            filter.before_id,
+            limit
+        )
+        .fetch_all(pool)
+        .await
+    }
 }
@@ -0,0 +1,364 @@
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, Transaction};
+
+pub const APPEAL_PENDING: &str = "pending";
+pub const APPEAL_APPROVED: &str = "approved";
+pub const APPEAL_REJECTED: &str = "rejected";
+
+/// One row of a user's ban history
+#[derive(Debug, Clone, Serialize)]
+pub struct UserBan {
+    pub id: i32,
+    pub user_id: i32,
+    pub moderator_id: Option<i32>,
+    pub reason: String,
+    pub banned_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>, // None = permanent
+    pub lifted_at: Option<NaiveDateTime>,
+    pub lifted_by: Option<i32>,
+    pub lift_reason: Option<String>,
+}
+
+impl UserBan {
+    /// Error body for actions refused because of this ban
+    pub fn forbidden_json(&self, message: &str) -> serde_json::Value {
+        serde_json::json!({
+            "error": message,
+            "banned": true,
+            "ban": {
+                "id": self.id,
+                "reason": self.reason,
+                "banned_until": self.expires_at,
+                "permanent": self.expires_at.is_none()
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BanAppeal {
+    pub id: i32,
+    pub ban_id: i32,
+    pub user_id: i32,
+    pub message: String,
+    pub status: String,
+    pub reviewed_by: Option<i32>,
+    pub review_note: Option<String>,
+    pub reviewed_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Appeal with the ban it is about, for the admin review queue
+#[derive(Debug, Clone, Serialize)]
+pub struct BanAppealSummary {
+    pub id: i32,
+    pub ban_id: i32,
+    pub user_id: i32,
+    pub username: String,
+    pub message: String,
+    pub status: String,
+    pub ban_reason: String,
+    pub banned_at: NaiveDateTime,
+    pub ban_expires_at: Option<NaiveDateTime>,
+    pub ban_lifted_at: Option<NaiveDateTime>,
+    pub reviewed_by: Option<i32>,
+    pub review_note: Option<String>,
+    pub reviewed_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug)]
+pub struct BanService;
+
+impl BanService {
+    /// The ban currently in force, if any. Expired bans never count, even before the
+    /// scheduled job has lifted them
+    pub async fn active_ban(pool: &PgPool, user_id: i32) -> Result<Option<UserBan>, sqlx::Error> {
+        sqlx::query_as!(
+            UserBan,
+            "SELECT id, user_id, moderator_id, reason, banned_at, expires_at, lifted_at, lifted_by, lift_reason
+             FROM user_bans
+             WHERE user_id = $1 AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > $2)
+             ORDER BY id DESC
+             LIMIT 1",
+            user_id,
+            Utc::now().naive_utc()
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Ban a user, replacing any ban already in force
+    pub async fn ban(
+        pool: &PgPool,
+        user_id: i32,
+        moderator_id: Option<i32>,
+        reason: &str,
+        expires_at: Option<NaiveDateTime>,
+    ) -> Result<UserBan, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!(
+            "UPDATE user_bans SET lifted_at = $2, lifted_by = $3, lift_reason = 'Replaced by a new ban'
+             WHERE user_id = $1 AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > $2)",
+            user_id,
+            now,
+            moderator_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let ban = sqlx::query_as!(
+            UserBan,
+            "INSERT INTO user_bans (user_id, moderator_id, reason, banned_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING id, user_id, moderator_id, reason, banned_at, expires_at, lifted_at, lifted_by, lift_reason",
+            user_id,
+            moderator_id,
+            reason,
+            now,
+            expires_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Self::sync_user(&mut tx, user_id, now).await?;
+        tx.commit().await?;
+
+        Ok(ban)
+    }
+
+    /// Lift every ban in force for the user, returns how many were lifted
+    pub async fn lift(
+        pool: &PgPool,
+        user_id: i32,
+        lifted_by: Option<i32>,
+        reason: &str,
+    ) -> Result<u64, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+        let mut tx = pool.begin().await?;
+
+        let lifted = sqlx::query!(
+            "UPDATE user_bans SET lifted_at = $2, lifted_by = $3, lift_reason = $4
+             WHERE user_id = $1 AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > $2)",
+            user_id,
+            now,
+            lifted_by,
+            reason
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        Self::sync_user(&mut tx, user_id, now).await?;
+        tx.commit().await?;
+
+        Ok(lifted)
+    }
+
+    /// Full ban history, newest first
+    pub async fn history(pool: &PgPool, user_id: i32) -> Result<Vec<UserBan>, sqlx::Error> {
+        sqlx::query_as!(
+            UserBan,
+            "SELECT id, user_id, moderator_id, reason, banned_at, expires_at, lifted_at, lifted_by, lift_reason
+             FROM user_bans
+             WHERE user_id = $1
+             ORDER BY id DESC",
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Lift bans past their expiry (run by the scheduled tasks), returns how many were lifted
+    pub async fn expire_bans(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+        let mut tx = pool.begin().await?;
+
+        let mut user_ids = sqlx::query_scalar!(
+            "UPDATE user_bans SET lifted_at = expires_at, lift_reason = 'expired'
+             WHERE lifted_at IS NULL AND expires_at IS NOT NULL AND expires_at <= $1
+             RETURNING user_id",
+            now
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let expired = user_ids.len() as u64;
+        user_ids.sort_unstable();
+        user_ids.dedup();
+        for user_id in user_ids {
+            Self::sync_user(&mut tx, user_id, now).await?;
+        }
+
+        tx.commit().await?;
+        Ok(expired)
+    }
+
+    // Mirror the ban in force onto users.is_banned / banned_until, which the rest of the app displays
+    async fn sync_user(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: i32,
+        now: NaiveDateTime,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE users SET
+                 is_banned = active.id IS NOT NULL,
+                 banned_until = active.expires_at,
+                 updated_at = NOW()
+             FROM (SELECT $1::INT AS user_id) target
+             LEFT JOIN LATERAL (
+                 SELECT id, expires_at FROM user_bans
+                 WHERE user_id = target.user_id AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > $2)
+                 ORDER BY id DESC
+                 LIMIT 1
+             ) active ON true
+             WHERE users.id = target.user_id",
+            user_id,
+            now
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn pending_appeal(pool: &PgPool, ban_id: i32) -> Result<Option<BanAppeal>, sqlx::Error> {
+        sqlx::query_as!(
+            BanAppeal,
+            "SELECT id, ban_id, user_id, message, status, reviewed_by, review_note, reviewed_at, created_at
+             FROM ban_appeals
+             WHERE ban_id = $1 AND status = 'pending'",
+            ban_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn submit_appeal(
+        pool: &PgPool,
+        ban_id: i32,
+        user_id: i32,
+        message: &str,
+    ) -> Result<BanAppeal, sqlx::Error> {
+        sqlx::query_as!(
+            BanAppeal,
+            "INSERT INTO ban_appeals (ban_id, user_id, message, created_at)
+             VALUES ($1, $2, $3, $4)
+             RETURNING id, ban_id, user_id, message, status, reviewed_by, review_note, reviewed_at, created_at",
+            ban_id,
+            user_id,
+            message,
+            Utc::now().naive_utc()
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    /// The user's own appeals, newest first
+    pub async fn appeals_for_user(pool: &PgPool, user_id: i32) -> Result<Vec<BanAppeal>, sqlx::Error> {
+        sqlx::query_as!(
+            BanAppeal,
+            "SELECT id, ban_id, user_id, message, status, reviewed_by, review_note, reviewed_at, created_at
+             FROM ban_appeals
+             WHERE user_id = $1
+             ORDER BY id DESC",
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Review queue, newest first, optionally filtered by status
+    pub async fn list_appeals(
+        pool: &PgPool,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<BanAppealSummary>, sqlx::Error> {
+        sqlx::query_as!(
+            BanAppealSummary,
+            "SELECT a.id, a.ban_id, a.user_id, u.username, a.message, a.status,
+                    b.reason AS ban_reason, b.banned_at, b.expires_at AS ban_expires_at, b.lifted_at AS ban_lifted_at,
+                    a.reviewed_by, a.review_note, a.reviewed_at, a.created_at
+             FROM ban_appeals a
+             JOIN user_bans b ON b.id = a.ban_id
+             JOIN users u ON u.id = a.user_id
+             WHERE ($1::VARCHAR IS NULL OR a.status = $1)
+             ORDER BY a.id DESC
+             LIMIT $2 OFFSET $3",
+            status,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn find_appeal(pool: &PgPool, id: i32) -> Result<Option<BanAppeal>, sqlx::Error> {
+        sqlx::query_as!(
+            BanAppeal,
+            "SELECT id, ban_id, user_id, message, status, reviewed_by, review_note, reviewed_at, created_at
+             FROM ban_appeals
+             WHERE id = $1",
+            id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Approve or reject a pending appeal. Approving lifts the appealed ban.
+    /// Returns None if the appeal doesn't exist or was already reviewed
+    pub async fn review_appeal(
+        pool: &PgPool,
+        appeal_id: i32,
+        reviewer_id: i32,
+        approve: bool,
+        note: Option<&str>,
+    ) -> Result<Option<BanAppeal>, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+        let status = if approve { APPEAL_APPROVED } else { APPEAL_REJECTED };
+        let mut tx = pool.begin().await?;
+
+        let appeal = sqlx::query_as!(
+            BanAppeal,
+            "UPDATE ban_appeals SET status = $2, reviewed_by = $3, review_note = $4, reviewed_at = $5
+             WHERE id = $1 AND status = 'pending'
+             RETURNING id, ban_id, user_id, message, status, reviewed_by, review_note, reviewed_at, created_at",
+            appeal_id,
+            status,
+            reviewer_id,
+            note,
+            now
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(appeal) = appeal else {
+            return Ok(None);
+        };
+
+        if approve {
+            sqlx::query!(
+                "UPDATE user_bans SET lifted_at = $2, lifted_by = $3, lift_reason = 'Appeal approved'
+                 WHERE id = $1 AND lifted_at IS NULL",
+                appeal.ban_id,
+                now,
+                reviewer_id
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            Self::sync_user(&mut tx, appeal.user_id, now).await?;
+        }
+
+        tx.commit().await?;
+        Ok(Some(appeal))
+    }
+
+    pub fn is_valid_appeal_status(status: &str) -> bool {
+        matches!(status, APPEAL_PENDING | APPEAL_APPROVED | APPEAL_REJECTED)
+    }
+}
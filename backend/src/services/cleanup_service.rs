@@ -14,12 +14,11 @@ impl CleanupService {
         let cutoff_naive = cutoff_date.naive_utc();
 
         // Delete unverified accounts that are older than cutoff_date
-        // Only delete traditional registration accounts (not Google OAuth or Web3)
+        // Only delete traditional registration accounts (wallet and provider accounts have no password)
         let result = sqlx::query!(
             "DELETE FROM users
              WHERE email_verified = false
-             AND password != 'google_oauth'
-             AND password != 'web3_auth'
+             AND password <> ''
              AND created_at < $1",
             cutoff_naive
         )
@@ -42,8 +41,7 @@ impl CleanupService {
             "SELECT username, created_at, email
              FROM users
              WHERE email_verified = false
-             AND password != 'google_oauth'
-             AND password != 'web3_auth'
+             AND password <> ''
              ORDER BY created_at DESC"
         )
         .fetch_all(pool)
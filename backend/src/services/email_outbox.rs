@@ -0,0 +1,310 @@
+use crate::services::email_transport::{EmailMessage, EmailTransport};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_SENDING: &str = "sending";
+pub const STATUS_SENT: &str = "sent";
+pub const STATUS_DEAD: &str = "dead";
+
+/// Outbox row as shown to admins. Bodies are left out on purpose, they carry one-time codes
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub recipient: String,
+    pub subject: String,
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub sent_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Message claimed by the worker for delivery
+#[derive(Debug, Clone)]
+pub struct ClaimedEmail {
+    pub id: i64,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub message: EmailMessage,
+}
+
+/// Result of one worker pass
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OutboxRun {
+    pub sent: usize,
+    pub retrying: usize,
+    pub dead: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmailOutbox;
+
+impl EmailOutbox {
+    pub const DEFAULT_MAX_ATTEMPTS: i32 = 8;
+    pub const BATCH_SIZE: i64 = 20;
+    pub const BASE_BACKOFF_SECONDS: i64 = 30;
+    pub const MAX_BACKOFF_SECONDS: i64 = 3600;
+    // A message stuck in 'sending' this long belongs to a worker that died mid-send
+    pub const STALE_LOCK_MINUTES: i64 = 5;
+    pub const RETENTION_DAYS: i64 = 7;
+
+    /// Queue a message for the background worker and return its id
+    pub async fn enqueue(pool: &PgPool, message: &EmailMessage) -> Result<i64, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+
+        let id = sqlx::query_scalar!(
+            "INSERT INTO email_outbox (sender, recipient, subject, text_body, html_body, status, max_attempts, next_attempt_at, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, 'pending', $6, $7, $7, $7)
+             RETURNING id",
+            message.from,
+            message.to,
+            message.subject,
+            message.text_body,
+            message.html_body,
+            Self::DEFAULT_MAX_ATTEMPTS,
+            now
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Delay before the next attempt after `attempts` failures: 30s, 1m, 2m, ... capped at 1 hour
+    pub fn backoff(attempts: i32) -> Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+        let seconds = Self::BASE_BACKOFF_SECONDS
+            .saturating_mul(2i64.saturating_pow(exponent))
+            .min(Self::MAX_BACKOFF_SECONDS);
+        Duration::seconds(seconds)
+    }
+
+    /// Lock due messages (and stale 'sending' ones) for this worker.
+    /// A stale message counts its interrupted send as an attempt, so one that crashes the worker can't loop forever
+    pub async fn claim_due(pool: &PgPool, limit: i64) -> Result<Vec<ClaimedEmail>, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+        let stale_before = now - Duration::minutes(Self::STALE_LOCK_MINUTES);
+
+        sqlx::query!(
+            "UPDATE email_outbox
+             SET status = 'dead', attempts = attempts + 1, last_error = 'Interrupted while sending', locked_at = NULL,
+                 updated_at = $1
+             WHERE status = 'sending' AND locked_at < $2 AND attempts + 1 >= max_attempts",
+            now,
+            stale_before
+        )
+        .execute(pool)
+        .await?;
+
+        let rows = sqlx::query!(
+            "UPDATE email_outbox
+             SET status = 'sending', locked_at = $1, updated_at = $1,
+                 attempts = attempts + CASE WHEN status = 'sending' THEN 1 ELSE 0 END
+             WHERE id IN (
+                 SELECT id FROM email_outbox
+                 WHERE (status = 'pending' AND next_attempt_at <= $1)
+                    OR (status = 'sending' AND locked_at < $2)
+                 ORDER BY next_attempt_at
+                 LIMIT $3
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, sender, recipient, subject, text_body, html_body, attempts, max_attempts",
+            now,
+            stale_before,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| ClaimedEmail {
+                id: row.id,
+                attempts: row.attempts,
+                max_attempts: row.max_attempts,
+                message: EmailMessage {
+                    from: row.sender,
+                    to: row.recipient,
+                    subject: row.subject,
+                    text_body: row.text_body,
+                    html_body: row.html_body,
+                },
+            })
+            .collect())
+    }
+
+    /// Mark a message delivered and drop its bodies
+    pub async fn mark_sent(pool: &PgPool, id: i64) -> Result<(), sqlx::Error> {
+        let now = Utc::now().naive_utc();
+
+        sqlx::query!(
+            "UPDATE email_outbox
+             SET status = 'sent', attempts = attempts + 1, sent_at = $2, locked_at = NULL,
+                 last_error = NULL, text_body = '', html_body = '', updated_at = $2
+             WHERE id = $1",
+            id,
+            now
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record a failed attempt, scheduling a retry or moving the message to the dead-letter state.
+    /// Dead messages keep their bodies until cleanup, so they can be retried. Returns true if the message is now dead
+    pub async fn mark_failed(
+        pool: &PgPool,
+        email: &ClaimedEmail,
+        error: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+        let attempts = email.attempts + 1;
+        let dead = attempts >= email.max_attempts;
+        let status = if dead { STATUS_DEAD } else { STATUS_PENDING };
+        let next_attempt_at = now + Self::backoff(attempts);
+
+        sqlx::query!(
+            "UPDATE email_outbox
+             SET status = $2, attempts = $3, next_attempt_at = $4, last_error = $5, locked_at = NULL, updated_at = $6
+             WHERE id = $1",
+            email.id,
+            status,
+            attempts,
+            next_attempt_at,
+            error,
+            now
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(dead)
+    }
+
+    /// Deliver one batch of due messages through `transport`
+    pub async fn process_due(
+        pool: &PgPool,
+        transport: Arc<dyn EmailTransport>,
+        limit: i64,
+    ) -> Result<OutboxRun, sqlx::Error> {
+        let mut run = OutboxRun::default();
+
+        for email in Self::claim_due(pool, limit).await? {
+            let sender = transport.clone();
+            let message = email.message.clone();
+
+            // SMTP delivery is blocking, keep it off the async executor
+            let result = tokio::task::spawn_blocking(move || sender.send(&message))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|sent| sent.map_err(|e| e.to_string()));
+
+            match result {
+                Ok(_) => {
+                    Self::mark_sent(pool, email.id).await?;
+                    run.sent += 1;
+                }
+                Err(e) => {
+                    eprintln!("Email {} to {} failed (attempt {}): {}", email.id, email.message.to, email.attempts + 1, e);
+                    if Self::mark_failed(pool, &email, &e).await? {
+                        eprintln!("Email {} moved to dead-letter after {} attempts", email.id, email.max_attempts);
+                        run.dead += 1;
+                    } else {
+                        run.retrying += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(run)
+    }
+
+    /// Attempt a message right away. A pending one skips its backoff and keeps its attempts,
+    /// a dead one goes back in the queue with a fresh set of attempts.
+    /// Returns false if the message does not exist or has been sent or is being sent
+    pub async fn retry(pool: &PgPool, id: i64) -> Result<bool, sqlx::Error> {
+        let now = Utc::now().naive_utc();
+
+        let result = sqlx::query!(
+            "UPDATE email_outbox
+             SET status = 'pending', attempts = CASE WHEN status = 'dead' THEN 0 ELSE attempts END,
+                 next_attempt_at = $2, updated_at = $2
+             WHERE id = $1 AND status IN ('pending', 'dead')",
+            id,
+            now
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn find(pool: &PgPool, id: i64) -> Result<Option<OutboxEntry>, sqlx::Error> {
+        sqlx::query_as!(
+            OutboxEntry,
+            "SELECT id, recipient, subject, status, attempts, max_attempts, next_attempt_at, last_error, sent_at, created_at, updated_at
+             FROM email_outbox WHERE id = $1",
+            id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Newest first, optionally filtered by status
+    pub async fn list(
+        pool: &PgPool,
+        status: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<OutboxEntry>, sqlx::Error> {
+        sqlx::query_as!(
+            OutboxEntry,
+            "SELECT id, recipient, subject, status, attempts, max_attempts, next_attempt_at, last_error, sent_at, created_at, updated_at
+             FROM email_outbox
+             WHERE ($1::VARCHAR IS NULL OR status = $1)
+             ORDER BY created_at DESC, id DESC
+             LIMIT $2 OFFSET $3",
+            status,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Number of messages in each status
+    pub async fn count_by_status(pool: &PgPool) -> Result<Vec<(String, i64)>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT status, COUNT(*) as \"count!\" FROM email_outbox GROUP BY status ORDER BY status"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| (row.status, row.count)).collect())
+    }
+
+    /// Delete messages older than the retention window, whatever their status. Sent and dead ones
+    /// are only kept for inspection, and anything still queued by then is long out of date
+    pub async fn cleanup(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let cutoff = Utc::now().naive_utc() - Duration::days(Self::RETENTION_DAYS);
+
+        let result = sqlx::query!(
+            "DELETE FROM email_outbox WHERE created_at < $1",
+            cutoff
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub fn is_valid_status(status: &str) -> bool {
+        matches!(status, STATUS_PENDING | STATUS_SENDING | STATUS_SENT | STATUS_DEAD)
+    }
+}
@@ -1,173 +1,205 @@
-use crate::services::brevo_smtp::BrevoSmtpService;
-use std::collections::HashMap;
-use std::sync::Mutex;
-use lazy_static::lazy_static;
+use crate::services::email_outbox::EmailOutbox;
+use crate::services::email_templates::{EmailConfig, EmailTemplates};
+use crate::services::email_transport::{transport_from_env, EmailMessage, EmailTransport, TransportError};
+use crate::services::one_time_code_store::{CodePurpose, OneTimeCodeStore};
 use chrono::{DateTime, Utc, Duration};
-
-lazy_static! {
-    static ref VERIFICATION_CODES: Mutex<HashMap<String, (String, DateTime<Utc>)>> = Mutex::new(HashMap::new());
-    static ref MFA_VERIFICATION_CODES: Mutex<HashMap<i32, (String, DateTime<Utc>)>> = Mutex::new(HashMap::new()); // user_id -> (code, expires_at)
-    static ref PASSWORD_RESET_TOKENS: Mutex<HashMap<String, (String, DateTime<Utc>)>> = Mutex::new(HashMap::new()); // token -> (email, expires_at)
-}
+use sqlx::PgPool;
+use std::sync::Arc;
 
 pub struct EmailService {
-    smtp_service: BrevoSmtpService,
+    transport: Arc<dyn EmailTransport>,
+    config: EmailConfig,
+    templates: EmailTemplates,
+    outbox: Option<PgPool>,
 }
 
 impl EmailService {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        println!("Initializing Brevo SMTP email service");
-        let smtp_service = BrevoSmtpService::new()?;
+    pub const VERIFICATION_CODE_EXPIRY_MINUTES: i64 = 3;
+    pub const MFA_CODE_EXPIRY_MINUTES: i64 = 2;
+    pub const PASSWORD_RESET_TOKEN_EXPIRY_MINUTES: i64 = 60;
+    pub const PASSWORDLESS_LOGIN_EXPIRY_MINUTES: i64 = 10;
+    pub const STEP_UP_CODE_EXPIRY_MINUTES: i64 = 5;
+
+    pub fn new() -> Result<Self, TransportError> {
+        let transport = transport_from_env()?;
+        println!("Initializing {} email service", transport.name());
+
+        Ok(Self::with_transport(transport))
+    }
+
+    pub fn with_transport(transport: Arc<dyn EmailTransport>) -> Self {
+        Self::with_config(transport, EmailConfig::from_env())
+    }
+
+    pub fn with_config(transport: Arc<dyn EmailTransport>, config: EmailConfig) -> Self {
+        let templates = EmailTemplates::from_config(&config);
+        Self { transport, config, templates, outbox: None }
+    }
 
-        Ok(Self { smtp_service })
+    /// Queue messages in `email_outbox` for the background worker instead of sending inline
+    pub fn with_outbox(mut self, pool: PgPool) -> Self {
+        self.outbox = Some(pool);
+        self
     }
 
     pub async fn send_verification_email(
         &self,
         to_email: &str,
         verification_code: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        self.smtp_service.send_verification_email(to_email, verification_code).await
+        locale: Option<&str>,
+    ) -> Result<(), TransportError> {
+        println!("Sending verification email to: {} via {}", to_email, self.transport.name());
+
+        let expiry = Self::VERIFICATION_CODE_EXPIRY_MINUTES.to_string();
+        self.send_template(to_email, "verification", locale, &[
+            ("code", verification_code),
+            ("expiry_minutes", &expiry),
+        ])
+        .await
     }
 
-    // Delegate other methods to GmailSmtpService for now
-    // These methods are specific to the in-memory storage approach
-    pub fn generate_verification_code() -> String {
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
-        format!("{:06}", rng.gen_range(100000..999999))
+    pub async fn send_mfa_code_email(
+        &self,
+        to_email: &str,
+        code: &str,
+        locale: Option<&str>,
+    ) -> Result<(), TransportError> {
+        println!("Sending MFA code email to: {} via {}", to_email, self.transport.name());
+
+        let expiry = Self::MFA_CODE_EXPIRY_MINUTES.to_string();
+        self.send_template(to_email, "mfa_code", locale, &[
+            ("code", code),
+            ("expiry_minutes", &expiry),
+        ])
+        .await
     }
 
-    pub fn store_verification_code(email: &str, code: &str) {
-        let mut codes = VERIFICATION_CODES.lock().unwrap();
-        // Remove any old code for this email first
-        codes.remove(email);
-        let expires_at = Utc::now() + Duration::minutes(3);
-        codes.insert(email.to_string(), (code.to_string(), expires_at));
-        println!("Stored verification code {} for email {} (expires in 3 minutes)", code, email);
-    }
-
-    pub fn verify_code(email: &str, code: &str) -> bool {
-        let mut codes = VERIFICATION_CODES.lock().unwrap();
-        if let Some((stored_code, expires_at)) = codes.get(email) {
-            if Utc::now() > *expires_at {
-                codes.remove(email);
-                return false;
-            }
-            if stored_code == code {
-                codes.remove(email);
-                return true;
-            }
-        }
-        false
+    pub async fn send_step_up_code_email(
+        &self,
+        to_email: &str,
+        code: &str,
+        locale: Option<&str>,
+    ) -> Result<(), TransportError> {
+        println!("Sending step-up code email to: {} via {}", to_email, self.transport.name());
+
+        let expiry = Self::STEP_UP_CODE_EXPIRY_MINUTES.to_string();
+        self.send_template(to_email, "step_up_code", locale, &[
+            ("code", code),
+            ("expiry_minutes", &expiry),
+        ])
+        .await
     }
 
-    pub fn get_verification_code(email: &str) -> Option<String> {
-        let codes = VERIFICATION_CODES.lock().unwrap();
-        if let Some((stored_code, expires_at)) = codes.get(email) {
-            if Utc::now() > *expires_at {
-                return None;
-            }
-            return Some(stored_code.clone());
-        }
-        None
+    // Stored locale for the account, if the user has one
+    pub async fn locale_for_user(pool: &PgPool, user_id: i32) -> Option<String> {
+        sqlx::query_scalar!("SELECT locale FROM users WHERE id = $1", user_id)
+            .fetch_optional(pool)
+            .await
+            .ok()
+            .flatten()
+            .flatten()
     }
 
-    pub fn get_all_codes_with_expiry() -> std::collections::HashMap<String, (String, DateTime<Utc>)> {
-        let codes = VERIFICATION_CODES.lock().unwrap();
-        codes.clone()
+    pub async fn locale_for_email(pool: &PgPool, email: &str) -> Option<String> {
+        sqlx::query_scalar!("SELECT locale FROM users WHERE email = $1", email)
+            .fetch_optional(pool)
+            .await
+            .ok()
+            .flatten()
+            .flatten()
     }
 
-    pub fn get_debug_codes() -> std::collections::HashMap<String, String> {
-        let codes = VERIFICATION_CODES.lock().unwrap();
-        let mut result = std::collections::HashMap::new();
+    // Codes live in the configured OneTimeCodeStore and are only kept hashed
+    pub fn generate_verification_code() -> String {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        format!("{:06}", rng.gen_range(100000..999999))
+    }
 
-        for (email, (code, expires_at)) in codes.iter() {
-            if Utc::now() < *expires_at {
-                result.insert(email.clone(), code.clone());
-            }
+    pub async fn store_verification_code(email: &str, code: &str) {
+        let expiry = Duration::minutes(Self::VERIFICATION_CODE_EXPIRY_MINUTES);
+        match OneTimeCodeStore::current().put(CodePurpose::EmailVerification, email, code, expiry).await {
+            Ok(_) => println!("Stored verification code for email {} (expires in {} minutes)", email, Self::VERIFICATION_CODE_EXPIRY_MINUTES),
+            Err(e) => eprintln!("Failed to store verification code: {}", e),
         }
+    }
 
-        result
+    pub async fn verify_code(email: &str, code: &str) -> bool {
+        OneTimeCodeStore::current()
+            .verify(CodePurpose::EmailVerification, email, code)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to verify code: {}", e);
+                false
+            })
     }
 
-    // Cleanup expired verification codes and return count of cleaned items
-    pub fn cleanup_expired_codes() -> usize {
-        let mut codes = VERIFICATION_CODES.lock().unwrap();
-        let mut mfa_codes = MFA_VERIFICATION_CODES.lock().unwrap();
-        let mut password_tokens = PASSWORD_RESET_TOKENS.lock().unwrap();
-        
-        let now = Utc::now();
-        let mut cleaned = 0;
+    pub async fn get_verification_code_expiry(email: &str) -> Option<DateTime<Utc>> {
+        OneTimeCodeStore::current()
+            .expires_at(CodePurpose::EmailVerification, email)
+            .await
+            .unwrap_or(None)
+    }
 
-        // Clean verification codes
-        codes.retain(|_, (_, expires_at)| {
-            if *expires_at > now {
-                true
-            } else {
-                cleaned += 1;
-                false
-            }
-        });
-
-        // Clean MFA codes
-        mfa_codes.retain(|_, (_, expires_at)| {
-            if *expires_at > now {
-                true
-            } else {
-                cleaned += 1;
-                false
-            }
-        });
-
-        // Clean password reset tokens
-        password_tokens.retain(|_, (_, expires_at)| {
-            if *expires_at > now {
-                true
-            } else {
-                cleaned += 1;
-                false
-            }
-        });
+    // Pending verification codes by email (codes are hashed, only expiry is available)
+    #[cfg(feature = "debug-endpoints")]
+    pub async fn get_debug_codes() -> std::collections::HashMap<String, DateTime<Utc>> {
+        OneTimeCodeStore::current()
+            .active_subjects(CodePurpose::EmailVerification)
+            .await
+            .unwrap_or_default()
+    }
 
-        cleaned
+    // Cleanup expired codes of every purpose and return count of cleaned items
+    pub async fn cleanup_expired_codes() -> u64 {
+        OneTimeCodeStore::current()
+            .cleanup_expired()
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to cleanup expired codes: {}", e);
+                0
+            })
     }
 
     // MFA Verification Code Methods
-    pub fn store_mfa_verification_code(user_id: i32, code: &str) {
-        let mut codes = MFA_VERIFICATION_CODES.lock().unwrap();
-        // Remove any old code for this user first
-        codes.remove(&user_id);
-        let expires_at = Utc::now() + Duration::minutes(2);
-        codes.insert(user_id, (code.to_string(), expires_at));
-        println!("Stored MFA verification code {} for user {} (expires in 2 minutes)", code, user_id);
-    }
-
-    pub fn get_mfa_verification_code(user_id: i32) -> Option<String> {
-        let codes = MFA_VERIFICATION_CODES.lock().unwrap();
-        if let Some((stored_code, expires_at)) = codes.get(&user_id) {
-            if Utc::now() > *expires_at {
-                return None;
-            }
-            return Some(stored_code.clone());
+    pub async fn store_mfa_verification_code(user_id: i32, code: &str) {
+        let expiry = Duration::minutes(Self::MFA_CODE_EXPIRY_MINUTES);
+        match OneTimeCodeStore::current().put(CodePurpose::MfaEmail, &user_id.to_string(), code, expiry).await {
+            Ok(_) => println!("Stored MFA verification code for user {} (expires in {} minutes)", user_id, Self::MFA_CODE_EXPIRY_MINUTES),
+            Err(e) => eprintln!("Failed to store MFA verification code: {}", e),
         }
-        None
     }
 
-    pub fn get_mfa_code_expiry(user_id: i32) -> Option<DateTime<Utc>> {
-        let codes = MFA_VERIFICATION_CODES.lock().unwrap();
-        codes.get(&user_id).map(|(_, expires_at)| *expires_at)
+    pub async fn verify_mfa_verification_code(user_id: i32, code: &str) -> bool {
+        OneTimeCodeStore::current()
+            .verify(CodePurpose::MfaEmail, &user_id.to_string(), code)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to verify MFA code: {}", e);
+                false
+            })
+    }
+
+    pub async fn has_mfa_verification_code(user_id: i32) -> bool {
+        Self::get_mfa_code_expiry(user_id)
+            .await
+            .map(|expires_at| expires_at > Utc::now())
+            .unwrap_or(false)
     }
 
-    pub fn clear_mfa_verification_code(user_id: i32) {
-        let mut codes = MFA_VERIFICATION_CODES.lock().unwrap();
-        codes.remove(&user_id);
+    pub async fn get_mfa_code_expiry(user_id: i32) -> Option<DateTime<Utc>> {
+        OneTimeCodeStore::current()
+            .expires_at(CodePurpose::MfaEmail, &user_id.to_string())
+            .await
+            .unwrap_or(None)
     }
 
-    pub fn get_all_mfa_codes_with_expiry() -> std::collections::HashMap<i32, (String, DateTime<Utc>)> {
-        let codes = MFA_VERIFICATION_CODES.lock().unwrap();
-        codes.clone()
+    pub async fn clear_mfa_verification_code(user_id: i32) {
+        if let Err(e) = OneTimeCodeStore::current().remove(CodePurpose::MfaEmail, &user_id.to_string()).await {
+            eprintln!("Failed to clear MFA verification code: {}", e);
+        }
     }
+
     pub fn generate_password_reset_token() -> String {
         use rand::Rng;
         let mut rng = rand::thread_rng();
@@ -181,49 +213,113 @@ impl EmailService {
         token
     }
 
-    pub fn store_password_reset_token(email: &str, token: &str) {
-        let mut tokens = PASSWORD_RESET_TOKENS.lock().unwrap();
-        
-        // Delete any existing tokens for this email
-        tokens.retain(|_, (stored_email, _)| stored_email != email);
-        
-        let expires_at = Utc::now() + Duration::hours(1); // 1 hour expiry
-        tokens.insert(token.to_string(), (email.to_string(), expires_at));
-        println!("Stored password reset token for email {}", email);
-    }
-
-    pub fn verify_password_reset_token(token: &str) -> Option<String> {
-        let mut tokens = PASSWORD_RESET_TOKENS.lock().unwrap();
-        if let Some((email, expires_at)) = tokens.get(token) {
-            if Utc::now() > *expires_at {
-                tokens.remove(token);
-                return None;
-            }
-            let email = email.clone();
-            tokens.remove(token); // Token is single-use
-            return Some(email);
+    pub async fn store_password_reset_token(email: &str, token: &str) {
+        // Storing replaces any existing token for this email
+        let expiry = Duration::minutes(Self::PASSWORD_RESET_TOKEN_EXPIRY_MINUTES);
+        match OneTimeCodeStore::current().put(CodePurpose::PasswordReset, email, token, expiry).await {
+            Ok(_) => println!("Stored password reset token for email {}", email),
+            Err(e) => eprintln!("Failed to store password reset token: {}", e),
         }
-        None
     }
 
-    pub fn get_debug_password_reset_tokens() -> std::collections::HashMap<String, String> {
-        let tokens = PASSWORD_RESET_TOKENS.lock().unwrap();
-        let mut result = std::collections::HashMap::new();
-
-        for (token, (email, expires_at)) in tokens.iter() {
-            if Utc::now() < *expires_at {
-                result.insert(token.clone(), email.clone());
-            }
-        }
+    pub async fn verify_password_reset_token(token: &str) -> Option<String> {
+        // Token is single-use
+        OneTimeCodeStore::current()
+            .consume(CodePurpose::PasswordReset, token)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to verify password reset token: {}", e);
+                None
+            })
+    }
 
-        result
+    // Pending password resets by email (tokens are hashed, only expiry is available)
+    #[cfg(feature = "debug-endpoints")]
+    pub async fn get_debug_password_reset_tokens() -> std::collections::HashMap<String, DateTime<Utc>> {
+        OneTimeCodeStore::current()
+            .active_subjects(CodePurpose::PasswordReset)
+            .await
+            .unwrap_or_default()
     }
 
     pub async fn send_password_reset_email(
         &self,
         to_email: &str,
         reset_token: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        self.smtp_service.send_password_reset_email(to_email, reset_token).await
+        locale: Option<&str>,
+    ) -> Result<(), TransportError> {
+        println!("Sending password reset email to: {} via {}", to_email, self.transport.name());
+
+        let reset_link = format!("{}/reset-password?token={}", self.config.frontend_url, reset_token);
+        let expiry = Self::PASSWORD_RESET_TOKEN_EXPIRY_MINUTES.to_string();
+        self.send_template(to_email, "password_reset", locale, &[
+            ("reset_link", &reset_link),
+            ("expiry_minutes", &expiry),
+        ])
+        .await
+    }
+
+    pub async fn send_passwordless_login_email(
+        &self,
+        to_email: &str,
+        login_token: &str,
+        code: &str,
+        locale: Option<&str>,
+    ) -> Result<(), TransportError> {
+        println!("Sending sign-in email to: {} via {}", to_email, self.transport.name());
+
+        let login_link = format!("{}/auth/passwordless?token={}", self.config.frontend_url, login_token);
+        let expiry = Self::PASSWORDLESS_LOGIN_EXPIRY_MINUTES.to_string();
+        self.send_template(to_email, "passwordless_login", locale, &[
+            ("login_link", &login_link),
+            ("code", code),
+            ("expiry_minutes", &expiry),
+        ])
+        .await
+    }
+
+    // Render a template with the common brand/timestamp variables and deliver it
+    async fn send_template(
+        &self,
+        to_email: &str,
+        template: &str,
+        locale: Option<&str>,
+        vars: &[(&str, &str)],
+    ) -> Result<(), TransportError> {
+        let sent_at = Utc::now().format("%H:%M:%S").to_string();
+        let mut all_vars = vec![
+            ("brand_name", self.config.brand_name.as_str()),
+            ("sent_at", sent_at.as_str()),
+        ];
+        all_vars.extend_from_slice(vars);
+
+        let rendered = self.templates.render(template, locale, &all_vars)?;
+        let message = EmailMessage {
+            from: self.config.from_mailbox(),
+            to: to_email.to_string(),
+            subject: rendered.subject,
+            text_body: rendered.text_body,
+            html_body: rendered.html_body,
+        };
+
+        self.send(message).await
+    }
+
+    async fn send(&self, message: EmailMessage) -> Result<(), TransportError> {
+        // Reject bad addresses now rather than after the worker's retries
+        message.to_lettre()?;
+
+        if let Some(pool) = &self.outbox {
+            let id = EmailOutbox::enqueue(pool, &message).await?;
+            println!("Queued email {} to {}", id, message.to);
+            return Ok(());
+        }
+
+        // SMTP delivery is blocking, keep it off the async executor
+        let transport = self.transport.clone();
+
+        tokio::task::spawn_blocking(move || transport.send(&message))
+            .await
+            .map_err(|e| format!("Email task failed: {}", e))?
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,258 @@
+use actix_web::HttpRequest;
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Sender, branding and template location for outgoing mail
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    pub from_address: String,
+    pub from_name: String,
+    pub brand_name: String,
+    pub frontend_url: String,
+    pub template_dir: PathBuf,
+    pub default_locale: String,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            from_address: "no-reply@localhost".to_string(),
+            from_name: "uty".to_string(),
+            brand_name: "USH".to_string(),
+            frontend_url: "http://localhost:5173".to_string(),
+            template_dir: PathBuf::from("templates/email"),
+            default_locale: "en".to_string(),
+        }
+    }
+}
+
+impl EmailConfig {
+    /// Read `EMAIL_FROM_ADDRESS`, `EMAIL_FROM_NAME`, `EMAIL_BRAND_NAME`, `FRONTEND_URL`,
+    /// `EMAIL_TEMPLATE_DIR` and `EMAIL_DEFAULT_LOCALE`, falling back to the defaults
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |key: &str, default: String| {
+            env::var(key)
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+                .unwrap_or(default)
+        };
+
+        Self {
+            from_address: var("EMAIL_FROM_ADDRESS", defaults.from_address),
+            from_name: var("EMAIL_FROM_NAME", defaults.from_name),
+            brand_name: var("EMAIL_BRAND_NAME", defaults.brand_name),
+            frontend_url: var("FRONTEND_URL", defaults.frontend_url)
+                .trim_end_matches('/')
+                .to_string(),
+            template_dir: PathBuf::from(var(
+                "EMAIL_TEMPLATE_DIR",
+                defaults.template_dir.to_string_lossy().into_owned(),
+            )),
+            default_locale: var("EMAIL_DEFAULT_LOCALE", defaults.default_locale),
+        }
+    }
+
+    /// Mailbox for the From header, e.g. `USH <no-reply@example.com>`
+    pub fn from_mailbox(&self) -> String {
+        if self.from_name.is_empty() {
+            self.from_address.clone()
+        } else {
+            format!("{} <{}>", self.from_name, self.from_address)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    NotFound(String),
+    MissingVariable(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateError::NotFound(name) => write!(f, "Email template not found: {}", name),
+            TemplateError::MissingVariable(name) => write!(f, "Missing email template variable: {}", name),
+            TemplateError::Io(e) => write!(f, "Failed to read email template: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl From<std::io::Error> for TemplateError {
+    fn from(e: std::io::Error) -> Self {
+        TemplateError::Io(e)
+    }
+}
+
+/// Subject, plain text and HTML parts of a rendered template
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedEmail {
+    pub locale: String,
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: String,
+}
+
+/// Templates on disk, laid out as `<dir>/<locale>/<name>.{subject,txt,html}`
+#[derive(Debug, Clone)]
+pub struct EmailTemplates {
+    directory: PathBuf,
+    default_locale: String,
+}
+
+impl EmailTemplates {
+    pub fn new(directory: impl Into<PathBuf>, default_locale: &str) -> Self {
+        Self {
+            directory: directory.into(),
+            default_locale: normalize_locale(default_locale).unwrap_or_else(|| "en".to_string()),
+        }
+    }
+
+    pub fn from_config(config: &EmailConfig) -> Self {
+        Self::new(&config.template_dir, &config.default_locale)
+    }
+
+    /// Render `name` for `locale`, trying the exact locale, then its language, then the default.
+    /// Variables are HTML-escaped in the HTML part only
+    pub fn render(
+        &self,
+        name: &str,
+        locale: Option<&str>,
+        vars: &[(&str, &str)],
+    ) -> Result<RenderedEmail, TemplateError> {
+        let locale = self
+            .resolve_locale(name, locale)
+            .ok_or_else(|| TemplateError::NotFound(name.to_string()))?;
+        let dir = self.directory.join(&locale);
+
+        let subject = substitute(&read_part(&dir, name, "subject")?, vars, false)?;
+        let text_body = substitute(&read_part(&dir, name, "txt")?, vars, false)?;
+        let html_body = substitute(&read_part(&dir, name, "html")?, vars, true)?;
+
+        Ok(RenderedEmail {
+            locale,
+            subject: subject.lines().next().unwrap_or_default().trim().to_string(),
+            text_body,
+            html_body,
+        })
+    }
+
+    /// First locale directory that has `name`, if any
+    pub fn resolve_locale(&self, name: &str, requested: Option<&str>) -> Option<String> {
+        let mut candidates = Vec::new();
+        if let Some(locale) = requested.and_then(normalize_locale) {
+            if let Some((language, _)) = locale.split_once('-') {
+                let language = language.to_string();
+                candidates.push(locale);
+                candidates.push(language);
+            } else {
+                candidates.push(locale);
+            }
+        }
+        candidates.push(self.default_locale.clone());
+
+        candidates
+            .into_iter()
+            .find(|locale| self.directory.join(locale).join(format!("{}.html", name)).is_file())
+    }
+}
+
+fn read_part(dir: &Path, name: &str, extension: &str) -> Result<String, TemplateError> {
+    let path = dir.join(format!("{}.{}", name, extension));
+    std::fs::read_to_string(&path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => TemplateError::NotFound(path.display().to_string()),
+        _ => TemplateError::Io(e),
+    })
+}
+
+// Replace `{{ name }}` placeholders, failing on any variable the caller did not provide
+fn substitute(template: &str, vars: &[(&str, &str)], escape: bool) -> Result<String, TemplateError> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| TemplateError::MissingVariable("unterminated placeholder".to_string()))?;
+        let key = after[..end].trim();
+
+        let value = vars
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| TemplateError::MissingVariable(key.to_string()))?;
+
+        if escape {
+            output.push_str(&escape_html(value));
+        } else {
+            output.push_str(value);
+        }
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Lowercase `pt_BR` style tags to `pt-br`, rejecting anything that is not a plain language tag
+pub fn normalize_locale(locale: &str) -> Option<String> {
+    let locale = locale.trim().replace('_', "-").to_lowercase();
+    let valid = !locale.is_empty()
+        && locale.len() <= 10
+        && locale.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !locale.starts_with('-');
+
+    if valid {
+        Some(locale)
+    } else {
+        None
+    }
+}
+
+/// Preferred locale from the `Accept-Language` header (highest q-value wins)
+pub fn locale_from_request(req: &HttpRequest) -> Option<String> {
+    let header = req.headers().get("Accept-Language")?.to_str().ok()?;
+
+    header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if tag == "*" || quality <= 0.0 {
+                return None;
+            }
+            normalize_locale(tag).map(|locale| (locale, quality))
+        })
+        .fold(None, |best: Option<(String, f32)>, (locale, quality)| match best {
+            Some((_, best_quality)) if best_quality >= quality => best,
+            _ => Some((locale, quality)),
+        })
+        .map(|(locale, _)| locale)
+}
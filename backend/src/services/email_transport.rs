@@ -0,0 +1,267 @@
+use lazy_static::lazy_static;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+lazy_static! {
+    // Outbox shared by every MemoryEmailTransport::shared() so captured mail survives per-request EmailService instances
+    static ref SHARED_OUTBOX: Arc<Mutex<Vec<EmailMessage>>> = Arc::new(Mutex::new(Vec::new()));
+}
+
+/// A rendered email, independent of how it gets delivered
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailMessage {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: String,
+}
+
+impl EmailMessage {
+    pub fn to_lettre(&self) -> Result<Message, TransportError> {
+        let message = Message::builder()
+            .from(self.from.parse()?)
+            .to(self.to.parse()?)
+            .subject(&self.subject)
+            .multipart(lettre::message::MultiPart::alternative_plain_html(
+                self.text_body.clone(),
+                self.html_body.clone(),
+            ))?;
+
+        Ok(message)
+    }
+}
+
+/// Delivery backend for EmailService, selected with `EMAIL_PROVIDER`
+pub trait EmailTransport: Send + Sync {
+    /// Provider name for logging
+    fn name(&self) -> &'static str;
+
+    /// Deliver a message (blocking, EmailService runs this off the async executor)
+    fn send(&self, message: &EmailMessage) -> Result<(), TransportError>;
+}
+
+/// Build the transport selected by `EMAIL_PROVIDER` (brevo, turbosmtp, smtp, file or memory)
+pub fn transport_from_env() -> Result<Arc<dyn EmailTransport>, TransportError> {
+    let provider = env::var("EMAIL_PROVIDER").unwrap_or_else(|_| "brevo".to_string());
+    transport_for_provider(&provider)
+}
+
+pub fn transport_for_provider(provider: &str) -> Result<Arc<dyn EmailTransport>, TransportError> {
+    match provider.trim().to_lowercase().as_str() {
+        "" | "brevo" => Ok(Arc::new(SmtpEmailTransport::brevo()?)),
+        "turbosmtp" | "turbo_smtp" | "turbo" => Ok(Arc::new(SmtpEmailTransport::turbo_smtp()?)),
+        "smtp" => Ok(Arc::new(SmtpEmailTransport::generic()?)),
+        "file" => Ok(Arc::new(FileEmailTransport::from_env())),
+        "memory" => Ok(Arc::new(MemoryEmailTransport::shared())),
+        other => Err(format!("Unknown EMAIL_PROVIDER: {}", other).into()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpSecurity {
+    StartTls, // Upgrade a plain connection (usually port 587)
+    Tls,      // Implicit TLS (usually port 465)
+    None,     // Plain SMTP, local relays only
+}
+
+impl SmtpSecurity {
+    fn parse(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "tls" | "ssl" => SmtpSecurity::Tls,
+            "none" | "plain" => SmtpSecurity::None,
+            _ => SmtpSecurity::StartTls,
+        }
+    }
+}
+
+/// SMTP relay transport shared by Brevo, TurboSMTP and generic SMTP servers
+pub struct SmtpEmailTransport {
+    name: &'static str,
+    mailer: SmtpTransport,
+}
+
+impl SmtpEmailTransport {
+    pub fn new(
+        name: &'static str,
+        server: &str,
+        port: u16,
+        credentials: Option<Credentials>,
+        security: SmtpSecurity,
+    ) -> Result<Self, TransportError> {
+        let builder = match security {
+            SmtpSecurity::StartTls => SmtpTransport::starttls_relay(server)?,
+            SmtpSecurity::Tls => SmtpTransport::relay(server)?,
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(server),
+        };
+
+        let builder = builder.port(port);
+        let mailer = match credentials {
+            Some(creds) => builder.credentials(creds).build(),
+            None => builder.build(),
+        };
+
+        println!("{} SMTP Config:", name);
+        println!("  Server: {}", server);
+        println!("  Port: {}", port);
+        println!("  Security: {:?}", security);
+
+        Ok(Self { name, mailer })
+    }
+
+    /// Brevo relay, STARTTLS on port 587
+    pub fn brevo() -> Result<Self, TransportError> {
+        let username = env::var("BREVO_SMTP_USERNAME")
+            .map_err(|_| "BREVO_SMTP_USERNAME environment variable not set")?;
+        let password = env::var("BREVO_SMTP_PASSWORD")
+            .map_err(|_| "BREVO_SMTP_PASSWORD environment variable not set")?;
+        let server = env::var("BREVO_SMTP_SERVER")
+            .unwrap_or_else(|_| "smtp-relay.brevo.com".to_string());
+        let port = Self::port_from_env("BREVO_SMTP_PORT", 587);
+
+        Self::new("Brevo", &server, port, Some(Credentials::new(username, password)), SmtpSecurity::StartTls)
+    }
+
+    /// TurboSMTP relay, SSL on 465/25025 and plain SMTP on its other ports
+    pub fn turbo_smtp() -> Result<Self, TransportError> {
+        let username = env::var("TURBO_SMTP_USERNAME")
+            .map_err(|_| "TURBO_SMTP_USERNAME environment variable not set")?;
+        let password = env::var("TURBO_SMTP_PASSWORD")
+            .map_err(|_| "TURBO_SMTP_PASSWORD environment variable not set")?;
+        let server = env::var("TURBO_SMTP_SERVER")
+            .unwrap_or_else(|_| "pro.turbo-smtp.com".to_string());
+        let port = Self::port_from_env("TURBO_SMTP_PORT", 587);
+
+        let security = match port {
+            465 | 25025 => SmtpSecurity::Tls,
+            _ => SmtpSecurity::None,
+        };
+
+        Self::new("TurboSMTP", &server, port, Some(Credentials::new(username, password)), security)
+    }
+
+    /// Any SMTP server configured through `SMTP_*` variables
+    pub fn generic() -> Result<Self, TransportError> {
+        let server = env::var("SMTP_SERVER")
+            .map_err(|_| "SMTP_SERVER environment variable not set")?;
+        let port = Self::port_from_env("SMTP_PORT", 587);
+        let security = SmtpSecurity::parse(&env::var("SMTP_SECURITY").unwrap_or_default());
+
+        // Credentials are optional for local relays
+        let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some(Credentials::new(username, password)),
+            _ => None,
+        };
+
+        Self::new("SMTP", &server, port, credentials, security)
+    }
+
+    fn port_from_env(key: &str, default: u16) -> u16 {
+        env::var(key)
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(default)
+    }
+}
+
+impl EmailTransport for SmtpEmailTransport {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn send(&self, message: &EmailMessage) -> Result<(), TransportError> {
+        let email = message.to_lettre()?;
+
+        match self.mailer.send(&email) {
+            Ok(response) => {
+                println!("✅ Email sent successfully via {}: {:?}", self.name, response.code());
+                Ok(())
+            }
+            Err(e) => {
+                println!("❌ {} SMTP error: {}", self.name, e);
+                Err(format!("SMTP Error: {}", e).into())
+            }
+        }
+    }
+}
+
+/// Writes every message as an `.eml` file, for local development without an SMTP account
+pub struct FileEmailTransport {
+    directory: PathBuf,
+}
+
+impl FileEmailTransport {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self { directory: directory.into() }
+    }
+
+    /// Directory from `EMAIL_FILE_DIR` (default `./mail`)
+    pub fn from_env() -> Self {
+        Self::new(env::var("EMAIL_FILE_DIR").unwrap_or_else(|_| "./mail".to_string()))
+    }
+}
+
+impl EmailTransport for FileEmailTransport {
+    fn name(&self) -> &'static str {
+        "File"
+    }
+
+    fn send(&self, message: &EmailMessage) -> Result<(), TransportError> {
+        std::fs::create_dir_all(&self.directory)?;
+
+        let file_name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            uuid::Uuid::new_v4()
+        );
+        let path = self.directory.join(file_name);
+
+        std::fs::write(&path, message.to_lettre()?.formatted())?;
+        println!("Email to {} written to {}", message.to, path.display());
+
+        Ok(())
+    }
+}
+
+/// Keeps sent messages in memory so tests can inspect them
+#[derive(Clone, Default)]
+pub struct MemoryEmailTransport {
+    outbox: Arc<Mutex<Vec<EmailMessage>>>,
+}
+
+impl MemoryEmailTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transport backed by the process-wide outbox (used for `EMAIL_PROVIDER=memory`)
+    pub fn shared() -> Self {
+        Self { outbox: SHARED_OUTBOX.clone() }
+    }
+
+    pub fn sent(&self) -> Vec<EmailMessage> {
+        self.outbox.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.outbox.lock().unwrap().clear();
+    }
+}
+
+impl EmailTransport for MemoryEmailTransport {
+    fn name(&self) -> &'static str {
+        "Memory"
+    }
+
+    fn send(&self, message: &EmailMessage) -> Result<(), TransportError> {
+        // Still build the MIME message so invalid addresses fail like they would over SMTP
+        message.to_lettre()?;
+        self.outbox.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use lazy_static::lazy_static;
+use reqwest::Client;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::services::jwks_cache::{JwksCache, JwksError};
+
+pub const DEFAULT_JWKS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+pub const DEFAULT_TOKENINFO_URL: &str = "https://oauth2.googleapis.com/tokeninfo";
+/// Google issues ID tokens with either form of the issuer
+pub const GOOGLE_ISSUERS: [&str; 2] = ["accounts.google.com", "https://accounts.google.com"];
+
+// Google JWT claims structure
+#[derive(Debug, Clone, Deserialize)]
+pub struct GoogleClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub email: String,
+    pub name: Option<String>,
+    pub picture: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct GoogleAuthConfig {
+    pub client_ids: Vec<String>, // Accepted `aud` values (web, android, ios clients...)
+    pub jwks_url: String,
+    pub tokeninfo_url: String,
+    pub accept_access_tokens: bool, // The implicit flow sends access tokens, ID tokens are checked locally
+}
+
+impl GoogleAuthConfig {
+    /// GOOGLE_CLIENT_IDS (comma separated, falls back to GOOGLE_CLIENT_ID), GOOGLE_JWKS_URL, GOOGLE_TOKENINFO_URL,
+    /// GOOGLE_ACCEPT_ACCESS_TOKENS (default true, set to false once the frontend only sends ID tokens)
+    pub fn from_env() -> Self {
+        // Empty values (as left by .env.example) count as unset
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+
+        let client_ids = var("GOOGLE_CLIENT_IDS")
+            .or_else(|| var("GOOGLE_CLIENT_ID"))
+            .unwrap_or_default()
+            .split(',')
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect();
+
+        Self {
+            client_ids,
+            jwks_url: var("GOOGLE_JWKS_URL").unwrap_or_else(|| DEFAULT_JWKS_URL.to_string()),
+            tokeninfo_url: var("GOOGLE_TOKENINFO_URL").unwrap_or_else(|| DEFAULT_TOKENINFO_URL.to_string()),
+            accept_access_tokens: var("GOOGLE_ACCEPT_ACCESS_TOKENS").is_none_or(|v| v.trim().to_lowercase() != "false"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GoogleTokenError {
+    NotConfigured,
+    Malformed(String),
+    UnknownKey(String),
+    Invalid(String),
+    Fetch(String),
+}
+
+impl fmt::Display for GoogleTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoogleTokenError::NotConfigured => write!(f, "Google sign-in is not configured (GOOGLE_CLIENT_IDS is empty)"),
+            GoogleTokenError::Malformed(e) => write!(f, "Malformed Google token: {}", e),
+            GoogleTokenError::UnknownKey(kid) => write!(f, "Google token signed with unknown key '{}'", kid),
+            GoogleTokenError::Invalid(e) => write!(f, "Google token rejected: {}", e),
+            GoogleTokenError::Fetch(e) => write!(f, "Failed to reach Google: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GoogleTokenError {}
+
+lazy_static! {
+    static ref SHARED_VERIFIER: GoogleIdTokenVerifier = GoogleIdTokenVerifier::new(GoogleAuthConfig::from_env());
+}
+
+/// Verifies Google ID tokens against Google's published signing keys (JWKS),
+/// and access tokens against the tokeninfo endpoint. Clones share the key cache
+#[derive(Clone)]
+pub struct GoogleIdTokenVerifier {
+    config: Arc<GoogleAuthConfig>,
+    client: Client,
+    cache: Arc<JwksCache>,
+}
+
+impl GoogleIdTokenVerifier {
+    pub fn new(config: GoogleAuthConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_else(|_| Client::new()),
+            cache: Arc::new(JwksCache::default()),
+        }
+    }
+
+    /// Process-wide verifier configured from the environment
+    pub fn shared() -> Self {
+        SHARED_VERIFIER.clone()
+    }
+
+    /// Verify either kind of token the frontend may send: ID tokens are JWTs, access tokens are opaque
+    /// and only accepted when `accept_access_tokens` is set
+    pub async fn verify(&self, token: &str) -> Result<GoogleClaims, GoogleTokenError> {
+        if token.split('.').count() == 3 {
+            self.verify_id_token(token).await
+        } else if self.config.accept_access_tokens {
+            self.verify_access_token(token).await
+        } else {
+            Err(GoogleTokenError::Invalid("an ID token is required".to_string()))
+        }
+    }
+
+    /// Check the signature against Google's JWKS, plus `aud`, `iss` and `exp`
+    pub async fn verify_id_token(&self, token: &str) -> Result<GoogleClaims, GoogleTokenError> {
+        if self.config.client_ids.is_empty() {
+            return Err(GoogleTokenError::NotConfigured);
+        }
+
+        let header = decode_header(token).map_err(|e| GoogleTokenError::Malformed(e.to_string()))?;
+        if header.alg != Algorithm::RS256 {
+            return Err(GoogleTokenError::Invalid(format!("unexpected algorithm {:?}", header.alg)));
+        }
+        let kid = header.kid.ok_or_else(|| GoogleTokenError::Malformed("missing kid".to_string()))?;
+        let key = self.decoding_key(&kid).await?;
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&self.config.client_ids);
+        validation.set_issuer(&GOOGLE_ISSUERS);
+        validation.set_required_spec_claims(&["exp", "iat", "aud", "iss", "sub"]);
+
+        decode::<GoogleClaims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| GoogleTokenError::Invalid(e.to_string()))
+    }
+
+    /// Ask Google about an OAuth access token (implicit flow) and check it was issued to one of our clients
+    pub async fn verify_access_token(&self, token: &str) -> Result<GoogleClaims, GoogleTokenError> {
+        if self.config.client_ids.is_empty() {
+            return Err(GoogleTokenError::NotConfigured);
+        }
+
+        #[derive(Deserialize)]
+        struct TokenInfo {
+            aud: Option<String>,
+            azp: Option<String>,
+            sub: Option<String>,
+            email: Option<String>,
+            email_verified: Option<serde_json::Value>, // "true" (string) from tokeninfo
+            exp: Option<serde_json::Value>,
+        }
+
+        let response = self
+            .client
+            .get(&self.config.tokeninfo_url)
+            .query(&[("access_token", token)])
+            .send()
+            .await
+            .map_err(|e| GoogleTokenError::Fetch(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(GoogleTokenError::Invalid("access token rejected by Google".to_string()));
+        }
+
+        let info: TokenInfo = response
+            .json()
+            .await
+            .map_err(|e| GoogleTokenError::Malformed(e.to_string()))?;
+
+        // A token issued to another app must not log in here. Both must name our client when present
+        let non_empty = |v: Option<String>| v.filter(|v| !v.trim().is_empty());
+        let audience = non_empty(info.aud)
+            .ok_or_else(|| GoogleTokenError::Invalid("token has no audience".to_string()))?;
+        let authorized_party = non_empty(info.azp);
+        if !self.config.client_ids.contains(&audience)
+            || authorized_party.is_some_and(|azp| !self.config.client_ids.contains(&azp))
+        {
+            return Err(GoogleTokenError::Invalid("token was issued to a different client".to_string()));
+        }
+
+        // Accounts are matched on the subject, one that is missing must never match anything
+        let subject = non_empty(info.sub)
+            .ok_or_else(|| GoogleTokenError::Invalid("token has no subject".to_string()))?;
+
+        let as_bool = |v: &serde_json::Value| v.as_bool().unwrap_or_else(|| v.as_str() == Some("true"));
+        let as_i64 = |v: &serde_json::Value| v.as_i64().or_else(|| v.as_str().and_then(|s| s.parse().ok()));
+
+        Ok(GoogleClaims {
+            iss: GOOGLE_ISSUERS[1].to_string(),
+            sub: subject,
+            aud: audience,
+            email: info.email.ok_or_else(|| GoogleTokenError::Invalid("token has no email scope".to_string()))?,
+            name: None,
+            picture: None,
+            email_verified: info.email_verified.as_ref().is_some_and(as_bool),
+            iat: 0,
+            exp: info.exp.as_ref().and_then(as_i64).unwrap_or(0),
+        })
+    }
+
+    async fn decoding_key(&self, kid: &str) -> Result<DecodingKey, GoogleTokenError> {
+        let jwk = self
+            .cache
+            .find(&self.client, &self.config.jwks_url, Some(kid))
+            .await
+            .map_err(|e| match e {
+                JwksError::Fetch(e) => GoogleTokenError::Fetch(e),
+                JwksError::Malformed(e) => GoogleTokenError::Malformed(e),
+                JwksError::UnknownKey(_) => GoogleTokenError::UnknownKey(kid.to_string()),
+            })?;
+        DecodingKey::from_jwk(&jwk).map_err(|e| GoogleTokenError::Malformed(e.to_string()))
+    }
+}
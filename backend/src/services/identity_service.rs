@@ -0,0 +1,453 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::models::user::User;
+use crate::services::oidc_service::OidcIdentity;
+use crate::services::wallet_service::WalletService;
+
+pub const PROVIDER_GOOGLE: &str = "google";
+/// Confirmation window for linking a provider to an existing account with the same email
+pub const LINK_TOKEN_TTL_MINUTES: i64 = 10;
+const LINK_TOKEN_PURPOSE: &str = "identity_link";
+
+/// An external login method linked to an account
+#[derive(Debug, Clone, Serialize)]
+pub struct UserIdentity {
+    pub id: i32,
+    pub user_id: i32,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub linked_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+/// A login vouched for by an external provider (Google, an OIDC provider)
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub username: Option<String>,
+    pub name: Option<String>,
+}
+
+impl From<OidcIdentity> for ExternalIdentity {
+    fn from(identity: OidcIdentity) -> Self {
+        Self {
+            provider: identity.provider,
+            subject: identity.subject,
+            email: identity.email,
+            email_verified: identity.email_verified,
+            username: identity.username,
+            name: identity.name,
+        }
+    }
+}
+
+/// Which account an external login belongs to
+pub enum ExternalLogin {
+    Linked(User),
+    /// A verified local account has the same email: it is only linked once the user confirms
+    ConfirmLink(User),
+    /// A local account has the same email but never verified it, so the match proves nothing
+    EmailNotVerified,
+    NewUser,
+}
+
+pub enum LinkOutcome {
+    Linked(UserIdentity),
+    AlreadyLinked(UserIdentity),
+    LinkedToOtherUser,
+}
+
+pub enum UnlinkOutcome {
+    Unlinked(UserIdentity),
+    NotFound,
+    LastLoginMethod,
+}
+
+/// Ways the user can currently sign in
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginMethods {
+    pub password: bool,
+    pub wallets: i64,
+    pub identities: i64,
+    pub passkeys: i64,
+}
+
+impl LoginMethods {
+    pub fn count(&self) -> i64 {
+        self.password as i64 + self.wallets + self.identities + self.passkeys
+    }
+}
+
+/// Pending "link this provider to my existing account" confirmation
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkClaims {
+    pub sub: i32, // Account to link to
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub purpose: String,
+    pub iat: usize,
+    pub exp: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct IdentityService;
+
+impl IdentityService {
+    pub async fn find_user(pool: &PgPool, provider: &str, subject: &str) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            "SELECT u.id, u.username, u.email, u.password, u.role, u.wallet_address, u.email_verified, u.totp_enabled, u.recovery_codes, u.is_banned, u.banned_until, u.last_login, u.created_at, u.updated_at
+             FROM user_identities i
+             JOIN users u ON u.id = i.user_id
+             WHERE i.provider = $1 AND i.subject = $2",
+            provider,
+            subject
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Work out which account an external login signs into. Only an identity that is already
+    /// linked logs in directly; a matching email never does by itself
+    pub async fn resolve_login(pool: &PgPool, identity: &ExternalIdentity) -> Result<ExternalLogin, sqlx::Error> {
+        if let Some(user) = Self::find_user(pool, &identity.provider, &identity.subject).await? {
+            Self::touch(pool, &identity.provider, &identity.subject).await?;
+            return Ok(ExternalLogin::Linked(user));
+        }
+
+        let Some(email) = identity.email.as_deref().filter(|_| identity.email_verified) else {
+            return Ok(ExternalLogin::NewUser);
+        };
+
+        // Google accounts from before identities were stored are keyed by email until first use
+        if identity.provider == PROVIDER_GOOGLE {
+            let upgraded = sqlx::query_scalar!(
+                "UPDATE user_identities SET subject = $2, last_used_at = $3
+                 WHERE provider = 'google' AND subject = $1
+                 RETURNING user_id",
+                format!("legacy:{}", email.to_lowercase()),
+                identity.subject,
+                Utc::now().naive_utc()
+            )
+            .fetch_optional(pool)
+            .await?;
+
+            if let Some(user_id) = upgraded {
+                let user = Self::user_by_id(pool, user_id).await?;
+                return Ok(ExternalLogin::Linked(user));
+            }
+        }
+
+        let existing = sqlx::query_as!(
+            User,
+            "SELECT id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at
+             FROM users WHERE LOWER(email) = LOWER($1)",
+            email
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(match existing {
+            Some(user) if user.email_verified => ExternalLogin::ConfirmLink(user),
+            Some(_) => ExternalLogin::EmailNotVerified,
+            None => ExternalLogin::NewUser,
+        })
+    }
+
+    /// New account for a first-time external login, with the identity already linked
+    pub async fn create_user(pool: &PgPool, identity: &ExternalIdentity) -> Result<User, sqlx::Error> {
+        let username = Self::available_username(pool, identity).await?;
+        let mut tx = pool.begin().await?;
+
+        let user = sqlx::query_as!(
+            User,
+            "INSERT INTO users (username, email, password, role, email_verified, created_at, updated_at)
+             VALUES ($1, $2, '', 'user', $3, NOW(), NOW())
+             RETURNING id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at",
+            username,
+            identity.email,
+            identity.email_verified
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO user_identities (user_id, provider, subject, email, linked_at, last_used_at)
+             VALUES ($1, $2, $3, $4, $5, $5)",
+            user.id,
+            identity.provider,
+            identity.subject,
+            identity.email,
+            Utc::now().naive_utc()
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    /// Link a provider account to a user. A provider account can belong to one user only
+    pub async fn link(
+        pool: &PgPool,
+        user_id: i32,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<LinkOutcome, sqlx::Error> {
+        let inserted = sqlx::query_as!(
+            UserIdentity,
+            "INSERT INTO user_identities (user_id, provider, subject, email, linked_at)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (provider, subject) DO NOTHING
+             RETURNING id, user_id, provider, subject, email, linked_at, last_used_at",
+            user_id,
+            provider,
+            subject,
+            email,
+            Utc::now().naive_utc()
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(identity) = inserted {
+            return Ok(LinkOutcome::Linked(identity));
+        }
+
+        let existing = sqlx::query_as!(
+            UserIdentity,
+            "SELECT id, user_id, provider, subject, email, linked_at, last_used_at
+             FROM user_identities WHERE provider = $1 AND subject = $2",
+            provider,
+            subject
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(if existing.user_id == user_id {
+            LinkOutcome::AlreadyLinked(existing)
+        } else {
+            LinkOutcome::LinkedToOtherUser
+        })
+    }
+
+    pub async fn list(pool: &PgPool, user_id: i32) -> Result<Vec<UserIdentity>, sqlx::Error> {
+        sqlx::query_as!(
+            UserIdentity,
+            "SELECT id, user_id, provider, subject, email, linked_at, last_used_at
+             FROM user_identities WHERE user_id = $1
+             ORDER BY linked_at",
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn login_methods(pool: &PgPool, user_id: i32) -> Result<LoginMethods, sqlx::Error> {
+        let mut conn = pool.acquire().await?;
+        Self::login_methods_on(&mut conn, user_id).await
+    }
+
+    /// Same as `login_methods`, inside a transaction that has locked the user row
+    pub async fn login_methods_on(conn: &mut PgConnection, user_id: i32) -> Result<LoginMethods, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT password <> '' AS \"password!\",
+                    (SELECT COUNT(*) FROM user_wallets WHERE user_id = users.id) AS \"wallets!\",
+                    (SELECT COUNT(*) FROM user_identities WHERE user_id = users.id) AS \"identities!\",
+                    (SELECT COUNT(*) FROM webauthn_credentials WHERE user_id = users.id) AS \"passkeys!\"
+             FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_one(conn)
+        .await?;
+
+        Ok(LoginMethods {
+            password: row.password,
+            wallets: row.wallets,
+            identities: row.identities,
+            passkeys: row.passkeys,
+        })
+    }
+
+    /// After a compromise report, remove the passkeys, linked identities and wallets added since `since`.
+    /// Whatever the account was created with stays, and nothing is removed if no way to sign in would be left.
+    /// Returns how many of each were removed, `None` if they were all kept for that reason
+    pub async fn remove_login_methods_since(
+        pool: &PgPool,
+        user_id: i32,
+        since: NaiveDateTime,
+    ) -> Result<Option<LoginMethods>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let created_at = sqlx::query_scalar!("SELECT created_at FROM users WHERE id = $1 FOR UPDATE", user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let since = since.max(created_at + Duration::minutes(1));
+
+        let passkeys = sqlx::query!(
+            "DELETE FROM webauthn_credentials WHERE user_id = $1 AND created_at >= $2",
+            user_id,
+            since
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        let identities = sqlx::query!(
+            "DELETE FROM user_identities WHERE user_id = $1 AND linked_at >= $2",
+            user_id,
+            since
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        let wallets = sqlx::query!(
+            "DELETE FROM user_wallets WHERE user_id = $1 AND created_at >= $2",
+            user_id,
+            since
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if Self::login_methods_on(&mut tx, user_id).await?.count() == 0 {
+            tx.rollback().await?;
+            return Ok(None);
+        }
+
+        if wallets > 0 {
+            WalletService::ensure_primary(&mut tx, user_id).await?;
+        }
+
+        tx.commit().await?;
+        Ok(Some(LoginMethods {
+            password: false,
+            wallets: wallets as i64,
+            identities: identities as i64,
+            passkeys: passkeys as i64,
+        }))
+    }
+
+    /// Remove a linked identity, unless it is the only way left to sign in
+    pub async fn unlink(pool: &PgPool, user_id: i32, identity_id: i32) -> Result<UnlinkOutcome, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        // Lock the account so two concurrent unlinks can't each leave the other as the last method
+        sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let identity = sqlx::query_as!(
+            UserIdentity,
+            "SELECT id, user_id, provider, subject, email, linked_at, last_used_at
+             FROM user_identities WHERE id = $1 AND user_id = $2",
+            identity_id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(identity) = identity else {
+            return Ok(UnlinkOutcome::NotFound);
+        };
+
+        if Self::login_methods_on(&mut tx, user_id).await?.count() <= 1 {
+            return Ok(UnlinkOutcome::LastLoginMethod);
+        }
+
+        sqlx::query!("DELETE FROM user_identities WHERE id = $1", identity_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(UnlinkOutcome::Unlinked(identity))
+    }
+
+    pub fn create_link_token(user_id: i32, identity: &ExternalIdentity, secret: &str) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = Utc::now();
+        let claims = LinkClaims {
+            sub: user_id,
+            provider: identity.provider.clone(),
+            subject: identity.subject.clone(),
+            email: identity.email.clone(),
+            purpose: LINK_TOKEN_PURPOSE.to_string(),
+            iat: now.timestamp() as usize,
+            exp: (now + Duration::minutes(LINK_TOKEN_TTL_MINUTES)).timestamp() as usize,
+        };
+
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes()))
+    }
+
+    pub fn verify_link_token(token: &str, secret: &str) -> Option<LinkClaims> {
+        decode::<LinkClaims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::default())
+            .ok()
+            .map(|data| data.claims)
+            .filter(|claims| claims.purpose == LINK_TOKEN_PURPOSE)
+    }
+
+    async fn touch(pool: &PgPool, provider: &str, subject: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE user_identities SET last_used_at = $3 WHERE provider = $1 AND subject = $2",
+            provider,
+            subject,
+            Utc::now().naive_utc()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn user_by_id(pool: &PgPool, user_id: i32) -> Result<User, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            "SELECT id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at
+             FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    // Provider username (or name, or email prefix) reduced to the characters registration allows,
+    // with a numeric suffix when it is already taken
+    async fn available_username(pool: &PgPool, identity: &ExternalIdentity) -> Result<String, sqlx::Error> {
+        let source = identity
+            .username
+            .as_deref()
+            .or(identity.name.as_deref())
+            .or_else(|| identity.email.as_deref().and_then(|e| e.split('@').next()))
+            .unwrap_or("user");
+
+        let mut base: String = source
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+            .take(40)
+            .collect();
+        if base.trim_matches('_').len() < 3 {
+            base = format!("user_{}", base.trim_matches('_'));
+        }
+
+        let mut candidate = base.clone();
+        for _ in 0..10 {
+            let taken = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM users WHERE username = $1)", candidate)
+                .fetch_one(pool)
+                .await?
+                .unwrap_or(false);
+            if !taken {
+                return Ok(candidate);
+            }
+            candidate = format!("{}_{}", base, rand::thread_rng().gen_range(1000..10000));
+        }
+
+        Ok(format!("{}_{}", base, uuid::Uuid::new_v4().simple()))
+    }
+}
@@ -0,0 +1,139 @@
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use reqwest::Client;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+#[derive(Debug)]
+pub enum JwksError {
+    Fetch(String),
+    Malformed(String),
+    UnknownKey(String),
+}
+
+impl fmt::Display for JwksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwksError::Fetch(e) => write!(f, "Failed to fetch signing keys: {}", e),
+            JwksError::Malformed(e) => write!(f, "Malformed signing keys: {}", e),
+            JwksError::UnknownKey(kid) => write!(f, "Signing key '{}' not found", kid),
+        }
+    }
+}
+
+impl std::error::Error for JwksError {}
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+    expires_at: Instant,
+}
+
+/// A published key set (JWKS), kept for as long as its Cache-Control allows.
+/// An unknown key refetches it, for key rotation, but not more often than MIN_REFETCH_SECONDS
+#[derive(Default)]
+pub struct JwksCache {
+    cache: RwLock<Option<CachedJwks>>,
+}
+
+impl JwksCache {
+    /// Used when the response has no usable Cache-Control max-age
+    pub const DEFAULT_CACHE_SECONDS: u64 = 3600;
+    pub const MIN_REFETCH_SECONDS: u64 = 30;
+
+    /// The key with id `kid` from the set at `url`. Without a `kid` the set must hold a single key
+    pub async fn find(&self, client: &Client, url: &str, kid: Option<&str>) -> Result<Jwk, JwksError> {
+        {
+            let cache = self.cache.read().await;
+            if let Some(cached) = cache.as_ref()
+                && cached.expires_at > Instant::now()
+                && let Some(jwk) = select(&cached.keys, kid)
+            {
+                return Ok(jwk.clone());
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+
+        // Another request may have refreshed while we waited for the lock
+        let fresh = cache.as_ref().is_some_and(|c| c.expires_at > Instant::now());
+        let recently_fetched = cache
+            .as_ref()
+            .is_some_and(|c| c.fetched_at.elapsed() < Duration::from_secs(Self::MIN_REFETCH_SECONDS));
+
+        if !fresh || !recently_fetched {
+            *cache = Some(fetch(client, url).await?);
+        }
+
+        let cached = cache.as_ref().expect("cache populated above");
+        select(&cached.keys, kid)
+            .cloned()
+            .ok_or_else(|| JwksError::UnknownKey(kid.unwrap_or("(none)").to_string()))
+    }
+}
+
+fn select<'a>(keys: &'a JwkSet, kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }
+}
+
+async fn fetch(client: &Client, url: &str) -> Result<CachedJwks, JwksError> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| JwksError::Fetch(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(JwksError::Fetch(format!("JWKS endpoint returned {}", response.status())));
+    }
+
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let ttl = cache_ttl(header("Cache-Control").as_deref(), header("Age").as_deref());
+
+    let keys: JwkSet = response
+        .json()
+        .await
+        .map_err(|e| JwksError::Malformed(e.to_string()))?;
+
+    let now = Instant::now();
+    Ok(CachedJwks {
+        keys,
+        fetched_at: now,
+        expires_at: now + ttl,
+    })
+}
+
+/// How long a JWKS response may be cached: Cache-Control max-age minus Age,
+/// zero for no-store/no-cache, and DEFAULT_CACHE_SECONDS when there is no max-age
+pub fn cache_ttl(cache_control: Option<&str>, age: Option<&str>) -> Duration {
+    let Some(cache_control) = cache_control else {
+        return Duration::from_secs(JwksCache::DEFAULT_CACHE_SECONDS);
+    };
+
+    let mut max_age = None;
+    for directive in cache_control.split(',').map(|d| d.trim().to_ascii_lowercase()) {
+        if directive == "no-store" || directive == "no-cache" {
+            return Duration::ZERO;
+        }
+        if let Some(value) = directive.strip_prefix("max-age=") {
+            max_age = value.trim_matches('"').parse::<u64>().ok();
+        }
+    }
+
+    let age = age.and_then(|a| a.trim().parse::<u64>().ok()).unwrap_or(0);
+    Duration::from_secs(
+        max_age
+            .map(|max_age| max_age.saturating_sub(age))
+            .unwrap_or(JwksCache::DEFAULT_CACHE_SECONDS),
+    )
+}
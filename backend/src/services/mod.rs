@@ -1,12 +1,32 @@
-pub mod turbo_smtp;
-pub mod brevo_smtp;
+pub mod email_transport;
+pub mod email_templates;
+pub mod email_outbox;
 pub mod email_service;
+pub mod one_time_code_store;
 pub mod session_manager;
 pub mod token_blacklist;
 pub mod account_lockout;
 pub mod audit_logger;
 pub mod refresh_token_service;
 pub mod web3_challenge_service;
+pub mod siwe;
 pub mod scheduled_tasks;
 pub mod mfa_service;
+pub mod recovery_code_service;
+pub mod totp_secret_service;
+pub mod totp_service;
+pub mod password_hash_service;
+pub mod password_policy_service;
+pub mod passwordless_service;
+pub mod step_up_service;
+pub mod personal_access_token_service;
 pub mod cleanup_service;
+pub mod permission_service;
+pub mod ban_service;
+pub mod jwks_cache;
+pub mod google_id_token;
+pub mod oidc_service;
+pub mod identity_service;
+pub mod wallet_service;
+pub mod wallet_chains;
+pub mod webauthn_service;
@@ -0,0 +1,701 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{Duration as ChronoDuration, Utc};
+use jsonwebtoken::jwk::AlgorithmParameters;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use lazy_static::lazy_static;
+use rand::Rng;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::services::jwks_cache::{JwksCache, JwksError};
+
+/// How long a user has to finish signing in at the provider
+pub const LOGIN_STATE_TTL_MINUTES: i64 = 10;
+
+const DEFAULT_SCOPES: [&str; 3] = ["openid", "email", "profile"];
+const ID_TOKEN_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+lazy_static! {
+    static ref SHARED_REGISTRY: OidcRegistry = OidcRegistry::from_env();
+}
+
+/// Which provider claims fill which `users` fields. Dotted paths reach into nested claims
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ClaimMapping {
+    pub subject: String,
+    pub email: String,
+    pub email_verified: String,
+    pub username: String,
+    pub name: String,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            subject: "sub".to_string(),
+            email: "email".to_string(),
+            email_verified: "email_verified".to_string(),
+            username: "preferred_username".to_string(),
+            name: "name".to_string(),
+        }
+    }
+}
+
+/// One identity provider, as written in the TOML file or the OIDC_<NAME>_* variables
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OidcProviderConfig {
+    #[serde(skip)]
+    pub name: String,
+    /// Preset to start from: github, gitlab, microsoft, or oidc (plain discovery). Defaults to the name
+    pub kind: Option<String>,
+    pub display_name: Option<String>,
+    /// Discovery runs against `<issuer>/.well-known/openid-configuration`
+    pub issuer: Option<String>,
+    /// Microsoft Entra tenant ID, used to build the issuer
+    pub tenant: Option<String>,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub scopes: Vec<String>,
+    // Explicit endpoints win over discovery (and are required for plain OAuth2 providers like GitHub)
+    pub authorization_endpoint: Option<String>,
+    pub token_endpoint: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: Option<String>,
+    /// GitHub-style list of the user's addresses, used when the profile has no verified email
+    pub emails_endpoint: Option<String>,
+    /// Treat the email as verified when the provider sends no email_verified claim
+    pub trust_email: bool,
+    pub claims: ClaimMapping,
+}
+
+impl OidcProviderConfig {
+    /// Fill unset fields from the provider preset and check the result is usable
+    pub fn prepare(mut self, frontend_url: &str) -> Result<Self, String> {
+        if self.client_id.trim().is_empty() {
+            return Err(format!("OIDC provider '{}' has no client_id", self.name));
+        }
+
+        let kind = self.kind.clone().unwrap_or_else(|| self.name.clone());
+        match kind.as_str() {
+            "github" => {
+                self.authorization_endpoint
+                    .get_or_insert_with(|| "https://github.com/login/oauth/authorize".to_string());
+                self.token_endpoint
+                    .get_or_insert_with(|| "https://github.com/login/oauth/access_token".to_string());
+                self.userinfo_endpoint
+                    .get_or_insert_with(|| "https://api.github.com/user".to_string());
+                self.emails_endpoint
+                    .get_or_insert_with(|| "https://api.github.com/user/emails".to_string());
+                if self.scopes.is_empty() {
+                    self.scopes = vec!["read:user".to_string(), "user:email".to_string()];
+                }
+                if self.claims == ClaimMapping::default() {
+                    self.claims.subject = "id".to_string();
+                    self.claims.username = "login".to_string();
+                }
+            }
+            "gitlab" => {
+                self.issuer.get_or_insert_with(|| "https://gitlab.com".to_string());
+            }
+            "microsoft" if self.issuer.is_none() => {
+                // Entra's multi-tenant endpoints issue tokens with the caller's tenant as issuer,
+                // so the tenant has to be pinned for the issuer check to mean anything
+                let tenant = self.tenant.clone().ok_or_else(|| {
+                    format!("OIDC provider '{}' needs a tenant (or an explicit issuer)", self.name)
+                })?;
+                self.issuer = Some(format!("https://login.microsoftonline.com/{}/v2.0", tenant));
+            }
+            _ => {}
+        }
+
+        if self.issuer.is_none() && (self.authorization_endpoint.is_none() || self.token_endpoint.is_none()) {
+            return Err(format!(
+                "OIDC provider '{}' needs an issuer or explicit authorization and token endpoints",
+                self.name
+            ));
+        }
+
+        if self.scopes.is_empty() {
+            self.scopes = DEFAULT_SCOPES.iter().map(|s| s.to_string()).collect();
+        }
+        if self.redirect_uri.is_none() {
+            self.redirect_uri = Some(format!("{}/auth/oidc/{}/callback", frontend_url, self.name));
+        }
+        if self.display_name.is_none() {
+            let mut chars = self.name.chars();
+            self.display_name = chars.next().map(|c| c.to_uppercase().chain(chars).collect());
+        }
+        self.issuer = self.issuer.map(|issuer| issuer.trim_end_matches('/').to_string());
+
+        Ok(self)
+    }
+
+    /// Override fields from OIDC_<NAME>_CLIENT_ID, _CLIENT_SECRET, _ISSUER, _TENANT, _KIND,
+    /// _DISPLAY_NAME, _REDIRECT_URI, _SCOPES and _TRUST_EMAIL
+    fn apply_env(&mut self) {
+        let prefix = format!("OIDC_{}_", self.name.to_ascii_uppercase().replace('-', "_"));
+        let var = |key: &str| {
+            std::env::var(format!("{}{}", prefix, key))
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+        };
+
+        if let Some(client_id) = var("CLIENT_ID") {
+            self.client_id = client_id;
+        }
+        self.client_secret = var("CLIENT_SECRET").or(self.client_secret.take());
+        self.issuer = var("ISSUER").or(self.issuer.take());
+        self.tenant = var("TENANT").or(self.tenant.take());
+        self.kind = var("KIND").or(self.kind.take());
+        self.display_name = var("DISPLAY_NAME").or(self.display_name.take());
+        self.redirect_uri = var("REDIRECT_URI").or(self.redirect_uri.take());
+        if let Some(scopes) = var("SCOPES") {
+            self.scopes = scopes
+                .split([' ', ','])
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect();
+        }
+        if let Some(trust_email) = var("TRUST_EMAIL") {
+            self.trust_email = matches!(trust_email.to_ascii_lowercase().as_str(), "1" | "true" | "yes");
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OidcConfigFile {
+    #[serde(default)]
+    providers: BTreeMap<String, OidcProviderConfig>,
+}
+
+/// Endpoints after discovery and explicit overrides
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: Option<String>,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: Option<String>,
+}
+
+pub struct OidcProvider {
+    pub config: OidcProviderConfig,
+    metadata: RwLock<Option<ProviderMetadata>>,
+    jwks: JwksCache,
+}
+
+/// Public description of a provider, for the login page
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderSummary {
+    pub name: String,
+    pub display_name: String,
+}
+
+/// Where to send the browser to sign in
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthorizationRequest {
+    pub authorization_url: String,
+    pub state: String,
+}
+
+/// The account the provider vouched for, with claims already mapped
+#[derive(Debug, Clone, Serialize)]
+pub struct OidcIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub username: Option<String>,
+    pub name: Option<String>,
+    pub claims: Value,
+    /// Set when the login was started to link this provider to an already signed-in user
+    pub link_user_id: Option<i32>,
+}
+
+#[derive(Debug)]
+pub enum OidcError {
+    UnknownProvider(String),
+    InvalidState,
+    Provider(String),
+    TokenExchange(String),
+    InvalidIdToken(String),
+    MissingClaim(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for OidcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OidcError::UnknownProvider(name) => write!(f, "Unknown login provider '{}'", name),
+            OidcError::InvalidState => write!(f, "Login request is invalid or has expired"),
+            OidcError::Provider(e) => write!(f, "Identity provider error: {}", e),
+            OidcError::TokenExchange(e) => write!(f, "Authorization code exchange failed: {}", e),
+            OidcError::InvalidIdToken(e) => write!(f, "ID token rejected: {}", e),
+            OidcError::MissingClaim(claim) => write!(f, "Identity provider did not return '{}'", claim),
+            OidcError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for OidcError {}
+
+impl From<sqlx::Error> for OidcError {
+    fn from(e: sqlx::Error) -> Self {
+        OidcError::Database(e)
+    }
+}
+
+/// Configured identity providers and the authorization code + PKCE flow against them
+#[derive(Clone)]
+pub struct OidcRegistry {
+    providers: Arc<BTreeMap<String, Arc<OidcProvider>>>,
+    client: Client,
+}
+
+impl OidcRegistry {
+    /// Build from provider configs, skipping (and reporting) the ones that are incomplete
+    pub fn new(configs: Vec<OidcProviderConfig>, frontend_url: &str) -> Self {
+        let mut providers = BTreeMap::new();
+        for config in configs {
+            match config.prepare(frontend_url) {
+                Ok(config) => {
+                    providers.insert(
+                        config.name.clone(),
+                        Arc::new(OidcProvider { config, metadata: RwLock::new(None), jwks: JwksCache::default() }),
+                    );
+                }
+                Err(e) => eprintln!("Skipping OIDC provider: {}", e),
+            }
+        }
+
+        Self {
+            providers: Arc::new(providers),
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .unwrap_or_else(|_| Client::new()),
+        }
+    }
+
+    /// Providers from the TOML file in OIDC_CONFIG_FILE plus the ones named in OIDC_PROVIDERS
+    /// (comma separated), with OIDC_<NAME>_* variables overriding file values
+    pub fn from_env() -> Self {
+        let var = |key: &str| std::env::var(key).ok().filter(|v| !v.trim().is_empty());
+
+        let mut configs = match var("OIDC_CONFIG_FILE") {
+            Some(path) => match Self::read_config_file(&path) {
+                Ok(providers) => providers,
+                Err(e) => {
+                    eprintln!("Failed to load OIDC providers from {}: {}", path, e);
+                    BTreeMap::new()
+                }
+            },
+            None => BTreeMap::new(),
+        };
+
+        for name in var("OIDC_PROVIDERS").unwrap_or_default().split(',') {
+            let name = name.trim().to_ascii_lowercase();
+            if !name.is_empty() {
+                configs.entry(name).or_default();
+            }
+        }
+
+        let configs = configs
+            .into_iter()
+            .map(|(name, mut config)| {
+                config.name = name;
+                config.apply_env();
+                config
+            })
+            .collect();
+
+        let frontend_url = var("FRONTEND_URL").unwrap_or_else(|| "http://localhost:5173".to_string());
+        Self::new(configs, frontend_url.trim_end_matches('/'))
+    }
+
+    fn read_config_file(path: &str) -> Result<BTreeMap<String, OidcProviderConfig>, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file: OidcConfigFile = toml::from_str(&contents).map_err(|e| e.to_string())?;
+        Ok(file.providers)
+    }
+
+    /// Process-wide registry configured from the environment
+    pub fn shared() -> Self {
+        SHARED_REGISTRY.clone()
+    }
+
+    pub fn providers(&self) -> Vec<ProviderSummary> {
+        self.providers
+            .values()
+            .map(|p| ProviderSummary {
+                name: p.config.name.clone(),
+                display_name: p.config.display_name.clone().unwrap_or_else(|| p.config.name.clone()),
+            })
+            .collect()
+    }
+
+    fn provider(&self, name: &str) -> Result<&Arc<OidcProvider>, OidcError> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| OidcError::UnknownProvider(name.to_string()))
+    }
+
+    /// Create the state, nonce and PKCE verifier for a new login and build the URL to redirect to.
+    /// With `link_user_id` the round trip links the provider account to that user instead of signing in
+    pub async fn start_login(
+        &self,
+        pool: &PgPool,
+        provider_name: &str,
+        link_user_id: Option<i32>,
+    ) -> Result<AuthorizationRequest, OidcError> {
+        let provider = self.provider(provider_name)?;
+        let metadata = self.metadata(provider).await?;
+        let config = &provider.config;
+        let redirect_uri = config.redirect_uri.clone().unwrap_or_default();
+
+        let state = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        sqlx::query!(
+            "INSERT INTO oidc_login_states (state, provider, code_verifier, nonce, redirect_uri, link_user_id, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            state,
+            config.name,
+            code_verifier,
+            nonce,
+            redirect_uri,
+            link_user_id,
+            Utc::now().naive_utc(),
+            (Utc::now() + ChronoDuration::minutes(LOGIN_STATE_TTL_MINUTES)).naive_utc()
+        )
+        .execute(pool)
+        .await?;
+
+        let scope = config.scopes.join(" ");
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", config.client_id.as_str()),
+                ("redirect_uri", redirect_uri.as_str()),
+                ("scope", scope.as_str()),
+                ("state", state.as_str()),
+                ("nonce", nonce.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| OidcError::Provider(format!("invalid authorization endpoint: {}", e)))?;
+
+        Ok(AuthorizationRequest {
+            authorization_url: url.to_string(),
+            state,
+        })
+    }
+
+    /// Redeem the authorization code the provider redirected back with. The state is single use
+    pub async fn complete_login(
+        &self,
+        pool: &PgPool,
+        provider_name: &str,
+        code: &str,
+        state: &str,
+    ) -> Result<OidcIdentity, OidcError> {
+        let provider = self.provider(provider_name)?;
+        let config = &provider.config;
+
+        let pending = sqlx::query!(
+            "DELETE FROM oidc_login_states WHERE state = $1 AND provider = $2
+             RETURNING code_verifier, nonce, redirect_uri, link_user_id, expires_at",
+            state,
+            config.name
+        )
+        .fetch_optional(pool)
+        .await?
+        .filter(|row| row.expires_at > Utc::now().naive_utc())
+        .ok_or(OidcError::InvalidState)?;
+
+        let metadata = self.metadata(provider).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", pending.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", pending.code_verifier.as_str()),
+        ];
+        if let Some(secret) = config.client_secret.as_deref() {
+            form.push(("client_secret", secret));
+        }
+
+        let tokens: Value = self
+            .client
+            .post(&metadata.token_endpoint)
+            .header("Accept", "application/json")
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| OidcError::Provider(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| OidcError::TokenExchange(e.to_string()))?;
+
+        if let Some(error) = tokens.get("error").and_then(Value::as_str) {
+            let description = tokens.get("error_description").and_then(Value::as_str).unwrap_or(error);
+            return Err(OidcError::TokenExchange(description.to_string()));
+        }
+        let access_token = tokens
+            .get("access_token")
+            .and_then(Value::as_str)
+            .ok_or_else(|| OidcError::TokenExchange("no access_token in response".to_string()))?;
+
+        let mut claims = serde_json::Map::new();
+        match tokens.get("id_token").and_then(Value::as_str) {
+            Some(id_token) => {
+                let id_claims = self.verify_id_token(provider, &metadata, id_token, &pending.nonce).await?;
+                if let Value::Object(id_claims) = id_claims {
+                    claims = id_claims;
+                }
+            }
+            // Providers with an issuer speak OpenID Connect and must identify the user with an ID token
+            None if config.issuer.is_some() => {
+                return Err(OidcError::InvalidIdToken("no id_token in token response".to_string()));
+            }
+            None => {}
+        }
+
+        // Userinfo must describe the user of the ID token (OpenID Connect Core 5.3.2), and only
+        // adds claims the verified ID token lacks
+        if let Some(userinfo_endpoint) = metadata.userinfo_endpoint.as_deref() {
+            let userinfo: Value = self.get_json(userinfo_endpoint, access_token).await?;
+            if let Some(id_sub) = claims.get("sub")
+                && userinfo.get("sub") != Some(id_sub)
+            {
+                return Err(OidcError::InvalidIdToken("userinfo subject does not match the ID token".to_string()));
+            }
+            if let Value::Object(userinfo) = userinfo {
+                for (name, value) in userinfo {
+                    claims.entry(name).or_insert(value);
+                }
+            }
+        }
+
+        let mut claims = Value::Object(claims);
+        let mut email_verified = match lookup_claim(&claims, &config.claims.email_verified) {
+            Some(value) => value.as_bool().unwrap_or_else(|| value.as_str() == Some("true")),
+            None => config.trust_email,
+        };
+
+        if let Some(emails_endpoint) = config.emails_endpoint.as_deref()
+            && (!email_verified || lookup_claim(&claims, &config.claims.email).is_none())
+        {
+            #[derive(Deserialize)]
+            struct ProviderEmail {
+                email: String,
+                #[serde(default)]
+                primary: bool,
+                #[serde(default)]
+                verified: bool,
+            }
+
+            let emails: Vec<ProviderEmail> = serde_json::from_value(self.get_json(emails_endpoint, access_token).await?)
+                .map_err(|e| OidcError::Provider(e.to_string()))?;
+            if let Some(primary) = emails.into_iter().find(|e| e.primary && e.verified) {
+                claims[config.claims.email.as_str()] = Value::String(primary.email);
+                email_verified = true;
+            }
+        }
+
+        let string_claim = |path: &str| {
+            lookup_claim(&claims, path).and_then(|value| match value {
+                Value::String(s) if !s.is_empty() => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+        };
+
+        let subject = string_claim(&config.claims.subject)
+            .ok_or_else(|| OidcError::MissingClaim(config.claims.subject.clone()))?;
+
+        Ok(OidcIdentity {
+            provider: config.name.clone(),
+            subject,
+            email: string_claim(&config.claims.email),
+            email_verified,
+            username: string_claim(&config.claims.username),
+            name: string_claim(&config.claims.name),
+            claims,
+            link_user_id: pending.link_user_id,
+        })
+    }
+
+    /// Check the ID token signature against the provider's JWKS (cached), plus issuer, audience, expiry and nonce
+    async fn verify_id_token(
+        &self,
+        provider: &OidcProvider,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        expected_nonce: &str,
+    ) -> Result<Value, OidcError> {
+        let header = decode_header(id_token).map_err(|e| OidcError::InvalidIdToken(e.to_string()))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(OidcError::InvalidIdToken(format!("unsupported algorithm {:?}", header.alg)));
+        }
+
+        let config = &provider.config;
+        let jwks_uri = metadata
+            .jwks_uri
+            .as_deref()
+            .ok_or_else(|| OidcError::Provider("provider publishes no jwks_uri".to_string()))?;
+        let jwk = provider
+            .jwks
+            .find(&self.client, jwks_uri, header.kid.as_deref())
+            .await
+            .map_err(|e| match e {
+                JwksError::UnknownKey(_) => OidcError::InvalidIdToken("signing key not found in provider JWKS".to_string()),
+                e => OidcError::Provider(e.to_string()),
+            })?;
+
+        // Symmetric keys in a JWKS would let anyone holding the client secret mint tokens
+        if matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)) {
+            return Err(OidcError::InvalidIdToken("symmetric signing keys are not accepted".to_string()));
+        }
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| OidcError::InvalidIdToken(e.to_string()))?;
+
+        let issuer = metadata.issuer.clone().or_else(|| config.issuer.clone()).unwrap_or_default();
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[config.client_id.as_str()]);
+        validation.set_issuer(&[issuer.as_str()]);
+        validation.set_required_spec_claims(&["exp", "iat", "aud", "iss", "sub"]);
+
+        let claims = decode::<Value>(id_token, &key, &validation)
+            .map_err(|e| OidcError::InvalidIdToken(e.to_string()))?
+            .claims;
+
+        if claims.get("nonce").and_then(Value::as_str) != Some(expected_nonce) {
+            return Err(OidcError::InvalidIdToken("nonce mismatch".to_string()));
+        }
+
+        Ok(claims)
+    }
+
+    async fn get_json(&self, url: &str, access_token: &str) -> Result<Value, OidcError> {
+        let response = self
+            .client
+            .get(url)
+            .bearer_auth(access_token)
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .map_err(|e| OidcError::Provider(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(OidcError::Provider(format!("{} returned {}", url, response.status())));
+        }
+
+        response.json().await.map_err(|e| OidcError::Provider(e.to_string()))
+    }
+
+    /// Discovery document merged with explicit endpoints, fetched once per provider
+    async fn metadata(&self, provider: &OidcProvider) -> Result<ProviderMetadata, OidcError> {
+        if let Some(metadata) = provider.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+
+        let config = &provider.config;
+        let mut metadata = match (&config.authorization_endpoint, &config.token_endpoint) {
+            (Some(authorization_endpoint), Some(token_endpoint)) => ProviderMetadata {
+                issuer: config.issuer.clone(),
+                authorization_endpoint: authorization_endpoint.clone(),
+                token_endpoint: token_endpoint.clone(),
+                userinfo_endpoint: None,
+                jwks_uri: None,
+            },
+            _ => {
+                let issuer = config.issuer.clone().unwrap_or_default();
+                let url = format!("{}/.well-known/openid-configuration", issuer);
+                let discovered: ProviderMetadata = self
+                    .client
+                    .get(&url)
+                    .send()
+                    .await
+                    .map_err(|e| OidcError::Provider(format!("discovery failed: {}", e)))?
+                    .json()
+                    .await
+                    .map_err(|e| OidcError::Provider(format!("invalid discovery document: {}", e)))?;
+
+                // The discovery document must be for the issuer we trust (OpenID Connect Discovery 4.3)
+                if discovered.issuer.as_deref().map(|i| i.trim_end_matches('/')) != Some(issuer.as_str()) {
+                    return Err(OidcError::Provider(format!("discovery document is not for issuer {}", issuer)));
+                }
+                discovered
+            }
+        };
+
+        if let Some(endpoint) = &config.authorization_endpoint {
+            metadata.authorization_endpoint = endpoint.clone();
+        }
+        if let Some(endpoint) = &config.token_endpoint {
+            metadata.token_endpoint = endpoint.clone();
+        }
+        if config.userinfo_endpoint.is_some() {
+            metadata.userinfo_endpoint = config.userinfo_endpoint.clone();
+        }
+        if config.jwks_uri.is_some() {
+            metadata.jwks_uri = config.jwks_uri.clone();
+        }
+
+        *provider.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Delete login attempts that were never completed
+    pub async fn cleanup_expired_states(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM oidc_login_states WHERE expires_at < $1",
+            Utc::now().naive_utc()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Follow a dotted path (e.g. `profile.email`) through the claims
+fn lookup_claim<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(claims, |value, key| value.get(key))
+        .filter(|value| !value.is_null())
+}
+
+// 32 random bytes, base64url: valid as state, nonce and PKCE verifier (43 chars)
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
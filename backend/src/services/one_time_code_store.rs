@@ -0,0 +1,527 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use lazy_static::lazy_static;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+lazy_static! {
+    // Process-wide store used by EmailService, in-memory until main installs the configured backend
+    static ref CODE_STORE: RwLock<OneTimeCodeStore> = RwLock::new(OneTimeCodeStore::in_memory());
+
+    // Key for the code hashes, random until main derives it from the server secret
+    static ref CODE_KEY: RwLock<hmac::Key> = RwLock::new(
+        hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()).expect("Failed to generate code key"),
+    );
+
+    // Check, count the attempt and consume in one step, so concurrent guesses can't get past
+    // MAX_ATTEMPTS and two requests can't both use the same code.
+    // KEYS: code hash, lookup entry of the submitted code. ARGV: submitted code hash, MAX_ATTEMPTS
+    static ref REDIS_VERIFY_SCRIPT: redis::Script = redis::Script::new(
+        r"
+        local stored = redis.call('HMGET', KEYS[1], 'code_hash', 'attempts')
+        if not stored[1] then return 0 end
+        if tonumber(stored[2] or '0') >= tonumber(ARGV[2]) then return 0 end
+        if stored[1] ~= ARGV[1] then
+            redis.call('HINCRBY', KEYS[1], 'attempts', 1)
+            return 0
+        end
+        redis.call('DEL', KEYS[1], KEYS[2])
+        return 1
+        ",
+    );
+}
+
+/// What a one-time code was issued for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CodePurpose {
+    EmailVerification, // subject: normalized email
+    MfaEmail,          // subject: user id
+    PasswordReset,     // subject: email, looked up by the token itself
+    PasswordlessCode,  // subject: user id
+    PasswordlessLink,  // subject: user id, the nonce of the signed link
+    StepUpEmail,       // subject: user id
+}
+
+impl CodePurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CodePurpose::EmailVerification => "email_verification",
+            CodePurpose::MfaEmail => "mfa_email",
+            CodePurpose::PasswordReset => "password_reset",
+            CodePurpose::PasswordlessCode => "passwordless_code",
+            CodePurpose::PasswordlessLink => "passwordless_link",
+            CodePurpose::StepUpEmail => "step_up_email",
+        }
+    }
+
+    // Codes found by their value alone (see OneTimeCodeStore::consume) can't be bound to the subject
+    fn looked_up_by_code(&self) -> bool {
+        matches!(self, CodePurpose::PasswordReset)
+    }
+}
+
+#[derive(Debug)]
+pub enum OneTimeCodeError {
+    Database(sqlx::Error),
+    Redis(redis::RedisError),
+}
+
+impl std::fmt::Display for OneTimeCodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OneTimeCodeError::Database(e) => write!(f, "One-time code database error: {}", e),
+            OneTimeCodeError::Redis(e) => write!(f, "One-time code Redis error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for OneTimeCodeError {}
+
+impl From<sqlx::Error> for OneTimeCodeError {
+    fn from(e: sqlx::Error) -> Self {
+        OneTimeCodeError::Database(e)
+    }
+}
+
+impl From<redis::RedisError> for OneTimeCodeError {
+    fn from(e: redis::RedisError) -> Self {
+        OneTimeCodeError::Redis(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredCode {
+    code_hash: String,
+    attempts: i32,
+    expires_at: DateTime<Utc>,
+}
+
+/// Storage for hashed, expiring, single-use codes.
+///
+/// Backed by the `one_time_codes` table, by Redis, or by process memory
+/// (single instance / tests only). Selected with `ONE_TIME_CODE_STORE`.
+#[derive(Clone)]
+pub enum OneTimeCodeStore {
+    Postgres(PgPool),
+    Redis(MultiplexedConnection),
+    Memory(Arc<Mutex<HashMap<(CodePurpose, String), StoredCode>>>),
+}
+
+impl OneTimeCodeStore {
+    pub const MAX_ATTEMPTS: i32 = 5; // Wrong guesses before the code is burned
+
+    pub fn postgres(pool: PgPool) -> Self {
+        OneTimeCodeStore::Postgres(pool)
+    }
+
+    pub async fn redis(redis_url: &str) -> Result<Self, OneTimeCodeError> {
+        let client = redis::Client::open(redis_url)?;
+        let connection = client.get_multiplexed_async_connection().await?;
+
+        Ok(OneTimeCodeStore::Redis(connection))
+    }
+
+    pub fn in_memory() -> Self {
+        OneTimeCodeStore::Memory(Arc::new(Mutex::new(HashMap::new())))
+    }
+
+    /// Build the store selected by `ONE_TIME_CODE_STORE` (postgres, redis or memory)
+    pub async fn from_env(pool: &PgPool, redis_url: &str) -> Result<Self, OneTimeCodeError> {
+        let backend = std::env::var("ONE_TIME_CODE_STORE").unwrap_or_else(|_| "postgres".to_string());
+
+        match backend.to_lowercase().as_str() {
+            "redis" => Self::redis(redis_url).await,
+            "memory" => Ok(Self::in_memory()),
+            _ => Ok(Self::postgres(pool.clone())),
+        }
+    }
+
+    /// Make this store the one used by EmailService
+    pub fn install(store: OneTimeCodeStore) {
+        *CODE_STORE.write().unwrap() = store;
+    }
+
+    /// Currently installed store
+    pub fn current() -> OneTimeCodeStore {
+        CODE_STORE.read().unwrap().clone()
+    }
+
+    /// Key the code hashes with the server secret. Instances sharing a store need the same secret
+    pub fn install_secret(secret: &str) {
+        let derived = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()), b"one-time-codes");
+        *CODE_KEY.write().unwrap() = hmac::Key::new(hmac::HMAC_SHA256, derived.as_ref());
+    }
+
+    /// HMAC-SHA256 of `purpose:subject:code`, so stored hashes can't be brute-forced without the
+    /// server secret or moved to another subject. Subject is left empty for codes looked up by value
+    pub fn hash_code(purpose: CodePurpose, subject: &str, code: &str) -> String {
+        let subject = if purpose.looked_up_by_code() { "" } else { subject };
+        let message = format!("{}:{}:{}", purpose.as_str(), subject, code);
+        hex::encode(hmac::sign(&CODE_KEY.read().unwrap(), message.as_bytes()))
+    }
+
+    fn redis_key(purpose: CodePurpose, subject: &str) -> String {
+        format!("otc:{}:{}", purpose.as_str(), subject)
+    }
+
+    fn redis_lookup_key(purpose: CodePurpose, code_hash: &str) -> String {
+        format!("otc_lookup:{}:{}", purpose.as_str(), code_hash)
+    }
+
+    /// Store a code for a subject, replacing any previous code for it
+    pub async fn put(
+        &self,
+        purpose: CodePurpose,
+        subject: &str,
+        code: &str,
+        ttl: Duration,
+    ) -> Result<(), OneTimeCodeError> {
+        let code_hash = Self::hash_code(purpose, subject, code);
+        let expires_at = Utc::now() + ttl;
+
+        match self {
+            OneTimeCodeStore::Postgres(pool) => {
+                sqlx::query!(
+                    "INSERT INTO one_time_codes (purpose, subject, code_hash, attempts, expires_at)
+                     VALUES ($1, $2, $3, 0, $4)
+                     ON CONFLICT (purpose, subject) DO UPDATE
+                     SET code_hash = EXCLUDED.code_hash, attempts = 0, expires_at = EXCLUDED.expires_at, created_at = CURRENT_TIMESTAMP",
+                    purpose.as_str(),
+                    subject,
+                    code_hash,
+                    expires_at.naive_utc()
+                )
+                .execute(pool)
+                .await?;
+            }
+            OneTimeCodeStore::Redis(connection) => {
+                let key = Self::redis_key(purpose, subject);
+                let mut conn = connection.clone();
+
+                // Drop the lookup entry of the code being replaced
+                let old_hash: Option<String> = conn.hget(&key, "code_hash").await?;
+                let ttl_seconds = ttl.num_seconds().max(1);
+
+                let mut pipe = redis::pipe();
+                pipe.atomic();
+                if let Some(old_hash) = old_hash {
+                    pipe.cmd("DEL").arg(Self::redis_lookup_key(purpose, &old_hash)).ignore();
+                }
+                pipe.cmd("DEL").arg(&key).ignore()
+                    .cmd("HSET").arg(&key)
+                    .arg("code_hash").arg(&code_hash)
+                    .arg("attempts").arg(0)
+                    .arg("expires_at").arg(expires_at.timestamp())
+                    .ignore()
+                    .cmd("EXPIRE").arg(&key).arg(ttl_seconds).ignore()
+                    .cmd("SET").arg(Self::redis_lookup_key(purpose, &code_hash)).arg(subject)
+                    .arg("EX").arg(ttl_seconds)
+                    .ignore();
+
+                let _: () = pipe.query_async(&mut conn).await?;
+            }
+            OneTimeCodeStore::Memory(codes) => {
+                let mut codes = codes.lock().unwrap();
+                codes.insert((purpose, subject.to_string()), StoredCode { code_hash, attempts: 0, expires_at });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check a code and consume it on success.
+    /// Failed checks count towards MAX_ATTEMPTS, after which the code no longer verifies.
+    pub async fn verify(
+        &self,
+        purpose: CodePurpose,
+        subject: &str,
+        code: &str,
+    ) -> Result<bool, OneTimeCodeError> {
+        let code_hash = Self::hash_code(purpose, subject, code);
+
+        match self {
+            OneTimeCodeStore::Postgres(pool) => {
+                let now = Utc::now().naive_utc();
+
+                // Deleting the row is what makes the code single-use
+                let consumed = sqlx::query!(
+                    "DELETE FROM one_time_codes
+                     WHERE purpose = $1 AND subject = $2 AND code_hash = $3 AND attempts < $4 AND expires_at > $5
+                     RETURNING id",
+                    purpose.as_str(),
+                    subject,
+                    code_hash,
+                    Self::MAX_ATTEMPTS,
+                    now
+                )
+                .fetch_optional(pool)
+                .await?;
+
+                if consumed.is_some() {
+                    return Ok(true);
+                }
+
+                sqlx::query!(
+                    "UPDATE one_time_codes SET attempts = attempts + 1
+                     WHERE purpose = $1 AND subject = $2",
+                    purpose.as_str(),
+                    subject
+                )
+                .execute(pool)
+                .await?;
+
+                Ok(false)
+            }
+            OneTimeCodeStore::Redis(connection) => {
+                let key = Self::redis_key(purpose, subject);
+                let mut conn = connection.clone();
+
+                let consumed: i64 = REDIS_VERIFY_SCRIPT
+                    .key(&key)
+                    .key(Self::redis_lookup_key(purpose, &code_hash))
+                    .arg(&code_hash)
+                    .arg(Self::MAX_ATTEMPTS)
+                    .invoke_async(&mut conn)
+                    .await?;
+
+                Ok(consumed == 1)
+            }
+            OneTimeCodeStore::Memory(codes) => {
+                let mut codes = codes.lock().unwrap();
+                let key = (purpose, subject.to_string());
+
+                let stored = match codes.get_mut(&key) {
+                    Some(stored) => stored,
+                    None => return Ok(false),
+                };
+
+                if Utc::now() > stored.expires_at || stored.attempts >= Self::MAX_ATTEMPTS {
+                    return Ok(false);
+                }
+
+                if stored.code_hash != code_hash {
+                    stored.attempts += 1;
+                    return Ok(false);
+                }
+
+                codes.remove(&key);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Consume a code by its value alone and return the subject it was issued for
+    pub async fn consume(
+        &self,
+        purpose: CodePurpose,
+        code: &str,
+    ) -> Result<Option<String>, OneTimeCodeError> {
+        let code_hash = Self::hash_code(purpose, "", code);
+
+        match self {
+            OneTimeCodeStore::Postgres(pool) => {
+                let now = Utc::now().naive_utc();
+
+                let record = sqlx::query!(
+                    "DELETE FROM one_time_codes
+                     WHERE purpose = $1 AND code_hash = $2 AND expires_at > $3
+                     RETURNING subject",
+                    purpose.as_str(),
+                    code_hash,
+                    now
+                )
+                .fetch_optional(pool)
+                .await?;
+
+                Ok(record.map(|r| r.subject))
+            }
+            OneTimeCodeStore::Redis(connection) => {
+                let mut conn = connection.clone();
+
+                let subject: Option<String> = redis::cmd("GETDEL")
+                    .arg(Self::redis_lookup_key(purpose, &code_hash))
+                    .query_async(&mut conn)
+                    .await?;
+
+                let subject = match subject {
+                    Some(subject) => subject,
+                    None => return Ok(None),
+                };
+
+                let key = Self::redis_key(purpose, &subject);
+                let stored_hash: Option<String> = conn.hget(&key, "code_hash").await?;
+                if stored_hash.as_deref() != Some(code_hash.as_str()) {
+                    return Ok(None);
+                }
+
+                let deleted: i64 = conn.del(&key).await?;
+                Ok(if deleted > 0 { Some(subject) } else { None })
+            }
+            OneTimeCodeStore::Memory(codes) => {
+                let mut codes = codes.lock().unwrap();
+                let now = Utc::now();
+
+                let key = codes
+                    .iter()
+                    .find(|((p, _), stored)| *p == purpose && stored.code_hash == code_hash && stored.expires_at > now)
+                    .map(|(key, _)| key.clone());
+
+                Ok(key.map(|key| {
+                    codes.remove(&key);
+                    key.1
+                }))
+            }
+        }
+    }
+
+    /// Expiry of the code stored for a subject, if any (may already be in the past)
+    pub async fn expires_at(
+        &self,
+        purpose: CodePurpose,
+        subject: &str,
+    ) -> Result<Option<DateTime<Utc>>, OneTimeCodeError> {
+        match self {
+            OneTimeCodeStore::Postgres(pool) => {
+                let record = sqlx::query!(
+                    "SELECT expires_at FROM one_time_codes WHERE purpose = $1 AND subject = $2",
+                    purpose.as_str(),
+                    subject
+                )
+                .fetch_optional(pool)
+                .await?;
+
+                Ok(record.map(|r| Utc.from_utc_datetime(&r.expires_at)))
+            }
+            OneTimeCodeStore::Redis(connection) => {
+                let mut conn = connection.clone();
+                let expires_at: Option<i64> = conn.hget(Self::redis_key(purpose, subject), "expires_at").await?;
+
+                Ok(expires_at.and_then(|ts| Utc.timestamp_opt(ts, 0).single()))
+            }
+            OneTimeCodeStore::Memory(codes) => {
+                let codes = codes.lock().unwrap();
+                Ok(codes.get(&(purpose, subject.to_string())).map(|stored| stored.expires_at))
+            }
+        }
+    }
+
+    /// Remove the code stored for a subject
+    pub async fn remove(&self, purpose: CodePurpose, subject: &str) -> Result<(), OneTimeCodeError> {
+        match self {
+            OneTimeCodeStore::Postgres(pool) => {
+                sqlx::query!(
+                    "DELETE FROM one_time_codes WHERE purpose = $1 AND subject = $2",
+                    purpose.as_str(),
+                    subject
+                )
+                .execute(pool)
+                .await?;
+            }
+            OneTimeCodeStore::Redis(connection) => {
+                let key = Self::redis_key(purpose, subject);
+                let mut conn = connection.clone();
+
+                let code_hash: Option<String> = conn.hget(&key, "code_hash").await?;
+                if let Some(code_hash) = code_hash {
+                    let _: i64 = conn.del(Self::redis_lookup_key(purpose, &code_hash)).await?;
+                }
+                let _: i64 = conn.del(&key).await?;
+            }
+            OneTimeCodeStore::Memory(codes) => {
+                let mut codes = codes.lock().unwrap();
+                codes.remove(&(purpose, subject.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Subjects holding an unexpired code, with their expiry (never the codes themselves)
+    #[cfg(feature = "debug-endpoints")]
+    pub async fn active_subjects(
+        &self,
+        purpose: CodePurpose,
+    ) -> Result<HashMap<String, DateTime<Utc>>, OneTimeCodeError> {
+        let now = Utc::now();
+
+        match self {
+            OneTimeCodeStore::Postgres(pool) => {
+                let records = sqlx::query!(
+                    "SELECT subject, expires_at FROM one_time_codes WHERE purpose = $1 AND expires_at > $2",
+                    purpose.as_str(),
+                    now.naive_utc()
+                )
+                .fetch_all(pool)
+                .await?;
+
+                Ok(records
+                    .into_iter()
+                    .map(|r| (r.subject, Utc.from_utc_datetime(&r.expires_at)))
+                    .collect())
+            }
+            OneTimeCodeStore::Redis(connection) => {
+                let prefix = Self::redis_key(purpose, "");
+                let mut conn = connection.clone();
+
+                let mut keys = Vec::new();
+                {
+                    let mut iter = conn.scan_match::<_, String>(format!("{}*", prefix)).await?;
+                    while let Some(key) = iter.next_item().await {
+                        keys.push(key);
+                    }
+                }
+
+                let mut result = HashMap::new();
+                for key in keys {
+                    let expires_at: Option<i64> = conn.hget(&key, "expires_at").await?;
+                    if let Some(expires_at) = expires_at.and_then(|ts| Utc.timestamp_opt(ts, 0).single()) {
+                        result.insert(key[prefix.len()..].to_string(), expires_at);
+                    }
+                }
+
+                Ok(result)
+            }
+            OneTimeCodeStore::Memory(codes) => {
+                let codes = codes.lock().unwrap();
+
+                Ok(codes
+                    .iter()
+                    .filter(|((p, _), stored)| *p == purpose && stored.expires_at > now)
+                    .map(|((_, subject), stored)| (subject.clone(), stored.expires_at))
+                    .collect())
+            }
+        }
+    }
+
+    /// Delete expired codes and return how many were removed (Redis expires keys itself)
+    pub async fn cleanup_expired(&self) -> Result<u64, OneTimeCodeError> {
+        match self {
+            OneTimeCodeStore::Postgres(pool) => {
+                let now = Utc::now().naive_utc();
+
+                let result = sqlx::query!(
+                    "DELETE FROM one_time_codes WHERE expires_at < $1",
+                    now
+                )
+                .execute(pool)
+                .await?;
+
+                Ok(result.rows_affected())
+            }
+            OneTimeCodeStore::Redis(_) => Ok(0),
+            OneTimeCodeStore::Memory(codes) => {
+                let mut codes = codes.lock().unwrap();
+                let now = Utc::now();
+                let before = codes.len();
+
+                codes.retain(|_, stored| stored.expires_at > now);
+
+                Ok((before - codes.len()) as u64)
+            }
+        }
+    }
+}
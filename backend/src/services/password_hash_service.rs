@@ -0,0 +1,158 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use lazy_static::lazy_static;
+use rand::RngCore;
+use tokio::sync::Semaphore;
+
+use crate::utils::auth::AuthError;
+
+/// Argon2id cost for new hashes (default: 19 MiB, 2 iterations, 1 lane, the OWASP minimum).
+/// Stored hashes carry their own parameters, so changing these only affects new hashes and
+/// those upgraded at login
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordHashConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashConfig {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+lazy_static! {
+    static ref SHARED_CONFIG: PasswordHashConfig = PasswordHashConfig::from_env();
+    // Process-wide, so a burst of logins queues here instead of tying up the blocking pool
+    static ref HASHING_PERMITS: Semaphore = Semaphore::new(max_concurrent_hashes());
+}
+
+impl PasswordHashConfig {
+    pub fn from_env() -> Self {
+        // Empty values (as left by .env.example) count as unset, invalid ones fall back to the default
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.trim().parse::<u32>().ok());
+        let defaults = Self::default();
+
+        let config = Self {
+            memory_kib: var("PASSWORD_ARGON2_MEMORY_KIB").unwrap_or(defaults.memory_kib),
+            iterations: var("PASSWORD_ARGON2_ITERATIONS").unwrap_or(defaults.iterations),
+            parallelism: var("PASSWORD_ARGON2_PARALLELISM").unwrap_or(defaults.parallelism),
+        };
+        if config.params().is_err() {
+            eprintln!("Invalid Argon2 parameters {:?}, using the defaults", config);
+            return defaults;
+        }
+        config
+    }
+
+    pub fn shared() -> Self {
+        SHARED_CONFIG.clone()
+    }
+
+    fn params(&self) -> Result<Params, AuthError> {
+        Params::new(self.memory_kib, self.iterations, self.parallelism, None).map_err(|_| AuthError::HashingError)
+    }
+}
+
+// PASSWORD_HASH_CONCURRENCY, or one per CPU
+fn max_concurrent_hashes() -> usize {
+    std::env::var("PASSWORD_HASH_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(2, |n| n.get()))
+}
+
+/// How a stored password hash was made, read from its prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordHashScheme {
+    /// `$argon2id$...`, used for everything new
+    Argon2id,
+    /// `$2b$...` from before Argon2, only verified and then replaced
+    Bcrypt,
+}
+
+impl PasswordHashScheme {
+    pub fn of(stored: &str) -> Option<Self> {
+        if stored.starts_with("$argon2id$") {
+            Some(Self::Argon2id)
+        } else if ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| stored.starts_with(prefix)) {
+            Some(Self::Bcrypt)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct PasswordHashService;
+
+impl PasswordHashService {
+    /// Hash a new password with Argon2id, off the request thread
+    pub async fn hash(config: &PasswordHashConfig, password: &str) -> Result<String, AuthError> {
+        let config = config.clone();
+        let password = password.to_string();
+        run_blocking(move || Self::hash_blocking(&config, &password)).await?
+    }
+
+    /// Check a password against a stored Argon2id or bcrypt hash, off the request thread
+    pub async fn verify(password: &str, stored: &str) -> Result<bool, AuthError> {
+        let password = password.to_string();
+        let stored = stored.to_string();
+        run_blocking(move || Self::verify_blocking(&password, &stored)).await?
+    }
+
+    pub fn hash_blocking(config: &PasswordHashConfig, password: &str) -> Result<String, AuthError> {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let salt = SaltString::encode_b64(&salt).map_err(|_| AuthError::HashingError)?;
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, config.params()?)
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|_| AuthError::HashingError)
+    }
+
+    pub fn verify_blocking(password: &str, stored: &str) -> Result<bool, AuthError> {
+        match PasswordHashScheme::of(stored) {
+            Some(PasswordHashScheme::Argon2id) => {
+                let hash = PasswordHash::new(stored).map_err(|_| AuthError::InvalidCredentials)?;
+                // The parameters come from the stored hash
+                Ok(Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+            }
+            Some(PasswordHashScheme::Bcrypt) => {
+                bcrypt::verify(password, stored).map_err(|_| AuthError::InvalidCredentials)
+            }
+            None => Err(AuthError::InvalidCredentials),
+        }
+    }
+
+    /// Whether a hash that just verified should be replaced: anything not Argon2id, or made
+    /// with other parameters than configured now
+    pub fn needs_rehash(config: &PasswordHashConfig, stored: &str) -> bool {
+        if PasswordHashScheme::of(stored) != Some(PasswordHashScheme::Argon2id) {
+            return true;
+        }
+        let Ok(hash) = PasswordHash::new(stored) else {
+            return true;
+        };
+        let current = hash.version == Some(Version::V0x13.into())
+            && Params::try_from(&hash).is_ok_and(|params| {
+                params.m_cost() == config.memory_kib
+                    && params.t_cost() == config.iterations
+                    && params.p_cost() == config.parallelism
+                    && params.output_len().is_none_or(|len| len == Params::DEFAULT_OUTPUT_LEN)
+            });
+        !current
+    }
+}
+
+// Run on tokio's blocking pool, at most `PASSWORD_HASH_CONCURRENCY` at a time
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T, AuthError> {
+    let _permit = HASHING_PERMITS.acquire().await.map_err(|_| AuthError::HashingError)?;
+    tokio::task::spawn_blocking(f).await.map_err(|_| AuthError::HashingError)
+}
@@ -0,0 +1,286 @@
+use std::path::PathBuf;
+
+use lazy_static::lazy_static;
+use ring::digest::{SHA1_FOR_LEGACY_USE_ONLY, digest};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::services::password_hash_service::PasswordHashService;
+use crate::utils::password_strength::strength_score;
+
+/// Rules for new passwords, from the TOML file at `PASSWORD_POLICY_FILE` (see
+/// backend/password_policy.example.toml). Missing keys keep their defaults
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// 0 to 4, see `utils::password_strength`. 0, the default, turns it off
+    pub min_strength_score: u8,
+    /// Reject passwords containing the username or email
+    pub reject_personal_info: bool,
+    /// Directory of SHA1 range files, one per 5 character hash prefix, each line a
+    /// `SUFFIX:COUNT` pair as served by the Pwned Passwords range API
+    pub breached_passwords_dir: Option<PathBuf>,
+    /// Passwords found at least this often in the breach list are rejected
+    pub breached_min_count: u64,
+    /// How many recent passwords, the current one included, can't be used again. 0 turns it off
+    pub history_size: u32,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: false,
+            min_strength_score: 0,
+            reject_personal_info: true,
+            breached_passwords_dir: None,
+            breached_min_count: 1,
+            history_size: 5,
+        }
+    }
+}
+
+lazy_static! {
+    static ref SHARED_POLICY: PasswordPolicy = PasswordPolicy::from_env();
+}
+
+/// Who the new password is for
+#[derive(Debug, Default)]
+pub struct PasswordOwner<'a> {
+    /// For the history check, `None` when registering
+    pub user_id: Option<i32>,
+    /// Hash of the password being replaced
+    pub current_hash: Option<&'a str>,
+    pub username: Option<&'a str>,
+    pub email: Option<&'a str>,
+}
+
+#[derive(Debug)]
+pub enum PasswordPolicyError {
+    /// The password breaks the policy, with a message for the user
+    Rejected(String),
+    Database(sqlx::Error),
+    Hashing,
+}
+
+impl std::fmt::Display for PasswordPolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordPolicyError::Rejected(reason) => write!(f, "{}", reason),
+            PasswordPolicyError::Database(e) => write!(f, "Database error: {}", e),
+            PasswordPolicyError::Hashing => write!(f, "Password hashing error"),
+        }
+    }
+}
+
+impl std::error::Error for PasswordPolicyError {}
+
+impl From<sqlx::Error> for PasswordPolicyError {
+    fn from(e: sqlx::Error) -> Self {
+        PasswordPolicyError::Database(e)
+    }
+}
+
+impl PasswordPolicy {
+    /// Read `PASSWORD_POLICY_FILE`. Without it, or if it can't be read, the defaults apply
+    pub fn from_env() -> Self {
+        let Some(path) = std::env::var("PASSWORD_POLICY_FILE").ok().filter(|v| !v.trim().is_empty()) else {
+            return Self::default();
+        };
+        match Self::read_file(&path) {
+            Ok(policy) => policy,
+            Err(e) => {
+                eprintln!("Failed to load password policy from {}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    fn read_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let policy: Self = toml::from_str(contents).map_err(|e| e.to_string())?;
+        if policy.min_length == 0 || policy.min_length > policy.max_length {
+            return Err("min_length must be between 1 and max_length".to_string());
+        }
+        if policy.min_strength_score > 4 {
+            return Err("min_strength_score must be between 0 and 4".to_string());
+        }
+        Ok(policy)
+    }
+
+    pub fn shared() -> Self {
+        SHARED_POLICY.clone()
+    }
+
+    /// The rules that need nothing but the password and who it is for
+    pub fn check(&self, password: &str, username: Option<&str>, email: Option<&str>) -> Result<(), String> {
+        let length = password.chars().count();
+        if length == 0 {
+            return Err("Password cannot be empty".to_string());
+        }
+        if length < self.min_length {
+            return Err(format!("Password must be at least {} characters", self.min_length));
+        }
+        if length > self.max_length {
+            return Err(format!("Password must not exceed {} characters", self.max_length));
+        }
+
+        let classes = [
+            (self.require_uppercase, password.chars().any(char::is_uppercase), "an uppercase letter"),
+            (self.require_lowercase, password.chars().any(char::is_lowercase), "a lowercase letter"),
+            (self.require_digit, password.chars().any(char::is_numeric), "a number"),
+            (self.require_symbol, password.chars().any(|c| !c.is_alphanumeric()), "a symbol"),
+        ];
+        if let Some((_, _, class)) = classes.iter().find(|(required, present, _)| *required && !present) {
+            return Err(format!("Password must contain at least one {}", class));
+        }
+
+        // The email's local part too, as that is often the username elsewhere
+        let personal: Vec<&str> = username
+            .into_iter()
+            .chain(email)
+            .chain(email.and_then(|e| e.split('@').next()))
+            .map(str::trim)
+            .filter(|s| s.chars().count() >= 3)
+            .collect();
+
+        if self.reject_personal_info {
+            let lower = password.to_lowercase();
+            if personal.iter().any(|p| lower.contains(&p.to_lowercase())) {
+                return Err("Password must not contain your username or email".to_string());
+            }
+        }
+
+        if self.min_strength_score > 0 && strength_score(password, &personal) < self.min_strength_score {
+            return Err("Password is too easy to guess. Try a longer phrase or fewer common words".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// How often the password appears in the breach list, 0 if it doesn't or there is no list
+    pub async fn breach_count(&self, password: &str) -> u64 {
+        let Some(dir) = &self.breached_passwords_dir else {
+            return 0;
+        };
+
+        let hash = hex::encode_upper(digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+
+        // Only the file for this prefix is read
+        let contents = match tokio::fs::read_to_string(dir.join(prefix)).await {
+            Ok(contents) => contents,
+            Err(_) => match tokio::fs::read_to_string(dir.join(format!("{}.txt", prefix))).await {
+                Ok(contents) => contents,
+                Err(_) => return 0,
+            },
+        };
+
+        contents
+            .lines()
+            .filter_map(|line| line.trim().split_once(':'))
+            .find(|(line_suffix, _)| line_suffix.eq_ignore_ascii_case(suffix))
+            .map(|(_, count)| count.trim().parse().unwrap_or(1))
+            .unwrap_or(0)
+    }
+}
+
+pub struct PasswordPolicyService;
+
+impl PasswordPolicyService {
+    /// Check a new password against the policy, the breach list and the owner's recent passwords
+    pub async fn check(
+        pool: &PgPool,
+        policy: &PasswordPolicy,
+        password: &str,
+        owner: &PasswordOwner<'_>,
+    ) -> Result<(), PasswordPolicyError> {
+        policy
+            .check(password, owner.username, owner.email)
+            .map_err(PasswordPolicyError::Rejected)?;
+
+        if policy.breach_count(password).await >= policy.breached_min_count.max(1) {
+            return Err(PasswordPolicyError::Rejected(
+                "This password has appeared in a data breach. Please choose a different one".to_string(),
+            ));
+        }
+
+        if let Some(user_id) = owner.user_id
+            && policy.history_size > 0
+        {
+            let recent = Self::recent_hashes(pool, user_id, owner.current_hash, policy.history_size).await?;
+            for hash in recent {
+                if PasswordHashService::verify(password, &hash).await.map_err(|_| PasswordPolicyError::Hashing)? {
+                    return Err(PasswordPolicyError::Rejected(format!(
+                        "Password was used recently. Please choose one you haven't used in your last {} passwords",
+                        policy.history_size
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The current hash and the ones before it, newest first
+    async fn recent_hashes(
+        pool: &PgPool,
+        user_id: i32,
+        current_hash: Option<&str>,
+        history_size: u32,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let current: Vec<String> = current_hash.filter(|h| !h.is_empty()).map(str::to_string).into_iter().collect();
+        let previous = sqlx::query_scalar!(
+            "SELECT password_hash FROM password_history WHERE user_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2",
+            user_id,
+            i64::from(history_size) - current.len() as i64
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(current.into_iter().chain(previous).collect())
+    }
+
+    /// Remember the hash being replaced, keeping only what the history check needs
+    pub async fn remember(pool: &PgPool, policy: &PasswordPolicy, user_id: i32, old_hash: &str) -> Result<(), sqlx::Error> {
+        if old_hash.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query!(
+            "INSERT INTO password_history (user_id, password_hash) VALUES ($1, $2)",
+            user_id,
+            old_hash
+        )
+        .execute(pool)
+        .await?;
+
+        // The current password is checked from users, so one fewer is kept here
+        sqlx::query!(
+            "DELETE FROM password_history
+             WHERE user_id = $1 AND id NOT IN (
+                 SELECT id FROM password_history WHERE user_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2
+             )",
+            user_id,
+            i64::from(policy.history_size.saturating_sub(1))
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
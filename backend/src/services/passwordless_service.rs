@@ -0,0 +1,96 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use ring::hmac;
+
+use crate::services::email_service::EmailService;
+use crate::services::one_time_code_store::{CodePurpose, OneTimeCodeError, OneTimeCodeStore};
+
+/// What goes into a sign-in email: a link for this device and a code for another one
+#[derive(Debug)]
+pub struct PasswordlessLogin {
+    pub token: String,
+    pub code: String,
+}
+
+/// Email sign-in without a password. Each email carries a signed link and a 6-digit code, both
+/// single-use and short-lived; redeeming either one burns the other
+pub struct PasswordlessService;
+
+impl PasswordlessService {
+    /// A new email is only sent once the previous one is this old
+    pub const RESEND_COOLDOWN_SECONDS: i64 = 60;
+
+    /// New link and code for the user, or `None` while the last email is still within the cooldown
+    pub async fn issue(user_id: i32, secret: &str) -> Result<Option<PasswordlessLogin>, OneTimeCodeError> {
+        let store = OneTimeCodeStore::current();
+        let subject = user_id.to_string();
+        let expiry = Duration::minutes(EmailService::PASSWORDLESS_LOGIN_EXPIRY_MINUTES);
+
+        if let Some(expires_at) = store.expires_at(CodePurpose::PasswordlessCode, &subject).await?
+            && expires_at - expiry + Duration::seconds(Self::RESEND_COOLDOWN_SECONDS) > Utc::now()
+        {
+            return Ok(None);
+        }
+
+        let mut nonce = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = URL_SAFE_NO_PAD.encode(nonce);
+        let code = EmailService::generate_verification_code();
+
+        store.put(CodePurpose::PasswordlessLink, &subject, &nonce, expiry).await?;
+        store.put(CodePurpose::PasswordlessCode, &subject, &code, expiry).await?;
+
+        Ok(Some(PasswordlessLogin { token: sign(user_id, &nonce, secret), code }))
+    }
+
+    /// The user a sign-in link was issued for, if it is genuine, unexpired and unused
+    pub async fn redeem_token(token: &str, secret: &str) -> Result<Option<i32>, OneTimeCodeError> {
+        let Some((user_id, nonce)) = verify_signature(token, secret) else {
+            return Ok(None);
+        };
+        let store = OneTimeCodeStore::current();
+        let subject = user_id.to_string();
+
+        if !store.verify(CodePurpose::PasswordlessLink, &subject, &nonce).await? {
+            return Ok(None);
+        }
+        store.remove(CodePurpose::PasswordlessCode, &subject).await?;
+        Ok(Some(user_id))
+    }
+
+    /// Check an emailed code; wrong guesses count towards `OneTimeCodeStore::MAX_ATTEMPTS`
+    pub async fn redeem_code(user_id: i32, code: &str) -> Result<bool, OneTimeCodeError> {
+        let store = OneTimeCodeStore::current();
+        let subject = user_id.to_string();
+
+        if !store.verify(CodePurpose::PasswordlessCode, &subject, code.trim()).await? {
+            return Ok(false);
+        }
+        store.remove(CodePurpose::PasswordlessLink, &subject).await?;
+        Ok(true)
+    }
+}
+
+// "<user id>.<nonce>.<HMAC-SHA256 of both>", so forged links are turned away before the store is asked
+fn sign(user_id: i32, nonce: &str, secret: &str) -> String {
+    let payload = format!("{}.{}", user_id, nonce);
+    let tag = hmac::sign(&link_key(secret), payload.as_bytes());
+    format!("{}.{}", payload, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+}
+
+fn verify_signature(token: &str, secret: &str) -> Option<(i32, String)> {
+    let (payload, tag) = token.trim().rsplit_once('.')?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+    hmac::verify(&link_key(secret), payload.as_bytes(), &tag).ok()?;
+
+    let (user_id, nonce) = payload.split_once('.')?;
+    Some((user_id.parse().ok()?, nonce.to_string()))
+}
+
+// Derived from the JWT secret, so a link can't pass for any other signed value
+fn link_key(secret: &str) -> hmac::Key {
+    let derived = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()), b"passwordless-login-link");
+    hmac::Key::new(hmac::HMAC_SHA256, derived.as_ref())
+}
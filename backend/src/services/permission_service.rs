@@ -0,0 +1,94 @@
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crate::models::auth::Claims;
+
+// Permission names, see the roles/permissions migration for what each role gets
+pub const USERS_READ: &str = "users.read";
+pub const USERS_CREATE: &str = "users.create";
+pub const USERS_UPDATE: &str = "users.update";
+pub const USERS_DELETE: &str = "users.delete";
+pub const USERS_BAN: &str = "users.ban";
+pub const USERS_ROLE_ASSIGN: &str = "users.role.assign";
+pub const POSTS_DELETE_ANY: &str = "posts.delete.any";
+pub const COMMENTS_DELETE_ANY: &str = "comments.delete.any";
+pub const EMAIL_OUTBOX_MANAGE: &str = "email_outbox.manage";
+pub const AUDIT_READ: &str = "audit.read";
+pub const BAN_APPEALS_REVIEW: &str = "ban_appeals.review";
+
+lazy_static! {
+    // role -> (permissions, loaded at), so the middleware doesn't hit the database on every request
+    static ref ROLE_CACHE: RwLock<HashMap<String, (HashSet<String>, Instant)>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Clone, Debug)]
+pub struct PermissionService;
+
+impl PermissionService {
+    pub const CACHE_TTL_SECONDS: u64 = 60;
+
+    /// All permissions granted to `role` (empty for unknown roles)
+    pub async fn for_role(pool: &PgPool, role: &str) -> Result<HashSet<String>, sqlx::Error> {
+        if let Some((permissions, loaded_at)) = ROLE_CACHE.read().unwrap().get(role)
+            && loaded_at.elapsed() < Duration::from_secs(Self::CACHE_TTL_SECONDS)
+        {
+            return Ok(permissions.clone());
+        }
+
+        let permissions: HashSet<String> = sqlx::query_scalar!(
+            "SELECT permission FROM role_permissions WHERE role = $1",
+            role
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+
+        ROLE_CACHE
+            .write()
+            .unwrap()
+            .insert(role.to_string(), (permissions.clone(), Instant::now()));
+
+        Ok(permissions)
+    }
+
+    pub async fn role_has_permission(pool: &PgPool, role: &str, permission: &str) -> Result<bool, sqlx::Error> {
+        Ok(Self::for_role(pool, role).await?.contains(permission))
+    }
+
+    /// Check the permission for the authenticated user, treating lookup errors as denied
+    pub async fn has_permission(pool: &PgPool, claims: &Claims, permission: &str) -> bool {
+        Self::role_has_permission(pool, &claims.role, permission)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to load permissions for role {}: {}", claims.role, e);
+                false
+            })
+    }
+
+    /// Sorted permission list, for API responses
+    pub async fn list_for_role(pool: &PgPool, role: &str) -> Result<Vec<String>, sqlx::Error> {
+        let mut permissions: Vec<String> = Self::for_role(pool, role).await?.into_iter().collect();
+        permissions.sort();
+        Ok(permissions)
+    }
+
+    pub async fn role_exists(pool: &PgPool, role: &str) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM roles WHERE name = $1) as \"exists!\"",
+            role
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    /// Forget cached role permissions (call after editing role_permissions)
+    pub fn invalidate_cache() {
+        ROLE_CACHE.write().unwrap().clear();
+    }
+}
@@ -0,0 +1,222 @@
+use actix_web::http::Method;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::models::auth::Claims;
+
+pub const POSTS_READ: &str = "posts:read";
+pub const POSTS_WRITE: &str = "posts:write";
+pub const PROFILE_READ: &str = "profile:read";
+pub const ADMIN_USERS: &str = "admin:users";
+
+/// Every scope a token can be given
+pub const SCOPES: &[&str] = &[POSTS_READ, POSTS_WRITE, PROFILE_READ, ADMIN_USERS];
+
+/// A personal access token as listed to its owner. The token itself is only shown once, on creation
+#[derive(Debug, Clone, Serialize)]
+pub struct PersonalAccessToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub rate_limit_per_minute: i32,
+    pub expires_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub last_used_ip: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl PersonalAccessToken {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+/// A token just created, with the only copy of its secret value
+#[derive(Debug, Serialize)]
+pub struct NewPersonalAccessToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub details: PersonalAccessToken,
+}
+
+/// Personal access tokens in `personal_access_tokens`, for scripts and bots acting as a user.
+/// They are accepted by `AuthMiddleware` on routes that name a scope, and only with that scope
+pub struct PersonalAccessTokenService;
+
+impl PersonalAccessTokenService {
+    /// Tells them apart from JWTs in the Authorization header
+    pub const PREFIX: &'static str = "pat_";
+    /// Characters kept in `token_prefix`, enough to recognise a token without revealing it
+    pub const DISPLAY_PREFIX_LENGTH: usize = 12;
+    pub const MAX_NAME_LENGTH: usize = 64;
+    pub const MAX_TOKENS_PER_USER: i64 = 20;
+    pub const DEFAULT_EXPIRY_DAYS: i64 = 30;
+    pub const MAX_EXPIRY_DAYS: i64 = 365;
+    pub const DEFAULT_RATE_LIMIT_PER_MINUTE: i32 = 60;
+    pub const MAX_RATE_LIMIT_PER_MINUTE: i32 = 600;
+
+    pub fn is_token(token: &str) -> bool {
+        token.starts_with(Self::PREFIX)
+    }
+
+    pub fn hash_token(token: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(token.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn generate_token() -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        format!("{}{}", Self::PREFIX, URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// The scope a request needs on a route declaring `scope`: a full scope such as `admin:users`
+    /// is needed as is, a resource such as `posts` needs `posts:read` to read and `posts:write` otherwise
+    pub fn required_scope(scope: &str, method: &Method) -> String {
+        if scope.contains(':') {
+            scope.to_string()
+        } else if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+            format!("{}:read", scope)
+        } else {
+            format!("{}:write", scope)
+        }
+    }
+
+    /// Active tokens of the user, newest first. Revoked and expired ones are left out
+    pub async fn list(pool: &PgPool, user_id: i32) -> Result<Vec<PersonalAccessToken>, sqlx::Error> {
+        sqlx::query_as!(
+            PersonalAccessToken,
+            "SELECT id, user_id, name, token_prefix, scopes, rate_limit_per_minute, expires_at, last_used_at, last_used_ip, created_at
+             FROM personal_access_tokens
+             WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+             ORDER BY created_at DESC, id DESC",
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn count_active(pool: &PgPool, user_id: i32) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            "SELECT COUNT(*) AS \"count!\" FROM personal_access_tokens
+             WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()",
+            user_id
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Store a new token. Name, scopes and limits are expected to be validated already
+    pub async fn create(
+        pool: &PgPool,
+        user_id: i32,
+        name: &str,
+        scopes: &[String],
+        expires_in_days: i64,
+        rate_limit_per_minute: i32,
+    ) -> Result<NewPersonalAccessToken, sqlx::Error> {
+        let token = Self::generate_token();
+        let expires_at = (Utc::now() + Duration::days(expires_in_days)).naive_utc();
+
+        let details = sqlx::query_as!(
+            PersonalAccessToken,
+            "INSERT INTO personal_access_tokens (user_id, name, token_hash, token_prefix, scopes, rate_limit_per_minute, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING id, user_id, name, token_prefix, scopes, rate_limit_per_minute, expires_at, last_used_at, last_used_ip, created_at",
+            user_id,
+            name,
+            Self::hash_token(&token),
+            &token[..Self::DISPLAY_PREFIX_LENGTH],
+            scopes,
+            rate_limit_per_minute,
+            expires_at
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(NewPersonalAccessToken { token, details })
+    }
+
+    /// Revoke one of the user's active tokens, `None` if there is no such token
+    pub async fn revoke(pool: &PgPool, user_id: i32, id: i32) -> Result<Option<PersonalAccessToken>, sqlx::Error> {
+        sqlx::query_as!(
+            PersonalAccessToken,
+            "UPDATE personal_access_tokens SET revoked_at = NOW()
+             WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+             RETURNING id, user_id, name, token_prefix, scopes, rate_limit_per_minute, expires_at, last_used_at, last_used_ip, created_at",
+            id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn revoke_all(pool: &PgPool, user_id: i32) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "UPDATE personal_access_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// The token and claims for its owner, if the token is known, unrevoked and unexpired.
+    /// The role is the owner's current one, so demoting a user also limits their tokens
+    pub async fn authenticate(pool: &PgPool, token: &str) -> Result<Option<(PersonalAccessToken, Claims)>, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT t.id, t.user_id, t.name, t.token_prefix, t.scopes, t.rate_limit_per_minute, t.expires_at,
+                    t.last_used_at, t.last_used_ip, t.created_at, u.username, u.role
+             FROM personal_access_tokens t
+             JOIN users u ON u.id = t.user_id
+             WHERE t.token_hash = $1 AND t.revoked_at IS NULL AND t.expires_at > NOW()",
+            Self::hash_token(token)
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.map(|row| {
+            let claims = Claims {
+                sub: row.user_id,
+                username: row.username,
+                role: row.role,
+                exp: row.expires_at.and_utc().timestamp() as usize,
+                iat: row.created_at.and_utc().timestamp() as usize,
+            };
+            let token = PersonalAccessToken {
+                id: row.id,
+                user_id: row.user_id,
+                name: row.name,
+                token_prefix: row.token_prefix,
+                scopes: row.scopes,
+                rate_limit_per_minute: row.rate_limit_per_minute,
+                expires_at: row.expires_at,
+                last_used_at: row.last_used_at,
+                last_used_ip: row.last_used_ip,
+                created_at: row.created_at,
+            };
+            (token, claims)
+        }))
+    }
+
+    pub async fn touch(pool: &PgPool, id: i32, ip_address: Option<&str>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE personal_access_tokens SET last_used_at = NOW(), last_used_ip = $2 WHERE id = $1",
+            id,
+            ip_address
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+// No 0/O or 1/I, so codes survive being written down
+const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Single-use 2FA recovery codes, kept in `users.recovery_codes` as hashes
+pub struct RecoveryCodeService;
+
+impl RecoveryCodeService {
+    pub const COUNT: usize = 8;
+    // 12 characters of a 32 character alphabet, 60 bits: too many to brute force the hashes
+    const CODE_LENGTH: usize = 12;
+
+    /// New plaintext codes, formatted "XXXX-XXXX-XXXX"
+    pub fn generate() -> Vec<String> {
+        let mut rng = rand::thread_rng();
+        (0..Self::COUNT)
+            .map(|_| {
+                let code: Vec<u8> = (0..Self::CODE_LENGTH)
+                    .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())])
+                    .collect();
+                code.chunks(4)
+                    .map(|group| String::from_utf8_lossy(group).into_owned())
+                    .collect::<Vec<_>>()
+                    .join("-")
+            })
+            .collect()
+    }
+
+    /// Salted with the user ID so equal codes of different users don't share a hash. Case,
+    /// dashes and spaces don't matter
+    pub fn hash(user_id: i32, code: &str) -> String {
+        let normalized: String = code
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .flat_map(char::to_uppercase)
+            .collect();
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{}:{}", user_id, normalized).as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Replace the user's codes with a new set, returned in plaintext to show once
+    pub async fn regenerate(pool: &PgPool, user_id: i32) -> Result<Vec<String>, sqlx::Error> {
+        let codes = Self::generate();
+        let hashes: Vec<String> = codes.iter().map(|code| Self::hash(user_id, code)).collect();
+
+        sqlx::query!(
+            "UPDATE users SET recovery_codes = $1 WHERE id = $2",
+            &hashes,
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(codes)
+    }
+
+    /// Use up `code`. Returns the number of codes left, or `None` if it isn't one of the
+    /// user's unused codes
+    pub async fn redeem(pool: &PgPool, user_id: i32, code: &str) -> Result<Option<usize>, sqlx::Error> {
+        let hash = Self::hash(user_id, code);
+
+        // Removing the hash in the same statement makes concurrent redemptions of a code fail
+        let remaining = sqlx::query_scalar!(
+            "UPDATE users SET recovery_codes = array_remove(recovery_codes, $2)
+             WHERE id = $1 AND $2 = ANY(recovery_codes)
+             RETURNING COALESCE(cardinality(recovery_codes), 0) AS \"remaining!\"",
+            user_id,
+            hash
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(remaining.map(|count| count as usize))
+    }
+
+    pub async fn clear(pool: &PgPool, user_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!("UPDATE users SET recovery_codes = NULL WHERE id = $1", user_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub fn remaining(recovery_codes: Option<&Vec<String>>) -> usize {
+        recovery_codes.map_or(0, Vec::len)
+    }
+}
@@ -3,6 +3,13 @@ use sqlx::PgPool;
 use chrono::{Utc, Duration};
 use uuid::Uuid;
 
+/// Stored refresh token row, looked up by the token itself
+#[derive(Debug, Clone)]
+pub struct RefreshTokenRecord {
+    pub user_id: i32,
+    pub token_family: String,
+}
+
 #[derive(Clone, Debug)]
 pub struct RefreshTokenService;
 
@@ -88,6 +95,45 @@ impl RefreshTokenService {
         }
     }
 
+    /// Find the owner and family of a refresh token (regardless of its state)
+    pub async fn find_token(
+        pool: &PgPool,
+        token: &str,
+    ) -> Result<Option<RefreshTokenRecord>, sqlx::Error> {
+        let token_hash = Self::hash_token(token);
+
+        let record = sqlx::query_as!(
+            RefreshTokenRecord,
+            "SELECT user_id, token_family FROM refresh_tokens WHERE token_hash = $1",
+            token_hash
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(record)
+    }
+
+    /// Atomically mark a token as used so it can only be rotated once.
+    /// Returns false if another request already consumed or revoked it.
+    pub async fn consume_token(
+        pool: &PgPool,
+        user_id: i32,
+        token: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let token_hash = Self::hash_token(token);
+
+        let result = sqlx::query!(
+            "UPDATE refresh_tokens SET is_revoked = TRUE
+             WHERE user_id = $1 AND token_hash = $2 AND is_revoked = FALSE",
+            user_id,
+            token_hash
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     /// Rotate a refresh token (generate new token, mark old as parent)
     pub async fn rotate_refresh_token(
         pool: &PgPool,
@@ -231,6 +277,37 @@ impl RefreshTokenService {
         Ok(())
     }
 
+    /// Revoke the refresh token family a session was issued with (logout of that session)
+    pub async fn revoke_for_session(pool: &PgPool, user_id: i32, session_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE refresh_tokens SET is_revoked = TRUE
+             WHERE user_id = $1
+             AND token_family = (SELECT refresh_token_family FROM sessions WHERE id = $2 AND user_id = $1)",
+            user_id,
+            session_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Revoke every refresh token of the user except the family of the given session
+    /// (logout other devices)
+    pub async fn revoke_all_except_session(pool: &PgPool, user_id: i32, session_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE refresh_tokens SET is_revoked = TRUE
+             WHERE user_id = $1
+             AND token_family IS DISTINCT FROM (SELECT refresh_token_family FROM sessions WHERE id = $2 AND user_id = $1)",
+            user_id,
+            session_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
     /// Get active token count for user (for multi-device tracking)
     pub async fn get_active_token_count(pool: &PgPool, user_id: i32) -> Result<i64, sqlx::Error> {
         let now = Utc::now().naive_utc();
@@ -1,8 +1,16 @@
 use sqlx::PgPool;
 use crate::services::web3_challenge_service::Web3ChallengeService;
+use crate::services::one_time_code_store::OneTimeCodeStore;
+use crate::services::email_outbox::EmailOutbox;
+use crate::services::email_transport::transport_from_env;
+use crate::services::ban_service::BanService;
+use crate::services::oidc_service::OidcRegistry;
+use crate::services::webauthn_service::WebAuthnService;
 
 /// Start background scheduled tasks
 pub fn start_scheduled_tasks(pool: PgPool) {
+    start_email_worker(pool.clone());
+
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(std::time::Duration::from_secs(600)); // Every 10 minutes
         
@@ -14,6 +22,77 @@ pub fn start_scheduled_tasks(pool: PgPool) {
             } else {
                 println!("Successfully cleaned up expired Web3 challenges");
             }
+
+            match OneTimeCodeStore::current().cleanup_expired().await {
+                Ok(count) => println!("Cleaned up {} expired one-time codes", count),
+                Err(e) => eprintln!("Error cleaning up expired one-time codes: {}", e),
+            }
+
+            match EmailOutbox::cleanup(&pool).await {
+                Ok(count) => println!("Cleaned up {} old outbox emails", count),
+                Err(e) => eprintln!("Error cleaning up email outbox: {}", e),
+            }
+
+            match OidcRegistry::cleanup_expired_states(&pool).await {
+                Ok(count) if count > 0 => println!("Cleaned up {} abandoned OIDC logins", count),
+                Ok(_) => {}
+                Err(e) => eprintln!("Error cleaning up OIDC login states: {}", e),
+            }
+
+            match WebAuthnService::cleanup_expired_challenges(&pool).await {
+                Ok(count) if count > 0 => println!("Cleaned up {} abandoned passkey ceremonies", count),
+                Ok(_) => {}
+                Err(e) => eprintln!("Error cleaning up passkey challenges: {}", e),
+            }
+
+            match BanService::expire_bans(&pool).await {
+                Ok(count) if count > 0 => println!("Lifted {} expired bans", count),
+                Ok(_) => {}
+                Err(e) => eprintln!("Error lifting expired bans: {}", e),
+            }
+        }
+    });
+}
+
+/// Drain the email outbox, polling every `EMAIL_OUTBOX_POLL_SECONDS` (default 5)
+fn start_email_worker(pool: PgPool) {
+    let poll_seconds = std::env::var("EMAIL_OUTBOX_POLL_SECONDS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|s| *s > 0)
+        .unwrap_or(5);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(poll_seconds));
+        let mut transport = None;
+        let mut warned = false;
+
+        loop {
+            interval.tick().await;
+
+            // Build the transport lazily so a misconfigured provider doesn't stop the server
+            if transport.is_none() {
+                match transport_from_env() {
+                    Ok(t) => transport = Some(t),
+                    Err(e) => {
+                        if !warned {
+                            eprintln!("Email worker idle, transport not configured: {}", e);
+                            warned = true;
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            let Some(transport) = transport.clone() else { continue };
+            match EmailOutbox::process_due(&pool, transport, EmailOutbox::BATCH_SIZE).await {
+                Ok(run) if run.sent + run.retrying + run.dead > 0 => println!(
+                    "Email outbox: {} sent, {} retrying, {} dead-lettered",
+                    run.sent, run.retrying, run.dead
+                ),
+                Ok(_) => {}
+                Err(e) => eprintln!("Error processing email outbox: {}", e),
+            }
         }
     });
 }
@@ -23,6 +23,8 @@ pub struct CreateSessionData {
     pub device_name: Option<String>,
     pub ip_address: Option<String>,
     pub user_agent: Option<String>,
+    /// Family of the refresh token issued alongside, revoked when the session is logged out
+    pub refresh_token_family: Option<String>,
 }
 
 pub struct SessionManager;
@@ -46,8 +48,8 @@ impl SessionManager {
         let session = sqlx::query_as!(
             SessionInfo,
             "INSERT INTO sessions 
-             (user_id, token_hash, device_name, ip_address, user_agent, last_activity, expires_at)
-             VALUES ($1, $2, $3, $4, $5, NOW(), $6)
+             (user_id, token_hash, device_name, ip_address, user_agent, last_activity, expires_at, refresh_token_family)
+             VALUES ($1, $2, $3, $4, $5, NOW(), $6, $7)
              RETURNING id, user_id, device_name, ip_address, user_agent, 
                        last_activity, expires_at, created_at",
             data.user_id,
@@ -55,7 +57,8 @@ impl SessionManager {
             data.device_name.clone(),
             data.ip_address.clone(),
             data.user_agent.clone(),
-            expires_at
+            expires_at,
+            data.refresh_token_family.clone()
         )
         .fetch_one(pool)
         .await?;
@@ -77,8 +80,8 @@ impl SessionManager {
         let session = sqlx::query_as!(
             SessionInfo,
             "INSERT INTO sessions 
-             (user_id, token_hash, device_name, ip_address, user_agent, last_activity, expires_at)
-             VALUES ($1, $2, $3, $4, $5, NOW(), $6)
+             (user_id, token_hash, device_name, ip_address, user_agent, last_activity, expires_at, refresh_token_family)
+             VALUES ($1, $2, $3, $4, $5, NOW(), $6, $7)
              RETURNING id, user_id, device_name, ip_address, user_agent, 
                        last_activity, expires_at, created_at",
             data.user_id,
@@ -86,7 +89,8 @@ impl SessionManager {
             data.device_name.clone(),
             data.ip_address.clone(),
             data.user_agent.clone(),
-            expires_at
+            expires_at,
+            data.refresh_token_family.clone()
         )
         .fetch_one(pool)
         .await?;
@@ -0,0 +1,347 @@
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use lazy_static::lazy_static;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::Url;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::services::wallet_chains::WalletChain;
+
+const PREAMBLE: &str = " wants you to sign in with your ";
+const DEFAULT_FRONTEND_URL: &str = "http://localhost:5173";
+const DEFAULT_STATEMENT: &str = "Sign in to USH with your wallet.";
+/// Sepolia, the network the frontend switches wallets to
+const DEFAULT_CHAIN_ID: u64 = 11155111;
+const DEFAULT_SOLANA_CHAIN_ID: &str = "mainnet";
+/// Tolerated clock difference between us and the wallet for `Issued At` / `Not Before`
+const CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// A Sign-In With Ethereum message (EIP-4361), or its Solana counterpart which only differs in
+/// the account type, address format and named clusters ("mainnet", "devnet") as chain IDs
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiweMessage {
+    pub chain: WalletChain,
+    pub domain: String,
+    pub address: String,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: String,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SiweError {
+    /// Not an EIP-4361 message
+    Malformed(String),
+    /// Well-formed, but not a message we would have issued (or no longer valid)
+    Invalid(String),
+}
+
+impl fmt::Display for SiweError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SiweError::Malformed(msg) => write!(f, "malformed sign-in message: {}", msg),
+            SiweError::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SiweError {}
+
+impl fmt::Display for SiweMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}{}{} account:", self.domain, PREAMBLE, self.chain.display_name())?;
+        writeln!(f, "{}", self.address)?;
+        writeln!(f)?;
+        if let Some(statement) = &self.statement {
+            writeln!(f, "{}", statement)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "URI: {}", self.uri)?;
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(f, "Chain ID: {}", self.chain_id)?;
+        writeln!(f, "Nonce: {}", self.nonce)?;
+        write!(f, "Issued At: {}", timestamp(&self.issued_at))?;
+        if let Some(expiration_time) = &self.expiration_time {
+            write!(f, "\nExpiration Time: {}", timestamp(expiration_time))?;
+        }
+        if let Some(not_before) = &self.not_before {
+            write!(f, "\nNot Before: {}", timestamp(not_before))?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, "\nRequest ID: {}", request_id)?;
+        }
+        if !self.resources.is_empty() {
+            write!(f, "\nResources:")?;
+            for resource in &self.resources {
+                write!(f, "\n- {}", resource)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SiweMessage {
+    type Err = SiweError;
+
+    fn from_str(message: &str) -> Result<Self, Self::Err> {
+        let malformed = |msg: &str| SiweError::Malformed(msg.to_string());
+        let mut lines = message.split('\n').peekable();
+
+        let (domain, chain) = lines
+            .next()
+            .and_then(|line| line.strip_suffix(" account:"))
+            .and_then(|line| line.split_once(PREAMBLE))
+            .filter(|(domain, _)| !domain.is_empty() && !domain.contains(char::is_whitespace))
+            .and_then(|(domain, chain)| Some((domain.to_string(), WalletChain::from_display_name(chain)?)))
+            .ok_or_else(|| malformed("missing domain line"))?;
+
+        let address = lines.next().ok_or_else(|| malformed("missing address"))?.to_string();
+        // Only the canonical spelling (EIP-55 checksummed for Ethereum) is accepted
+        match chain.verifier().display_address(&address) {
+            Ok(display) if display == address => {}
+            Ok(_) => return Err(malformed("address is not in its canonical (checksummed) form")),
+            Err(_) => return Err(malformed("invalid address")),
+        }
+
+        if lines.next() != Some("") {
+            return Err(malformed("expected an empty line after the address"));
+        }
+        let statement = match lines.next() {
+            Some("") => None,
+            Some(statement) => {
+                if lines.next() != Some("") {
+                    return Err(malformed("expected an empty line after the statement"));
+                }
+                Some(statement.to_string())
+            }
+            None => return Err(malformed("message ends after the address")),
+        };
+
+        let mut field = |name: &str| -> Result<String, SiweError> {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .and_then(|line| line.strip_prefix(": "))
+                .map(str::to_string)
+                .ok_or_else(|| SiweError::Malformed(format!("missing {}", name)))
+        };
+
+        let uri = field("URI")?;
+        let version = field("Version")?;
+        let chain_id = field("Chain ID")?;
+        if chain == WalletChain::Ethereum && chain_id.parse::<u64>().is_err() {
+            return Err(malformed("invalid Chain ID"));
+        }
+        let nonce = field("Nonce")?;
+        let issued_at = parse_timestamp(&field("Issued At")?)?;
+
+        let mut optional = |name: &str| -> Option<String> {
+            let prefix = format!("{}: ", name);
+            let value = lines.peek()?.strip_prefix(prefix.as_str())?.to_string();
+            lines.next();
+            Some(value)
+        };
+
+        let expiration_time = optional("Expiration Time").map(|t| parse_timestamp(&t)).transpose()?;
+        let not_before = optional("Not Before").map(|t| parse_timestamp(&t)).transpose()?;
+        let request_id = optional("Request ID");
+
+        let mut resources = Vec::new();
+        if lines.peek() == Some(&"Resources:") {
+            lines.next();
+            while let Some(resource) = lines.peek().and_then(|line| line.strip_prefix("- ")) {
+                resources.push(resource.to_string());
+                lines.next();
+            }
+        }
+
+        if let Some(extra) = lines.next() {
+            return Err(SiweError::Malformed(format!("unexpected line: {}", extra)));
+        }
+
+        Ok(SiweMessage {
+            chain,
+            domain,
+            address,
+            statement,
+            uri,
+            version,
+            chain_id,
+            nonce,
+            issued_at,
+            expiration_time,
+            not_before,
+            request_id,
+            resources,
+        })
+    }
+}
+
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, SiweError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| SiweError::Malformed(format!("invalid timestamp {}", value)))
+}
+
+/// What the sign-in messages we issue look like, and so what `validate` accepts
+#[derive(Debug, Clone)]
+pub struct SiweConfig {
+    /// RFC 3986 authority the wallet shows the user, e.g. "app.example.com"
+    pub domain: String,
+    pub uri: String,
+    /// Accepted EIP-155 chain IDs, the first one is used when the client doesn't ask for one
+    pub chain_ids: Vec<u64>,
+    /// Accepted Solana clusters, likewise
+    pub solana_chain_ids: Vec<String>,
+    pub statement: Option<String>,
+    pub ttl_seconds: i64,
+}
+
+lazy_static! {
+    static ref SHARED_CONFIG: SiweConfig = SiweConfig::from_env();
+}
+
+impl SiweConfig {
+    pub const DEFAULT_TTL_SECONDS: i64 = 300;
+
+    /// Read `SIWE_DOMAIN`, `SIWE_URI`, `SIWE_CHAIN_IDS` / `SIWE_SOLANA_CHAIN_IDS` (comma separated)
+    /// and `SIWE_STATEMENT`. Domain and URI default to `FRONTEND_URL`, the chains to Sepolia
+    /// and Solana mainnet
+    pub fn from_env() -> Self {
+        // Empty values (as left by .env.example) count as unset
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+
+        let frontend_url = var("FRONTEND_URL").unwrap_or_else(|| DEFAULT_FRONTEND_URL.to_string());
+        let uri = var("SIWE_URI").unwrap_or_else(|| frontend_url.trim_end_matches('/').to_string());
+        let domain = var("SIWE_DOMAIN").unwrap_or_else(|| authority(&uri).unwrap_or_else(|| "localhost".to_string()));
+
+        let mut chain_ids: Vec<u64> = var("SIWE_CHAIN_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|id| id.trim().parse().ok())
+            .collect();
+        if chain_ids.is_empty() {
+            chain_ids.push(DEFAULT_CHAIN_ID);
+        }
+
+        let mut solana_chain_ids: Vec<String> = var("SIWE_SOLANA_CHAIN_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect();
+        if solana_chain_ids.is_empty() {
+            solana_chain_ids.push(DEFAULT_SOLANA_CHAIN_ID.to_string());
+        }
+
+        Self {
+            domain,
+            uri,
+            chain_ids,
+            solana_chain_ids,
+            statement: Some(var("SIWE_STATEMENT").unwrap_or_else(|| DEFAULT_STATEMENT.to_string())),
+            ttl_seconds: Self::DEFAULT_TTL_SECONDS,
+        }
+    }
+
+    pub fn shared() -> Self {
+        SHARED_CONFIG.clone()
+    }
+
+    /// A fresh single-use nonce (EIP-4361 asks for at least 8 alphanumeric characters)
+    pub fn generate_nonce() -> String {
+        rand::thread_rng().sample_iter(&Alphanumeric).take(24).map(char::from).collect()
+    }
+
+    /// Accepted chain IDs of a chain, the default one first
+    pub fn chain_ids(&self, chain: WalletChain) -> Vec<String> {
+        match chain {
+            WalletChain::Ethereum => self.chain_ids.iter().map(u64::to_string).collect(),
+            WalletChain::Solana => self.solana_chain_ids.clone(),
+        }
+    }
+
+    /// The message for `address` to sign. `chain_id` must be one of `chain_ids(chain)`
+    pub fn message(
+        &self,
+        chain: WalletChain,
+        address: &str,
+        chain_id: &str,
+        nonce: &str,
+        now: DateTime<Utc>,
+    ) -> Result<SiweMessage, String> {
+        Ok(SiweMessage {
+            chain,
+            domain: self.domain.clone(),
+            address: chain.verifier().display_address(address)?,
+            statement: self.statement.clone(),
+            uri: self.uri.clone(),
+            version: "1".to_string(),
+            chain_id: chain_id.to_string(),
+            nonce: nonce.to_string(),
+            issued_at: now,
+            expiration_time: Some(now + Duration::seconds(self.ttl_seconds)),
+            not_before: None,
+            request_id: None,
+            resources: Vec::new(),
+        })
+    }
+
+    /// Check a signed message was meant for us and is still valid. The nonce is checked
+    /// (and consumed) separately against the stored challenge
+    pub fn validate(&self, message: &SiweMessage, now: DateTime<Utc>) -> Result<(), SiweError> {
+        let invalid = |msg: String| Err(SiweError::Invalid(msg));
+        let skew = Duration::seconds(CLOCK_SKEW_SECONDS);
+
+        if message.version != "1" {
+            return invalid(format!("unsupported version {}", message.version));
+        }
+        if message.domain != self.domain {
+            return invalid(format!("message is for {}, not {}", message.domain, self.domain));
+        }
+        if message.uri != self.uri {
+            return invalid(format!("unexpected URI {}", message.uri));
+        }
+        if !self.chain_ids(message.chain).contains(&message.chain_id) {
+            return invalid(format!("chain {} is not supported", message.chain_id));
+        }
+        if message.nonce.len() < 8 || !message.nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return invalid("invalid nonce".to_string());
+        }
+        if message.issued_at > now + skew {
+            return invalid("message is issued in the future".to_string());
+        }
+        match message.expiration_time {
+            Some(expiration_time) if expiration_time > now => {}
+            Some(_) => return invalid("message has expired".to_string()),
+            None => return invalid("message has no expiration time".to_string()),
+        }
+        if message.not_before.is_some_and(|not_before| not_before > now + skew) {
+            return invalid("message is not valid yet".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// "https://app.example.com:8443/path" -> "app.example.com:8443"
+fn authority(uri: &str) -> Option<String> {
+    let url = Url::parse(uri).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use ring::digest::{SHA256, digest};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::services::email_service::EmailService;
+use crate::services::one_time_code_store::{CodePurpose, OneTimeCodeError, OneTimeCodeStore};
+use crate::services::webauthn_service::WebAuthnService;
+
+/// How the user proved it was still them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepUpMethod {
+    Password,
+    Totp,
+    Email,
+    Passkey,
+    Wallet,
+}
+
+impl StepUpMethod {
+    pub fn parse(method: &str) -> Option<Self> {
+        match method.trim().to_lowercase().as_str() {
+            "password" => Some(StepUpMethod::Password),
+            "totp" => Some(StepUpMethod::Totp),
+            "email" => Some(StepUpMethod::Email),
+            "passkey" => Some(StepUpMethod::Passkey),
+            "wallet" => Some(StepUpMethod::Wallet),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StepUpMethod::Password => "password",
+            StepUpMethod::Totp => "totp",
+            StepUpMethod::Email => "email",
+            StepUpMethod::Passkey => "passkey",
+            StepUpMethod::Wallet => "wallet",
+        }
+    }
+}
+
+/// The "elevated" claim: the user re-authenticated at `auth_time` in the session whose access
+/// token hashes to `sid`. Can't be mistaken for an access or MFA token, which need other fields
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepUpClaims {
+    pub sub: i32,
+    pub sid: String,
+    pub method: StepUpMethod,
+    pub auth_time: usize,
+    pub exp: usize,
+    pub iat: usize,
+}
+
+impl StepUpClaims {
+    /// Whether the re-authentication happened no more than `max_age_seconds` ago
+    pub fn is_fresh(&self, max_age_seconds: i64) -> bool {
+        Utc::now().timestamp() - self.auth_time as i64 <= max_age_seconds
+    }
+}
+
+pub struct StepUpService;
+
+impl StepUpService {
+    /// Header carrying the step-up token next to the access token
+    pub const HEADER: &'static str = "X-Step-Up-Token";
+    /// Upper bound for any route's max age; routes usually ask for less
+    pub const TOKEN_LIFETIME_MINUTES: i64 = 15;
+    /// Max age for the sensitive account changes that check step-up themselves
+    pub const DEFAULT_MAX_AGE_SECONDS: i64 = 300;
+
+    // Ties the step-up token to one session, so it is useless with any other access token
+    fn session_id(access_token: &str) -> String {
+        hex::encode(digest(&SHA256, access_token.as_bytes()))
+    }
+
+    pub fn issue(
+        user_id: i32,
+        access_token: &str,
+        method: StepUpMethod,
+        secret: &str,
+    ) -> Result<(String, DateTime<Utc>), jsonwebtoken::errors::Error> {
+        let now = Utc::now();
+        let expires_at = now + Duration::minutes(Self::TOKEN_LIFETIME_MINUTES);
+        let claims = StepUpClaims {
+            sub: user_id,
+            sid: Self::session_id(access_token),
+            method,
+            auth_time: now.timestamp() as usize,
+            exp: expires_at.timestamp() as usize,
+            iat: now.timestamp() as usize,
+        };
+
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes()))?;
+        Ok((token, expires_at))
+    }
+
+    /// Claims of a step-up token issued to this user for this access token, if still valid
+    pub fn verify(token: &str, user_id: i32, access_token: &str, secret: &str) -> Option<StepUpClaims> {
+        let claims = decode::<StepUpClaims>(
+            token.trim(),
+            &DecodingKey::from_secret(secret.as_bytes()),
+            &Validation::new(Algorithm::HS256),
+        )
+        .ok()?
+        .claims;
+
+        (claims.sub == user_id && claims.sid == Self::session_id(access_token)).then_some(claims)
+    }
+
+    /// Methods the user can step up with
+    pub async fn available_methods(pool: &PgPool, user_id: i32) -> Result<Vec<StepUpMethod>, sqlx::Error> {
+        let user = sqlx::query!(
+            "SELECT password, email, email_verified, totp_enabled,
+                    EXISTS(SELECT 1 FROM user_wallets WHERE user_id = users.id) AS has_wallet
+             FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let mut methods = Vec::new();
+        if !user.password.is_empty() {
+            methods.push(StepUpMethod::Password);
+        }
+        if user.totp_enabled.unwrap_or(false) {
+            methods.push(StepUpMethod::Totp);
+        }
+        if user.email.is_some() && user.email_verified {
+            methods.push(StepUpMethod::Email);
+        }
+        if WebAuthnService::has_passkeys(pool, user_id).await? {
+            methods.push(StepUpMethod::Passkey);
+        }
+        if user.has_wallet.unwrap_or(false) {
+            methods.push(StepUpMethod::Wallet);
+        }
+        Ok(methods)
+    }
+
+    /// New emailed code for the user, replacing any earlier one
+    pub async fn create_email_code(user_id: i32) -> Result<String, OneTimeCodeError> {
+        let code = EmailService::generate_verification_code();
+        OneTimeCodeStore::current()
+            .put(
+                CodePurpose::StepUpEmail,
+                &user_id.to_string(),
+                &code,
+                Duration::minutes(EmailService::STEP_UP_CODE_EXPIRY_MINUTES),
+            )
+            .await?;
+        Ok(code)
+    }
+
+    pub async fn verify_email_code(user_id: i32, code: &str) -> Result<bool, OneTimeCodeError> {
+        OneTimeCodeStore::current()
+            .verify(CodePurpose::StepUpEmail, &user_id.to_string(), code.trim())
+            .await
+    }
+}
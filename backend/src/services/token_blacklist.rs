@@ -66,26 +66,40 @@ impl TokenBlacklist {
         Ok(result.is_some())
     }
 
-    /// Blacklist all user tokens (e.g., password change, logout all)
+    /// Revoke every access token issued to the user so far (e.g., account compromise).
+    /// Tokens aren't stored, so this sets a cutoff that the auth middleware compares with `iat`;
+    /// tokens issued within the same second are revoked too
     pub async fn blacklist_all_user_tokens(
         pool: &PgPool,
         user_id: i32,
         reason: &str,
     ) -> Result<(), sqlx::Error> {
-        // Get all active sessions and blacklist their tokens
-        let sessions = sqlx::query!(
-            "SELECT id FROM sessions WHERE user_id = $1 AND expires_at > NOW()",
-            user_id
+        sqlx::query!(
+            "UPDATE users SET tokens_revoked_at = $2 WHERE id = $1",
+            user_id,
+            Utc::now().naive_utc()
         )
-        .fetch_all(pool)
+        .execute(pool)
         .await?;
 
-        println!("[TokenBlacklist] Blacklisting {} sessions for user {}", sessions.len(), user_id);
+        println!("[TokenBlacklist] All tokens revoked for user {} with reason: {}", user_id, reason);
 
-        // Sessions are already invalidated, but we blacklist for extra security
         Ok(())
     }
 
+    /// Check whether a token issued at `issued_at` (unix seconds) predates the user's revocation cutoff
+    pub async fn is_revoked_for_user(pool: &PgPool, user_id: i32, issued_at: i64) -> Result<bool, sqlx::Error> {
+        let revoked_at = sqlx::query_scalar!(
+            "SELECT tokens_revoked_at FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+
+        Ok(revoked_at.is_some_and(|cutoff| issued_at <= cutoff.and_utc().timestamp()))
+    }
+
     /// Clean up expired blacklisted tokens
     pub async fn cleanup_expired_tokens(pool: &PgPool) -> Result<u64, sqlx::Error> {
         let result = sqlx::query!(
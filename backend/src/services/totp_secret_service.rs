@@ -0,0 +1,210 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use lazy_static::lazy_static;
+use ring::aead::{Aad, AES_256_GCM, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+
+const KEY_LEN: usize = 32;
+
+/// A key encryption key, e.g. "2025-12:<base64 of 32 random bytes>"
+#[derive(Clone)]
+pub struct MasterKey {
+    pub id: String,
+    key: [u8; KEY_LEN],
+}
+
+// Never print key material
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MasterKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+/// Master keys for TOTP secrets. New secrets are encrypted with the first key, the others
+/// are kept to decrypt rows that haven't been re-encrypted yet
+#[derive(Debug, Clone, Default)]
+pub struct TotpKeyring {
+    keys: Vec<MasterKey>,
+}
+
+lazy_static! {
+    static ref SHARED_KEYRING: TotpKeyring = TotpKeyring::from_env().unwrap_or_else(|e| panic!("{}", e));
+}
+
+#[derive(Debug)]
+pub enum TotpSecretError {
+    /// The row was encrypted with a key that is not configured
+    UnknownKey(String),
+    /// Wrong key or tampered ciphertext
+    Corrupt,
+    NoMasterKey,
+    /// The secret or TOTP parameters are rejected by totp-rs
+    InvalidParameters,
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for TotpSecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TotpSecretError::UnknownKey(id) => write!(f, "TOTP master key '{}' is not configured", id),
+            TotpSecretError::Corrupt => write!(f, "TOTP secret could not be decrypted"),
+            TotpSecretError::NoMasterKey => write!(f, "No TOTP master key configured (TOTP_MASTER_KEYS)"),
+            TotpSecretError::InvalidParameters => write!(f, "Invalid TOTP secret or parameters"),
+            TotpSecretError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TotpSecretError {}
+
+impl From<sqlx::Error> for TotpSecretError {
+    fn from(e: sqlx::Error) -> Self {
+        TotpSecretError::Database(e)
+    }
+}
+
+impl TotpKeyring {
+    /// Read `TOTP_MASTER_KEYS_FILE`, or else `TOTP_MASTER_KEYS`. Without either, secrets are
+    /// stored unencrypted
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+
+        if let Some(path) = var("TOTP_MASTER_KEYS_FILE") {
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read TOTP_MASTER_KEYS_FILE {}: {}", path, e))?;
+            return Self::parse(&contents);
+        }
+        Self::parse(&var("TOTP_MASTER_KEYS").unwrap_or_default())
+    }
+
+    pub fn shared() -> Self {
+        SHARED_KEYRING.clone()
+    }
+
+    /// `id:base64key` entries separated by commas or newlines, current key first. Lines
+    /// starting with '#' are comments
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut keys: Vec<MasterKey> = Vec::new();
+
+        for entry in spec
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split(','))
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (id, encoded) = entry
+                .split_once(':')
+                .ok_or_else(|| "TOTP master keys must be given as id:base64key".to_string())?;
+            let id = id.trim();
+            if id.is_empty()
+                || id.len() > 64
+                || !id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            {
+                return Err(format!("Invalid TOTP master key id '{}'", id));
+            }
+            if keys.iter().any(|k| k.id == id) {
+                return Err(format!("Duplicate TOTP master key id '{}'", id));
+            }
+
+            let key: [u8; KEY_LEN] = STANDARD
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| format!("TOTP master key '{}' must be 32 bytes, base64 encoded", id))?;
+
+            keys.push(MasterKey { id: id.to_string(), key });
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// The key new secrets are encrypted with
+    pub fn current(&self) -> Option<&MasterKey> {
+        self.keys.first()
+    }
+
+    fn key(&self, id: &str) -> Result<&MasterKey, TotpSecretError> {
+        self.keys
+            .iter()
+            .find(|k| k.id == id)
+            .ok_or_else(|| TotpSecretError::UnknownKey(id.to_string()))
+    }
+
+    /// Encrypt a base32 secret for `user_id`. Returns the stored form and the ID of the master
+    /// key, which is `None` (plaintext) when no key is configured
+    pub fn encrypt(&self, user_id: i32, secret: &str) -> Result<(String, Option<String>), TotpSecretError> {
+        let Some(master) = self.current() else {
+            return Ok((secret.to_string(), None));
+        };
+
+        // Envelope: a fresh data key encrypts the secret and the master key encrypts the data key
+        let mut data_key = [0u8; KEY_LEN];
+        SystemRandom::new().fill(&mut data_key).map_err(|_| TotpSecretError::Corrupt)?;
+
+        let mut envelope = seal(&master.key, &dek_aad(&master.id), &data_key)?;
+        envelope.extend(seal(&data_key, &secret_aad(user_id), secret.as_bytes())?);
+
+        Ok((STANDARD.encode(envelope), Some(master.id.clone())))
+    }
+
+    pub fn decrypt(&self, user_id: i32, stored: &str, key_id: Option<&str>) -> Result<String, TotpSecretError> {
+        let Some(key_id) = key_id else {
+            // Written before encryption was configured
+            return Ok(stored.to_string());
+        };
+        let master = self.key(key_id)?;
+
+        let envelope = STANDARD.decode(stored).map_err(|_| TotpSecretError::Corrupt)?;
+        let wrapped_len = NONCE_LEN + KEY_LEN + AES_256_GCM.tag_len();
+        if envelope.len() < wrapped_len {
+            return Err(TotpSecretError::Corrupt);
+        }
+        let (wrapped_key, sealed_secret) = envelope.split_at(wrapped_len);
+
+        let data_key: [u8; KEY_LEN] = open(&master.key, &dek_aad(key_id), wrapped_key)?
+            .try_into()
+            .map_err(|_| TotpSecretError::Corrupt)?;
+        let secret = open(&data_key, &secret_aad(user_id), sealed_secret)?;
+
+        String::from_utf8(secret).map_err(|_| TotpSecretError::Corrupt)
+    }
+}
+
+// Binding the ciphertexts to their key and user keeps secrets from being swapped between rows
+fn dek_aad(key_id: &str) -> Vec<u8> {
+    format!("totp-dek:{}", key_id).into_bytes()
+}
+
+fn secret_aad(user_id: i32) -> Vec<u8> {
+    format!("totp-secret:{}", user_id).into_bytes()
+}
+
+// AES-256-GCM, output is nonce || ciphertext || tag
+fn seal(key: &[u8; KEY_LEN], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, TotpSecretError> {
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).map_err(|_| TotpSecretError::Corrupt)?);
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|_| TotpSecretError::Corrupt)?;
+
+    let mut in_out = plaintext.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut in_out)
+        .map_err(|_| TotpSecretError::Corrupt)?;
+
+    Ok([&nonce[..], &in_out].concat())
+}
+
+fn open(key: &[u8; KEY_LEN], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, TotpSecretError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(TotpSecretError::Corrupt);
+    }
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).map_err(|_| TotpSecretError::Corrupt)?);
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| TotpSecretError::Corrupt)?;
+
+    let mut in_out = ciphertext.to_vec();
+    let plaintext = key
+        .open_in_place(nonce, Aad::from(aad), &mut in_out)
+        .map_err(|_| TotpSecretError::Corrupt)?;
+
+    Ok(plaintext.to_vec())
+}
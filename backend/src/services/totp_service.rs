@@ -0,0 +1,394 @@
+use chrono::{NaiveDateTime, Utc};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sqlx::PgPool;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::services::totp_secret_service::{TotpKeyring, TotpSecretError};
+
+const DEFAULT_ISSUER: &str = "USH";
+
+/// Parameters for new authenticators. Existing ones keep the algorithm, digits and period they
+/// were set up with
+#[derive(Debug, Clone)]
+pub struct TotpConfig {
+    pub issuer: String,
+    pub algorithm: Algorithm,
+    pub digits: usize,
+    pub period: u64,
+    /// Steps before and after the current one that are still accepted, for clock drift
+    pub skew: u64,
+}
+
+lazy_static! {
+    static ref SHARED_CONFIG: TotpConfig = TotpConfig::from_env();
+}
+
+impl Default for TotpConfig {
+    fn default() -> Self {
+        Self {
+            issuer: DEFAULT_ISSUER.to_string(),
+            algorithm: Algorithm::SHA1,
+            digits: 6,
+            period: 30,
+            skew: 1,
+        }
+    }
+}
+
+impl TotpConfig {
+    pub fn from_env() -> Self {
+        // Empty values (as left by .env.example) count as unset, invalid ones fall back to the default
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let defaults = Self::default();
+
+        Self {
+            // ':' separates issuer and account in the otpauth label
+            issuer: var("TOTP_ISSUER")
+                .map(|issuer| issuer.replace(':', "").trim().to_string())
+                .filter(|issuer| !issuer.is_empty())
+                .unwrap_or(defaults.issuer),
+            algorithm: var("TOTP_ALGORITHM")
+                .and_then(|name| parse_algorithm(name.trim()))
+                .unwrap_or(defaults.algorithm),
+            digits: var("TOTP_DIGITS")
+                .and_then(|digits| digits.trim().parse().ok())
+                .filter(|digits| (6..=8).contains(digits))
+                .unwrap_or(defaults.digits),
+            period: var("TOTP_PERIOD")
+                .and_then(|period| period.trim().parse().ok())
+                .filter(|period| (15..=300).contains(period))
+                .unwrap_or(defaults.period),
+            skew: var("TOTP_SKEW")
+                .and_then(|skew| skew.trim().parse().ok())
+                .filter(|skew| *skew <= 10)
+                .unwrap_or(defaults.skew),
+        }
+    }
+
+    pub fn shared() -> Self {
+        SHARED_CONFIG.clone()
+    }
+}
+
+fn parse_algorithm(name: &str) -> Option<Algorithm> {
+    match name.to_ascii_uppercase().replace('-', "").as_str() {
+        "SHA1" => Some(Algorithm::SHA1),
+        "SHA256" => Some(Algorithm::SHA256),
+        "SHA512" => Some(Algorithm::SHA512),
+        _ => None,
+    }
+}
+
+fn algorithm_name(algorithm: Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::SHA1 => "SHA1",
+        Algorithm::SHA256 => "SHA256",
+        Algorithm::SHA512 => "SHA512",
+    }
+}
+
+/// An authenticator app holding one of the user's TOTP secrets
+#[derive(Debug, Clone, Serialize)]
+pub struct TotpAuthenticator {
+    pub id: i32,
+    pub user_id: i32,
+    pub label: String,
+    #[serde(skip_serializing)]
+    pub secret: String, // encrypted, see TotpKeyring
+    #[serde(skip_serializing)]
+    pub key_id: Option<String>,
+    pub algorithm: String,
+    pub digits: i16,
+    pub period: i32,
+    #[serde(skip_serializing)]
+    pub last_used_step: Option<i64>,
+    pub confirmed_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+/// What an authenticator app needs to be set up. Shown until the first code is confirmed
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollment {
+    pub authenticator: TotpAuthenticator,
+    pub secret: String,
+    pub qr_code_url: String, // otpauth:// URI
+    pub qr_code_png: String, // base64 PNG of the URI
+}
+
+pub enum RemoveAuthenticatorOutcome {
+    Removed(TotpAuthenticator),
+    NotFound,
+    LastAuthenticator,
+}
+
+/// TOTP authenticators in `totp_authenticators`. A code is only accepted for a time step after
+/// the last one accepted for its authenticator, so each code works once
+pub struct TotpService;
+
+impl TotpService {
+    pub const MAX_LABEL_LENGTH: usize = 64;
+    pub const DEFAULT_LABEL: &'static str = "Authenticator app";
+
+    pub async fn list(pool: &PgPool, user_id: i32) -> Result<Vec<TotpAuthenticator>, sqlx::Error> {
+        sqlx::query_as!(
+            TotpAuthenticator,
+            "SELECT id, user_id, label, secret, key_id, algorithm, digits, period, last_used_step, confirmed_at, created_at, last_used_at
+             FROM totp_authenticators WHERE user_id = $1 ORDER BY created_at, id",
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Start adding an authenticator. There is at most one pending (unconfirmed) authenticator
+    /// per user, reused when setup is started again so a QR code already scanned stays valid
+    pub async fn enroll(
+        pool: &PgPool,
+        keyring: &TotpKeyring,
+        config: &TotpConfig,
+        user_id: i32,
+        account_name: &str,
+        label: &str,
+    ) -> Result<TotpEnrollment, TotpSecretError> {
+        let pending = sqlx::query_as!(
+            TotpAuthenticator,
+            "UPDATE totp_authenticators SET label = $2
+             WHERE id = (SELECT id FROM totp_authenticators WHERE user_id = $1 AND confirmed_at IS NULL ORDER BY id DESC LIMIT 1)
+             RETURNING id, user_id, label, secret, key_id, algorithm, digits, period, last_used_step, confirmed_at, created_at, last_used_at",
+            user_id,
+            label
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        let (authenticator, secret) = match pending {
+            Some(authenticator) => {
+                let secret = keyring.decrypt(user_id, &authenticator.secret, authenticator.key_id.as_deref())?;
+                (authenticator, secret)
+            }
+            None => {
+                let secret = crate::utils::totp::generate_totp_secret().map_err(|_| TotpSecretError::InvalidParameters)?;
+                let (stored, key_id) = keyring.encrypt(user_id, &secret)?;
+
+                let authenticator = sqlx::query_as!(
+                    TotpAuthenticator,
+                    "INSERT INTO totp_authenticators (user_id, label, secret, key_id, algorithm, digits, period)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7)
+                     RETURNING id, user_id, label, secret, key_id, algorithm, digits, period, last_used_step, confirmed_at, created_at, last_used_at",
+                    user_id,
+                    label,
+                    stored,
+                    key_id,
+                    algorithm_name(config.algorithm),
+                    config.digits as i16,
+                    config.period as i32
+                )
+                .fetch_one(pool)
+                .await?;
+                (authenticator, secret)
+            }
+        };
+
+        let totp = totp(&authenticator, &secret, Some(config.issuer.clone()), account_name.replace(':', ""))?;
+        let qr_code_png = totp.get_qr_base64().map_err(|_| TotpSecretError::InvalidParameters)?;
+
+        Ok(TotpEnrollment {
+            qr_code_url: totp.get_url(),
+            qr_code_png,
+            secret,
+            authenticator,
+        })
+    }
+
+    /// Check `code` against the user's authenticators and use it up. Until one is confirmed
+    /// the pending one counts, which is how setup is finished
+    pub async fn verify(
+        pool: &PgPool,
+        keyring: &TotpKeyring,
+        config: &TotpConfig,
+        user_id: i32,
+        code: &str,
+    ) -> Result<Option<TotpAuthenticator>, TotpSecretError> {
+        let authenticators = Self::list(pool, user_id).await?;
+        let any_confirmed = authenticators.iter().any(|a| a.confirmed_at.is_some());
+
+        for authenticator in authenticators.iter().filter(|a| a.confirmed_at.is_some() == any_confirmed) {
+            if let Some(step) = matching_step(keyring, config, authenticator, code)? {
+                return Self::use_step(pool, authenticator.id, step).await;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Finish adding a pending authenticator with a code from it
+    pub async fn confirm(
+        pool: &PgPool,
+        keyring: &TotpKeyring,
+        config: &TotpConfig,
+        authenticator: &TotpAuthenticator,
+        code: &str,
+    ) -> Result<Option<TotpAuthenticator>, TotpSecretError> {
+        match matching_step(keyring, config, authenticator, code)? {
+            Some(step) => Self::use_step(pool, authenticator.id, step).await,
+            None => Ok(None),
+        }
+    }
+
+    // Moving last_used_step forward in the same statement makes concurrent uses of a code fail
+    async fn use_step(pool: &PgPool, id: i32, step: i64) -> Result<Option<TotpAuthenticator>, TotpSecretError> {
+        let now = Utc::now().naive_utc();
+
+        let authenticator = sqlx::query_as!(
+            TotpAuthenticator,
+            "UPDATE totp_authenticators
+             SET last_used_step = $2, last_used_at = $3, confirmed_at = COALESCE(confirmed_at, $3)
+             WHERE id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+             RETURNING id, user_id, label, secret, key_id, algorithm, digits, period, last_used_step, confirmed_at, created_at, last_used_at",
+            id,
+            step,
+            now
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(authenticator)
+    }
+
+    pub async fn rename(pool: &PgPool, user_id: i32, id: i32, label: &str) -> Result<Option<TotpAuthenticator>, sqlx::Error> {
+        sqlx::query_as!(
+            TotpAuthenticator,
+            "UPDATE totp_authenticators SET label = $3 WHERE id = $1 AND user_id = $2
+             RETURNING id, user_id, label, secret, key_id, algorithm, digits, period, last_used_step, confirmed_at, created_at, last_used_at",
+            id,
+            user_id,
+            label
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Delete an authenticator unless it is the last confirmed one (disabling 2FA removes that)
+    pub async fn remove(pool: &PgPool, user_id: i32, id: i32) -> Result<RemoveAuthenticatorOutcome, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        // Lock the account so concurrent removals can't each leave the other as the last one
+        sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let authenticator = sqlx::query_as!(
+            TotpAuthenticator,
+            "SELECT id, user_id, label, secret, key_id, algorithm, digits, period, last_used_step, confirmed_at, created_at, last_used_at
+             FROM totp_authenticators WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(authenticator) = authenticator else {
+            return Ok(RemoveAuthenticatorOutcome::NotFound);
+        };
+
+        if authenticator.confirmed_at.is_some() {
+            let confirmed = sqlx::query_scalar!(
+                "SELECT COUNT(*) AS \"count!\" FROM totp_authenticators WHERE user_id = $1 AND confirmed_at IS NOT NULL",
+                user_id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if confirmed <= 1 {
+                return Ok(RemoveAuthenticatorOutcome::LastAuthenticator);
+            }
+        }
+
+        sqlx::query!("DELETE FROM totp_authenticators WHERE id = $1", id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(RemoveAuthenticatorOutcome::Removed(authenticator))
+    }
+
+    pub async fn clear(pool: &PgPool, user_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM totp_authenticators WHERE user_id = $1", user_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Re-encrypt every secret not under the current master key (including plaintext ones),
+    /// so old keys can be retired. Returns the number of secrets rewritten
+    pub async fn reencrypt_all(pool: &PgPool, keyring: &TotpKeyring) -> Result<u64, TotpSecretError> {
+        let current = keyring.current().ok_or(TotpSecretError::NoMasterKey)?;
+
+        let rows = sqlx::query!(
+            "SELECT id, user_id, secret, key_id FROM totp_authenticators WHERE key_id IS DISTINCT FROM $1",
+            current.id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut rewritten = 0;
+        for row in rows {
+            let secret = keyring.decrypt(row.user_id, &row.secret, row.key_id.as_deref())?;
+            let (stored, key_id) = keyring.encrypt(row.user_id, &secret)?;
+
+            // Skip rows changed since they were read
+            let result = sqlx::query!(
+                "UPDATE totp_authenticators SET secret = $1, key_id = $2
+                 WHERE id = $3 AND secret = $4 AND key_id IS NOT DISTINCT FROM $5",
+                stored,
+                key_id,
+                row.id,
+                row.secret,
+                row.key_id
+            )
+            .execute(pool)
+            .await?;
+            rewritten += result.rows_affected();
+        }
+
+        Ok(rewritten)
+    }
+}
+
+fn totp(authenticator: &TotpAuthenticator, secret: &str, issuer: Option<String>, account_name: String) -> Result<TOTP, TotpSecretError> {
+    let algorithm = parse_algorithm(&authenticator.algorithm).ok_or(TotpSecretError::InvalidParameters)?;
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|_| TotpSecretError::InvalidParameters)?;
+
+    // Skew is applied by `matching_step`, which needs to know the step a code matched
+    TOTP::new(algorithm, authenticator.digits as usize, 0, authenticator.period as u64, secret, issuer, account_name)
+        .map_err(|_| TotpSecretError::InvalidParameters)
+}
+
+// The time step `code` belongs to, if it is within `config.skew` steps of now and newer than
+// the last step accepted for this authenticator
+fn matching_step(
+    keyring: &TotpKeyring,
+    config: &TotpConfig,
+    authenticator: &TotpAuthenticator,
+    code: &str,
+) -> Result<Option<i64>, TotpSecretError> {
+    let code = code.trim();
+    if code.len() != authenticator.digits as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+
+    let secret = keyring.decrypt(authenticator.user_id, &authenticator.secret, authenticator.key_id.as_deref())?;
+    let totp = totp(authenticator, &secret, None, String::new())?;
+
+    let period = authenticator.period as u64;
+    let current = Utc::now().timestamp() as u64 / period;
+
+    Ok((current.saturating_sub(config.skew)..=current + config.skew)
+        .map(|step| step as i64)
+        .filter(|step| authenticator.last_used_step.is_none_or(|last| *step > last))
+        .find(|step| totp.check(code, *step as u64 * period)))
+}
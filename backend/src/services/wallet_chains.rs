@@ -0,0 +1,139 @@
+use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey};
+use ethers::types::{Address, Signature};
+use ethers::utils::to_checksum;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::validation::validate_wallet_address;
+
+/// The chains wallets can sign in with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalletChain {
+    #[default]
+    Ethereum,
+    Solana,
+}
+
+/// Address format and signature scheme of one chain
+pub trait WalletVerifier: Send + Sync {
+    /// Validate an address and return the form it is stored in
+    fn normalize_address(&self, address: &str) -> Result<String, String>;
+
+    /// The form an address takes in sign-in messages
+    fn display_address(&self, address: &str) -> Result<String, String>;
+
+    /// Check that `signature` is `address`'s signature of `message`, as produced by the
+    /// chain's wallets (`personal_sign` / `signMessage`)
+    fn verify_signature(&self, address: &str, message: &str, signature: &str) -> Result<(), String>;
+}
+
+impl WalletChain {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WalletChain::Ethereum => "ethereum",
+            WalletChain::Solana => "solana",
+        }
+    }
+
+    /// How the chain is named in sign-in messages ("... with your Solana account")
+    pub fn display_name(self) -> &'static str {
+        match self {
+            WalletChain::Ethereum => "Ethereum",
+            WalletChain::Solana => "Solana",
+        }
+    }
+
+    pub fn from_display_name(name: &str) -> Option<Self> {
+        match name {
+            "Ethereum" => Some(WalletChain::Ethereum),
+            "Solana" => Some(WalletChain::Solana),
+            _ => None,
+        }
+    }
+
+    pub fn verifier(self) -> &'static dyn WalletVerifier {
+        match self {
+            WalletChain::Ethereum => &EthereumVerifier,
+            WalletChain::Solana => &SolanaVerifier,
+        }
+    }
+}
+
+/// EVM addresses with EIP-191 `personal_sign` (secp256k1) signatures
+pub struct EthereumVerifier;
+
+impl WalletVerifier for EthereumVerifier {
+    // Mixed case is only a checksum, so addresses are stored lowercase
+    fn normalize_address(&self, address: &str) -> Result<String, String> {
+        validate_wallet_address(address.trim())?;
+        Ok(address.trim().to_lowercase())
+    }
+
+    // EIP-55 checksummed
+    fn display_address(&self, address: &str) -> Result<String, String> {
+        let parsed: Address = self.normalize_address(address)?.parse().map_err(|_| "Invalid wallet address".to_string())?;
+        Ok(to_checksum(&parsed, None))
+    }
+
+    // Message is hashed with Keccak256 and the "\x19Ethereum Signed Message:" prefix
+    fn verify_signature(&self, address: &str, message: &str, signature: &str) -> Result<(), String> {
+        let sig_bytes = hex::decode(signature.strip_prefix("0x").unwrap_or(signature))
+            .map_err(|_| "Invalid signature".to_string())?;
+        if sig_bytes.len() != 65 {
+            return Err("Invalid signature length (expected 65 bytes)".to_string());
+        }
+        if message.is_empty() {
+            return Err("Message cannot be empty".to_string());
+        }
+
+        let signature = Signature::try_from(sig_bytes.as_slice()).map_err(|_| "Invalid signature".to_string())?;
+        let recovered = signature.recover(message).map_err(|_| "Invalid signature".to_string())?;
+
+        if format!("{:?}", recovered) != self.normalize_address(address)? {
+            return Err("Signature does not match the address".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Base58 ed25519 public keys, signing the raw UTF-8 message (Phantom's `signMessage`)
+pub struct SolanaVerifier;
+
+impl SolanaVerifier {
+    fn public_key(address: &str) -> Result<VerifyingKey, String> {
+        let bytes: [u8; 32] = bs58::decode(address.trim())
+            .into_vec()
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| "Solana address must be a base58 encoded 32 byte public key".to_string())?;
+        VerifyingKey::from_bytes(&bytes).map_err(|_| "Invalid Solana public key".to_string())
+    }
+}
+
+impl WalletVerifier for SolanaVerifier {
+    // Base58 is case-sensitive, so the address is kept as is
+    fn normalize_address(&self, address: &str) -> Result<String, String> {
+        Self::public_key(address)?;
+        Ok(address.trim().to_string())
+    }
+
+    fn display_address(&self, address: &str) -> Result<String, String> {
+        self.normalize_address(address)
+    }
+
+    // Wallets return the 64 byte signature, which clients send base58 or 0x-hex encoded
+    fn verify_signature(&self, address: &str, message: &str, signature: &str) -> Result<(), String> {
+        let public_key = Self::public_key(address)?;
+        let sig_bytes = match signature.strip_prefix("0x") {
+            Some(hex_signature) => hex::decode(hex_signature).ok(),
+            None => bs58::decode(signature).into_vec().ok(),
+        }
+        .ok_or_else(|| "Invalid signature".to_string())?;
+        let signature = Ed25519Signature::from_slice(&sig_bytes)
+            .map_err(|_| "Invalid signature length (expected 64 bytes)".to_string())?;
+
+        public_key
+            .verify_strict(message.as_bytes(), &signature)
+            .map_err(|_| "Signature does not match the address".to_string())
+    }
+}
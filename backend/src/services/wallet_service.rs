@@ -0,0 +1,327 @@
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+
+use crate::models::user::User;
+use crate::services::identity_service::IdentityService;
+use crate::services::wallet_chains::WalletChain;
+
+/// A wallet linked to an account
+#[derive(Debug, Clone, Serialize)]
+pub struct UserWallet {
+    pub id: i32,
+    pub user_id: i32,
+    pub address: String,
+    /// "ethereum" or "solana"
+    pub chain: String,
+    pub label: Option<String>,
+    pub is_primary: bool,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+pub enum AddWalletOutcome {
+    Added(UserWallet),
+    AlreadyAdded(UserWallet),
+    OwnedByOtherUser,
+}
+
+pub enum RemoveWalletOutcome {
+    Removed(UserWallet),
+    NotFound,
+    LastLoginMethod,
+}
+
+#[derive(Clone, Debug)]
+pub struct WalletService;
+
+impl WalletService {
+    pub const MAX_LABEL_LENGTH: usize = 64;
+
+    /// EVM addresses are case-insensitive (mixed case is only a checksum), so they are stored
+    /// lowercase. Other (base58) addresses are case-sensitive and kept as is
+    pub fn normalize_address(address: &str) -> String {
+        let address = address.trim();
+        if address.starts_with("0x") || address.starts_with("0X") {
+            address.to_lowercase()
+        } else {
+            address.to_string()
+        }
+    }
+
+    /// The account a wallet signs into
+    pub async fn find_user(pool: &PgPool, address: &str) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            "SELECT u.id, u.username, u.email, u.password, u.role, u.wallet_address, u.email_verified, u.totp_enabled, u.recovery_codes, u.is_banned, u.banned_until, u.last_login, u.created_at, u.updated_at
+             FROM user_wallets w
+             JOIN users u ON u.id = w.user_id
+             WHERE w.address = $1",
+            Self::normalize_address(address)
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn touch(pool: &PgPool, address: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE user_wallets SET last_used_at = $2 WHERE address = $1",
+            Self::normalize_address(address),
+            Utc::now().naive_utc()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list(pool: &PgPool, user_id: i32) -> Result<Vec<UserWallet>, sqlx::Error> {
+        sqlx::query_as!(
+            UserWallet,
+            "SELECT id, user_id, address, chain, label, is_primary, created_at, last_used_at
+             FROM user_wallets WHERE user_id = $1
+             ORDER BY is_primary DESC, created_at",
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// New account for a first-time wallet sign-in
+    pub async fn create_user(pool: &PgPool, username: &str, address: &str, chain: WalletChain) -> Result<User, sqlx::Error> {
+        let address = Self::normalize_address(address);
+        let mut tx = pool.begin().await?;
+
+        // Web3 users don't have email initially - they can add it later
+        let user = sqlx::query_as!(
+            User,
+            "INSERT INTO users (username, email, password, role, wallet_address, email_verified)
+             VALUES ($1, NULL, '', 'user', $2, true)
+             RETURNING id, username, email, password, role, wallet_address, email_verified, totp_enabled, recovery_codes, is_banned, banned_until, last_login, created_at, updated_at",
+            username,
+            address
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO user_wallets (user_id, address, chain, is_primary, created_at, last_used_at)
+             VALUES ($1, $2, $3, true, $4, $4)",
+            user.id,
+            address,
+            chain.as_str(),
+            Utc::now().naive_utc()
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    /// Link a wallet whose ownership has been proven. The first wallet becomes the primary one
+    pub async fn add(
+        pool: &PgPool,
+        user_id: i32,
+        address: &str,
+        chain: WalletChain,
+        label: Option<&str>,
+    ) -> Result<AddWalletOutcome, sqlx::Error> {
+        let address = Self::normalize_address(address);
+        let mut tx = pool.begin().await?;
+        Self::lock_user(&mut tx, user_id).await?;
+
+        let has_primary = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM user_wallets WHERE user_id = $1 AND is_primary)",
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .unwrap_or(false);
+
+        let inserted = sqlx::query_as!(
+            UserWallet,
+            "INSERT INTO user_wallets (user_id, address, chain, label, is_primary, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (address) DO NOTHING
+             RETURNING id, user_id, address, chain, label, is_primary, created_at, last_used_at",
+            user_id,
+            address,
+            chain.as_str(),
+            label,
+            !has_primary,
+            Utc::now().naive_utc()
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(wallet) = inserted else {
+            let existing = sqlx::query_as!(
+                UserWallet,
+                "SELECT id, user_id, address, chain, label, is_primary, created_at, last_used_at
+                 FROM user_wallets WHERE address = $1",
+                address
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            return Ok(if existing.user_id == user_id {
+                AddWalletOutcome::AlreadyAdded(existing)
+            } else {
+                AddWalletOutcome::OwnedByOtherUser
+            });
+        };
+
+        if wallet.is_primary {
+            Self::sync_primary(&mut tx, user_id).await?;
+        }
+        tx.commit().await?;
+
+        Ok(AddWalletOutcome::Added(wallet))
+    }
+
+    /// Rename a wallet and/or make it the primary one
+    pub async fn update(
+        pool: &PgPool,
+        user_id: i32,
+        wallet_id: i32,
+        label: Option<&str>,
+        make_primary: bool,
+    ) -> Result<Option<UserWallet>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        Self::lock_user(&mut tx, user_id).await?;
+
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM user_wallets WHERE id = $1 AND user_id = $2)",
+            wallet_id,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .unwrap_or(false);
+        if !exists {
+            return Ok(None);
+        }
+
+        if let Some(label) = label {
+            sqlx::query!(
+                "UPDATE user_wallets SET label = NULLIF($2, '') WHERE id = $1",
+                wallet_id,
+                label
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if make_primary {
+            sqlx::query!(
+                "UPDATE user_wallets SET is_primary = false WHERE user_id = $1 AND is_primary AND id <> $2",
+                user_id,
+                wallet_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!("UPDATE user_wallets SET is_primary = true WHERE id = $1", wallet_id)
+                .execute(&mut *tx)
+                .await?;
+            Self::sync_primary(&mut tx, user_id).await?;
+        }
+
+        let wallet = sqlx::query_as!(
+            UserWallet,
+            "SELECT id, user_id, address, chain, label, is_primary, created_at, last_used_at
+             FROM user_wallets WHERE id = $1",
+            wallet_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(wallet))
+    }
+
+    /// Unlink a wallet unless it is the last way to sign in. Removing the primary wallet
+    /// promotes the oldest remaining one
+    pub async fn remove(pool: &PgPool, user_id: i32, wallet_id: i32) -> Result<RemoveWalletOutcome, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        Self::lock_user(&mut tx, user_id).await?;
+
+        let wallet = sqlx::query_as!(
+            UserWallet,
+            "SELECT id, user_id, address, chain, label, is_primary, created_at, last_used_at
+             FROM user_wallets WHERE id = $1 AND user_id = $2",
+            wallet_id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(wallet) = wallet else {
+            return Ok(RemoveWalletOutcome::NotFound);
+        };
+
+        if IdentityService::login_methods_on(&mut tx, user_id).await?.count() <= 1 {
+            return Ok(RemoveWalletOutcome::LastLoginMethod);
+        }
+
+        sqlx::query!("DELETE FROM user_wallets WHERE id = $1", wallet_id)
+            .execute(&mut *tx)
+            .await?;
+
+        if wallet.is_primary {
+            Self::ensure_primary(&mut tx, user_id).await?;
+        }
+
+        tx.commit().await?;
+        Ok(RemoveWalletOutcome::Removed(wallet))
+    }
+
+    /// After wallets were removed: promote the oldest remaining one if the primary went with them
+    pub(crate) async fn ensure_primary(conn: &mut PgConnection, user_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE user_wallets SET is_primary = true
+             WHERE id = (SELECT id FROM user_wallets WHERE user_id = $1 ORDER BY created_at, id LIMIT 1)
+               AND NOT EXISTS (SELECT 1 FROM user_wallets WHERE user_id = $1 AND is_primary)",
+            user_id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Self::sync_primary(conn, user_id).await
+    }
+
+    pub async fn primary(pool: &PgPool, user_id: i32) -> Result<Option<UserWallet>, sqlx::Error> {
+        sqlx::query_as!(
+            UserWallet,
+            "SELECT id, user_id, address, chain, label, is_primary, created_at, last_used_at
+             FROM user_wallets WHERE user_id = $1 AND is_primary",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    // Serializes wallet changes per account, so the primary flag and the last-login-method
+    // check can't race
+    async fn lock_user(conn: &mut PgConnection, user_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+            .fetch_one(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    // users.wallet_address mirrors the primary wallet
+    async fn sync_primary(conn: &mut PgConnection, user_id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE users SET wallet_address = (SELECT address FROM user_wallets WHERE user_id = $1 AND is_primary),
+                              updated_at = NOW()
+             WHERE id = $1",
+            user_id
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
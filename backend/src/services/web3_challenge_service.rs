@@ -29,43 +29,25 @@ impl Web3ChallengeService {
         Ok(expires_at)
     }
 
-    /// Verify a Web3 challenge exists and is not expired
-    pub async fn verify_challenge(
+    /// Use up a challenge issued for `address`. Returns false if it is unknown, expired or
+    /// already used; a single UPDATE so two concurrent sign-ins can't both succeed
+    pub async fn consume(
         pool: &PgPool,
         address: &str,
         challenge: &str,
     ) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
-            "SELECT id, expires_at, used_at FROM web3_challenges 
-             WHERE address = $1 AND challenge = $2 AND used_at IS NULL",
+            "UPDATE web3_challenges SET used_at = NOW()
+             WHERE address = $1 AND challenge = $2 AND used_at IS NULL AND expires_at > $3
+             RETURNING id",
             address,
-            challenge
+            challenge,
+            Utc::now().naive_utc()
         )
         .fetch_optional(pool)
         .await?;
 
-        match result {
-            Some(record) => {
-                let expires_at = DateTime::<Utc>::from_naive_utc_and_offset(record.expires_at, Utc);
-                Ok(expires_at > Utc::now())
-            }
-            None => Ok(false),
-        }
-    }
-
-    /// Mark a challenge as used
-    pub async fn mark_used(
-        pool: &PgPool,
-        challenge: &str,
-    ) -> Result<(), sqlx::Error> {
-        sqlx::query!(
-            "UPDATE web3_challenges SET used_at = NOW() WHERE challenge = $1",
-            challenge
-        )
-        .execute(pool)
-        .await?;
-
-        Ok(())
+        Ok(result.is_some())
     }
 
     /// Get active challenge for address (if any)
@@ -0,0 +1,631 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{Duration, NaiveDateTime, Utc};
+use ciborium::value::Value as Cbor;
+use lazy_static::lazy_static;
+use rand::Rng;
+use reqwest::Url;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::models::user::User;
+use crate::services::identity_service::IdentityService;
+
+const DEFAULT_FRONTEND_URL: &str = "http://localhost:5173";
+
+// COSE algorithm identifiers we can verify, in order of preference
+const COSE_ES256: i64 = -7;
+const COSE_EDDSA: i64 = -8;
+const COSE_RS256: i64 = -257;
+
+// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Relying party settings: which site passkeys are bound to and which origins may use them
+#[derive(Debug, Clone)]
+pub struct WebAuthnConfig {
+    /// Registrable domain, e.g. "example.com" (no scheme or port)
+    pub rp_id: String,
+    pub rp_name: String,
+    /// Accepted `origin`s in client data, e.g. "https://app.example.com"
+    pub origins: Vec<String>,
+}
+
+lazy_static! {
+    static ref SHARED_CONFIG: WebAuthnConfig = WebAuthnConfig::from_env();
+}
+
+impl WebAuthnConfig {
+    /// Read `WEBAUTHN_RP_ID`, `WEBAUTHN_RP_NAME` and `WEBAUTHN_ORIGINS` (comma separated).
+    /// The RP ID and origin default to `FRONTEND_URL`
+    pub fn from_env() -> Self {
+        // Empty values (as left by .env.example) count as unset
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+
+        let frontend_url = var("FRONTEND_URL").unwrap_or_else(|| DEFAULT_FRONTEND_URL.to_string());
+        let frontend = Url::parse(&frontend_url).ok();
+
+        let rp_id = var("WEBAUTHN_RP_ID")
+            .or_else(|| frontend.as_ref().and_then(|url| url.host_str()).map(str::to_string))
+            .unwrap_or_else(|| "localhost".to_string());
+
+        let mut origins: Vec<String> = var("WEBAUTHN_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        if origins.is_empty() {
+            origins.extend(frontend.map(|url| url.origin().ascii_serialization()));
+        }
+
+        Self {
+            rp_id,
+            rp_name: var("WEBAUTHN_RP_NAME").unwrap_or_else(|| "USH".to_string()),
+            origins,
+        }
+    }
+
+    pub fn shared() -> Self {
+        SHARED_CONFIG.clone()
+    }
+}
+
+/// What a pending challenge was issued for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CeremonyPurpose {
+    Register,
+    Login, // Passwordless, the user is whoever owns the credential
+    Mfa,   // Second factor for a user who already passed the first one
+}
+
+impl CeremonyPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CeremonyPurpose::Register => "register",
+            CeremonyPurpose::Login => "login",
+            CeremonyPurpose::Mfa => "mfa",
+        }
+    }
+}
+
+/// A registered passkey
+#[derive(Debug, Clone, Serialize)]
+pub struct PasskeyCredential {
+    pub id: i32,
+    pub user_id: i32,
+    pub credential_id: String,
+    #[serde(skip_serializing)]
+    pub public_key: Vec<u8>,
+    pub algorithm: i32, // COSE identifier, -7 ES256, -8 EdDSA, -257 RS256
+    pub sign_count: i64,
+    pub transports: Vec<String>,
+    pub name: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+/// `navigator.credentials.create()` result, as serialized by `PublicKeyCredential.toJSON()`
+#[derive(Debug, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+/// `navigator.credentials.get()` result, as serialized by `PublicKeyCredential.toJSON()`
+#[derive(Debug, Deserialize)]
+pub struct AuthenticationCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+#[derive(Debug)]
+pub enum WebAuthnError {
+    /// Unknown, expired or already used challenge
+    InvalidChallenge,
+    /// Malformed or not meant for us (type, origin, RP ID)
+    InvalidResponse(String),
+    UnknownCredential,
+    CredentialExists,
+    UserNotVerified,
+    InvalidSignature,
+    /// The signature counter went backwards: the authenticator may have been cloned
+    CounterRegression,
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for WebAuthnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebAuthnError::InvalidChallenge => write!(f, "Invalid or expired passkey challenge"),
+            WebAuthnError::InvalidResponse(msg) => write!(f, "Invalid passkey response: {}", msg),
+            WebAuthnError::UnknownCredential => write!(f, "Unknown passkey"),
+            WebAuthnError::CredentialExists => write!(f, "This passkey is already registered"),
+            WebAuthnError::UserNotVerified => write!(f, "The authenticator did not verify the user"),
+            WebAuthnError::InvalidSignature => write!(f, "Invalid passkey signature"),
+            WebAuthnError::CounterRegression => write!(f, "Passkey signature counter went backwards"),
+            WebAuthnError::Database(e) => write!(f, "Passkey database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WebAuthnError {}
+
+impl From<sqlx::Error> for WebAuthnError {
+    fn from(e: sqlx::Error) -> Self {
+        WebAuthnError::Database(e)
+    }
+}
+
+pub enum RemovePasskeyOutcome {
+    Removed(PasskeyCredential),
+    NotFound,
+    LastLoginMethod,
+}
+
+#[derive(Clone, Debug)]
+pub struct WebAuthnService;
+
+impl WebAuthnService {
+    pub const CHALLENGE_TTL_SECONDS: i64 = 300;
+    pub const MAX_NAME_LENGTH: usize = 64;
+
+    /// Options for `navigator.credentials.create()`. Attestation is not requested, so the
+    /// authenticator's make and model are not checked
+    pub async fn start_registration(pool: &PgPool, config: &WebAuthnConfig, user: &User) -> Result<Value, sqlx::Error> {
+        let challenge = Self::create_challenge(pool, CeremonyPurpose::Register, Some(user.id)).await?;
+        let existing = Self::list(pool, user.id).await?;
+        let params: Vec<Value> = [COSE_ES256, COSE_EDDSA, COSE_RS256]
+            .iter()
+            .map(|alg| json!({ "type": "public-key", "alg": alg }))
+            .collect();
+
+        Ok(json!({
+            "challenge": challenge,
+            "rp": { "id": config.rp_id, "name": config.rp_name },
+            "user": {
+                "id": URL_SAFE_NO_PAD.encode(user.id.to_string()),
+                "name": user.email.as_deref().unwrap_or(&user.username),
+                "displayName": user.username
+            },
+            "pubKeyCredParams": params,
+            "timeout": Self::CHALLENGE_TTL_SECONDS * 1000,
+            "attestation": "none",
+            "authenticatorSelection": { "residentKey": "preferred", "userVerification": "preferred" },
+            "excludeCredentials": existing
+                .iter()
+                .map(|c| json!({ "type": "public-key", "id": c.credential_id, "transports": c.transports }))
+                .collect::<Vec<_>>()
+        }))
+    }
+
+    pub async fn finish_registration(
+        pool: &PgPool,
+        config: &WebAuthnConfig,
+        user_id: i32,
+        credential: &RegistrationCredential,
+        name: Option<&str>,
+    ) -> Result<PasskeyCredential, WebAuthnError> {
+        let response = &credential.response;
+        let client_data = decode(&response.client_data_json)?;
+        let challenge = check_client_data(config, &client_data, "webauthn.create")?;
+        Self::consume_challenge(pool, &challenge, CeremonyPurpose::Register, Some(user_id)).await?;
+
+        let attestation: Cbor = ciborium::de::from_reader(decode(&response.attestation_object)?.as_slice())
+            .map_err(|_| invalid("attestation object is not CBOR"))?;
+        let auth_data = cbor_map_get(&attestation, |key| key.as_text() == Some("authData"))
+            .and_then(Cbor::as_bytes)
+            .ok_or_else(|| invalid("attestation object has no authData"))?;
+
+        let auth_data = AuthenticatorData::parse(auth_data)?;
+        auth_data.check(config, false)?;
+        let attested = auth_data.attested.ok_or_else(|| invalid("no attested credential data"))?;
+
+        let credential_id = URL_SAFE_NO_PAD.encode(&attested.credential_id);
+        if credential_id != credential.id {
+            return Err(invalid("credential id mismatch"));
+        }
+        let algorithm = CoseKey::parse(&attested.public_key)?.algorithm;
+
+        let name = name.map(str::trim).filter(|n| !n.is_empty());
+        let inserted = sqlx::query_as!(
+            PasskeyCredential,
+            "INSERT INTO webauthn_credentials (user_id, credential_id, public_key, algorithm, sign_count, transports, name, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (credential_id) DO NOTHING
+             RETURNING id, user_id, credential_id, public_key, algorithm, sign_count, transports, name, created_at, last_used_at",
+            user_id,
+            credential_id,
+            attested.public_key,
+            algorithm as i32,
+            auth_data.sign_count as i64,
+            &response.transports,
+            name,
+            Utc::now().naive_utc()
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        inserted.ok_or(WebAuthnError::CredentialExists)
+    }
+
+    /// Options for `navigator.credentials.get()`. Without a user this is a passwordless login
+    /// with a discoverable credential, which must also verify the user (PIN, biometrics)
+    pub async fn start_authentication(pool: &PgPool, config: &WebAuthnConfig, user_id: Option<i32>) -> Result<Value, sqlx::Error> {
+        let purpose = if user_id.is_some() { CeremonyPurpose::Mfa } else { CeremonyPurpose::Login };
+        let challenge = Self::create_challenge(pool, purpose, user_id).await?;
+
+        let allow_credentials = match user_id {
+            Some(user_id) => Self::list(pool, user_id).await?,
+            None => Vec::new(),
+        };
+
+        Ok(json!({
+            "challenge": challenge,
+            "rpId": config.rp_id,
+            "timeout": Self::CHALLENGE_TTL_SECONDS * 1000,
+            "userVerification": if user_id.is_some() { "preferred" } else { "required" },
+            "allowCredentials": allow_credentials
+                .iter()
+                .map(|c| json!({ "type": "public-key", "id": c.credential_id, "transports": c.transports }))
+                .collect::<Vec<_>>()
+        }))
+    }
+
+    /// Check an assertion for a challenge from `start_authentication` with the same `user_id`.
+    /// Returns the credential used, whose `user_id` is the account to sign in to
+    pub async fn finish_authentication(
+        pool: &PgPool,
+        config: &WebAuthnConfig,
+        user_id: Option<i32>,
+        credential: &AuthenticationCredential,
+    ) -> Result<PasskeyCredential, WebAuthnError> {
+        let purpose = if user_id.is_some() { CeremonyPurpose::Mfa } else { CeremonyPurpose::Login };
+        let response = &credential.response;
+        let client_data = decode(&response.client_data_json)?;
+        let challenge = check_client_data(config, &client_data, "webauthn.get")?;
+        Self::consume_challenge(pool, &challenge, purpose, user_id).await?;
+
+        let stored = sqlx::query_as!(
+            PasskeyCredential,
+            "SELECT id, user_id, credential_id, public_key, algorithm, sign_count, transports, name, created_at, last_used_at
+             FROM webauthn_credentials WHERE credential_id = $1",
+            credential.id
+        )
+        .fetch_optional(pool)
+        .await?
+        .filter(|c| user_id.is_none_or(|user_id| c.user_id == user_id))
+        .ok_or(WebAuthnError::UnknownCredential)?;
+
+        let raw_auth_data = decode(&response.authenticator_data)?;
+        let auth_data = AuthenticatorData::parse(&raw_auth_data)?;
+        auth_data.check(config, purpose == CeremonyPurpose::Login)?;
+
+        // The signature covers authenticatorData || SHA-256(clientDataJSON)
+        let mut signed = raw_auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+        CoseKey::parse(&stored.public_key)?.verify(&signed, &decode(&response.signature)?)?;
+
+        // Authenticators that keep a counter must increase it on every use. Checked in the update,
+        // so two concurrent uses of the same assertion can't both pass
+        let updated = sqlx::query_as!(
+            PasskeyCredential,
+            "UPDATE webauthn_credentials SET sign_count = $2, last_used_at = $3
+             WHERE id = $1 AND (sign_count < $2 OR (sign_count = 0 AND $2 = 0))
+             RETURNING id, user_id, credential_id, public_key, algorithm, sign_count, transports, name, created_at, last_used_at",
+            stored.id,
+            auth_data.sign_count as i64,
+            Utc::now().naive_utc()
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or(WebAuthnError::CounterRegression)?;
+
+        Ok(updated)
+    }
+
+    pub async fn list(pool: &PgPool, user_id: i32) -> Result<Vec<PasskeyCredential>, sqlx::Error> {
+        sqlx::query_as!(
+            PasskeyCredential,
+            "SELECT id, user_id, credential_id, public_key, algorithm, sign_count, transports, name, created_at, last_used_at
+             FROM webauthn_credentials WHERE user_id = $1 ORDER BY created_at, id",
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn has_passkeys(pool: &PgPool, user_id: i32) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM webauthn_credentials WHERE user_id = $1)",
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(exists.unwrap_or(false))
+    }
+
+    pub async fn rename(pool: &PgPool, user_id: i32, id: i32, name: &str) -> Result<Option<PasskeyCredential>, sqlx::Error> {
+        sqlx::query_as!(
+            PasskeyCredential,
+            "UPDATE webauthn_credentials SET name = NULLIF($3, '') WHERE id = $1 AND user_id = $2
+             RETURNING id, user_id, credential_id, public_key, algorithm, sign_count, transports, name, created_at, last_used_at",
+            id,
+            user_id,
+            name.trim()
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Delete a passkey unless it is the last way to sign in
+    pub async fn remove(pool: &PgPool, user_id: i32, id: i32) -> Result<RemovePasskeyOutcome, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        // Lock the account so concurrent removals can't each leave the other as the last method
+        sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let credential = sqlx::query_as!(
+            PasskeyCredential,
+            "SELECT id, user_id, credential_id, public_key, algorithm, sign_count, transports, name, created_at, last_used_at
+             FROM webauthn_credentials WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(credential) = credential else {
+            return Ok(RemovePasskeyOutcome::NotFound);
+        };
+
+        if IdentityService::login_methods_on(&mut tx, user_id).await?.count() <= 1 {
+            return Ok(RemovePasskeyOutcome::LastLoginMethod);
+        }
+
+        sqlx::query!("DELETE FROM webauthn_credentials WHERE id = $1", id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(RemovePasskeyOutcome::Removed(credential))
+    }
+
+    /// Delete ceremonies that were never completed
+    pub async fn cleanup_expired_challenges(pool: &PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM webauthn_challenges WHERE expires_at < $1",
+            Utc::now().naive_utc()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn create_challenge(pool: &PgPool, purpose: CeremonyPurpose, user_id: Option<i32>) -> Result<String, sqlx::Error> {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill(&mut bytes);
+        let challenge = URL_SAFE_NO_PAD.encode(bytes);
+
+        sqlx::query!(
+            "INSERT INTO webauthn_challenges (challenge, purpose, user_id, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5)",
+            challenge,
+            purpose.as_str(),
+            user_id,
+            Utc::now().naive_utc(),
+            (Utc::now() + Duration::seconds(Self::CHALLENGE_TTL_SECONDS)).naive_utc()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(challenge)
+    }
+
+    // Single use: deleted whether or not the rest of the response checks out
+    async fn consume_challenge(
+        pool: &PgPool,
+        challenge: &str,
+        purpose: CeremonyPurpose,
+        user_id: Option<i32>,
+    ) -> Result<(), WebAuthnError> {
+        sqlx::query_scalar!(
+            "DELETE FROM webauthn_challenges
+             WHERE challenge = $1 AND purpose = $2 AND user_id IS NOT DISTINCT FROM $3
+             RETURNING expires_at",
+            challenge,
+            purpose.as_str(),
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .filter(|expires_at| *expires_at > Utc::now().naive_utc())
+        .map(|_| ())
+        .ok_or(WebAuthnError::InvalidChallenge)
+    }
+}
+
+fn invalid(msg: &str) -> WebAuthnError {
+    WebAuthnError::InvalidResponse(msg.to_string())
+}
+
+// Browsers send base64url without padding; tolerate padding anyway
+fn decode(value: &str) -> Result<Vec<u8>, WebAuthnError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| invalid("expected base64url"))
+}
+
+/// Check `clientDataJSON` and return the challenge it signs
+fn check_client_data(config: &WebAuthnConfig, client_data: &[u8], expected_type: &str) -> Result<String, WebAuthnError> {
+    #[derive(Deserialize)]
+    struct ClientData {
+        #[serde(rename = "type")]
+        kind: String,
+        challenge: String,
+        origin: String,
+        #[serde(rename = "crossOrigin", default)]
+        cross_origin: bool,
+    }
+
+    let client_data: ClientData = serde_json::from_slice(client_data).map_err(|_| invalid("malformed clientDataJSON"))?;
+    if client_data.kind != expected_type {
+        return Err(invalid("unexpected ceremony type"));
+    }
+    if !config.origins.contains(&client_data.origin) || client_data.cross_origin {
+        return Err(invalid("unexpected origin"));
+    }
+
+    Ok(client_data.challenge)
+}
+
+struct AttestedCredential {
+    credential_id: Vec<u8>,
+    public_key: Vec<u8>, // COSE_Key, CBOR encoded
+}
+
+struct AuthenticatorData {
+    rp_id_hash: [u8; 32],
+    flags: u8,
+    sign_count: u32,
+    attested: Option<AttestedCredential>,
+}
+
+impl AuthenticatorData {
+    // rpIdHash (32) | flags (1) | signCount (4, big endian) | [aaguid (16) | idLength (2) | id | COSE_Key] | [extensions]
+    fn parse(data: &[u8]) -> Result<Self, WebAuthnError> {
+        if data.len() < 37 {
+            return Err(invalid("authenticator data is too short"));
+        }
+        let rp_id_hash: [u8; 32] = data[..32].try_into().unwrap();
+        let flags = data[32];
+        let sign_count = u32::from_be_bytes(data[33..37].try_into().unwrap());
+
+        let attested = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+            let rest = &data[37..];
+            if rest.len() < 18 {
+                return Err(invalid("attested credential data is too short"));
+            }
+            let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+            let rest = &rest[18..];
+            if rest.len() < id_len {
+                return Err(invalid("credential id is truncated"));
+            }
+            let (credential_id, mut key_and_extensions) = rest.split_at(id_len);
+
+            // The COSE key is followed by optional extensions, so its length is where the CBOR item ends
+            let before = key_and_extensions.len();
+            let _: Cbor = ciborium::de::from_reader(&mut key_and_extensions).map_err(|_| invalid("malformed credential public key"))?;
+            let key_len = before - key_and_extensions.len();
+
+            Some(AttestedCredential {
+                credential_id: credential_id.to_vec(),
+                public_key: rest[id_len..id_len + key_len].to_vec(),
+            })
+        } else {
+            None
+        };
+
+        Ok(Self { rp_id_hash, flags, sign_count, attested })
+    }
+
+    fn check(&self, config: &WebAuthnConfig, require_user_verification: bool) -> Result<(), WebAuthnError> {
+        if self.rp_id_hash[..] != Sha256::digest(config.rp_id.as_bytes())[..] {
+            return Err(invalid("credential is for another site"));
+        }
+        if self.flags & FLAG_USER_PRESENT == 0 {
+            return Err(invalid("user presence was not confirmed"));
+        }
+        if require_user_verification && self.flags & FLAG_USER_VERIFIED == 0 {
+            return Err(WebAuthnError::UserNotVerified);
+        }
+        Ok(())
+    }
+}
+
+enum PublicKey {
+    Es256(Vec<u8>), // Uncompressed SEC1 point
+    EdDsa(Vec<u8>),
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+struct CoseKey {
+    algorithm: i64,
+    key: PublicKey,
+}
+
+impl CoseKey {
+    // COSE_Key map: 1 kty, 3 alg; EC2: -1 crv, -2 x, -3 y; OKP: -1 crv, -2 x; RSA: -1 n, -2 e
+    fn parse(cose: &[u8]) -> Result<Self, WebAuthnError> {
+        let map: Cbor = ciborium::de::from_reader(cose).map_err(|_| invalid("malformed credential public key"))?;
+        let int = |label: i64| cbor_map_get(&map, |key| key.as_integer() == Some(label.into()));
+        let bytes = |label: i64| int(label).and_then(Cbor::as_bytes).cloned();
+        let number = |label: i64| int(label).and_then(Cbor::as_integer).and_then(|i| i64::try_from(i).ok());
+
+        let unsupported = || invalid("unsupported credential public key");
+        let algorithm = number(3).ok_or_else(unsupported)?;
+        let key = match (number(1), algorithm, number(-1)) {
+            // EC2, P-256
+            (Some(2), COSE_ES256, Some(1)) => {
+                let (x, y) = bytes(-2).zip(bytes(-3)).ok_or_else(unsupported)?;
+                if x.len() != 32 || y.len() != 32 {
+                    return Err(unsupported());
+                }
+                PublicKey::Es256([&[0x04][..], &x, &y].concat())
+            }
+            // OKP, Ed25519
+            (Some(1), COSE_EDDSA, Some(6)) => PublicKey::EdDsa(bytes(-2).ok_or_else(unsupported)?),
+            (Some(3), COSE_RS256, _) => {
+                let (n, e) = bytes(-1).zip(bytes(-2)).ok_or_else(unsupported)?;
+                PublicKey::Rs256 { n, e }
+            }
+            _ => return Err(unsupported()),
+        };
+
+        Ok(Self { algorithm, key })
+    }
+
+    fn verify(&self, message: &[u8], sig: &[u8]) -> Result<(), WebAuthnError> {
+        let result = match &self.key {
+            // WebAuthn ES256 signatures are ASN.1 DER encoded
+            PublicKey::Es256(point) => UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point).verify(message, sig),
+            PublicKey::EdDsa(key) => UnparsedPublicKey::new(&signature::ED25519, key).verify(message, sig),
+            PublicKey::Rs256 { n, e } => {
+                RsaPublicKeyComponents { n, e }.verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sig)
+            }
+        };
+        result.map_err(|_| WebAuthnError::InvalidSignature)
+    }
+}
+
+fn cbor_map_get(map: &Cbor, matches: impl Fn(&Cbor) -> bool) -> Option<&Cbor> {
+    map.as_map()?.iter().find(|(key, _)| matches(key)).map(|(_, value)| value)
+}
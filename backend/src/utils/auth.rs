@@ -1,4 +1,3 @@
-use bcrypt::{DEFAULT_COST, hash, verify};
 use chrono::{Duration, Utc};
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
 
@@ -30,16 +29,6 @@ impl std::error::Error for AuthError {}
 pub struct AuthUtils;
 
 impl AuthUtils {
-    /// Hash password with bcrypt
-    pub fn hash_password(password: &str) -> Result<String, AuthError> {
-        hash(password, DEFAULT_COST).map_err(|_| AuthError::HashingError)
-    }
-
-    /// Verify password against hash
-    pub fn verify_password(password: &str, hash: &str) -> Result<bool, AuthError> {
-        verify(password, hash).map_err(|_| AuthError::InvalidCredentials)
-    }
-
     /// Create JWT token
     pub fn create_token(
         user_id: i32,
@@ -126,7 +115,8 @@ impl AuthUtils {
         }
     }
 
-    /// Check if user has required role
+    /// Check if user has required role. Prefer `AuthMiddleware::require_permission` and
+    /// `PermissionService` for anything a moderator or support role might need
     pub fn has_role(user_role: &str, required_role: &str) -> bool {
         match (user_role, required_role) {
             ("admin", _) => true, // Admin has access to everything
@@ -1,4 +1,5 @@
 // Utils modules
 pub mod auth;
 pub mod validation;
-pub mod totp;
\ No newline at end of file
+pub mod totp;
+pub mod password_strength;
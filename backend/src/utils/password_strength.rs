@@ -0,0 +1,208 @@
+//! Password strength estimate in the style of zxcvbn: the password is split into guessable
+//! patterns (common words, the user's own details, repeats, sequences, keyboard runs, years) and
+//! brute-forced characters, and the score follows from the estimated number of guesses
+
+// Most common first, the rank is part of the guess estimate
+const COMMON_WORDS: &[&str] = &[
+    "password", "123456", "qwerty", "admin", "welcome", "letmein", "iloveyou", "monkey", "dragon",
+    "football", "baseball", "master", "shadow", "sunshine", "princess", "login", "abc123", "test",
+    "secret", "trustno1", "superman", "batman", "starwars", "freedom", "whatever", "passw0rd",
+    "hello", "charlie", "michael", "jordan", "ashley", "jennifer", "hunter", "killer", "soccer",
+    "hockey", "ranger", "buster", "thomas", "tigger", "robert", "summer", "winter", "spring",
+    "autumn", "love", "pass", "user", "root", "guest", "changeme", "default", "access", "secure",
+    "computer", "internet", "orange", "banana", "cookie", "cheese", "flower", "purple", "silver",
+    "golden", "money", "angel", "pepper", "ginger", "maggie", "daniel", "andrew", "joshua",
+    "matrix", "mustang", "corvette", "harley", "london", "paris", "berlin", "qazwsx", "zaq1",
+    "asdf", "zxcv", "abcd", "wallet", "crypto", "bitcoin", "ethereum", "company",
+];
+
+const KEYBOARD_ROWS: &[&str] = &["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// 0 (too guessable) to 4 (very unguessable), as in zxcvbn
+pub fn strength_score(password: &str, user_inputs: &[&str]) -> u8 {
+    match log10_guesses(password, user_inputs) {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Estimated guesses to find the password, as a power of ten
+pub fn log10_guesses(password: &str, user_inputs: &[&str]) -> f64 {
+    let original: Vec<char> = password.chars().collect();
+    let lower: Vec<char> = original.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let unleeted: Vec<char> = lower.iter().copied().map(unleet).collect();
+    let brute_force = cardinality(&original).log10();
+
+    // The user's own details rank like the most common word
+    let words: Vec<Word> = COMMON_WORDS
+        .iter()
+        .enumerate()
+        .map(|(rank, word)| Word::new(word, rank as f64 + 1.0))
+        .chain(
+            user_inputs
+                .iter()
+                .map(|input| Word::new(&input.to_ascii_lowercase(), 1.0))
+                .filter(|word| word.chars.len() >= 3),
+        )
+        .collect();
+
+    let mut guesses = 0.0;
+    let mut patterns = 0;
+    let mut i = 0;
+    while i < lower.len() {
+        match best_match(&original[i..], &lower[i..], &unleeted[i..], &words) {
+            Some((len, pattern_guesses)) => {
+                guesses += pattern_guesses;
+                patterns += 1;
+                i += len;
+            }
+            None => {
+                guesses += brute_force;
+                i += 1;
+            }
+        }
+    }
+
+    // Some room for the order the patterns are tried in
+    guesses + (1..=patterns).map(|n| (n as f64).log10()).sum::<f64>()
+}
+
+struct Word {
+    chars: Vec<char>,
+    unleeted: Vec<char>,
+    rank: f64,
+}
+
+impl Word {
+    fn new(word: &str, rank: f64) -> Self {
+        let chars: Vec<char> = word.chars().collect();
+        let unleeted = chars.iter().copied().map(unleet).collect();
+        Self { chars, unleeted, rank }
+    }
+}
+
+// Longest pattern at the start, cheapest on ties, as (length, log10 of guesses)
+fn best_match(original: &[char], lower: &[char], unleeted: &[char], words: &[Word]) -> Option<(usize, f64)> {
+    let mut candidates: Vec<(usize, f64)> = Vec::new();
+
+    for word in words {
+        let leet = if lower.starts_with(&word.chars) {
+            false
+        } else if unleeted.starts_with(&word.unleeted) {
+            true
+        } else {
+            continue;
+        };
+        let mut guesses = word.rank.log10();
+        if original[..word.chars.len()].iter().any(|c| c.is_uppercase()) {
+            guesses += 2f64.log10();
+        }
+        if leet {
+            guesses += 2f64.log10();
+        }
+        candidates.push((word.chars.len(), guesses));
+    }
+
+    let repeat = lower.iter().take_while(|c| **c == lower[0]).count();
+    if repeat >= 3 {
+        candidates.push((repeat, (cardinality(&original[..1]) * repeat as f64).log10()));
+    }
+
+    let sequence = sequence_len(lower);
+    if sequence >= 3 {
+        let base = if matches!(lower[0], 'a' | 'z' | '0' | '1' | '9') {
+            4.0
+        } else if lower[0].is_ascii_digit() {
+            10.0
+        } else {
+            26.0
+        };
+        candidates.push((sequence, (base * sequence as f64).log10()));
+    }
+
+    let run = keyboard_run_len(lower);
+    if run >= 4 {
+        candidates.push((run, (40.0 * run as f64).log10()));
+    }
+
+    if lower.len() >= 4 && lower[..4].iter().all(|c| c.is_ascii_digit()) {
+        let year: u32 = lower[..4].iter().collect::<String>().parse().unwrap_or(0);
+        if (1900..=2039).contains(&year) {
+            candidates.push((4, 120f64.log10()));
+        }
+    }
+
+    candidates
+        .into_iter()
+        .max_by(|a, b| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)))
+}
+
+// abc, 1234, fedc: same step of one between neighbours
+fn sequence_len(chars: &[char]) -> usize {
+    if chars.len() < 2 || !chars[0].is_ascii_alphanumeric() {
+        return chars.len().min(1);
+    }
+    let step = chars[1] as i32 - chars[0] as i32;
+    if step.abs() != 1 {
+        return 1;
+    }
+    1 + chars
+        .windows(2)
+        .take_while(|w| w[1].is_ascii_alphanumeric() && w[1] as i32 - w[0] as i32 == step)
+        .count()
+}
+
+// qwer, lkjh: neighbours on the same keyboard row, either direction
+fn keyboard_run_len(chars: &[char]) -> usize {
+    KEYBOARD_ROWS
+        .iter()
+        .flat_map(|row| {
+            let row: Vec<char> = row.chars().collect();
+            let reversed: Vec<char> = row.iter().rev().copied().collect();
+            [row, reversed]
+        })
+        .map(|row| {
+            let Some(start) = row.iter().position(|c| *c == chars[0]) else {
+                return 0;
+            };
+            row[start..].iter().zip(chars).take_while(|(a, b)| a == b).count()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// Size of the character set an attacker would have to try
+fn cardinality(chars: &[char]) -> f64 {
+    let mut size = 0.0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        size += 26.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        size += 26.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        size += 10.0;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        size += 33.0;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        size += 100.0;
+    }
+    f64::max(size, 10.0)
+}
+
+fn unleet(c: char) -> char {
+    match c {
+        '@' | '4' => 'a',
+        '3' => 'e',
+        '1' | '!' => 'i',
+        '0' => 'o',
+        '$' | '5' => 's',
+        '7' => 't',
+        c => c,
+    }
+}
@@ -1,3 +1,5 @@
+use crate::services::password_policy_service::PasswordPolicy;
+
 /// Input validation utilities for security
 
 pub fn validate_username(username: &str) -> Result<&str, String> {
@@ -47,34 +49,10 @@ pub fn validate_email(email: &str) -> Result<&str, String> {
     Ok(email)
 }
 
+/// The password policy's own rules (see `PasswordPolicy`). New passwords also go through
+/// `PasswordPolicyService::check`, which adds the breach list and password history
 pub fn validate_password(password: &str) -> Result<&str, String> {
-    if password.is_empty() {
-        return Err("Password cannot be empty".to_string());
-    }
-    
-    if password.len() < 8 {
-        return Err("Password must be at least 8 characters".to_string());
-    }
-    
-    if password.len() > 128 {
-        return Err("Password must not exceed 128 characters".to_string());
-    }
-    
-    // Check for at least one uppercase letter
-    if !password.chars().any(|c| c.is_uppercase()) {
-        return Err("Password must contain at least one uppercase letter".to_string());
-    }
-    
-    // Check for at least one lowercase letter
-    if !password.chars().any(|c| c.is_lowercase()) {
-        return Err("Password must contain at least one lowercase letter".to_string());
-    }
-    
-    // Check for at least one number
-    if !password.chars().any(|c| c.is_numeric()) {
-        return Err("Password must contain at least one number".to_string());
-    }
-    
+    PasswordPolicy::shared().check(password, None, None)?;
     Ok(password)
 }
 
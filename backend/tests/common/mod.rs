@@ -1,5 +1,9 @@
+// Each test crate uses its own subset of these helpers
+#![allow(dead_code)]
+
 use sqlx::PgPool;
 use std::env;
+use totp_rs::{Algorithm, Secret, TOTP};
 
 pub async fn setup_test_db() -> PgPool {
     let database_url = env::var("DATABASE_URL")
@@ -12,6 +16,12 @@ pub async fn setup_test_db() -> PgPool {
     pool
 }
 
+/// Username and email that don't collide with other tests or earlier runs
+pub fn unique_name(prefix: &str) -> (String, String) {
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    (format!("{}_{}", prefix, &suffix[..12]), format!("{}_{}@example.com", prefix, suffix))
+}
+
 pub async fn create_test_user(pool: &PgPool, username: &str, email: &str, email_verified: bool) -> (i32, String, String) {
     use bcrypt::{hash, DEFAULT_COST};
     
@@ -53,3 +63,17 @@ pub fn get_test_jwt_token() -> String {
     let key = EncodingKey::from_secret(b"test-secret");
     encode(&Header::default(), &claims, &key).expect("Failed to create test token")
 }
+
+/// TOTP code `steps` periods from now, with the authenticator app defaults (SHA-1, 6 digits)
+pub fn totp_code(secret: &str, steps: i64) -> String {
+    totp_code_with(Algorithm::SHA1, 6, secret, steps)
+}
+
+/// TOTP code `steps` periods from now for a non-default algorithm or length
+pub fn totp_code_with(algorithm: Algorithm, digits: usize, secret: &str, steps: i64) -> String {
+    let bytes = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    TOTP::new(algorithm, digits, 0, 30, bytes, None, String::new())
+        .unwrap()
+        .generate((now + steps * 30) as u64)
+}
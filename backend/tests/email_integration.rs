@@ -64,3 +64,194 @@ async fn test_verification_email_payload() {
     assert!(payload.contains(code), "Email should contain verification code");
     assert!(payload.contains("15 minutes"), "Email should mention validity period");
 }
+
+#[actix_web::test]
+async fn test_verification_email_content() {
+    use backend::services::email_service::EmailService;
+    use backend::services::email_transport::MemoryEmailTransport;
+    use std::sync::Arc;
+
+    let transport = MemoryEmailTransport::new();
+    let service = EmailService::with_transport(Arc::new(transport.clone()));
+
+    service
+        .send_verification_email("user@example.com", "482913", None)
+        .await
+        .expect("Failed to send verification email");
+
+    let sent = transport.sent();
+    assert_eq!(sent.len(), 1, "Exactly one email should be sent");
+    assert_eq!(sent[0].to, "user@example.com");
+    assert!(sent[0].subject.starts_with("Email Verification Code"), "Subject should describe the email");
+    assert!(sent[0].html_body.contains("482913"), "Email should contain verification code");
+    assert!(sent[0].html_body.contains("3 minutes"), "Email should mention validity period");
+}
+
+#[actix_web::test]
+async fn test_password_reset_email_content() {
+    use backend::services::email_service::EmailService;
+    use backend::services::email_transport::MemoryEmailTransport;
+    use std::sync::Arc;
+
+    let transport = MemoryEmailTransport::new();
+    let service = EmailService::with_transport(Arc::new(transport.clone()));
+
+    service
+        .send_password_reset_email("user@example.com", "resetToken123", None)
+        .await
+        .expect("Failed to send password reset email");
+
+    let sent = transport.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "user@example.com");
+    assert!(sent[0].subject.contains("Password Reset"));
+    assert!(sent[0].html_body.contains("/reset-password?token=resetToken123"), "Email should contain reset link");
+    assert!(sent[0].html_body.contains("60 minutes"), "Email should mention validity period");
+    assert!(sent[0].text_body.contains("/reset-password?token=resetToken123"), "Plain text part should contain reset link");
+}
+
+#[actix_web::test]
+async fn test_invalid_recipient_rejected() {
+    use backend::services::email_service::EmailService;
+    use backend::services::email_transport::MemoryEmailTransport;
+    use std::sync::Arc;
+
+    let transport = MemoryEmailTransport::new();
+    let service = EmailService::with_transport(Arc::new(transport.clone()));
+
+    let result = service.send_verification_email("not-an-email", "123456", None).await;
+
+    assert!(result.is_err(), "Invalid address should fail like it would over SMTP");
+    assert!(transport.sent().is_empty());
+}
+
+#[actix_web::test]
+async fn test_file_transport_writes_eml() {
+    use backend::services::email_service::EmailService;
+    use backend::services::email_transport::FileEmailTransport;
+    use std::sync::Arc;
+
+    let dir = std::env::temp_dir().join(format!("mail_{}", uuid::Uuid::new_v4()));
+    let service = EmailService::with_transport(Arc::new(FileEmailTransport::new(&dir)));
+
+    service
+        .send_verification_email("user@example.com", "482913", None)
+        .await
+        .expect("Failed to write email");
+
+    let files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].extension().and_then(|e| e.to_str()), Some("eml"));
+
+    let raw = std::fs::read_to_string(&files[0]).unwrap();
+    assert!(raw.contains("To: user@example.com"));
+    assert!(raw.contains("Subject: Email Verification Code"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[actix_web::test]
+async fn test_transport_selection() {
+    use backend::services::email_transport::transport_for_provider;
+
+    assert_eq!(transport_for_provider("memory").unwrap().name(), "Memory");
+    assert_eq!(transport_for_provider("file").unwrap().name(), "File");
+    assert!(transport_for_provider("carrier-pigeon").is_err(), "Unknown provider should be rejected");
+}
+
+#[actix_web::test]
+async fn test_email_uses_user_locale() {
+    use backend::services::email_service::EmailService;
+    use backend::services::email_transport::MemoryEmailTransport;
+    use std::sync::Arc;
+
+    let transport = MemoryEmailTransport::new();
+    let service = EmailService::with_transport(Arc::new(transport.clone()));
+
+    service
+        .send_verification_email("user@example.com", "482913", Some("id-ID"))
+        .await
+        .expect("Failed to send verification email");
+
+    let sent = transport.sent();
+    assert!(sent[0].subject.starts_with("Kode Verifikasi Email"), "Indonesian template should be used");
+    assert!(sent[0].text_body.contains("482913"));
+    assert!(sent[0].html_body.contains("3 menit"));
+}
+
+#[actix_web::test]
+async fn test_unknown_locale_falls_back_to_default() {
+    use backend::services::email_templates::{EmailConfig, EmailTemplates};
+
+    let templates = EmailTemplates::from_config(&EmailConfig::default());
+
+    assert_eq!(templates.resolve_locale("verification", Some("fr-FR")).as_deref(), Some("en"));
+    assert_eq!(templates.resolve_locale("verification", Some("id")).as_deref(), Some("id"));
+    assert_eq!(templates.resolve_locale("verification", None).as_deref(), Some("en"));
+    assert_eq!(templates.resolve_locale("no_such_template", None), None);
+}
+
+#[actix_web::test]
+async fn test_mfa_email_expiry_from_constant() {
+    use backend::services::email_service::EmailService;
+    use backend::services::email_transport::MemoryEmailTransport;
+    use std::sync::Arc;
+
+    let transport = MemoryEmailTransport::new();
+    let service = EmailService::with_transport(Arc::new(transport.clone()));
+
+    service
+        .send_mfa_code_email("user@example.com", "135790", None)
+        .await
+        .expect("Failed to send MFA email");
+
+    let expiry = format!("{} minutes", EmailService::MFA_CODE_EXPIRY_MINUTES);
+    let sent = transport.sent();
+    assert!(sent[0].subject.starts_with("Your Login Code"));
+    assert!(sent[0].text_body.contains("135790"));
+    assert!(sent[0].text_body.contains(&expiry), "Expiry should come from MFA_CODE_EXPIRY_MINUTES");
+    assert!(sent[0].html_body.contains(&expiry));
+}
+
+#[actix_web::test]
+async fn test_configurable_sender_and_brand() {
+    use backend::services::email_service::EmailService;
+    use backend::services::email_templates::EmailConfig;
+    use backend::services::email_transport::MemoryEmailTransport;
+    use std::sync::Arc;
+
+    let config = EmailConfig {
+        from_address: "no-reply@acme.test".to_string(),
+        from_name: "Acme".to_string(),
+        brand_name: "Acme & Co".to_string(),
+        frontend_url: "https://app.acme.test".to_string(),
+        ..EmailConfig::default()
+    };
+    let transport = MemoryEmailTransport::new();
+    let service = EmailService::with_config(Arc::new(transport.clone()), config);
+
+    service
+        .send_password_reset_email("user@example.com", "tok123", None)
+        .await
+        .expect("Failed to send password reset email");
+
+    let sent = transport.sent();
+    assert_eq!(sent[0].from, "Acme <no-reply@acme.test>");
+    assert!(sent[0].subject.contains("Acme & Co"));
+    assert!(sent[0].html_body.contains("Acme &amp; Co"), "Variables should be escaped in HTML");
+    assert!(sent[0].text_body.contains("Acme & Co"), "Plain text should not be escaped");
+    assert!(sent[0].html_body.contains("https://app.acme.test/reset-password?token=tok123"));
+}
+
+#[actix_web::test]
+async fn test_locale_from_accept_language() {
+    use backend::services::email_templates::locale_from_request;
+
+    let req = actix_web::test::TestRequest::default()
+        .insert_header(("Accept-Language", "en;q=0.5, id-ID, fr;q=0.8"))
+        .to_http_request();
+    assert_eq!(locale_from_request(&req).as_deref(), Some("id-id"));
+
+    let req = actix_web::test::TestRequest::default().to_http_request();
+    assert_eq!(locale_from_request(&req), None);
+}
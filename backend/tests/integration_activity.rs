@@ -0,0 +1,275 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::auth::traditional::login;
+use backend::routes::api::config;
+use backend::services::audit_logger::{describe_device, AuditLogger, SecurityEventKind};
+use backend::services::identity_service::IdentityService;
+use backend::services::refresh_token_service::RefreshTokenService;
+use backend::services::token_blacklist::TokenBlacklist;
+use backend::utils::auth::AuthUtils;
+
+const FIREFOX_WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+#[actix_web::test]
+async fn test_device_and_kind_mapping() {
+    assert_eq!(describe_device(FIREFOX_WINDOWS), "Firefox on Windows (Desktop)");
+    assert_eq!(
+        describe_device("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 Version/17.0 Mobile/15E148 Safari/604.1"),
+        "Safari on iOS (Mobile)"
+    );
+    assert_eq!(
+        describe_device("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 Chrome/126.0 Safari/537.36 Edg/126.0"),
+        "Edge on Linux (Desktop)"
+    );
+    assert_eq!(describe_device("curl/8.5.0"), "curl (Desktop)");
+
+    assert_eq!(SecurityEventKind::from_event_type("2FA_ENABLED"), SecurityEventKind::TwoFactorEnabled);
+    assert_eq!(SecurityEventKind::from_event_type("SESSION_REVOKED"), SecurityEventKind::SessionRevoked);
+    assert_eq!(SecurityEventKind::from_event_type("SOMETHING_NEW"), SecurityEventKind::Other);
+}
+
+#[actix_web::test]
+async fn test_activity_returns_typed_events() {
+    let pool = common::setup_test_db().await;
+    let (username, email) = common::unique_name("activity");
+    let (user_id, _, _) = common::create_test_user(&pool, &username, &email, true).await;
+
+    for (event_type, status) in [
+        (AuditLogger::EVENT_LOGIN, AuditLogger::STATUS_SUCCESS),
+        (AuditLogger::EVENT_FAILED_LOGIN, AuditLogger::STATUS_FAILED),
+        (AuditLogger::EVENT_TOKEN_REFRESH, AuditLogger::STATUS_SUCCESS),
+        (AuditLogger::EVENT_PASSWORD_CHANGE, AuditLogger::STATUS_SUCCESS),
+    ] {
+        AuditLogger::log(&pool, Some(user_id), event_type, "test event", Some("203.0.113.7"), Some(FIREFOX_WINDOWS), status, None)
+            .await
+            .unwrap();
+    }
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", "test-secret").unwrap();
+
+    let req = test::TestRequest::get()
+        .uri("/api/auth/activity")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let events = body["events"].as_array().unwrap();
+
+    // Newest first, token refreshes are not part of the activity feed
+    let kinds: Vec<&str> = events.iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["password_changed", "failed_login", "login"]);
+    assert_eq!(events[0]["ip_address"], "203.0.113.7");
+    assert_eq!(events[0]["device"], "Firefox on Windows (Desktop)");
+    assert_eq!(events[1]["status"], "failed");
+    assert!(body["next_cursor"].is_null());
+
+    // Keyset pagination
+    let req = test::TestRequest::get()
+        .uri("/api/auth/activity?limit=2")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["events"].as_array().unwrap().len(), 2);
+    let cursor = body["next_cursor"].as_str().expect("Should have another page").to_string();
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/auth/activity?limit=2&cursor={}", cursor))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["events"][0]["kind"], "login");
+    assert!(body["next_cursor"].is_null());
+
+    sqlx::query!("DELETE FROM audit_logs WHERE user_id = $1", user_id).execute(&pool).await.unwrap();
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_not_me_revokes_access_and_forces_reset() {
+    let pool = common::setup_test_db().await;
+    let (username, email) = common::unique_name("notme");
+    let (user_id, _, _) = common::create_test_user(&pool, &username, &email, true).await;
+
+    let refresh_token = RefreshTokenService::generate_token();
+    RefreshTokenService::create_refresh_token(&pool, user_id, &refresh_token).await.unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .route("/login", web::post().to(login))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", "test-secret").unwrap();
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/activity/not-me")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["password_reset_required"], true);
+
+    // The access token that was already issued no longer works
+    let req = test::TestRequest::get()
+        .uri("/api/auth/activity")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let err = test::try_call_service(&app, req).await.expect_err("Revoked token should be rejected");
+    assert_eq!(err.as_response_error().status_code(), 401);
+
+    // Neither does the refresh token
+    assert!(!RefreshTokenService::verify_refresh_token(&pool, user_id, &refresh_token).await.unwrap());
+
+    // Logging in with the old password is refused until it is reset
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .set_json(serde_json::json!({ "username": username, "password": "Test@1234" }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["password_reset_required"], true);
+
+    let reported = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM audit_logs WHERE user_id = $1 AND event_type = 'COMPROMISE_REPORTED'",
+        user_id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(reported, Some(1));
+
+    sqlx::query!("DELETE FROM audit_logs WHERE user_id = $1", user_id).execute(&pool).await.unwrap();
+    sqlx::query!("DELETE FROM refresh_tokens WHERE user_id = $1", user_id).execute(&pool).await.unwrap();
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_not_me_removes_sign_in_methods_added_since() {
+    let pool = common::setup_test_db().await;
+    let (username, email) = common::unique_name("notme");
+    let (user_id, _, _) = common::create_test_user(&pool, &username, &email, true).await;
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+
+    // A month-old account: a wallet from before the reported login, a passkey and a Google login added after it
+    sqlx::query!("UPDATE users SET created_at = NOW() - INTERVAL '30 days' WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    sqlx::query!(
+        "INSERT INTO user_wallets (user_id, address, is_primary, created_at) VALUES ($1, $2, true, NOW() - INTERVAL '20 days')",
+        user_id,
+        format!("0x{}", &suffix[..32])
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO webauthn_credentials (user_id, credential_id, public_key, algorithm, created_at)
+         VALUES ($1, $2, '\\x00', -7, NOW() - INTERVAL '2 days')",
+        user_id,
+        format!("cred-{}", suffix)
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO user_identities (user_id, provider, subject, linked_at) VALUES ($1, 'google', $2, NOW() - INTERVAL '1 hour')",
+        user_id,
+        format!("attacker-{}", suffix)
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let event_id = sqlx::query_scalar!(
+        "INSERT INTO audit_logs (user_id, event_type, event_action, status, created_at)
+         VALUES ($1, 'LOGIN', 'User login successful', 'success', NOW() - INTERVAL '3 days')
+         RETURNING id",
+        user_id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", "test-secret").unwrap();
+
+    // Only the user's own activity can be reported
+    let req = test::TestRequest::post()
+        .uri("/api/auth/activity/not-me")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "event_id": 0 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/activity/not-me")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "event_id": event_id }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["removed_login_methods"]["passkeys"], 1);
+    assert_eq!(body["removed_login_methods"]["identities"], 1);
+    assert_eq!(body["removed_login_methods"]["wallets"], 0);
+
+    let methods = IdentityService::login_methods(&pool, user_id).await.unwrap();
+    assert_eq!((methods.wallets, methods.identities, methods.passkeys), (1, 0, 0));
+
+    sqlx::query!("DELETE FROM audit_logs WHERE user_id = $1", user_id).execute(&pool).await.unwrap();
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_revoking_all_tokens_ignores_the_database_time_zone() {
+    // A session time zone ahead of UTC must not push the cutoff into the future
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .after_connect(|conn, _| Box::pin(async move {
+            sqlx::query("SET TIME ZONE 'Asia/Jakarta'").execute(conn).await.map(|_| ())
+        }))
+        .connect(&std::env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/web3_auth_test".to_string()))
+        .await
+        .unwrap();
+    let (username, email) = common::unique_name("revokeall");
+    let (user_id, _, _) = common::create_test_user(&pool, &username, &email, true).await;
+
+    TokenBlacklist::blacklist_all_user_tokens(&pool, user_id, "test").await.unwrap();
+    let now = chrono::Utc::now().timestamp();
+    assert!(TokenBlacklist::is_revoked_for_user(&pool, user_id, now - 60).await.unwrap());
+    assert!(!TokenBlacklist::is_revoked_for_user(&pool, user_id, now + 2).await.unwrap());
+
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_revocation_check_fails_closed() {
+    let pool = common::setup_test_db().await;
+    let (username, email) = common::unique_name("failclosed");
+    let (user_id, _, _) = common::create_test_user(&pool, &username, &email, true).await;
+
+    // The revocation cutoff can't be read, so the token can't be trusted either
+    let broken = common::setup_test_db().await;
+    broken.close().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(broken))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", "test-secret").unwrap();
+    let req = test::TestRequest::get()
+        .uri("/api/auth/activity")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let err = test::try_call_service(&app, req).await.expect_err("Unverifiable token should be rejected");
+    assert_eq!(err.as_response_error().status_code(), 500);
+
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
@@ -327,3 +327,188 @@ async fn test_audit_log_timestamp_is_set() {
     assert!(log_timestamp >= before);
     assert!(log_timestamp <= after);
 }
+
+async fn seed_search_logs(pool: &PgPool) -> i32 {
+    use backend::services::audit_logger::AuditLogger;
+
+    let (user_id, _, _) = common::create_test_user(pool, &format!("audit_{}", uuid::Uuid::new_v4().simple()), &format!("audit_{}@example.com", uuid::Uuid::new_v4()), true).await;
+
+    for i in 0..5 {
+        AuditLogger::log_login(pool, user_id, Some("10.9.8.7"), Some("Mozilla/5.0")).await.unwrap();
+        AuditLogger::log_failed_login(pool, user_id, &format!("bad, \"password\" {}", i), Some("10.9.8.6"), None).await.unwrap();
+    }
+
+    user_id
+}
+
+#[actix_web::test]
+async fn test_audit_search_filters_and_keyset_pagination() {
+    use actix_web::test;
+    use backend::utils::auth::AuthUtils;
+
+    let pool = common::setup_test_db().await;
+    let user_id = seed_search_logs(&pool).await;
+    let app = actix_web::test::init_service(
+        actix_web::App::new()
+            .app_data(actix_web::web::Data::new(pool))
+            .app_data(actix_web::web::Data::new("test-secret".to_string()))
+            .configure(backend::routes::api::config)
+    ).await;
+    let admin_token = AuthUtils::create_token(1, "audit_admin", "admin", "test-secret").unwrap();
+
+    // Filter by user and event type, two rows per page
+    let mut cursor: Option<String> = None;
+    let mut ids = Vec::new();
+    loop {
+        let mut uri = format!("/api/admin/audit-logs?user_id={}&event_type=failed_login&limit=2", user_id);
+        if let Some(cursor) = &cursor {
+            uri.push_str(&format!("&cursor={}", cursor));
+        }
+        let req = test::TestRequest::get()
+            .uri(&uri)
+            .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        for log in body["data"].as_array().unwrap() {
+            assert_eq!(log["event_type"], "FAILED_LOGIN");
+            assert_eq!(log["user_id"], user_id);
+            ids.push(log["id"].as_i64().unwrap());
+        }
+
+        match body["next_cursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+
+    assert_eq!(ids.len(), 5, "Pages should cover every matching row exactly once");
+    assert!(ids.windows(2).all(|w| w[0] > w[1]), "Results should be newest first");
+
+    // IP, status and time range filters
+    let from = (chrono::Utc::now() - chrono::Duration::hours(1)).format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/api/admin/audit-logs?user_id={}&ip=10.9.8.7&status=success&from={}",
+            user_id,
+            from
+        ))
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["data"].as_array().unwrap().len(), 5);
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/admin/audit-logs?user_id={}&to={}", user_id, from))
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(body["data"].as_array().unwrap().is_empty());
+
+    // Bad input
+    let req = test::TestRequest::get()
+        .uri("/api/admin/audit-logs?from=yesterday")
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_audit_search_requires_permission() {
+    use actix_web::test;
+    use backend::utils::auth::AuthUtils;
+
+    let pool = common::setup_test_db().await;
+    let app = actix_web::test::init_service(
+        actix_web::App::new()
+            .app_data(actix_web::web::Data::new(pool))
+            .app_data(actix_web::web::Data::new("test-secret".to_string()))
+            .configure(backend::routes::api::config)
+    ).await;
+
+    for role in ["user", "moderator", "support"] {
+        let token = AuthUtils::create_token(1, "audit_staff", role, "test-secret").unwrap();
+        let req = test::TestRequest::get()
+            .uri("/api/admin/audit-logs")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        let err = test::try_call_service(&app, req).await.expect_err("Should be forbidden");
+        assert_eq!(err.as_response_error().status_code(), 403, "{} should not read audit logs", role);
+    }
+}
+
+#[actix_web::test]
+async fn test_audit_export_ndjson_and_csv() {
+    use actix_web::test;
+    use backend::utils::auth::AuthUtils;
+
+    let pool = common::setup_test_db().await;
+    let user_id = seed_search_logs(&pool).await;
+    let app = actix_web::test::init_service(
+        actix_web::App::new()
+            .app_data(actix_web::web::Data::new(pool.clone()))
+            .app_data(actix_web::web::Data::new("test-secret".to_string()))
+            .configure(backend::routes::api::config)
+    ).await;
+    let admin_token = AuthUtils::create_token(1, "audit_admin", "admin", "test-secret").unwrap();
+
+    // NDJSON: one JSON object per line
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/admin/audit-logs/export?user_id={}", user_id))
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("Content-Type").unwrap(), "application/x-ndjson");
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    let lines: Vec<serde_json::Value> = body.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(lines.len(), 10);
+    assert!(lines.iter().all(|l| l["user_id"] == user_id));
+
+    // CSV: header plus quoted fields
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/admin/audit-logs/export?format=csv&user_id={}&event_type=FAILED_LOGIN", user_id))
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    let mut lines = body.lines();
+    assert_eq!(lines.next().unwrap(), "id,user_id,event_type,event_action,ip_address,user_agent,status,details,created_at");
+    let rows: Vec<&str> = lines.collect();
+    assert_eq!(rows.len(), 5);
+    assert!(rows[0].contains("\"Failed login attempt: bad, \"\"password\"\" 4\""), "Fields should be CSV-quoted: {}", rows[0]);
+
+    // The export itself is audited
+    let exports = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM audit_logs WHERE user_id = 1 AND event_type = 'AUDIT_EXPORT' AND details->'filters'->>'user_id' = $1",
+        user_id.to_string()
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(exports, Some(2));
+
+    // Cells a spreadsheet would evaluate are neutralised
+    backend::services::audit_logger::AuditLogger::log_login(&pool, user_id, Some("10.9.8.7"), Some("=HYPERLINK(\"http://evil.example\")"))
+        .await
+        .unwrap();
+    backend::services::audit_logger::AuditLogger::log_login(&pool, user_id, Some("10.9.8.7"), Some("@SUM(1+1)"))
+        .await
+        .unwrap();
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/admin/audit-logs/export?format=csv&user_id={}&event_type=LOGIN", user_id))
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let body = String::from_utf8(test::read_body(test::call_service(&app, req).await).await.to_vec()).unwrap();
+    assert!(body.contains(",\"'=HYPERLINK(\"\"http://evil.example\"\")\","), "Formula should be escaped: {}", body);
+    assert!(body.contains(",'@SUM(1+1),"), "Formula should be escaped: {}", body);
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/audit-logs/export?format=xml")
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
@@ -0,0 +1,180 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::handlers::interaction::create_comment;
+use backend::middleware::auth::AuthMiddleware;
+use backend::routes::api::config;
+use backend::services::ban_service::BanService;
+use backend::utils::auth::AuthUtils;
+use chrono::{Duration, Utc};
+
+async fn is_banned_flag(pool: &sqlx::PgPool, user_id: i32) -> (Option<bool>, Option<chrono::NaiveDateTime>) {
+    let row = sqlx::query!("SELECT is_banned, banned_until FROM users WHERE id = $1", user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    (row.is_banned, row.banned_until)
+}
+
+#[actix_web::test]
+async fn test_timed_ban_expires() {
+    let pool = common::setup_test_db().await;
+    let (name, email) = common::unique_name("timedban");
+    let (user_id, _, _) = common::create_test_user(&pool, &name, &email, true).await;
+    let (mod_name, mod_email) = common::unique_name("moderator");
+    let (moderator_id, _, _) = common::create_test_user(&pool, &mod_name, &mod_email, true).await;
+
+    let until = (Utc::now() + Duration::hours(2)).naive_utc();
+    let ban = BanService::ban(&pool, user_id, Some(moderator_id), "Spam", Some(until)).await.unwrap();
+    assert_eq!(ban.moderator_id, Some(moderator_id));
+    assert_eq!(BanService::active_ban(&pool, user_id).await.unwrap().map(|b| b.id), Some(ban.id));
+    assert_eq!(is_banned_flag(&pool, user_id).await, (Some(true), ban.expires_at));
+
+    // Once the expiry passes the ban stops applying, even before the job runs
+    sqlx::query!("UPDATE user_bans SET expires_at = NOW() - INTERVAL '1 minute' WHERE id = $1", ban.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(BanService::active_ban(&pool, user_id).await.unwrap().is_none());
+
+    // The job lifts it and clears the flag on users
+    assert!(BanService::expire_bans(&pool).await.unwrap() >= 1);
+    assert_eq!(is_banned_flag(&pool, user_id).await, (Some(false), None));
+
+    let history = BanService::history(&pool, user_id).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].lift_reason.as_deref(), Some("expired"));
+    assert!(history[0].lifted_at.is_some());
+
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[user_id, moderator_id]).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_new_ban_replaces_and_lift_clears() {
+    let pool = common::setup_test_db().await;
+    let (name, email) = common::unique_name("reban");
+    let (user_id, _, _) = common::create_test_user(&pool, &name, &email, true).await;
+
+    let first = BanService::ban(&pool, user_id, None, "First", Some((Utc::now() + Duration::days(1)).naive_utc()))
+        .await
+        .unwrap();
+    let second = BanService::ban(&pool, user_id, None, "Permanent", None).await.unwrap();
+
+    let active = BanService::active_ban(&pool, user_id).await.unwrap().unwrap();
+    assert_eq!(active.id, second.id);
+    assert_eq!(is_banned_flag(&pool, user_id).await, (Some(true), None));
+
+    let history = BanService::history(&pool, user_id).await.unwrap();
+    let replaced = history.iter().find(|b| b.id == first.id).unwrap();
+    assert_eq!(replaced.lift_reason.as_deref(), Some("Replaced by a new ban"));
+
+    assert_eq!(BanService::lift(&pool, user_id, None, "Lifted by moderator").await.unwrap(), 1);
+    assert!(BanService::active_ban(&pool, user_id).await.unwrap().is_none());
+    assert_eq!(is_banned_flag(&pool, user_id).await, (Some(false), None));
+
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_banned_user_appeal_flow() {
+    let pool = common::setup_test_db().await;
+    let (name, email) = common::unique_name("appealer");
+    let (user_id, _, _) = common::create_test_user(&pool, &name, &email, true).await;
+    let (admin_name, admin_email) = common::unique_name("appealadmin");
+    let (admin_id, _, _) = common::create_test_user(&pool, &admin_name, &admin_email, true).await;
+
+    let post_id = sqlx::query_scalar!(
+        "INSERT INTO posts (title, content, user_id) VALUES ('Post', 'Body', $1) RETURNING id",
+        admin_id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+
+    BanService::ban(&pool, user_id, Some(admin_id), "Harassment", Some((Utc::now() + Duration::days(7)).naive_utc()))
+        .await
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .route("/comments/{id}", web::post().to(create_comment).wrap(AuthMiddleware::new()))
+            .configure(config)
+    ).await;
+    let user_token = AuthUtils::create_token(user_id, &name, "user", "test-secret").unwrap();
+    let admin_token = AuthUtils::create_token(admin_id, &admin_name, "admin", "test-secret").unwrap();
+
+    // Banned users cannot comment and are told why
+    let req = test::TestRequest::post()
+        .uri(&format!("/comments/{}", post_id))
+        .insert_header(("Authorization", format!("Bearer {}", user_token)))
+        .set_json(serde_json::json!({ "content": "Hello" }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["ban"]["reason"], "Harassment");
+
+    let req = test::TestRequest::get()
+        .uri("/api/auth/ban")
+        .insert_header(("Authorization", format!("Bearer {}", user_token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["banned"], true);
+    assert_eq!(body["can_appeal"], true);
+
+    // Appeal too short, then valid, then a duplicate
+    let appeal = |message: &str| {
+        test::TestRequest::post()
+            .uri("/api/auth/ban/appeal")
+            .insert_header(("Authorization", format!("Bearer {}", user_token)))
+            .set_json(serde_json::json!({ "message": message }))
+            .to_request()
+    };
+    assert_eq!(test::call_service(&app, appeal("sorry")).await.status(), 400);
+    let resp = test::call_service(&app, appeal("That was my brother using my laptop, sorry.")).await;
+    assert_eq!(resp.status(), 201);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let appeal_id = body["appeal"]["id"].as_i64().unwrap();
+    assert_eq!(test::call_service(&app, appeal("Please look at my appeal again.")).await.status(), 409);
+
+    // Regular users cannot see the review queue
+    let req = test::TestRequest::get()
+        .uri("/api/admin/ban-appeals")
+        .insert_header(("Authorization", format!("Bearer {}", user_token)))
+        .to_request();
+    let err = test::try_call_service(&app, req).await.expect_err("Non-admin should be rejected");
+    assert_eq!(err.as_response_error().status_code(), 403);
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/ban-appeals?limit=200")
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let listed = body["data"].as_array().unwrap().iter().find(|a| a["id"] == appeal_id).expect("Appeal should be listed");
+    assert_eq!(listed["ban_reason"], "Harassment");
+    assert_eq!(listed["username"], name.as_str());
+
+    // Approving lifts the ban
+    let review = |approve: bool| {
+        test::TestRequest::post()
+            .uri(&format!("/api/admin/ban-appeals/{}/review", appeal_id))
+            .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+            .set_json(serde_json::json!({ "approve": approve, "note": "Benefit of the doubt" }))
+            .to_request()
+    };
+    assert_eq!(test::call_service(&app, review(true)).await.status(), 200);
+    assert_eq!(test::call_service(&app, review(false)).await.status(), 409);
+    assert!(BanService::active_ban(&pool, user_id).await.unwrap().is_none());
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/comments/{}", post_id))
+        .insert_header(("Authorization", format!("Bearer {}", user_token)))
+        .set_json(serde_json::json!({ "content": "Hello again" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    sqlx::query!("DELETE FROM posts WHERE id = $1", post_id).execute(&pool).await.unwrap();
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[user_id, admin_id]).execute(&pool).await.unwrap();
+}
@@ -0,0 +1,98 @@
+// Debug endpoints are only mounted with the `debug-endpoints` feature, and then only for admins
+
+use actix_web::{test, web, App};
+use backend::routes::api::config;
+
+const DEBUG_ENDPOINTS: &[(&str, &str)] = &[
+    ("GET", "/api/auth/email/debug-codes"),
+    ("GET", "/api/auth/password/debug-tokens"),
+    ("GET", "/api/auth/password/test-email"),
+    ("GET", "/api/auth/rate-limit-stats"),
+    ("GET", "/api/auth/debug/blacklist/stats"),
+    ("POST", "/api/auth/debug/blacklist/cleanup"),
+    ("POST", "/api/auth/debug/cleanup/unverified-accounts"),
+    ("GET", "/api/auth/debug/cleanup/unverified-stats"),
+    ("POST", "/api/auth/debug/hash-password"),
+    ("GET", "/api/auth/debug-2fa"),
+];
+
+fn debug_request(method: &str, uri: &str) -> test::TestRequest {
+    match method {
+        "POST" => test::TestRequest::post().uri(uri).set_json(serde_json::json!({"password": "Test@1234"})),
+        _ => test::TestRequest::get().uri(uri),
+    }
+}
+
+#[cfg(not(feature = "debug-endpoints"))]
+#[actix_web::test]
+async fn test_debug_endpoints_not_mounted_in_release_builds() {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+
+    for (method, uri) in DEBUG_ENDPOINTS {
+        let resp = test::call_service(&app, debug_request(method, uri).to_request()).await;
+        assert_eq!(resp.status(), 404, "{} {} should not exist without debug-endpoints", method, uri);
+    }
+}
+
+#[cfg(feature = "debug-endpoints")]
+#[actix_web::test]
+async fn test_debug_endpoints_require_admin() {
+    use backend::utils::auth::AuthUtils;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+
+    let user_token = AuthUtils::create_token(1, "debug_user", "user", "test-secret").unwrap();
+
+    for (method, uri) in DEBUG_ENDPOINTS {
+        // No token
+        let err = test::try_call_service(&app, debug_request(method, uri).to_request())
+            .await
+            .expect_err("Debug endpoint should reject anonymous requests");
+        assert_eq!(err.as_response_error().status_code(), 401, "{} {}", method, uri);
+
+        // Regular user
+        let req = debug_request(method, uri)
+            .insert_header(("Authorization", format!("Bearer {}", user_token)))
+            .to_request();
+        let err = test::try_call_service(&app, req)
+            .await
+            .expect_err("Debug endpoint should reject non-admin users");
+        assert_eq!(err.as_response_error().status_code(), 403, "{} {}", method, uri);
+    }
+
+    // Admin can reach a debug endpoint
+    let admin_token = AuthUtils::create_token(1, "debug_admin", "admin", "test-secret").unwrap();
+    let req = test::TestRequest::post()
+        .uri("/api/auth/debug/hash-password")
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .set_json(serde_json::json!({"password": "Test@1234"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[cfg(feature = "debug-endpoints")]
+#[actix_web::test]
+async fn test_debug_routes_do_not_shadow_auth_routes() {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/email/check-expiry")
+        .set_json(serde_json::json!({"email": "nobody@example.com"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 200);
+}
@@ -0,0 +1,293 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::routes::api::config;
+use backend::services::email_outbox::EmailOutbox;
+use backend::services::email_service::EmailService;
+use backend::services::email_transport::{EmailMessage, EmailTransport, MemoryEmailTransport, TransportError};
+use backend::utils::auth::AuthUtils;
+use std::sync::Arc;
+
+// The worker claims every due row in the shared test database, so tests that touch queued rows take turns
+static WORKER_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+struct FailingTransport;
+
+impl EmailTransport for FailingTransport {
+    fn name(&self) -> &'static str {
+        "Failing"
+    }
+
+    fn send(&self, _message: &EmailMessage) -> Result<(), TransportError> {
+        Err("relay unavailable".into())
+    }
+}
+
+fn unique_recipient() -> String {
+    format!("outbox_{}@example.com", uuid::Uuid::new_v4().simple())
+}
+
+fn message(to: &str) -> EmailMessage {
+    EmailMessage {
+        from: "USH <no-reply@example.com>".to_string(),
+        to: to.to_string(),
+        subject: "Outbox test".to_string(),
+        text_body: "Your code: 123456".to_string(),
+        html_body: "<p>Your code: 123456</p>".to_string(),
+    }
+}
+
+#[actix_web::test]
+async fn test_queued_email_not_sent_inline() {
+    let _guard = WORKER_LOCK.lock().await;
+    let pool = common::setup_test_db().await;
+    let transport = MemoryEmailTransport::new();
+    let service = EmailService::with_transport(Arc::new(transport.clone())).with_outbox(pool.clone());
+    let recipient = unique_recipient();
+
+    service
+        .send_verification_email(&recipient, "482913", None)
+        .await
+        .expect("Failed to queue email");
+
+    assert!(transport.sent().is_empty(), "Queued email should not hit the transport inline");
+
+    let row = sqlx::query!("SELECT status, attempts FROM email_outbox WHERE recipient = $1", recipient)
+        .fetch_one(&pool)
+        .await
+        .expect("Email should be in the outbox");
+    assert_eq!(row.status, "pending");
+    assert_eq!(row.attempts, 0);
+}
+
+#[actix_web::test]
+async fn test_invalid_recipient_not_queued() {
+    let pool = common::setup_test_db().await;
+    let service = EmailService::with_transport(Arc::new(MemoryEmailTransport::new())).with_outbox(pool.clone());
+
+    let result = service.send_verification_email("not-an-email", "482913", None).await;
+
+    assert!(result.is_err(), "Invalid address should be rejected before queueing");
+}
+
+#[actix_web::test]
+async fn test_worker_delivers_and_clears_body() {
+    let _guard = WORKER_LOCK.lock().await;
+    let pool = common::setup_test_db().await;
+    let recipient = unique_recipient();
+    let id = EmailOutbox::enqueue(&pool, &message(&recipient)).await.expect("Failed to enqueue");
+
+    let transport = MemoryEmailTransport::new();
+    let run = EmailOutbox::process_due(&pool, Arc::new(transport.clone()), 1000)
+        .await
+        .expect("Worker run failed");
+
+    assert!(run.sent >= 1);
+    assert!(transport.sent().iter().any(|m| m.to == recipient), "Queued email should be delivered");
+
+    let entry = EmailOutbox::find(&pool, id).await.unwrap().expect("Row should exist");
+    assert_eq!(entry.status, "sent");
+    assert_eq!(entry.attempts, 1);
+    assert!(entry.sent_at.is_some());
+
+    let bodies = sqlx::query!("SELECT text_body, html_body FROM email_outbox WHERE id = $1", id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert!(bodies.text_body.is_empty() && bodies.html_body.is_empty(), "Delivered bodies should be dropped");
+}
+
+#[actix_web::test]
+async fn test_failures_back_off_then_dead_letter() {
+    let _guard = WORKER_LOCK.lock().await;
+    let pool = common::setup_test_db().await;
+    let id = EmailOutbox::enqueue(&pool, &message(&unique_recipient())).await.expect("Failed to enqueue");
+    sqlx::query!("UPDATE email_outbox SET max_attempts = 2 WHERE id = $1", id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // First failure schedules a retry
+    EmailOutbox::process_due(&pool, Arc::new(FailingTransport), 1000).await.unwrap();
+
+    let entry = EmailOutbox::find(&pool, id).await.unwrap().unwrap();
+    assert_eq!(entry.status, "pending");
+    assert_eq!(entry.attempts, 1);
+    assert_eq!(entry.last_error.as_deref(), Some("relay unavailable"));
+    assert!(entry.next_attempt_at > chrono::Utc::now().naive_utc(), "Retry should be delayed");
+
+    // Not due yet, so another pass leaves it alone
+    EmailOutbox::process_due(&pool, Arc::new(FailingTransport), 1000).await.unwrap();
+    assert_eq!(EmailOutbox::find(&pool, id).await.unwrap().unwrap().attempts, 1);
+
+    // Second failure exhausts max_attempts
+    sqlx::query!("UPDATE email_outbox SET next_attempt_at = next_attempt_at - INTERVAL '1 day' WHERE id = $1", id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    EmailOutbox::process_due(&pool, Arc::new(FailingTransport), 1000).await.unwrap();
+
+    let entry = EmailOutbox::find(&pool, id).await.unwrap().unwrap();
+    assert_eq!(entry.status, "dead");
+    assert_eq!(entry.attempts, 2);
+
+    let bodies = sqlx::query!("SELECT text_body, html_body FROM email_outbox WHERE id = $1", id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert!(!bodies.text_body.is_empty() && !bodies.html_body.is_empty(), "Dead bodies are kept so they can be retried");
+}
+
+#[actix_web::test]
+async fn test_stale_sending_counts_as_attempt() {
+    let _guard = WORKER_LOCK.lock().await;
+    let pool = common::setup_test_db().await;
+    let reclaimed = EmailOutbox::enqueue(&pool, &message(&unique_recipient())).await.unwrap();
+    let exhausted = EmailOutbox::enqueue(&pool, &message(&unique_recipient())).await.unwrap();
+    // Both were being sent by a worker that died; the second has no attempts left
+    sqlx::query!(
+        "UPDATE email_outbox SET status = 'sending', locked_at = NOW() - INTERVAL '1 hour',
+             attempts = CASE WHEN id = $2 THEN max_attempts - 1 ELSE 0 END
+         WHERE id IN ($1, $2)",
+        reclaimed,
+        exhausted
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    EmailOutbox::process_due(&pool, Arc::new(FailingTransport), 1000).await.unwrap();
+
+    let entry = EmailOutbox::find(&pool, reclaimed).await.unwrap().unwrap();
+    assert_eq!(entry.status, "pending");
+    assert_eq!(entry.attempts, 2, "The interrupted send and the failed one both count");
+
+    let entry = EmailOutbox::find(&pool, exhausted).await.unwrap().unwrap();
+    assert_eq!(entry.status, "dead");
+    assert_eq!(entry.attempts, entry.max_attempts);
+}
+
+#[actix_web::test]
+async fn test_cleanup_purges_old_messages() {
+    let pool = common::setup_test_db().await;
+    let old = EmailOutbox::enqueue(&pool, &message(&unique_recipient())).await.unwrap();
+    let recent = EmailOutbox::enqueue(&pool, &message(&unique_recipient())).await.unwrap();
+    sqlx::query!(
+        "UPDATE email_outbox SET status = 'dead', created_at = NOW() - INTERVAL '8 days' WHERE id = $1",
+        old
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    assert!(EmailOutbox::cleanup(&pool).await.unwrap() >= 1);
+
+    assert!(EmailOutbox::find(&pool, old).await.unwrap().is_none());
+    assert!(EmailOutbox::find(&pool, recent).await.unwrap().is_some());
+    sqlx::query!("DELETE FROM email_outbox WHERE id = $1", recent).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_backoff_schedule() {
+    assert_eq!(EmailOutbox::backoff(1).num_seconds(), 30);
+    assert_eq!(EmailOutbox::backoff(2).num_seconds(), 60);
+    assert_eq!(EmailOutbox::backoff(3).num_seconds(), 120);
+    assert_eq!(EmailOutbox::backoff(50).num_seconds(), EmailOutbox::MAX_BACKOFF_SECONDS);
+}
+
+#[actix_web::test]
+async fn test_admin_retry_failed_message() {
+    let _guard = WORKER_LOCK.lock().await;
+    let pool = common::setup_test_db().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+
+    let id = EmailOutbox::enqueue(&pool, &message(&unique_recipient())).await.unwrap();
+    sqlx::query!(
+        "UPDATE email_outbox SET attempts = 3, last_error = 'boom', next_attempt_at = NOW() + INTERVAL '1 hour' WHERE id = $1",
+        id
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let dead = EmailOutbox::enqueue(&pool, &message(&unique_recipient())).await.unwrap();
+    sqlx::query!("UPDATE email_outbox SET status = 'dead', attempts = 8, last_error = 'boom' WHERE id = $1", dead)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let admin_token = AuthUtils::create_token(1, "outbox_admin", "admin", "test-secret").unwrap();
+    let user_token = AuthUtils::create_token(2, "outbox_user", "user", "test-secret").unwrap();
+
+    // Regular users cannot see the outbox
+    let req = test::TestRequest::get()
+        .uri("/api/admin/email-outbox")
+        .insert_header(("Authorization", format!("Bearer {}", user_token)))
+        .to_request();
+    let err = test::try_call_service(&app, req).await.expect_err("Non-admin should be rejected");
+    assert_eq!(err.as_response_error().status_code(), 403);
+
+    // Dead message shows up in the dead-letter listing
+    let req = test::TestRequest::get()
+        .uri("/api/admin/email-outbox?status=dead&limit=200")
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let listed = body["data"].as_array().unwrap().iter().any(|m| m["id"] == dead);
+    assert!(listed, "Dead message should be listed");
+    assert!(body["data"][0].get("html_body").is_none(), "Bodies should not be exposed");
+
+    // Retry skips the backoff, without giving the message more attempts
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/admin/email-outbox/{}/retry", id))
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+
+    let entry = EmailOutbox::find(&pool, id).await.unwrap().unwrap();
+    assert_eq!(entry.status, "pending");
+    assert_eq!(entry.attempts, 3);
+    assert!(entry.next_attempt_at <= chrono::Utc::now().naive_utc());
+
+    // Dead messages go back in the queue with their attempts reset
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/admin/email-outbox/{}/retry", dead))
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+
+    let entry = EmailOutbox::find(&pool, dead).await.unwrap().unwrap();
+    assert_eq!(entry.status, "pending");
+    assert_eq!(entry.attempts, 0);
+    assert!(entry.next_attempt_at <= chrono::Utc::now().naive_utc());
+
+    // Sent messages cannot be retried
+    EmailOutbox::mark_sent(&pool, id).await.unwrap();
+    let req = test::TestRequest::post()
+        .uri(&format!("/api/admin/email-outbox/{}/retry", id))
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 409);
+
+    // Unknown id and bad filter
+    let req = test::TestRequest::post()
+        .uri("/api/admin/email-outbox/0/retry")
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/email-outbox?status=lost")
+        .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
@@ -0,0 +1,245 @@
+mod common;
+
+use actix_web::{test, web, App, HttpResponse, HttpServer};
+use backend::auth::google::google_callback;
+use backend::services::google_id_token::{GoogleAuthConfig, GoogleIdTokenVerifier, GoogleTokenError};
+use backend::services::jwks_cache::{cache_ttl, JwksCache};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const CLIENT_ID: &str = "test-client.apps.googleusercontent.com";
+const KEY_A_PEM: &str = include_str!("fixtures/google_test_key_a.pem");
+const KEY_B_PEM: &str = include_str!("fixtures/google_test_key_b.pem");
+// Public modulus of fixture key A (base64url), as it appears in a JWKS
+const KEY_A_N: &str = "jnZrZB-yBdVW6pKRQVjuzViz0B2wv9NLs-y6GcGQOFH9AqP_lem-6WjF6vSMgV01LAlzAPvmh-QUvLzSIAy0AENSOiZuDEfAfwYX7a-0j7-ADZ5X5RJAp34My3rlQ6brm8UwCa5Piqq5redTk3kCQlRayIziZDcgcSc29DGV3TGrIPz9WhjSy9nd_Jv8fMKU-a4HwCTy4AMUb0MvhpMiEApM_oPlm1OUvE0ZNqd1CiCfgVAzo_F_2dz-o3xUYAaeGVR7beAeMhySFyutitUuLmVMv0B4p0vDbq17mbWOEitXBnVbnt4LZkJMDFePq03QMukgiloXjRiIF9Y6ETPc7Q";
+
+/// Serve a JWKS containing key A (kid "key-a") with the given Cache-Control,
+/// returns the URL and a counter of requests served
+async fn start_jwks_stub(cache_control: &'static str) -> (String, Arc<AtomicUsize>) {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+
+    let server = HttpServer::new(move || {
+        let counter = counter.clone();
+        App::new().route(
+            "/certs",
+            web::get().to(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    HttpResponse::Ok()
+                        .insert_header(("Cache-Control", cache_control))
+                        .json(serde_json::json!({
+                            "keys": [{ "kty": "RSA", "use": "sig", "alg": "RS256", "kid": "key-a", "n": KEY_A_N, "e": "AQAB" }]
+                        }))
+                }
+            }),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    let port = server.addrs()[0].port();
+    actix_web::rt::spawn(server.run());
+
+    (format!("http://127.0.0.1:{}/certs", port), hits)
+}
+
+fn verifier(jwks_url: &str) -> GoogleIdTokenVerifier {
+    GoogleIdTokenVerifier::new(GoogleAuthConfig {
+        client_ids: vec!["other-client".to_string(), CLIENT_ID.to_string()],
+        jwks_url: jwks_url.to_string(),
+        tokeninfo_url: "http://127.0.0.1:9/tokeninfo".to_string(),
+        accept_access_tokens: true,
+    })
+}
+
+fn id_token(pem: &str, kid: &str, claims: serde_json::Value) -> String {
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(kid.to_string());
+    encode(&header, &claims, &EncodingKey::from_rsa_pem(pem.as_bytes()).unwrap()).unwrap()
+}
+
+fn claims(email: &str) -> serde_json::Value {
+    let now = Utc::now().timestamp();
+    serde_json::json!({
+        "iss": "https://accounts.google.com",
+        "sub": "1234567890",
+        "aud": CLIENT_ID,
+        "email": email,
+        "email_verified": true,
+        "name": "Google Tester",
+        "iat": now,
+        "exp": now + 3600
+    })
+}
+
+#[actix_web::test]
+async fn test_valid_token_is_verified_and_keys_are_cached() {
+    let (url, hits) = start_jwks_stub("public, max-age=600").await;
+    let verifier = verifier(&url);
+
+    let token = id_token(KEY_A_PEM, "key-a", claims("tester@example.com"));
+    let verified = verifier.verify(&token).await.expect("Token should verify");
+    assert_eq!(verified.email, "tester@example.com");
+    assert_eq!(verified.aud, CLIENT_ID);
+
+    // Second verification is served from the cache, including from a clone
+    verifier.clone().verify(&token).await.unwrap();
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+    // The short form of the issuer is accepted too
+    let mut short_iss = claims("tester@example.com");
+    short_iss["iss"] = "accounts.google.com".into();
+    assert!(verifier.verify(&id_token(KEY_A_PEM, "key-a", short_iss)).await.is_ok());
+}
+
+#[actix_web::test]
+async fn test_invalid_tokens_are_rejected() {
+    let (url, _) = start_jwks_stub("public, max-age=600").await;
+    let verifier = verifier(&url);
+
+    let mut wrong_aud = claims("tester@example.com");
+    wrong_aud["aud"] = "someone-elses-app".into();
+    let mut wrong_iss = claims("tester@example.com");
+    wrong_iss["iss"] = "https://evil.example.com".into();
+    let mut expired = claims("tester@example.com");
+    expired["exp"] = (Utc::now().timestamp() - 3600).into();
+
+    for claims in [wrong_aud, wrong_iss, expired] {
+        let result = verifier.verify(&id_token(KEY_A_PEM, "key-a", claims)).await;
+        assert!(matches!(result, Err(GoogleTokenError::Invalid(_))), "{:?}", result);
+    }
+
+    // Signed with a different key but claiming Google's kid
+    let forged = id_token(KEY_B_PEM, "key-a", claims("tester@example.com"));
+    assert!(matches!(verifier.verify(&forged).await, Err(GoogleTokenError::Invalid(_))));
+
+    let unknown_kid = id_token(KEY_B_PEM, "key-b", claims("tester@example.com"));
+    assert!(matches!(verifier.verify(&unknown_kid).await, Err(GoogleTokenError::UnknownKey(_))));
+
+    // Unsigned payloads are no longer trusted
+    let payload = URL_SAFE_NO_PAD.encode(claims("tester@example.com").to_string());
+    let unsigned = format!("{}.{}.", URL_SAFE_NO_PAD.encode(r#"{"alg":"none","kid":"key-a"}"#), payload);
+    assert!(verifier.verify(&unsigned).await.is_err());
+}
+
+#[actix_web::test]
+async fn test_no_store_refetches_and_unconfigured_client_ids() {
+    let (url, hits) = start_jwks_stub("no-store").await;
+    let verifier = verifier(&url);
+
+    let token = id_token(KEY_A_PEM, "key-a", claims("tester@example.com"));
+    verifier.verify(&token).await.unwrap();
+    verifier.verify(&token).await.unwrap();
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+    let unconfigured = GoogleIdTokenVerifier::new(GoogleAuthConfig {
+        client_ids: vec![],
+        jwks_url: url,
+        tokeninfo_url: "http://127.0.0.1:9/tokeninfo".to_string(),
+        accept_access_tokens: true,
+    });
+    assert!(matches!(unconfigured.verify(&token).await, Err(GoogleTokenError::NotConfigured)));
+}
+
+/// Serve a tokeninfo endpoint answering for a few fixed access tokens
+async fn start_tokeninfo_stub() -> String {
+    let server = HttpServer::new(|| {
+        App::new().route(
+            "/tokeninfo",
+            web::get().to(|query: web::Query<std::collections::HashMap<String, String>>| async move {
+                let info = match query.get("access_token").map(String::as_str) {
+                    Some("good") => serde_json::json!({ "aud": CLIENT_ID, "azp": CLIENT_ID, "sub": "1234567890", "email": "tester@example.com", "email_verified": "true" }),
+                    Some("no-sub") => serde_json::json!({ "aud": CLIENT_ID, "email": "tester@example.com" }),
+                    Some("empty-sub") => serde_json::json!({ "aud": CLIENT_ID, "sub": "", "email": "tester@example.com" }),
+                    Some("azp-only") => serde_json::json!({ "azp": CLIENT_ID, "sub": "1234567890", "email": "tester@example.com" }),
+                    Some("other-azp") => serde_json::json!({ "aud": CLIENT_ID, "azp": "someone-elses-app", "sub": "1234567890", "email": "tester@example.com" }),
+                    _ => return HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid_token" })),
+                };
+                HttpResponse::Ok().json(info)
+            }),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    let port = server.addrs()[0].port();
+    actix_web::rt::spawn(server.run());
+
+    format!("http://127.0.0.1:{}/tokeninfo", port)
+}
+
+#[actix_web::test]
+async fn test_access_tokens_need_subject_and_audience() {
+    let config = GoogleAuthConfig {
+        client_ids: vec![CLIENT_ID.to_string()],
+        jwks_url: "http://127.0.0.1:9/certs".to_string(),
+        tokeninfo_url: start_tokeninfo_stub().await,
+        accept_access_tokens: true,
+    };
+    let verifier = GoogleIdTokenVerifier::new(config.clone());
+
+    let verified = verifier.verify("good").await.expect("Access token should verify");
+    assert_eq!((verified.sub.as_str(), verified.aud.as_str()), ("1234567890", CLIENT_ID));
+    assert!(verified.email_verified);
+
+    for token in ["no-sub", "empty-sub", "azp-only", "other-azp", "revoked"] {
+        let result = verifier.verify(token).await;
+        assert!(matches!(result, Err(GoogleTokenError::Invalid(_))), "{}: {:?}", token, result);
+    }
+
+    // Deployments that only send ID tokens can turn access tokens off
+    let id_tokens_only = GoogleIdTokenVerifier::new(GoogleAuthConfig { accept_access_tokens: false, ..config });
+    assert!(matches!(id_tokens_only.verify("good").await, Err(GoogleTokenError::Invalid(_))));
+}
+
+#[actix_web::test]
+async fn test_cache_ttl_follows_cache_control() {
+    assert_eq!(cache_ttl(Some("public, max-age=19846, must-revalidate, no-transform"), None), Duration::from_secs(19846));
+    assert_eq!(cache_ttl(Some("public, max-age=600"), Some("100")), Duration::from_secs(500));
+    assert_eq!(cache_ttl(Some("max-age=60"), Some("120")), Duration::ZERO);
+    assert_eq!(cache_ttl(Some("no-cache"), None), Duration::ZERO);
+    assert_eq!(cache_ttl(Some("private, no-store"), None), Duration::ZERO);
+    assert_eq!(cache_ttl(None, None), Duration::from_secs(JwksCache::DEFAULT_CACHE_SECONDS));
+}
+
+#[actix_web::test]
+async fn test_google_callback_uses_verified_claims() {
+    let pool = common::setup_test_db().await;
+    let (url, _) = start_jwks_stub("public, max-age=600").await;
+    let email = format!("google_{}@example.com", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .app_data(web::Data::new(verifier(&url)))
+            .route("/google/callback", web::post().to(google_callback))
+    ).await;
+
+    let forged = id_token(KEY_B_PEM, "key-a", claims(&email));
+    let req = test::TestRequest::post()
+        .uri("/google/callback")
+        .set_json(serde_json::json!({ "id_token": forged }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    let req = test::TestRequest::post()
+        .uri("/google/callback")
+        .set_json(serde_json::json!({ "id_token": id_token(KEY_A_PEM, "key-a", claims(&email)) }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["requires_mfa"], true);
+    assert_eq!(body["user"]["email"], email.as_str());
+
+    sqlx::query!("DELETE FROM users WHERE email = $1", email).execute(&pool).await.unwrap();
+}
@@ -0,0 +1,317 @@
+mod common;
+
+use actix_web::{test, web, App, HttpResponse, HttpServer};
+use backend::auth::google::google_callback;
+use backend::auth::identities::confirm_identity_link;
+use backend::routes::api::config;
+use backend::services::google_id_token::{GoogleAuthConfig, GoogleIdTokenVerifier};
+use backend::services::step_up_service::{StepUpMethod, StepUpService};
+use backend::utils::auth::AuthUtils;
+use chrono::Utc;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use std::time::Duration;
+
+const CLIENT_ID: &str = "test-client.apps.googleusercontent.com";
+const SECRET: &str = "test-secret";
+const KEY_A_PEM: &str = include_str!("fixtures/google_test_key_a.pem");
+const KEY_A_N: &str = "jnZrZB-yBdVW6pKRQVjuzViz0B2wv9NLs-y6GcGQOFH9AqP_lem-6WjF6vSMgV01LAlzAPvmh-QUvLzSIAy0AENSOiZuDEfAfwYX7a-0j7-ADZ5X5RJAp34My3rlQ6brm8UwCa5Piqq5redTk3kCQlRayIziZDcgcSc29DGV3TGrIPz9WhjSy9nd_Jv8fMKU-a4HwCTy4AMUb0MvhpMiEApM_oPlm1OUvE0ZNqd1CiCfgVAzo_F_2dz-o3xUYAaeGVR7beAeMhySFyutitUuLmVMv0B4p0vDbq17mbWOEitXBnVbnt4LZkJMDFePq03QMukgiloXjRiIF9Y6ETPc7Q";
+
+async fn google_verifier() -> GoogleIdTokenVerifier {
+    let server = HttpServer::new(|| {
+        App::new().route(
+            "/certs",
+            web::get().to(|| async {
+                HttpResponse::Ok().json(serde_json::json!({
+                    "keys": [{ "kty": "RSA", "use": "sig", "alg": "RS256", "kid": "key-a", "n": KEY_A_N, "e": "AQAB" }]
+                }))
+            }),
+        )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let port = server.addrs()[0].port();
+    actix_web::rt::spawn(server.run());
+
+    GoogleIdTokenVerifier::new(GoogleAuthConfig {
+        client_ids: vec![CLIENT_ID.to_string()],
+        jwks_url: format!("http://127.0.0.1:{}/certs", port),
+        tokeninfo_url: "http://127.0.0.1:9/tokeninfo".to_string(),
+        accept_access_tokens: true,
+    })
+}
+
+fn google_token(sub: &str, email: &str) -> String {
+    let now = Utc::now().timestamp();
+    let claims = serde_json::json!({
+        "iss": "https://accounts.google.com",
+        "sub": sub,
+        "aud": CLIENT_ID,
+        "email": email,
+        "email_verified": true,
+        "name": "Google Tester",
+        "iat": now,
+        "exp": now + 3600
+    });
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("key-a".to_string());
+    encode(&header, &claims, &EncodingKey::from_rsa_pem(KEY_A_PEM.as_bytes()).unwrap()).unwrap()
+}
+
+#[actix_web::test]
+async fn test_google_email_match_is_not_a_login() {
+    let pool = common::setup_test_db().await;
+    let (username, email) = common::unique_name("idmatch");
+    let (user_id, _, _) = common::create_test_user(&pool, &username, &email, true).await;
+    let (_, unverified_email) = common::unique_name("idunver");
+    let (unverified_id, _, _) = common::create_test_user(&pool, &common::unique_name("idunver").0, &unverified_email, false).await;
+    let sub = uuid::Uuid::new_v4().to_string();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .app_data(web::Data::new(google_verifier().await))
+            .route("/google/callback", web::post().to(google_callback))
+            .route("/identities/confirm-link", web::post().to(confirm_identity_link))
+    ).await;
+    let callback = |sub: &str, email: &str| {
+        test::TestRequest::post()
+            .uri("/google/callback")
+            .set_json(serde_json::json!({ "id_token": google_token(sub, email) }))
+            .to_request()
+    };
+
+    let resp = test::call_service(&app, callback(&sub, &email)).await;
+    assert_eq!(resp.status(), 409);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["link_required"], true);
+    assert!(body.get("temp_token").is_none());
+
+    let req = test::TestRequest::post()
+        .uri("/identities/confirm-link")
+        .set_json(serde_json::json!({ "link_token": body["link_token"] }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let confirmed: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(confirmed["requires_mfa"], true);
+    assert_eq!(confirmed["user"]["id"], user_id);
+
+    // Linked by subject now, so a changed Google email still reaches the same account.
+    // Session tokens only differ by their issue second
+    actix_web::rt::time::sleep(Duration::from_millis(1100)).await;
+    let resp = test::call_service(&app, callback(&sub, "renamed@example.com")).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["user"]["id"], user_id);
+
+    // An unverified local address proves nothing, so there is nothing to confirm
+    let resp = test::call_service(&app, callback(&uuid::Uuid::new_v4().to_string(), &unverified_email)).await;
+    assert_eq!(resp.status(), 409);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body.get("link_token").is_none());
+
+    // A link token is not an access token
+    let req = test::TestRequest::post()
+        .uri("/identities/confirm-link")
+        .set_json(serde_json::json!({ "link_token": AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap() }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[user_id, unverified_id][..]).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_legacy_google_accounts_are_upgraded_to_subject() {
+    let pool = common::setup_test_db().await;
+    let (username, email) = common::unique_name("idlegacy");
+    let user_id = sqlx::query_scalar!(
+        "INSERT INTO users (username, email, password, role, email_verified) VALUES ($1, $2, '', 'user', true) RETURNING id",
+        username,
+        email
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO user_identities (user_id, provider, subject, email) VALUES ($1, 'google', $2, $3)",
+        user_id,
+        format!("legacy:{}", email),
+        email
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let sub = uuid::Uuid::new_v4().to_string();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .app_data(web::Data::new(google_verifier().await))
+            .route("/google/callback", web::post().to(google_callback))
+    ).await;
+
+    let req = test::TestRequest::post()
+        .uri("/google/callback")
+        .set_json(serde_json::json!({ "id_token": google_token(&sub, &email.to_uppercase()) }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["user"]["id"], user_id);
+    assert_eq!(body["user"]["has_password"], false);
+
+    let subject = sqlx::query_scalar!("SELECT subject FROM user_identities WHERE user_id = $1", user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(subject, sub);
+
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_link_list_and_unlink_keep_a_login_method() {
+    let pool = common::setup_test_db().await;
+    let (username, email) = common::unique_name("idlink");
+    let (user_id, _, _) = common::create_test_user(&pool, &username, &email, true).await;
+    let (other_name, other_email) = common::unique_name("idother");
+    let (other_id, _, _) = common::create_test_user(&pool, &other_name, &other_email, true).await;
+    let sub = uuid::Uuid::new_v4().to_string();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .app_data(web::Data::new(google_verifier().await))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+    let other_token = AuthUtils::create_token(other_id, &other_name, "user", SECRET).unwrap();
+    // Linking and unlinking sign-in methods need a recent step-up
+    let (step_up, _) = StepUpService::issue(user_id, &token, StepUpMethod::Password, SECRET).unwrap();
+    let (other_step_up, _) = StepUpService::issue(other_id, &other_token, StepUpMethod::Password, SECRET).unwrap();
+    let link = |bearer: &str, step_up_token: &str| {
+        test::TestRequest::post()
+            .uri("/api/auth/identities/google")
+            .insert_header(("Authorization", format!("Bearer {}", bearer)))
+            .insert_header(("X-Step-Up-Token", step_up_token.to_string()))
+            .set_json(serde_json::json!({ "token": google_token(&sub, &email) }))
+            .to_request()
+    };
+    let list = || {
+        test::TestRequest::get()
+            .uri("/api/auth/identities")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/identities/google")
+        .set_json(serde_json::json!({ "token": google_token(&sub, &email) }))
+        .to_request();
+    let err = test::try_call_service(&app, req).await.expect_err("Linking requires a session");
+    assert_eq!(err.as_response_error().status_code(), 401);
+
+    assert_eq!(test::call_service(&app, link(&token, "")).await.status(), 403);
+    assert_eq!(test::call_service(&app, link(&token, &step_up)).await.status(), 200);
+    assert_eq!(test::call_service(&app, link(&token, &step_up)).await.status(), 200);
+    assert_eq!(test::call_service(&app, link(&other_token, &other_step_up)).await.status(), 409);
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, list()).await;
+    let identities = body["identities"].as_array().unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0]["provider"], "google");
+    assert_eq!(body["login_methods"]["password"], true);
+    let identity_id = identities[0]["id"].as_i64().unwrap();
+
+    // Without a password the Google account is the only way in
+    sqlx::query!("UPDATE users SET password = '' WHERE id = $1", user_id).execute(&pool).await.unwrap();
+    let unlink = |bearer: &str, step_up_token: &str| {
+        test::TestRequest::delete()
+            .uri(&format!("/api/auth/identities/{}", identity_id))
+            .insert_header(("Authorization", format!("Bearer {}", bearer)))
+            .insert_header(("X-Step-Up-Token", step_up_token.to_string()))
+            .to_request()
+    };
+    assert_eq!(test::call_service(&app, unlink(&token, &step_up)).await.status(), 409);
+    assert_eq!(test::call_service(&app, unlink(&other_token, &other_step_up)).await.status(), 404);
+
+    let wallet = format!("0x{}", &uuid::Uuid::new_v4().simple().to_string()[..32]);
+    sqlx::query!(
+        "INSERT INTO user_wallets (user_id, address, is_primary) VALUES ($1, $2, true)",
+        user_id,
+        wallet
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    assert_eq!(test::call_service(&app, unlink(&token, &step_up)).await.status(), 200);
+    let body: serde_json::Value = test::call_and_read_body_json(&app, list()).await;
+    assert!(body["identities"].as_array().unwrap().is_empty());
+
+    // ...and now the wallet is
+    let req = test::TestRequest::put()
+        .uri("/api/auth/profile")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .insert_header(("X-Step-Up-Token", step_up.clone()))
+        .set_json(serde_json::json!({ "wallet_address": null }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 409);
+
+    let events = sqlx::query_scalar!(
+        "SELECT event_type FROM audit_logs WHERE user_id = $1 AND event_type LIKE 'IDENTITY_%' ORDER BY id",
+        user_id
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(events, ["IDENTITY_LINKED", "IDENTITY_UNLINKED"]);
+
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[user_id, other_id][..]).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_adding_an_email_needs_a_step_up_and_a_new_verification() {
+    let pool = common::setup_test_db().await;
+    let (username, email) = common::unique_name("addemail");
+    let (user_id, _, _) = common::create_test_user(&pool, &username, &email, true).await;
+    let (_, new_email) = common::unique_name("addemail");
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+    let (step_up, _) = StepUpService::issue(user_id, &token, StepUpMethod::Password, SECRET).unwrap();
+    let add_email = |address: &str, step_up_token: Option<&str>| {
+        let mut req = test::TestRequest::post()
+            .uri("/api/auth/add-email")
+            .insert_header(("Authorization", format!("Bearer {}", token)));
+        if let Some(step_up_token) = step_up_token {
+            req = req.insert_header(("X-Step-Up-Token", step_up_token.to_string()));
+        }
+        req.set_json(serde_json::json!({ "email": address })).to_request()
+    };
+    let verified = async || {
+        sqlx::query_scalar!("SELECT email_verified FROM users WHERE id = $1", user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+    };
+
+    assert_eq!(test::call_service(&app, add_email(&new_email, None)).await.status(), 403);
+
+    // The same address keeps its verification, a different one needs verifying again
+    assert_eq!(test::call_service(&app, add_email(&email, Some(&step_up))).await.status(), 200);
+    assert!(verified().await);
+    assert_eq!(test::call_service(&app, add_email(&new_email, Some(&step_up))).await.status(), 200);
+    assert!(!verified().await);
+
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
@@ -0,0 +1,489 @@
+mod common;
+
+use actix_web::{test, web, App, HttpResponse, HttpServer};
+use backend::auth::identities::{confirm_identity_link, link_oidc_identity, start_oidc_link};
+use backend::auth::oidc::{list_oidc_providers, oidc_callback, oidc_start};
+use backend::services::oidc_service::{OidcProviderConfig, OidcRegistry};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const CLIENT_ID: &str = "ush-backend";
+const KEY_A_PEM: &str = include_str!("fixtures/google_test_key_a.pem");
+const KEY_A_N: &str = "jnZrZB-yBdVW6pKRQVjuzViz0B2wv9NLs-y6GcGQOFH9AqP_lem-6WjF6vSMgV01LAlzAPvmh-QUvLzSIAy0AENSOiZuDEfAfwYX7a-0j7-ADZ5X5RJAp34My3rlQ6brm8UwCa5Piqq5redTk3kCQlRayIziZDcgcSc29DGV3TGrIPz9WhjSy9nd_Jv8fMKU-a4HwCTy4AMUb0MvhpMiEApM_oPlm1OUvE0ZNqd1CiCfgVAzo_F_2dz-o3xUYAaeGVR7beAeMhySFyutitUuLmVMv0B4p0vDbq17mbWOEitXBnVbnt4LZkJMDFePq03QMukgiloXjRiIF9Y6ETPc7Q";
+
+/// What the stub identity provider remembers between /authorize and /token
+#[derive(Default)]
+struct StubState {
+    issuer: String,
+    subject: String,
+    email: String,
+    email_verified: bool,
+    nonce_override: Option<String>,
+    userinfo_override: Option<serde_json::Value>, // Served instead of the usual userinfo
+    jwks_fetches: usize,
+    codes: HashMap<String, (String, String)>, // code -> (nonce, code_challenge)
+}
+
+#[derive(serde::Deserialize)]
+struct TokenForm {
+    code: String,
+    code_verifier: String,
+    client_id: String,
+}
+
+async fn discovery(state: web::Data<Mutex<StubState>>) -> HttpResponse {
+    let issuer = state.lock().unwrap().issuer.clone();
+    HttpResponse::Ok().json(serde_json::json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+        "jwks_uri": format!("{}/jwks", issuer)
+    }))
+}
+
+async fn jwks(state: web::Data<Mutex<StubState>>) -> HttpResponse {
+    state.lock().unwrap().jwks_fetches += 1;
+    HttpResponse::Ok().json(serde_json::json!({
+        "keys": [{ "kty": "RSA", "use": "sig", "alg": "RS256", "kid": "key-a", "n": KEY_A_N, "e": "AQAB" }]
+    }))
+}
+
+// Checks PKCE like a real provider, then issues a signed ID token carrying the nonce
+async fn token(state: web::Data<Mutex<StubState>>, form: web::Form<TokenForm>) -> HttpResponse {
+    let state = state.lock().unwrap();
+    let Some((nonce, challenge)) = state.codes.get(&form.code) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid_grant" }));
+    };
+    if URL_SAFE_NO_PAD.encode(Sha256::digest(form.code_verifier.as_bytes())) != *challenge || form.client_id != CLIENT_ID {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "invalid_grant",
+            "error_description": "PKCE verification failed"
+        }));
+    }
+
+    let now = Utc::now().timestamp();
+    let claims = serde_json::json!({
+        "iss": state.issuer,
+        "sub": state.subject,
+        "aud": CLIENT_ID,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": state.nonce_override.clone().unwrap_or_else(|| nonce.clone()),
+        "email": state.email
+    });
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("key-a".to_string());
+    let id_token = encode(&header, &claims, &EncodingKey::from_rsa_pem(KEY_A_PEM.as_bytes()).unwrap()).unwrap();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "access_token": "stub-access-token",
+        "token_type": "Bearer",
+        "id_token": id_token
+    }))
+}
+
+async fn userinfo(state: web::Data<Mutex<StubState>>) -> HttpResponse {
+    let state = state.lock().unwrap();
+    if let Some(userinfo) = &state.userinfo_override {
+        return HttpResponse::Ok().json(userinfo);
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "sub": state.subject,
+        "email": state.email,
+        "email_verified": state.email_verified,
+        "preferred_username": "kc.tester",
+        "name": "Keycloak Tester"
+    }))
+}
+
+async fn start_stub_provider(email: &str) -> web::Data<Mutex<StubState>> {
+    let state = web::Data::new(Mutex::new(StubState {
+        subject: uuid::Uuid::new_v4().to_string(),
+        email: email.to_string(),
+        email_verified: true,
+        ..Default::default()
+    }));
+    let shared = state.clone();
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(shared.clone())
+            .route("/.well-known/openid-configuration", web::get().to(discovery))
+            .route("/jwks", web::get().to(jwks))
+            .route("/token", web::post().to(token))
+            .route("/userinfo", web::get().to(userinfo))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    state.lock().unwrap().issuer = format!("http://127.0.0.1:{}", server.addrs()[0].port());
+    actix_web::rt::spawn(server.run());
+    state
+}
+
+fn registry(issuer: &str) -> OidcRegistry {
+    OidcRegistry::new(
+        vec![OidcProviderConfig {
+            name: "keycloak".to_string(),
+            display_name: Some("Company SSO".to_string()),
+            issuer: Some(issuer.to_string()),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: Some("stub-secret".to_string()),
+            ..Default::default()
+        }],
+        "http://localhost:5173",
+    )
+}
+
+/// Play the browser's part at /authorize: read the nonce and PKCE challenge and hand back a code
+fn authorize(stub: &web::Data<Mutex<StubState>>, authorization_url: &str) -> String {
+    let url = reqwest::Url::parse(authorization_url).unwrap();
+    let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+    assert_eq!(params["code_challenge_method"], "S256");
+    assert_eq!(params["redirect_uri"], "http://localhost:5173/auth/oidc/keycloak/callback");
+
+    let code = uuid::Uuid::new_v4().to_string();
+    stub.lock()
+        .unwrap()
+        .codes
+        .insert(code.clone(), (params["nonce"].clone(), params["code_challenge"].clone()));
+    code
+}
+
+#[actix_web::test]
+async fn test_oidc_login_with_pkce_creates_user_and_completes_login() {
+    let pool = common::setup_test_db().await;
+    let email = format!("oidc_{}@example.com", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let stub = start_stub_provider(&email).await;
+    let issuer = stub.lock().unwrap().issuer.clone();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .app_data(web::Data::new(registry(&issuer)))
+            .route("/oidc/providers", web::get().to(list_oidc_providers))
+            .route("/oidc/{provider}/start", web::get().to(oidc_start))
+            .route("/oidc/{provider}/callback", web::post().to(oidc_callback))
+    ).await;
+
+    let req = test::TestRequest::get().uri("/oidc/providers").to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["providers"][0]["name"], "keycloak");
+    assert_eq!(body["providers"][0]["display_name"], "Company SSO");
+
+    let req = test::TestRequest::get().uri("/oidc/gitea/start").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    let req = test::TestRequest::get().uri("/oidc/keycloak/start").to_request();
+    let start: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let authorization_url = start["authorization_url"].as_str().unwrap();
+    assert!(authorization_url.starts_with(&format!("{}/authorize?", issuer)));
+    let code = authorize(&stub, authorization_url);
+
+    let callback = |state: &str| {
+        test::TestRequest::post()
+            .uri("/oidc/keycloak/callback")
+            .set_json(serde_json::json!({ "code": code, "state": state }))
+            .to_request()
+    };
+    let resp = test::call_service(&app, callback(start["state"].as_str().unwrap())).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["token"].is_string());
+    assert!(body["refresh_token"].is_string());
+    assert_eq!(body["requires_mfa"], false);
+    assert_eq!(body["user"]["email"], email.as_str());
+    assert_eq!(body["user"]["username"], "kc_tester");
+
+    // The state is single use
+    assert_eq!(test::call_service(&app, callback(start["state"].as_str().unwrap())).await.status(), 400);
+
+    sqlx::query!("DELETE FROM users WHERE email = $1", email).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_oidc_rejects_nonce_mismatch_and_unverified_email() {
+    let pool = common::setup_test_db().await;
+    let email = format!("oidc_{}@example.com", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let stub = start_stub_provider(&email).await;
+    let issuer = stub.lock().unwrap().issuer.clone();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .app_data(web::Data::new(registry(&issuer)))
+            .route("/oidc/{provider}/start", web::get().to(oidc_start))
+            .route("/oidc/{provider}/callback", web::post().to(oidc_callback))
+    ).await;
+
+    let login = |stub_state: &web::Data<Mutex<StubState>>, start: serde_json::Value| {
+        let code = authorize(stub_state, start["authorization_url"].as_str().unwrap());
+        test::TestRequest::post()
+            .uri("/oidc/keycloak/callback")
+            .set_json(serde_json::json!({ "code": code, "state": start["state"] }))
+            .to_request()
+    };
+    let start = || test::TestRequest::get().uri("/oidc/keycloak/start").to_request();
+
+    // A replayed ID token from another login carries someone else's nonce
+    stub.lock().unwrap().nonce_override = Some("replayed-nonce".to_string());
+    let started: serde_json::Value = test::call_and_read_body_json(&app, start()).await;
+    assert_eq!(test::call_service(&app, login(&stub, started)).await.status(), 401);
+
+    // Unverified addresses can't be matched to accounts
+    {
+        let mut state = stub.lock().unwrap();
+        state.nonce_override = None;
+        state.email_verified = false;
+    }
+    let started: serde_json::Value = test::call_and_read_body_json(&app, start()).await;
+    assert_eq!(test::call_service(&app, login(&stub, started)).await.status(), 400);
+
+    // A made-up state is refused before anything is sent to the provider
+    let req = test::TestRequest::post()
+        .uri("/oidc/keycloak/callback")
+        .set_json(serde_json::json!({ "code": "anything", "state": "not-a-real-state" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let created = sqlx::query_scalar!("SELECT COUNT(*) FROM users WHERE email = $1", email)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(created, Some(0));
+}
+
+#[actix_web::test]
+async fn test_oidc_userinfo_cannot_override_the_id_token() {
+    let pool = common::setup_test_db().await;
+    let email = format!("oidc_{}@example.com", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let stub = start_stub_provider(&email).await;
+    let (issuer, subject) = {
+        let state = stub.lock().unwrap();
+        (state.issuer.clone(), state.subject.clone())
+    };
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .app_data(web::Data::new(registry(&issuer)))
+            .route("/oidc/{provider}/start", web::get().to(oidc_start))
+            .route("/oidc/{provider}/callback", web::post().to(oidc_callback))
+    ).await;
+
+    let login = async |userinfo: serde_json::Value| {
+        stub.lock().unwrap().userinfo_override = Some(userinfo);
+        let req = test::TestRequest::get().uri("/oidc/keycloak/start").to_request();
+        let started: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let code = authorize(&stub, started["authorization_url"].as_str().unwrap());
+        let req = test::TestRequest::post()
+            .uri("/oidc/keycloak/callback")
+            .set_json(serde_json::json!({ "code": code, "state": started["state"] }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status().as_u16();
+        (status, test::read_body_json::<serde_json::Value, _>(resp).await)
+    };
+
+    // Userinfo without a subject, or with another one, describes someone else
+    let (status, _) = login(serde_json::json!({ "email": "attacker@example.com", "email_verified": true })).await;
+    assert_eq!(status, 401);
+    let (status, _) = login(serde_json::json!({ "sub": "someone-else", "email": email, "email_verified": true })).await;
+    assert_eq!(status, 401);
+
+    // Claims from the ID token win, userinfo only adds the missing ones
+    let (status, body) = login(serde_json::json!({
+        "sub": subject,
+        "email": "attacker@example.com",
+        "email_verified": true,
+        "preferred_username": "kc.tester"
+    }))
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["user"]["email"], email.as_str());
+    assert_eq!(body["user"]["username"], "kc_tester");
+
+    // The provider's keys were fetched once for all three logins
+    assert_eq!(stub.lock().unwrap().jwks_fetches, 1);
+
+    sqlx::query!("DELETE FROM users WHERE email = $1", email).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_oidc_email_match_requires_confirmed_link() {
+    let pool = common::setup_test_db().await;
+    let suffix = &uuid::Uuid::new_v4().simple().to_string()[..12];
+    let (user_id, _, email) = common::create_test_user(&pool, &format!("oidc_{}", suffix), &format!("oidc_{}@example.com", suffix), true).await;
+    let stub = start_stub_provider(&email).await;
+    let issuer = stub.lock().unwrap().issuer.clone();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .app_data(web::Data::new(registry(&issuer)))
+            .route("/oidc/{provider}/start", web::get().to(oidc_start))
+            .route("/oidc/{provider}/callback", web::post().to(oidc_callback))
+            .route("/identities/confirm-link", web::post().to(confirm_identity_link))
+    ).await;
+
+    let start = || test::TestRequest::get().uri("/oidc/keycloak/start").to_request();
+    let login = |started: serde_json::Value| {
+        let code = authorize(&stub, started["authorization_url"].as_str().unwrap());
+        test::TestRequest::post()
+            .uri("/oidc/keycloak/callback")
+            .set_json(serde_json::json!({ "code": code, "state": started["state"] }))
+            .to_request()
+    };
+
+    // Same email as a local account: no login, only an offer to link
+    let started: serde_json::Value = test::call_and_read_body_json(&app, start()).await;
+    let resp = test::call_service(&app, login(started)).await;
+    assert_eq!(resp.status(), 409);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["link_required"], true);
+    assert!(body.get("token").is_none());
+
+    let confirm = || {
+        test::TestRequest::post()
+            .uri("/identities/confirm-link")
+            .set_json(serde_json::json!({ "link_token": body["link_token"] }))
+            .to_request()
+    };
+    let resp = test::call_service(&app, confirm()).await;
+    assert_eq!(resp.status(), 200);
+    let confirmed: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(confirmed["user"]["id"], user_id);
+    assert!(confirmed["token"].is_string());
+
+    // The confirmation can't be used to sign in a second time
+    assert_eq!(test::call_service(&app, confirm()).await.status(), 400);
+
+    // From now on the provider account signs straight in
+    let started: serde_json::Value = test::call_and_read_body_json(&app, start()).await;
+    let resp = test::call_service(&app, login(started)).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["user"]["id"], user_id);
+
+    let events = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM audit_logs WHERE user_id = $1 AND event_type = 'IDENTITY_LINKED'",
+        user_id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(events, Some(1));
+
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_oidc_link_round_trip_is_bound_to_the_user() {
+    let pool = common::setup_test_db().await;
+    let suffix = &uuid::Uuid::new_v4().simple().to_string()[..12];
+    let (user_id, username, _) = common::create_test_user(&pool, &format!("oidl_{}", suffix), &format!("oidl_{}@example.com", suffix), true).await;
+    let (other_id, other_name, _) = common::create_test_user(&pool, &format!("oido_{}", suffix), &format!("oido_{}@example.com", suffix), true).await;
+    let stub = start_stub_provider(&format!("elsewhere_{}@example.com", suffix)).await;
+    let issuer = stub.lock().unwrap().issuer.clone();
+    let secret = "test-secret";
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(secret.to_string()))
+            .app_data(web::Data::new(registry(&issuer)))
+            .route("/oidc/{provider}/callback", web::post().to(oidc_callback))
+            .service(
+                web::scope("/identities")
+                    .wrap(backend::middleware::auth::AuthMiddleware::new())
+                    .route("/oidc/{provider}/start", web::post().to(start_oidc_link))
+                    .route("/oidc/{provider}", web::post().to(link_oidc_identity)),
+            )
+    ).await;
+
+    let token = backend::utils::auth::AuthUtils::create_token(user_id, &username, "user", secret).unwrap();
+    let other_token = backend::utils::auth::AuthUtils::create_token(other_id, &other_name, "user", secret).unwrap();
+    let start_link = || {
+        test::TestRequest::post()
+            .uri("/identities/oidc/keycloak/start")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+    let finish = |uri: &str, bearer: &str, started: &serde_json::Value| {
+        let code = authorize(&stub, started["authorization_url"].as_str().unwrap());
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", bearer)))
+            .set_json(serde_json::json!({ "code": code, "state": started["state"] }))
+            .to_request()
+    };
+
+    // A link round trip can't be turned into a login, nor finished by another user
+    let started: serde_json::Value = test::call_and_read_body_json(&app, start_link()).await;
+    assert_eq!(test::call_service(&app, finish("/oidc/keycloak/callback", &token, &started)).await.status(), 400);
+    let started: serde_json::Value = test::call_and_read_body_json(&app, start_link()).await;
+    assert_eq!(test::call_service(&app, finish("/identities/oidc/keycloak", &other_token, &started)).await.status(), 400);
+
+    let started: serde_json::Value = test::call_and_read_body_json(&app, start_link()).await;
+    let resp = test::call_service(&app, finish("/identities/oidc/keycloak", &token, &started)).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["identity"]["provider"], "keycloak");
+    assert_eq!(body["identity"]["user_id"], user_id);
+
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[user_id, other_id][..]).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_provider_presets() {
+    let github = OidcProviderConfig {
+        name: "github".to_string(),
+        client_id: "gh-client".to_string(),
+        ..Default::default()
+    }
+    .prepare("https://app.example.com")
+    .unwrap();
+    assert_eq!(github.authorization_endpoint.as_deref(), Some("https://github.com/login/oauth/authorize"));
+    assert_eq!(github.claims.subject, "id");
+    assert_eq!(github.claims.username, "login");
+    assert_eq!(github.scopes, vec!["read:user", "user:email"]);
+    assert_eq!(github.redirect_uri.as_deref(), Some("https://app.example.com/auth/oidc/github/callback"));
+    assert_eq!(github.display_name.as_deref(), Some("Github"));
+
+    let entra = OidcProviderConfig {
+        name: "entra".to_string(),
+        kind: Some("microsoft".to_string()),
+        tenant: Some("contoso-tenant-id".to_string()),
+        client_id: "entra-client".to_string(),
+        ..Default::default()
+    }
+    .prepare("https://app.example.com")
+    .unwrap();
+    assert_eq!(entra.issuer.as_deref(), Some("https://login.microsoftonline.com/contoso-tenant-id/v2.0"));
+    assert_eq!(entra.scopes, vec!["openid", "email", "profile"]);
+
+    // Without a tenant the issuer can't be checked
+    let no_tenant = OidcProviderConfig {
+        name: "microsoft".to_string(),
+        client_id: "entra-client".to_string(),
+        ..Default::default()
+    };
+    assert!(no_tenant.prepare("https://app.example.com").is_err());
+
+    let no_client = OidcProviderConfig {
+        name: "gitlab".to_string(),
+        ..Default::default()
+    };
+    assert!(no_client.prepare("https://app.example.com").is_err());
+}
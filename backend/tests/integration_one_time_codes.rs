@@ -0,0 +1,162 @@
+mod common;
+
+use backend::services::one_time_code_store::{CodePurpose, OneTimeCodeStore};
+use chrono::Duration;
+
+fn unique_subject() -> String {
+    format!("test_{}@example.com", uuid::Uuid::new_v4())
+}
+
+async fn stores() -> Vec<OneTimeCodeStore> {
+    let pool = common::setup_test_db().await;
+    vec![OneTimeCodeStore::postgres(pool), OneTimeCodeStore::in_memory()]
+}
+
+#[actix_web::test]
+async fn test_code_is_single_use() {
+    for store in stores().await {
+        let subject = unique_subject();
+        store.put(CodePurpose::EmailVerification, &subject, "123456", Duration::minutes(3))
+            .await
+            .expect("Failed to store code");
+
+        let first = store.verify(CodePurpose::EmailVerification, &subject, "123456").await.unwrap();
+        let second = store.verify(CodePurpose::EmailVerification, &subject, "123456").await.unwrap();
+
+        assert!(first, "Code should verify once");
+        assert!(!second, "Code should not verify twice");
+    }
+}
+
+#[actix_web::test]
+async fn test_wrong_code_rejected() {
+    for store in stores().await {
+        let subject = unique_subject();
+        store.put(CodePurpose::EmailVerification, &subject, "123456", Duration::minutes(3))
+            .await
+            .expect("Failed to store code");
+
+        let wrong = store.verify(CodePurpose::EmailVerification, &subject, "654321").await.unwrap();
+        let right = store.verify(CodePurpose::EmailVerification, &subject, "123456").await.unwrap();
+
+        assert!(!wrong);
+        assert!(right, "A single wrong guess should not burn the code");
+    }
+}
+
+#[actix_web::test]
+async fn test_attempt_limit_burns_code() {
+    for store in stores().await {
+        let subject = unique_subject();
+        store.put(CodePurpose::EmailVerification, &subject, "123456", Duration::minutes(3))
+            .await
+            .expect("Failed to store code");
+
+        for _ in 0..OneTimeCodeStore::MAX_ATTEMPTS {
+            let _ = store.verify(CodePurpose::EmailVerification, &subject, "000000").await;
+        }
+
+        let right = store.verify(CodePurpose::EmailVerification, &subject, "123456").await.unwrap();
+        assert!(!right, "Code should be unusable after too many wrong attempts");
+    }
+}
+
+#[actix_web::test]
+async fn test_expired_code_rejected() {
+    for store in stores().await {
+        let subject = unique_subject();
+        store.put(CodePurpose::EmailVerification, &subject, "123456", Duration::seconds(-1))
+            .await
+            .expect("Failed to store code");
+
+        let valid = store.verify(CodePurpose::EmailVerification, &subject, "123456").await.unwrap();
+        assert!(!valid);
+
+        let cleaned = store.cleanup_expired().await.unwrap();
+        assert!(cleaned >= 1);
+    }
+}
+
+#[actix_web::test]
+async fn test_new_code_replaces_old_one() {
+    for store in stores().await {
+        let subject = unique_subject();
+        store.put(CodePurpose::EmailVerification, &subject, "111111", Duration::minutes(3)).await.unwrap();
+        store.put(CodePurpose::EmailVerification, &subject, "222222", Duration::minutes(3)).await.unwrap();
+
+        let old = store.verify(CodePurpose::EmailVerification, &subject, "111111").await.unwrap();
+        let new = store.verify(CodePurpose::EmailVerification, &subject, "222222").await.unwrap();
+
+        assert!(!old);
+        assert!(new);
+    }
+}
+
+#[actix_web::test]
+async fn test_purposes_are_isolated() {
+    for store in stores().await {
+        let subject = unique_subject();
+        store.put(CodePurpose::EmailVerification, &subject, "123456", Duration::minutes(3)).await.unwrap();
+
+        let valid = store.verify(CodePurpose::MfaEmail, &subject, "123456").await.unwrap();
+        assert!(!valid, "Code issued for one purpose must not verify another");
+    }
+}
+
+#[actix_web::test]
+async fn test_consume_reset_token() {
+    for store in stores().await {
+        let email = unique_subject();
+        let token = format!("reset_{}", uuid::Uuid::new_v4().simple());
+        store.put(CodePurpose::PasswordReset, &email, &token, Duration::hours(1)).await.unwrap();
+
+        let first = store.consume(CodePurpose::PasswordReset, &token).await.unwrap();
+        let second = store.consume(CodePurpose::PasswordReset, &token).await.unwrap();
+
+        assert_eq!(first, Some(email));
+        assert_eq!(second, None, "Reset token should be single-use");
+    }
+}
+
+#[cfg(feature = "debug-endpoints")]
+#[actix_web::test]
+async fn test_expiry_lookup_and_remove() {
+    for store in stores().await {
+        let subject = unique_subject();
+        store.put(CodePurpose::MfaEmail, &subject, "123456", Duration::minutes(2)).await.unwrap();
+
+        let expiry = store.expires_at(CodePurpose::MfaEmail, &subject).await.unwrap();
+        assert!(expiry.is_some());
+
+        let active = store.active_subjects(CodePurpose::MfaEmail).await.unwrap();
+        assert!(active.contains_key(&subject));
+
+        store.remove(CodePurpose::MfaEmail, &subject).await.unwrap();
+        let expiry = store.expires_at(CodePurpose::MfaEmail, &subject).await.unwrap();
+        assert!(expiry.is_none());
+    }
+}
+
+#[actix_web::test]
+async fn test_codes_stored_hashed() {
+    let pool = common::setup_test_db().await;
+    let store = OneTimeCodeStore::postgres(pool.clone());
+    let subject = unique_subject();
+
+    store.put(CodePurpose::EmailVerification, &subject, "123456", Duration::minutes(3)).await.unwrap();
+
+    let record = sqlx::query!(
+        "SELECT code_hash FROM one_time_codes WHERE purpose = 'email_verification' AND subject = $1",
+        subject
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("Failed to fetch");
+
+    assert_ne!(record.code_hash, "123456");
+    assert_eq!(record.code_hash, OneTimeCodeStore::hash_code(CodePurpose::EmailVerification, &subject, "123456"));
+
+    // Bound to the subject and purpose, so the same code hashes differently elsewhere
+    assert_ne!(record.code_hash, OneTimeCodeStore::hash_code(CodePurpose::EmailVerification, &unique_subject(), "123456"));
+    assert_ne!(record.code_hash, OneTimeCodeStore::hash_code(CodePurpose::MfaEmail, &subject, "123456"));
+}
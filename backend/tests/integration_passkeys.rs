@@ -0,0 +1,338 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::auth::step_up::step_up;
+use backend::middleware::auth::AuthMiddleware;
+use backend::routes::api::config;
+use backend::services::mfa_service::MFAService;
+use backend::services::webauthn_service::WebAuthnConfig;
+use backend::utils::auth::AuthUtils;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ciborium::value::Value as Cbor;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use sha2::{Digest, Sha256};
+
+const SECRET: &str = "test-secret";
+const RP_ID: &str = "app.example.com";
+const ORIGIN: &str = "https://app.example.com";
+
+fn webauthn_config() -> WebAuthnConfig {
+    WebAuthnConfig {
+        rp_id: RP_ID.to_string(),
+        rp_name: "USH".to_string(),
+        origins: vec![ORIGIN.to_string()],
+    }
+}
+
+/// A software ES256 authenticator
+struct Authenticator {
+    key: EcdsaKeyPair,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+}
+
+impl Authenticator {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        Self {
+            key: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng).unwrap(),
+            credential_id: uuid::Uuid::new_v4().as_bytes().to_vec(),
+            sign_count: 0,
+        }
+    }
+
+    fn id(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.credential_id)
+    }
+
+    fn client_data(kind: &str, options: &serde_json::Value, origin: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "type": kind,
+            "challenge": options["publicKey"]["challenge"],
+            "origin": origin
+        }))
+        .unwrap()
+    }
+
+    fn auth_data(&mut self, rp_id: &str, flags: u8) -> Vec<u8> {
+        self.sign_count += 1;
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        data
+    }
+
+    /// `navigator.credentials.create()` for the given /register/start response
+    fn register(&mut self, options: &serde_json::Value) -> serde_json::Value {
+        let point = self.key.public_key().as_ref();
+        let cose_key = Cbor::Map(vec![
+            (Cbor::Integer(1.into()), Cbor::Integer(2.into())),
+            (Cbor::Integer(3.into()), Cbor::Integer((-7).into())),
+            (Cbor::Integer((-1).into()), Cbor::Integer(1.into())),
+            (Cbor::Integer((-2).into()), Cbor::Bytes(point[1..33].to_vec())),
+            (Cbor::Integer((-3).into()), Cbor::Bytes(point[33..65].to_vec())),
+        ]);
+
+        // User present and verified, with attested credential data
+        let mut auth_data = self.auth_data(RP_ID, 0x45);
+        auth_data.extend_from_slice(&[0u8; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        ciborium::ser::into_writer(&cose_key, &mut auth_data).unwrap();
+
+        let attestation = Cbor::Map(vec![
+            (Cbor::Text("fmt".to_string()), Cbor::Text("none".to_string())),
+            (Cbor::Text("attStmt".to_string()), Cbor::Map(vec![])),
+            (Cbor::Text("authData".to_string()), Cbor::Bytes(auth_data)),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        serde_json::json!({
+            "id": self.id(),
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(Self::client_data("webauthn.create", options, ORIGIN)),
+                "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object),
+                "transports": ["internal"]
+            }
+        })
+    }
+
+    /// `navigator.credentials.get()` for the given /login/start or /mfa/start response
+    fn assert(&mut self, options: &serde_json::Value, origin: &str, flags: u8) -> serde_json::Value {
+        let client_data = Self::client_data("webauthn.get", options, origin);
+        let auth_data = self.auth_data(RP_ID, flags);
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+        let signature = self.key.sign(&SystemRandom::new(), &signed).unwrap();
+
+        serde_json::json!({
+            "id": self.id(),
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticatorData": URL_SAFE_NO_PAD.encode(auth_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature.as_ref())
+            }
+        })
+    }
+}
+
+#[actix_web::test]
+async fn test_passkey_registration_and_passwordless_login() {
+    let pool = common::setup_test_db().await;
+    let username = format!("passkey_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &format!("{}@example.com", username), true).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .app_data(web::Data::new(webauthn_config()))
+            .route("/step-up", web::post().to(step_up).wrap(AuthMiddleware::new()))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+    let peer = "203.0.113.45:5000";
+    let mut authenticator = Authenticator::new();
+
+    let req = test::TestRequest::post().uri("/api/auth/passkeys/register/start").to_request();
+    let err = test::try_call_service(&app, req).await.expect_err("Registration requires a session");
+    assert_eq!(err.as_response_error().status_code(), 401);
+
+    // Adding a sign-in method also needs a recent step-up
+    let req = test::TestRequest::post()
+        .uri("/api/auth/passkeys/register/start")
+        .peer_addr(peer.parse().unwrap())
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    let req = test::TestRequest::post()
+        .uri("/step-up")
+        .peer_addr(peer.parse().unwrap())
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "method": "password", "password": "Test@1234" }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let step_up_token = body["step_up_token"].as_str().unwrap().to_string();
+    let post = |uri: &str, body: serde_json::Value| {
+        test::TestRequest::post()
+            .uri(uri)
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .insert_header(("X-Step-Up-Token", step_up_token.clone()))
+            .set_json(body)
+            .to_request()
+    };
+
+    let options: serde_json::Value = test::call_and_read_body_json(&app, post("/api/auth/passkeys/register/start", serde_json::json!({}))).await;
+    assert_eq!(options["publicKey"]["rp"]["id"], RP_ID);
+    let credential = authenticator.register(&options);
+    let resp = test::call_service(&app, post("/api/auth/passkeys/register/finish", serde_json::json!({ "credential": credential, "name": "Laptop" }))).await;
+    assert_eq!(resp.status(), 201);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["passkey"]["name"], "Laptop");
+    assert!(body["passkey"].get("public_key").is_none());
+
+    // The challenge is single use
+    let resp = test::call_service(&app, post("/api/auth/passkeys/register/finish", serde_json::json!({ "credential": credential }))).await;
+    assert_eq!(resp.status(), 401);
+
+    // New challenges exclude the registered credential
+    let options: serde_json::Value = test::call_and_read_body_json(&app, post("/api/auth/passkeys/register/start", serde_json::json!({}))).await;
+    assert_eq!(options["publicKey"]["excludeCredentials"][0]["id"], authenticator.id());
+
+    // Passwordless sign-in
+    let login_start = || post("/api/auth/passkeys/login/start", serde_json::json!({}));
+    let login_finish = |credential: serde_json::Value| post("/api/auth/passkeys/login/finish", serde_json::json!({ "credential": credential }));
+
+    let options: serde_json::Value = test::call_and_read_body_json(&app, login_start()).await;
+    assert_eq!(options["publicKey"]["userVerification"], "required");
+    let resp = test::call_service(&app, login_finish(authenticator.assert(&options, "https://evil.example.com", 0x05))).await;
+    assert_eq!(resp.status(), 400);
+
+    // User present but not verified
+    let options: serde_json::Value = test::call_and_read_body_json(&app, login_start()).await;
+    let resp = test::call_service(&app, login_finish(authenticator.assert(&options, ORIGIN, 0x01))).await;
+    assert_eq!(resp.status(), 401);
+
+    let options: serde_json::Value = test::call_and_read_body_json(&app, login_start()).await;
+    let resp = test::call_service(&app, login_finish(authenticator.assert(&options, ORIGIN, 0x05))).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["token"].is_string());
+    assert_eq!(body["user"]["id"], user_id);
+
+    // A cloned authenticator replays an old counter
+    let options: serde_json::Value = test::call_and_read_body_json(&app, login_start()).await;
+    authenticator.sign_count = 1;
+    let resp = test::call_service(&app, login_finish(authenticator.assert(&options, ORIGIN, 0x05))).await;
+    assert_eq!(resp.status(), 401);
+
+    // Rename, then removal: the password keeps the account reachable
+    let list = test::TestRequest::get()
+        .uri("/api/auth/passkeys")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, list).await;
+    let id = body["passkeys"][0]["id"].as_i64().unwrap();
+
+    let rename = test::TestRequest::put()
+        .uri(&format!("/api/auth/passkeys/{}", id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "name": "Work laptop" }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, rename).await;
+    assert_eq!(body["passkey"]["name"], "Work laptop");
+
+    let remove = test::TestRequest::delete()
+        .uri(&format!("/api/auth/passkeys/{}", id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .insert_header(("X-Step-Up-Token", step_up_token.clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, remove).await.status(), 200);
+
+    // Added, removed and the failed attempts are in the activity trail
+    let activity = test::TestRequest::get()
+        .uri("/api/auth/activity")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, activity).await;
+    let kinds: Vec<&str> = body["events"].as_array().unwrap().iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds.first(), Some(&"passkey_removed"));
+    assert!(kinds.contains(&"passkey_added"));
+    assert!(kinds.contains(&"failed_login"));
+    assert!(kinds.contains(&"login"));
+}
+
+#[actix_web::test]
+async fn test_passkey_as_second_factor() {
+    let pool = common::setup_test_db().await;
+    let username = format!("passkey_mfa_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &format!("{}@example.com", username), true).await;
+    let (other_id, other_name, _) = common::create_test_user(&pool, &format!("{}_other", username), &format!("{}_other@example.com", username), true).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .app_data(web::Data::new(webauthn_config()))
+            .route("/step-up", web::post().to(step_up).wrap(AuthMiddleware::new()))
+            .configure(config)
+    ).await;
+    let peer = "203.0.113.46:5000";
+    let post = |uri: &str, bearer: Option<&str>, body: serde_json::Value| {
+        let mut req = test::TestRequest::post().uri(uri).peer_addr(peer.parse().unwrap()).set_json(body);
+        if let Some(bearer) = bearer {
+            req = req.insert_header(("Authorization", format!("Bearer {}", bearer)));
+        }
+        req.to_request()
+    };
+    let stepped_up = |uri: &str, bearer: &str, step_up_token: &str, body: serde_json::Value| {
+        test::TestRequest::post()
+            .uri(uri)
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("Authorization", format!("Bearer {}", bearer)))
+            .insert_header(("X-Step-Up-Token", step_up_token.to_string()))
+            .set_json(body)
+            .to_request()
+    };
+
+    // Register a passkey for each account
+    let mut authenticator = Authenticator::new();
+    let mut other_authenticator = Authenticator::new();
+    for (id, name, authenticator) in [(user_id, &username, &mut authenticator), (other_id, &other_name, &mut other_authenticator)] {
+        let token = AuthUtils::create_token(id, name, "user", SECRET).unwrap();
+        let body: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            post("/step-up", Some(&token), serde_json::json!({ "method": "password", "password": "Test@1234" })),
+        )
+        .await;
+        let step_up_token = body["step_up_token"].as_str().unwrap();
+        let options: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            stepped_up("/api/auth/passkeys/register/start", &token, step_up_token, serde_json::json!({})),
+        )
+        .await;
+        let credential = authenticator.register(&options);
+        let resp = test::call_service(
+            &app,
+            stepped_up("/api/auth/passkeys/register/finish", &token, step_up_token, serde_json::json!({ "credential": credential })),
+        )
+        .await;
+        assert_eq!(resp.status(), 201);
+    }
+
+    // As issued by a password login for an account with 2FA
+    let temp_token = MFAService::generate_temp_mfa_token(user_id, &username, None, SECRET).unwrap();
+
+    let mfa_start = || post("/api/auth/passkeys/mfa/start", None, serde_json::json!({ "temp_token": temp_token }));
+    let verify = |credential: serde_json::Value| {
+        post("/api/auth/verify-mfa", None, serde_json::json!({ "temp_token": temp_token, "method": "passkey", "credential": credential }))
+    };
+
+    let options: serde_json::Value = test::call_and_read_body_json(&app, mfa_start()).await;
+    assert_eq!(options["publicKey"]["allowCredentials"][0]["id"], authenticator.id());
+
+    // Another account's passkey can't complete this login
+    let resp = test::call_service(&app, verify(other_authenticator.assert(&options, ORIGIN, 0x05))).await;
+    assert_eq!(resp.status(), 401);
+
+    // Login challenges are not accepted as MFA
+    let options: serde_json::Value = test::call_and_read_body_json(&app, post("/api/auth/passkeys/login/start", None, serde_json::json!({}))).await;
+    let resp = test::call_service(&app, verify(authenticator.assert(&options, ORIGIN, 0x05))).await;
+    assert_eq!(resp.status(), 401);
+
+    // User presence is enough for a second factor
+    let options: serde_json::Value = test::call_and_read_body_json(&app, mfa_start()).await;
+    let resp = test::call_service(&app, verify(authenticator.assert(&options, ORIGIN, 0x01))).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["token"].is_string());
+
+    let resp = test::call_service(&app, post("/api/auth/verify-mfa", None, serde_json::json!({ "temp_token": temp_token, "method": "passkey" }))).await;
+    assert_eq!(resp.status(), 400);
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::auth::traditional::login;
+use backend::services::password_hash_service::{PasswordHashConfig, PasswordHashScheme, PasswordHashService};
+
+// Cheap parameters, to keep the tests fast
+fn test_config() -> PasswordHashConfig {
+    PasswordHashConfig { memory_kib: 1024, iterations: 1, parallelism: 1 }
+}
+
+async fn stored_hash(pool: &sqlx::PgPool, user_id: i32) -> String {
+    sqlx::query_scalar("SELECT password FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn test_argon2id_hashes_and_legacy_bcrypt() {
+    let config = test_config();
+    let hash = PasswordHashService::hash(&config, "Correct@Horse1").await.unwrap();
+    assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+    assert_ne!(hash, PasswordHashService::hash(&config, "Correct@Horse1").await.unwrap());
+
+    assert!(PasswordHashService::verify("Correct@Horse1", &hash).await.unwrap());
+    assert!(!PasswordHashService::verify("Correct@Horse2", &hash).await.unwrap());
+    assert!(!PasswordHashService::needs_rehash(&config, &hash));
+    assert!(PasswordHashService::needs_rehash(&PasswordHashConfig { iterations: 2, ..test_config() }, &hash));
+
+    // Old bcrypt hashes still verify, but are always replaced
+    let legacy = bcrypt::hash("Correct@Horse1", 4).unwrap();
+    assert_eq!(PasswordHashScheme::of(&legacy), Some(PasswordHashScheme::Bcrypt));
+    assert!(PasswordHashService::verify("Correct@Horse1", &legacy).await.unwrap());
+    assert!(!PasswordHashService::verify("Correct@Horse2", &legacy).await.unwrap());
+    assert!(PasswordHashService::needs_rehash(&config, &legacy));
+
+    assert!(PasswordHashService::verify("Correct@Horse1", "plaintext").await.is_err());
+
+    // Bursts queue for the bounded pool instead of failing
+    let hashes = futures_util::future::join_all((0..16).map(|_| PasswordHashService::hash(&config, "Correct@Horse1"))).await;
+    assert!(hashes.into_iter().all(|hash| hash.is_ok()));
+}
+
+#[actix_web::test]
+async fn test_login_upgrades_old_hashes() {
+    let pool = common::setup_test_db().await;
+    let username = format!("rehash_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &format!("{}@example.com", username), true).await;
+    assert_eq!(PasswordHashScheme::of(&stored_hash(&pool, user_id).await), Some(PasswordHashScheme::Bcrypt));
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .app_data(web::Data::new(test_config()))
+            .route("/login", web::post().to(login))
+    ).await;
+    let login_with = |password: &str| {
+        test::TestRequest::post()
+            .uri("/login")
+            .peer_addr("203.0.113.48:4000".parse().unwrap())
+            .set_json(serde_json::json!({ "username": username, "password": password }))
+            .to_request()
+    };
+
+    // A failed attempt leaves the hash alone
+    assert_eq!(test::call_service(&app, login_with("Wrong@1234")).await.status(), 401);
+    assert_eq!(PasswordHashScheme::of(&stored_hash(&pool, user_id).await), Some(PasswordHashScheme::Bcrypt));
+
+    assert_eq!(test::call_service(&app, login_with("Test@1234")).await.status(), 200);
+    let upgraded = stored_hash(&pool, user_id).await;
+    assert!(upgraded.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+
+    // Already current: logging in again keeps the same hash
+    assert_eq!(test::call_service(&app, login_with("Test@1234")).await.status(), 200);
+    assert_eq!(stored_hash(&pool, user_id).await, upgraded);
+
+    sqlx::query("DELETE FROM users WHERE id = $1").bind(user_id).execute(&pool).await.unwrap();
+}
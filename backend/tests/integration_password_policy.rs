@@ -0,0 +1,108 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::routes::api::config;
+use backend::services::password_hash_service::PasswordHashConfig;
+use backend::services::password_policy_service::{PasswordOwner, PasswordPolicy, PasswordPolicyError, PasswordPolicyService};
+use backend::services::step_up_service::{StepUpMethod, StepUpService};
+use backend::utils::auth::AuthUtils;
+use backend::utils::password_strength::strength_score;
+use ring::digest::{SHA1_FOR_LEGACY_USE_ONLY, digest};
+
+const SECRET: &str = "test-secret";
+
+#[actix_web::test]
+async fn test_rules_strength_and_breach_list() {
+    let example = PasswordPolicy::parse(include_str!("../password_policy.example.toml")).unwrap();
+    assert_eq!((example.min_strength_score, example.history_size), (2, 5));
+    assert!(PasswordPolicy::parse("min_strength_score = 5").is_err());
+    assert!(PasswordPolicy::parse("min_length = 20\nmax_length = 10").is_err());
+    assert!(PasswordPolicy::parse("minimum_length = 10").is_err());
+
+    // Common words, sequences, keyboard runs and years are cheap to guess
+    assert_eq!(strength_score("password", &[]), 0);
+    assert!(strength_score("P@ssw0rd2024", &[]) < 2);
+    assert!(strength_score("Qwerty123456", &[]) < 2);
+    assert!(strength_score("Velvet-Harbor-71", &[]) >= 3);
+    assert!(strength_score("marigold", &["marigold@example.com", "marigold"]) < strength_score("marigold", &[]));
+
+    let policy = PasswordPolicy { require_symbol: true, min_strength_score: 2, ..PasswordPolicy::default() };
+    assert!(policy.check("Velvet-Harbor-71", Some("alice"), Some("alice@example.com")).is_ok());
+    assert!(policy.check("VelvetHarbor71", None, None).unwrap_err().contains("symbol"));
+    assert!(policy.check("Sh0rt-a", None, None).is_err());
+    assert!(policy.check("Password2024!", None, None).unwrap_err().contains("too easy"));
+    let personal = policy.check("Velvet-Alice-71", Some("alice"), None).unwrap_err();
+    assert!(personal.contains("username or email"));
+    assert!(policy.check("Velvet-Jsmith-71", None, Some("jsmith@example.com")).is_err());
+
+    // Range files named by the hash prefix, as downloaded from the Pwned Passwords API
+    let dir = std::env::temp_dir().join(format!("pwned_{}", uuid::Uuid::new_v4().simple()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let hash = hex::encode_upper(digest(&SHA1_FOR_LEGACY_USE_ONLY, b"Velvet-Harbor-71"));
+    std::fs::write(dir.join(&hash[..5]), format!("0000000000000000000000000000000000A:3\r\n{}:42\r\n", &hash[5..])).unwrap();
+
+    let policy = PasswordPolicy { breached_passwords_dir: Some(dir.clone()), ..PasswordPolicy::default() };
+    assert_eq!(policy.breach_count("Velvet-Harbor-71").await, 42);
+    assert_eq!(policy.breach_count("Copper-Lantern-42").await, 0);
+
+    let pool = common::setup_test_db().await;
+    let result = PasswordPolicyService::check(&pool, &policy, "Velvet-Harbor-71", &PasswordOwner::default()).await;
+    assert!(matches!(result, Err(PasswordPolicyError::Rejected(reason)) if reason.contains("breach")));
+    let rare = PasswordPolicy { breached_min_count: 100, ..policy };
+    assert!(PasswordPolicyService::check(&pool, &rare, "Velvet-Harbor-71", &PasswordOwner::default()).await.is_ok());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[actix_web::test]
+async fn test_recent_passwords_cannot_be_reused() {
+    let pool = common::setup_test_db().await;
+    let username = format!("pwhist_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &format!("{}@example.com", username), true).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .app_data(web::Data::new(PasswordHashConfig { memory_kib: 1024, iterations: 1, parallelism: 1 }))
+            .app_data(web::Data::new(PasswordPolicy { history_size: 3, ..PasswordPolicy::default() }))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+    let (step_up_token, _) = StepUpService::issue(user_id, &token, StepUpMethod::Password, SECRET).unwrap();
+    let change = async |new_password: &str| {
+        let req = test::TestRequest::post()
+            .uri("/api/auth/password/change")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .insert_header(("X-Step-Up-Token", step_up_token.as_str()))
+            .set_json(serde_json::json!({ "new_password": new_password }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status().as_u16();
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        (status, body["error"].as_str().unwrap_or_default().to_string())
+    };
+
+    assert_eq!(change("Velvet-Harbor-71").await.0, 200);
+    let (status, error) = change("Velvet-Harbor-71").await;
+    assert_eq!(status, 400);
+    assert!(error.contains("used recently"));
+    assert!(change(&format!("Velvet-{}-71", username)).await.1.contains("username or email"));
+
+    assert_eq!(change("Copper-Lantern-42").await.0, 200);
+    assert_eq!(change("Quiet-Meadow-93").await.0, 200);
+    assert_eq!(change("Velvet-Harbor-71").await.0, 400);
+
+    // Three in total: the current one and two before it
+    assert_eq!(change("Amber-Falcon-58").await.0, 200);
+    assert_eq!(change("Velvet-Harbor-71").await.0, 200);
+
+    let kept: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM password_history WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(kept, 2);
+
+    sqlx::query("DELETE FROM users WHERE id = $1").bind(user_id).execute(&pool).await.unwrap();
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::auth::passwordless::{passwordless_complete, passwordless_start};
+use backend::services::one_time_code_store::{CodePurpose, OneTimeCodeStore};
+use backend::services::passwordless_service::PasswordlessService;
+
+const SECRET: &str = "test-secret";
+
+// The link token and the code from the newest sign-in email to the address
+async fn sign_in_email(pool: &sqlx::PgPool, email: &str) -> (String, String) {
+    let body: String = sqlx::query_scalar(
+        "SELECT text_body FROM email_outbox WHERE recipient = $1 ORDER BY id DESC LIMIT 1",
+    )
+    .bind(email)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+
+    let token = body.split("token=").nth(1).unwrap().split_whitespace().next().unwrap().to_string();
+    let code = body
+        .split_whitespace()
+        .find(|word| word.len() == 6 && word.chars().all(|c| c.is_ascii_digit()))
+        .unwrap()
+        .to_string();
+    (token, code)
+}
+
+// As if the last email had expired, to get past the resend cooldown
+async fn forget_codes(user_id: i32) {
+    let store = OneTimeCodeStore::current();
+    store.remove(CodePurpose::PasswordlessCode, &user_id.to_string()).await.unwrap();
+    store.remove(CodePurpose::PasswordlessLink, &user_id.to_string()).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_passwordless_login_by_link_and_code() {
+    // Keep emails in the outbox instead of sending them
+    unsafe { std::env::set_var("EMAIL_PROVIDER", "memory") };
+
+    let pool = common::setup_test_db().await;
+    let username = format!("nopass_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let email = format!("{}@example.com", username);
+    let (user_id, _, _) = common::create_test_user(&pool, &username, &email, true).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .route("/start", web::post().to(passwordless_start))
+            .route("/complete", web::post().to(passwordless_complete))
+    ).await;
+    let start = |email: &str| {
+        test::TestRequest::post().uri("/start").set_json(serde_json::json!({ "email": email })).to_request()
+    };
+    let complete = |body: serde_json::Value| {
+        test::TestRequest::post()
+            .uri("/complete")
+            .peer_addr("203.0.113.49:4000".parse().unwrap())
+            .set_json(body)
+            .to_request()
+    };
+
+    // Unknown addresses get the same answer
+    let resp = test::call_service(&app, start("nobody-here@example.com")).await;
+    assert_eq!(resp.status(), 200);
+
+    assert_eq!(test::call_service(&app, start(&email.to_uppercase())).await.status(), 200);
+    let (token, code) = sign_in_email(&pool, &email).await;
+
+    // The link logs in once, and burns the code sent with it
+    let resp = test::call_service(&app, complete(serde_json::json!({ "token": token }))).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["token"].is_string() && body["refresh_token"].is_string());
+    assert_eq!(body["user"]["id"], user_id);
+
+    assert_eq!(test::call_service(&app, complete(serde_json::json!({ "token": token }))).await.status(), 401);
+    let resp = test::call_service(&app, complete(serde_json::json!({ "email": email, "code": code }))).await;
+    assert_eq!(resp.status(), 401);
+
+    // Tampered links are refused
+    let (user_part, rest) = token.split_once('.').unwrap();
+    let forged = format!("{}.{}", user_part.parse::<i32>().unwrap() + 1, rest);
+    assert_eq!(test::call_service(&app, complete(serde_json::json!({ "token": forged }))).await.status(), 401);
+
+    // A second email is only sent after the cooldown
+    forget_codes(user_id).await;
+    PasswordlessService::issue(user_id, SECRET).await.unwrap().unwrap();
+    assert!(PasswordlessService::issue(user_id, SECRET).await.unwrap().is_none());
+    forget_codes(user_id).await;
+
+    assert_eq!(test::call_service(&app, start(&email)).await.status(), 200);
+    let (token, code) = sign_in_email(&pool, &email).await;
+    let resp = test::call_service(&app, complete(serde_json::json!({ "email": email, "code": "000000" }))).await;
+    assert_eq!(resp.status(), 401);
+    let resp = test::call_service(&app, complete(serde_json::json!({ "email": email, "code": code }))).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(test::call_service(&app, complete(serde_json::json!({ "token": token }))).await.status(), 401);
+
+    // With 2FA on, the link only gets as far as the MFA challenge
+    sqlx::query("UPDATE users SET totp_enabled = true WHERE id = $1").bind(user_id).execute(&pool).await.unwrap();
+    forget_codes(user_id).await;
+    assert_eq!(test::call_service(&app, start(&email)).await.status(), 200);
+    let (token, _) = sign_in_email(&pool, &email).await;
+    let resp = test::call_service(&app, complete(serde_json::json!({ "token": token }))).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["requires_mfa"], true);
+    assert!(body["temp_token"].is_string());
+    assert!(body["token"].is_null());
+
+    sqlx::query("DELETE FROM email_outbox WHERE recipient = $1").bind(&email).execute(&pool).await.unwrap();
+    sqlx::query("DELETE FROM users WHERE id = $1").bind(user_id).execute(&pool).await.unwrap();
+}
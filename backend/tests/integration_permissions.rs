@@ -0,0 +1,182 @@
+mod common;
+
+use actix_web::{test, web, App, HttpResponse};
+use backend::handlers::interaction::delete_comment;
+use backend::middleware::auth::AuthMiddleware;
+use backend::routes::api::config;
+use backend::services::permission_service::{self as permissions, PermissionService};
+use backend::utils::auth::AuthUtils;
+
+fn token_for(user_id: i32, role: &str) -> String {
+    AuthUtils::create_token(user_id, &format!("perm_{}", role), role, "test-secret").unwrap()
+}
+
+#[actix_web::test]
+async fn test_seeded_role_permissions() {
+    let pool = common::setup_test_db().await;
+
+    let moderator = PermissionService::for_role(&pool, "moderator").await.unwrap();
+    assert!(moderator.contains(permissions::USERS_BAN));
+    assert!(moderator.contains(permissions::POSTS_DELETE_ANY));
+    assert!(!moderator.contains(permissions::USERS_UPDATE), "Moderators must not edit accounts");
+
+    let support = PermissionService::for_role(&pool, "support").await.unwrap();
+    assert!(support.contains(permissions::USERS_READ));
+    assert!(!support.contains(permissions::USERS_BAN));
+    assert!(!support.contains(permissions::POSTS_DELETE_ANY));
+
+    let admin = PermissionService::for_role(&pool, "admin").await.unwrap();
+    assert!(admin.contains(permissions::USERS_ROLE_ASSIGN));
+    assert!(admin.contains(permissions::POSTS_DELETE_ANY));
+
+    assert!(PermissionService::for_role(&pool, "user").await.unwrap().is_empty());
+    assert!(PermissionService::for_role(&pool, "no_such_role").await.unwrap().is_empty());
+
+    assert!(PermissionService::role_exists(&pool, "moderator").await.unwrap());
+    assert!(!PermissionService::role_exists(&pool, "no_such_role").await.unwrap());
+}
+
+#[actix_web::test]
+async fn test_require_permission_middleware() {
+    let pool = common::setup_test_db().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .route(
+                "/moderate",
+                web::delete()
+                    .to(|| async { HttpResponse::Ok().finish() })
+                    .wrap(AuthMiddleware::require_permission(permissions::POSTS_DELETE_ANY)),
+            )
+    ).await;
+
+    for role in ["admin", "moderator"] {
+        let req = test::TestRequest::delete()
+            .uri("/moderate")
+            .insert_header(("Authorization", format!("Bearer {}", token_for(1, role))))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200, "{} should be allowed", role);
+    }
+
+    for role in ["support", "user"] {
+        let req = test::TestRequest::delete()
+            .uri("/moderate")
+            .insert_header(("Authorization", format!("Bearer {}", token_for(1, role))))
+            .to_request();
+        let err = test::try_call_service(&app, req).await.expect_err("Should be forbidden");
+        assert_eq!(err.as_response_error().status_code(), 403, "{} should be rejected", role);
+    }
+
+    let err = test::try_call_service(&app, test::TestRequest::delete().uri("/moderate").to_request())
+        .await
+        .expect_err("Anonymous request should be rejected");
+    assert_eq!(err.as_response_error().status_code(), 401);
+}
+
+#[actix_web::test]
+async fn test_moderator_deletes_others_comment() {
+    let pool = common::setup_test_db().await;
+    let (author_name, author_email) = common::unique_name("author");
+    let (author_id, _, _) = common::create_test_user(&pool, &author_name, &author_email, true).await;
+    let (other_name, other_email) = common::unique_name("other");
+    let (other_id, _, _) = common::create_test_user(&pool, &other_name, &other_email, true).await;
+
+    let post_id = sqlx::query_scalar!(
+        "INSERT INTO posts (title, content, user_id) VALUES ('Post', 'Body', $1) RETURNING id",
+        author_id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let comment_id = sqlx::query_scalar!(
+        "INSERT INTO comments (post_id, user_id, content) VALUES ($1, $2, 'Comment') RETURNING id",
+        post_id,
+        author_id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .route(
+                "/comments/{id}",
+                web::delete().to(delete_comment).wrap(AuthMiddleware::new()),
+            )
+    ).await;
+
+    // Unrelated regular user cannot delete it
+    let req = test::TestRequest::delete()
+        .uri(&format!("/comments/{}", comment_id))
+        .insert_header(("Authorization", format!("Bearer {}", token_for(other_id, "user"))))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+
+    // Support staff cannot either
+    let req = test::TestRequest::delete()
+        .uri(&format!("/comments/{}", comment_id))
+        .insert_header(("Authorization", format!("Bearer {}", token_for(other_id, "support"))))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+
+    // A moderator can
+    let req = test::TestRequest::delete()
+        .uri(&format!("/comments/{}", comment_id))
+        .insert_header(("Authorization", format!("Bearer {}", token_for(other_id, "moderator"))))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+
+    sqlx::query!("DELETE FROM posts WHERE id = $1", post_id).execute(&pool).await.unwrap();
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[author_id, other_id]).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_creating_a_user_with_a_role_needs_role_assign() {
+    let pool = common::setup_test_db().await;
+    // Can create accounts, but not hand out roles
+    let creator_role = format!("creator_{}", &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    sqlx::query!("INSERT INTO roles (name) VALUES ($1)", creator_role).execute(&pool).await.unwrap();
+    sqlx::query!("INSERT INTO role_permissions (role, permission) VALUES ($1, $2)", creator_role, permissions::USERS_CREATE)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+    let create = |role: &str, new_role: Option<&str>| {
+        let (username, email) = common::unique_name("created");
+        test::TestRequest::post()
+            .uri("/api/users")
+            .insert_header(("Authorization", format!("Bearer {}", token_for(1, role))))
+            .set_json(serde_json::json!({ "username": username, "email": email, "password": "Velvet-Harbor-71", "role": new_role }))
+            .to_request()
+    };
+
+    assert_eq!(test::call_service(&app, create(&creator_role, Some("admin"))).await.status(), 403);
+    assert_eq!(test::call_service(&app, create("admin", Some("no_such_role"))).await.status(), 400);
+
+    let resp = test::call_service(&app, create(&creator_role, None)).await;
+    assert_eq!(resp.status(), 201);
+    let created: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(created["role"], "user");
+
+    let resp = test::call_service(&app, create("admin", Some("moderator"))).await;
+    assert_eq!(resp.status(), 201);
+    let promoted: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(promoted["role"], "moderator");
+
+    let ids = [created["id"].as_i64().unwrap() as i32, promoted["id"].as_i64().unwrap() as i32];
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &ids[..]).execute(&pool).await.unwrap();
+    sqlx::query!("DELETE FROM roles WHERE name = $1", creator_role).execute(&pool).await.unwrap();
+}
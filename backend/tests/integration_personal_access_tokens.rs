@@ -0,0 +1,174 @@
+mod common;
+
+use actix_web::{test, web, App, HttpRequest, HttpResponse};
+use backend::auth::personal_access_tokens::{create_access_token, list_access_tokens, revoke_access_token};
+use backend::middleware::auth::{get_access_token, get_current_user, AuthMiddleware};
+use backend::middleware::step_up::StepUpMiddleware;
+use backend::services::audit_logger::AuditLogger;
+use backend::services::permission_service as permissions;
+use backend::services::personal_access_token_service::{self as tokens, PersonalAccessTokenService};
+use backend::services::step_up_service::{StepUpMethod, StepUpService};
+use backend::utils::auth::AuthUtils;
+
+const SECRET: &str = "test-secret";
+
+// Who the request was authenticated as, and with which token
+async fn whoami(req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "user_id": get_current_user(&req).map(|claims| claims.sub),
+        "token_id": get_access_token(&req).map(|token| token.id)
+    }))
+}
+
+#[actix_web::test]
+async fn test_personal_access_tokens() {
+    let pool = common::setup_test_db().await;
+    let username = format!("pat_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &format!("{}@example.com", username), true).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .route("/tokens", web::get().to(list_access_tokens).wrap(AuthMiddleware::new()))
+            .route(
+                "/tokens",
+                web::post()
+                    .to(create_access_token)
+                    .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                    .wrap(AuthMiddleware::new()),
+            )
+            .route(
+                "/tokens/{id}",
+                web::delete()
+                    .to(revoke_access_token)
+                    .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                    .wrap(AuthMiddleware::new()),
+            )
+            .service(
+                web::scope("/posts")
+                    .wrap(AuthMiddleware::new().token_scope("posts"))
+                    .route("", web::get().to(whoami))
+                    .route("", web::post().to(whoami)),
+            )
+            .route(
+                "/users",
+                web::get()
+                    .to(whoami)
+                    .wrap(AuthMiddleware::require_permission(permissions::USERS_READ).token_scope(tokens::ADMIN_USERS)),
+            )
+    ).await;
+    let jwt = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+    let (step_up, _) = StepUpService::issue(user_id, &jwt, StepUpMethod::Password, SECRET).unwrap();
+    let call = async |method: &str, uri: &str, bearer: &str, body: Option<serde_json::Value>| {
+        let req = match method {
+            "GET" => test::TestRequest::get(),
+            "POST" => test::TestRequest::post(),
+            _ => test::TestRequest::delete(),
+        }
+        .uri(uri)
+        .insert_header(("Authorization", format!("Bearer {}", bearer)))
+        .insert_header(("X-Step-Up-Token", step_up.clone()));
+        let req = match body {
+            Some(body) => req.set_json(body),
+            None => req,
+        };
+        // Rejections by AuthMiddleware come back as errors
+        match test::try_call_service(&app, req.to_request()).await {
+            Ok(resp) => {
+                let status = resp.status().as_u16();
+                let body: serde_json::Value = serde_json::from_slice(&test::read_body(resp).await).unwrap_or_default();
+                (status, body)
+            }
+            Err(err) => (err.as_response_error().status_code().as_u16(), serde_json::Value::Null),
+        }
+    };
+
+    // Creating a token needs a recent step-up
+    let req = test::TestRequest::post()
+        .uri("/tokens")
+        .insert_header(("Authorization", format!("Bearer {}", jwt)))
+        .set_json(serde_json::json!({ "name": "ci", "scopes": ["posts:read"] }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    let (status, _) = call("POST", "/tokens", &jwt, Some(serde_json::json!({ "name": "ci", "scopes": ["posts:delete"] }))).await;
+    assert_eq!(status, 400);
+    let (status, _) = call("POST", "/tokens", &jwt, Some(serde_json::json!({ "name": "ci", "scopes": [] }))).await;
+    assert_eq!(status, 400);
+    let (status, _) = call("POST", "/tokens", &jwt, Some(serde_json::json!({ "name": "ci", "scopes": ["posts:read"], "expires_in_days": 0 }))).await;
+    assert_eq!(status, 400);
+
+    let (status, body) = call("POST", "/tokens", &jwt, Some(serde_json::json!({
+        "name": "ci bot",
+        "scopes": ["posts:read", "admin:users"],
+        "rate_limit_per_minute": 3
+    }))).await;
+    assert_eq!(status, 201);
+    let token = body["token"]["token"].as_str().unwrap().to_string();
+    let token_id = body["token"]["id"].as_i64().unwrap();
+    assert!(token.starts_with(PersonalAccessTokenService::PREFIX));
+    assert_eq!(body["token"]["token_prefix"], &token[..PersonalAccessTokenService::DISPLAY_PREFIX_LENGTH]);
+
+    // The audit trail says what the token was granted
+    let (action, details): (String, serde_json::Value) = sqlx::query_as(
+        "SELECT event_action, details FROM audit_logs WHERE user_id = $1 AND event_type = $2 ORDER BY id DESC LIMIT 1",
+    )
+    .bind(user_id)
+    .bind(AuditLogger::EVENT_ACCESS_TOKEN_CREATED)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(action, "create with scopes: posts:read, admin:users");
+    assert_eq!(details["scopes"], serde_json::json!(["posts:read", "admin:users"]));
+    assert_eq!(details["step_up_method"], "password");
+
+    // Only the hash is stored, and the list never shows the token
+    let stored: String = sqlx::query_scalar("SELECT token_hash FROM personal_access_tokens WHERE id = $1")
+        .bind(token_id as i32)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(stored, PersonalAccessTokenService::hash_token(&token));
+    let (_, body) = call("GET", "/tokens", &jwt, None).await;
+    assert_eq!(body["tokens"][0]["name"], "ci bot");
+    assert!(body["tokens"][0]["token"].is_null() && body["tokens"][0]["token_hash"].is_null());
+
+    // Scopes: reading posts is allowed, writing isn't, and admin:users still needs the permission
+    let (status, body) = call("GET", "/posts", &token, None).await;
+    assert_eq!(status, 200);
+    assert_eq!((body["user_id"].as_i64(), body["token_id"].as_i64()), (Some(user_id as i64), Some(token_id)));
+    let (status, body) = call("POST", "/posts", &token, None).await;
+    assert_eq!(status, 403);
+    assert_eq!(body["required_scope"], "posts:write");
+    assert_eq!(call("GET", "/users", &token, None).await.0, 403);
+
+    // Tokens can't manage tokens, or do anything else not opted in
+    assert_eq!(call("GET", "/tokens", &token, None).await.0, 403);
+
+    // Each token has its own rate limit, three per minute here
+    assert_eq!(call("GET", "/posts", &token, None).await.0, 200);
+    let (status, body) = call("GET", "/posts", &token, None).await;
+    assert_eq!(status, 429);
+    assert!(body["retry_after"].is_number());
+    assert_eq!(call("GET", "/posts", &jwt, None).await.0, 200);
+
+    let (status, _) = call("DELETE", &format!("/tokens/{}", token_id), &jwt, None).await;
+    assert_eq!(status, 200);
+    assert_eq!(call("GET", "/posts", &token, None).await.0, 401);
+    assert_eq!(call("DELETE", &format!("/tokens/{}", token_id), &jwt, None).await.0, 404);
+    let (_, body) = call("GET", "/tokens", &jwt, None).await;
+    assert_eq!(body["tokens"], serde_json::json!([]));
+
+    // Expired tokens stop working
+    let (_, body) = call("POST", "/tokens", &jwt, Some(serde_json::json!({ "name": "old", "scopes": ["posts:read"] }))).await;
+    let token = body["token"]["token"].as_str().unwrap().to_string();
+    sqlx::query("UPDATE personal_access_tokens SET expires_at = NOW() - INTERVAL '1 minute' WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(call("GET", "/posts", &token, None).await.0, 401);
+
+    sqlx::query("DELETE FROM users WHERE id = $1").bind(user_id).execute(&pool).await.unwrap();
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::routes::api::config;
+use backend::services::mfa_service::MFAService;
+use backend::services::recovery_code_service::RecoveryCodeService;
+use backend::services::step_up_service::{StepUpMethod, StepUpService};
+use backend::utils::auth::AuthUtils;
+use backend::utils::totp::generate_totp_secret;
+
+const SECRET: &str = "test-secret";
+
+// Code for `steps` periods from now. Each code is accepted once, so later checks use the next step
+#[actix_web::test]
+async fn test_hash_ignores_case_and_dashes() {
+    let hash = RecoveryCodeService::hash(7, "ABCD-EFGH-JKLM");
+    assert_eq!(hash, RecoveryCodeService::hash(7, "abcdefghjklm"));
+    assert_eq!(hash, RecoveryCodeService::hash(7, " abcd-efgh-jklm "));
+    assert_ne!(hash, RecoveryCodeService::hash(8, "ABCD-EFGH-JKLM"));
+
+    let codes = RecoveryCodeService::generate();
+    assert_eq!(codes.len(), RecoveryCodeService::COUNT);
+    assert!(codes.iter().all(|code| code.len() == 14 && code.split('-').count() == 3));
+}
+
+#[actix_web::test]
+async fn test_recovery_codes_are_hashed_single_use_and_regenerable() {
+    let pool = common::setup_test_db().await;
+    let username = format!("recovery_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &format!("{}@example.com", username), true).await;
+    let totp_secret = generate_totp_secret().unwrap();
+    sqlx::query("UPDATE users SET totp_enabled = true WHERE id = $1")
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO totp_authenticators (user_id, label, secret, confirmed_at) VALUES ($1, 'Phone', $2, NOW())")
+        .bind(user_id)
+        .bind(&totp_secret)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+    let peer = "203.0.113.47:5000";
+    let (step_up, _) = StepUpService::issue(user_id, &token, StepUpMethod::Totp, SECRET).unwrap();
+    let regenerate = |code: &str| {
+        test::TestRequest::post()
+            .uri("/api/auth/recovery-codes/regenerate")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .insert_header(("X-Step-Up-Token", step_up.clone()))
+            .set_json(serde_json::json!({ "code": code }))
+            .to_request()
+    };
+    let remaining = || async {
+        let req = test::TestRequest::get()
+            .uri("/api/auth/me")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        body["recovery_codes_remaining"].as_u64().unwrap()
+    };
+
+    assert_eq!(remaining().await, 0);
+    assert_eq!(test::call_service(&app, regenerate("000000")).await.status(), 401);
+
+    // Regenerating needs a recent step-up as well
+    let req = test::TestRequest::post()
+        .uri("/api/auth/recovery-codes/regenerate")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "code": common::totp_code(&totp_secret, 0) }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, regenerate(&common::totp_code(&totp_secret, 0))).await;
+    let codes: Vec<String> = serde_json::from_value(body["recovery_codes"].clone()).unwrap();
+    assert_eq!(codes.len(), RecoveryCodeService::COUNT);
+    assert_eq!(remaining().await, 8);
+
+    // Only hashes are stored
+    let stored: Vec<String> = sqlx::query_scalar("SELECT recovery_codes FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert!(!stored.contains(&codes[0]));
+    assert!(stored.contains(&RecoveryCodeService::hash(user_id, &codes[0])));
+
+    let temp_token = MFAService::generate_temp_mfa_token(user_id, &username, None, SECRET).unwrap();
+    let verify = |method: &str, code: &str| {
+        test::TestRequest::post()
+            .uri("/api/auth/verify-mfa")
+            .peer_addr(peer.parse().unwrap())
+            .set_json(serde_json::json!({ "temp_token": temp_token, "method": method, "code": code }))
+            .to_request()
+    };
+
+    // Recovery codes are their own method, not TOTP codes
+    assert_eq!(test::call_service(&app, verify("totp", &codes[0])).await.status(), 401);
+
+    // Typed without dashes in lowercase
+    let typed = codes[0].replace('-', "").to_lowercase();
+    let resp = test::call_service(&app, verify("recovery_code", &typed)).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["token"].is_string());
+    assert_eq!(remaining().await, 7);
+
+    // Used up
+    assert_eq!(test::call_service(&app, verify("recovery_code", &codes[0])).await.status(), 401);
+    assert_eq!(test::call_service(&app, verify("recovery_code", "AAAA-BBBB-CCCC")).await.status(), 401);
+
+    // A new set replaces the old one
+    let body: serde_json::Value = test::call_and_read_body_json(&app, regenerate(&common::totp_code(&totp_secret, 1))).await;
+    assert_eq!(body["recovery_codes"].as_array().unwrap().len(), 8);
+    assert_eq!(test::call_service(&app, verify("recovery_code", &codes[1])).await.status(), 401);
+
+    let activity = test::TestRequest::get()
+        .uri("/api/auth/activity")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, activity).await;
+    let events = body["events"].as_array().unwrap();
+    let kinds: Vec<&str> = events.iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds.iter().filter(|kind| **kind == "recovery_codes_regenerated").count(), 2);
+    let used = events.iter().find(|e| e["kind"] == "recovery_code_used").unwrap();
+    assert_eq!(used["description"], "Recovery code used for 2FA bypass - 7 codes remaining");
+}
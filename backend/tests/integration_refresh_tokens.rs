@@ -437,3 +437,246 @@ async fn test_token_hash_consistency() {
     assert!(!hash1.is_empty());
     assert_eq!(hash1.len(), 64); // SHA256 produces 64 hex characters
 }
+
+#[actix_web::test]
+async fn test_refresh_endpoint_rotates_token() {
+    use actix_web::{test, web, App};
+    use backend::auth::refresh_tokens::refresh_token;
+
+    let pool = common::setup_test_db().await;
+    
+    let (user_id, _, _) = common::create_test_user(&pool, &format!("testuser_{}", uuid::Uuid::new_v4()), &format!("test_{}@example.com", uuid::Uuid::new_v4()), true).await;
+    
+    let token = RefreshTokenService::generate_token();
+    let _ = RefreshTokenService::create_refresh_token(&pool, user_id, &token).await;
+    
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .route("/api/auth/refresh", web::post().to(refresh_token))
+    ).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/auth/refresh")
+        .set_json(serde_json::json!({"refresh_token": token}))
+        .to_request();
+    
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let new_token = body["refresh_token"].as_str().expect("Missing refresh_token").to_string();
+    
+    assert!(body["token"].as_str().is_some());
+    assert_eq!(body["user"]["id"], user_id);
+    assert_ne!(new_token, token);
+    
+    // Old token is consumed, new token is usable
+    let old_valid = RefreshTokenService::verify_refresh_token(&pool, user_id, &token)
+        .await
+        .expect("Failed to verify");
+    let new_valid = RefreshTokenService::verify_refresh_token(&pool, user_id, &new_token)
+        .await
+        .expect("Failed to verify");
+    
+    assert!(!old_valid);
+    assert!(new_valid);
+}
+
+#[actix_web::test]
+async fn test_refresh_endpoint_replay_revokes_family() {
+    use actix_web::{test, web, App};
+    use backend::auth::refresh_tokens::refresh_token;
+
+    let pool = common::setup_test_db().await;
+    
+    let (user_id, _, _) = common::create_test_user(&pool, &format!("testuser_{}", uuid::Uuid::new_v4()), &format!("test_{}@example.com", uuid::Uuid::new_v4()), true).await;
+    
+    let token = RefreshTokenService::generate_token();
+    let _ = RefreshTokenService::create_refresh_token(&pool, user_id, &token).await;
+    
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .route("/api/auth/refresh", web::post().to(refresh_token))
+    ).await;
+    
+    // First use rotates normally
+    let req = test::TestRequest::post()
+        .uri("/api/auth/refresh")
+        .set_json(serde_json::json!({"refresh_token": token}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let new_token = body["refresh_token"].as_str().expect("Missing refresh_token").to_string();
+    
+    // Replaying the old token is rejected
+    let req = test::TestRequest::post()
+        .uri("/api/auth/refresh")
+        .set_json(serde_json::json!({"refresh_token": token}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 401);
+    
+    // The rotated token from the same family is revoked as well
+    let new_valid = RefreshTokenService::verify_refresh_token(&pool, user_id, &new_token)
+        .await
+        .expect("Failed to verify");
+    assert!(!new_valid);
+    
+    let reuse_events = sqlx::query!(
+        "SELECT COUNT(*) as count FROM audit_logs WHERE user_id = $1 AND event_type = 'REFRESH_TOKEN_REUSE'",
+        user_id
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("Failed to fetch");
+    
+    assert_eq!(reuse_events.count.unwrap_or(0), 1);
+}
+
+#[actix_web::test]
+async fn test_refresh_endpoint_unknown_token() {
+    use actix_web::{test, web, App};
+    use backend::auth::refresh_tokens::refresh_token;
+
+    let pool = common::setup_test_db().await;
+    
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .route("/api/auth/refresh", web::post().to(refresh_token))
+    ).await;
+    
+    let req = test::TestRequest::post()
+        .uri("/api/auth/refresh")
+        .set_json(serde_json::json!({"refresh_token": RefreshTokenService::generate_token()}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn test_logging_out_and_changing_the_password_revoke_refresh_tokens() {
+    use actix_web::{test, web, App};
+    use backend::auth::account::{logout_all_sessions, logout_other_sessions, logout_session};
+    use backend::auth::password::{change_password, reset_password};
+    use backend::auth::refresh_tokens::refresh_token;
+    use backend::middleware::auth::AuthMiddleware;
+    use backend::middleware::step_up::StepUpMiddleware;
+    use backend::services::email_service::EmailService;
+    use backend::services::password_policy_service::PasswordPolicy;
+    use backend::services::session_manager::{CreateSessionData, SessionManager};
+    use backend::services::step_up_service::{StepUpMethod, StepUpService};
+    use backend::utils::auth::AuthUtils;
+
+    let pool = common::setup_test_db().await;
+    let suffix = &Uuid::new_v4().simple().to_string()[..8];
+    let email = format!("revoke_{}@example.com", suffix);
+    let (user_id, username, _) = common::create_test_user(&pool, &format!("revoke_{}", suffix), &email, true).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .app_data(web::Data::new(PasswordPolicy::default()))
+            .route("/refresh", web::post().to(refresh_token))
+            .route("/password/reset", web::post().to(reset_password))
+            .route(
+                "/password/change",
+                web::post()
+                    .to(change_password)
+                    .wrap(StepUpMiddleware::max_age(StepUpService::DEFAULT_MAX_AGE_SECONDS))
+                    .wrap(AuthMiddleware::new()),
+            )
+            .route("/sessions/{id}", web::delete().to(logout_session).wrap(AuthMiddleware::new()))
+            .route("/sessions/logout-all", web::post().to(logout_all_sessions).wrap(AuthMiddleware::new()))
+            .route("/sessions/logout-others", web::post().to(logout_other_sessions).wrap(AuthMiddleware::new()))
+    ).await;
+
+    // A signed-in device: its access token, session and refresh token
+    let sign_in = async |device: &str| {
+        let access_token = AuthUtils::create_token(user_id, &format!("{}-{}", username, device), "user", "test-secret").unwrap();
+        let refresh = RefreshTokenService::generate_token();
+        let family = RefreshTokenService::create_refresh_token(&pool, user_id, &refresh).await.unwrap();
+        let session = SessionManager::create_session(&pool, CreateSessionData {
+            user_id,
+            token: access_token.clone(),
+            device_name: Some(device.to_string()),
+            ip_address: None,
+            user_agent: None,
+            refresh_token_family: Some(family),
+        })
+        .await
+        .unwrap();
+        (access_token, refresh, session.id)
+    };
+    let refresh = async |token: &str| {
+        let req = test::TestRequest::post()
+            .uri("/refresh")
+            .set_json(serde_json::json!({ "refresh_token": token }))
+            .to_request();
+        test::call_service(&app, req).await.status().as_u16()
+    };
+    let post = |uri: &str, access_token: &str, body: serde_json::Value| {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", access_token)))
+            .set_json(body)
+            .to_request()
+    };
+
+    // Logging out one session revokes only its refresh tokens
+    let (laptop, laptop_refresh, _) = sign_in("laptop").await;
+    let (_, phone_refresh, phone_session) = sign_in("phone").await;
+    let req = test::TestRequest::delete()
+        .uri(&format!("/sessions/{}", phone_session))
+        .insert_header(("Authorization", format!("Bearer {}", laptop)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    assert_eq!(refresh(&phone_refresh).await, 401);
+
+    // Logging out the other sessions keeps the current one refreshable
+    let (_, tablet_refresh, _) = sign_in("tablet").await;
+    let resp = test::call_service(&app, post("/sessions/logout-others", &laptop, serde_json::json!({}))).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(refresh(&tablet_refresh).await, 401);
+    assert_eq!(refresh(&laptop_refresh).await, 200);
+
+    // Logging out everywhere revokes everything
+    let (desktop, desktop_refresh, _) = sign_in("desktop").await;
+    let resp = test::call_service(&app, post("/sessions/logout-all", &desktop, serde_json::json!({}))).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(refresh(&desktop_refresh).await, 401);
+
+    // So does changing the password
+    let (desktop, desktop_refresh, _) = sign_in("desktop-again").await;
+    let (step_up, _) = StepUpService::issue(user_id, &desktop, StepUpMethod::Password, "test-secret").unwrap();
+    let req = test::TestRequest::post()
+        .uri("/password/change")
+        .insert_header(("Authorization", format!("Bearer {}", desktop)))
+        .insert_header(("X-Step-Up-Token", step_up))
+        .set_json(serde_json::json!({ "new_password": "Changed@5678" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    assert_eq!(refresh(&desktop_refresh).await, 401);
+
+    // ...and resetting it
+    let (_, kiosk_refresh, _) = sign_in("kiosk").await;
+    let reset_token = format!("reset-{}", suffix);
+    EmailService::store_password_reset_token(&email, &reset_token).await;
+    let req = test::TestRequest::post()
+        .uri("/password/reset")
+        .set_json(serde_json::json!({ "token": reset_token, "new_password": "Reset@567890" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    assert_eq!(refresh(&kiosk_refresh).await, 401);
+
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
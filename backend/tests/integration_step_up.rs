@@ -0,0 +1,166 @@
+mod common;
+
+use actix_web::{test, web, App, HttpResponse};
+use backend::auth::security::disable_2fa;
+use backend::auth::step_up::{start_step_up, step_up, step_up_status};
+use backend::middleware::auth::AuthMiddleware;
+use backend::middleware::step_up::StepUpMiddleware;
+use backend::services::account_lockout::AccountLockout;
+use backend::services::totp_service::TotpConfig;
+use backend::utils::auth::AuthUtils;
+use backend::utils::totp::generate_totp_secret;
+
+const SECRET: &str = "test-secret";
+
+#[actix_web::test]
+async fn test_step_up_guards_sensitive_routes() {
+    // Keep emails in the outbox instead of sending them
+    unsafe { std::env::set_var("EMAIL_PROVIDER", "memory") };
+
+    let pool = common::setup_test_db().await;
+    let username = format!("stepup_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let email = format!("{}@example.com", username);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &email, true).await;
+
+    let totp_secret = generate_totp_secret().unwrap();
+    sqlx::query("UPDATE users SET totp_enabled = true WHERE id = $1").bind(user_id).execute(&pool).await.unwrap();
+    sqlx::query("INSERT INTO totp_authenticators (user_id, label, secret, confirmed_at) VALUES ($1, 'Phone', $2, NOW())")
+        .bind(user_id)
+        .bind(&totp_secret)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .app_data(web::Data::new(TotpConfig { skew: 5, ..TotpConfig::default() }))
+            .route("/step-up", web::get().to(step_up_status).wrap(AuthMiddleware::new()))
+            .route("/step-up", web::post().to(step_up).wrap(AuthMiddleware::new()))
+            .route("/step-up/start", web::post().to(start_step_up).wrap(AuthMiddleware::new()))
+            // Asks for a step-up newer than any token can be
+            .route(
+                "/newer",
+                web::post()
+                    .to(HttpResponse::Ok)
+                    .wrap(StepUpMiddleware::max_age(-1))
+                    .wrap(AuthMiddleware::new()),
+            )
+            .route(
+                "/disable-2fa",
+                web::post().to(disable_2fa).wrap(StepUpMiddleware::max_age(300)).wrap(AuthMiddleware::new()),
+            )
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+    let post = |uri: &str, access_token: &str, step_up_token: Option<&str>, body: serde_json::Value| {
+        let mut req = test::TestRequest::post()
+            .uri(uri)
+            .peer_addr("203.0.113.50:4000".parse().unwrap())
+            .insert_header(("Authorization", format!("Bearer {}", access_token)));
+        if let Some(step_up_token) = step_up_token {
+            req = req.insert_header(("X-Step-Up-Token", step_up_token.to_string()));
+        }
+        req.set_json(body).to_request()
+    };
+
+    // Without a step-up the guarded route says how to get one
+    let resp = test::call_service(&app, post("/disable-2fa", &token, None, serde_json::json!({}))).await;
+    assert_eq!(resp.status(), 403);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["step_up_required"], true);
+    assert_eq!(body["methods"], serde_json::json!(["password", "totp", "email"]));
+
+    let resp = test::call_service(&app, post("/step-up", &token, None, serde_json::json!({"method": "password", "password": "wrong"}))).await;
+    assert_eq!(resp.status(), 401);
+    let resp = test::call_service(&app, post("/step-up", &token, None, serde_json::json!({"method": "passkey"}))).await;
+    assert_eq!(resp.status(), 400);
+
+    let resp = test::call_service(&app, post("/step-up", &token, None, serde_json::json!({"method": "password", "password": "Test@1234"}))).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["method"], "password");
+    let step_up_token = body["step_up_token"].as_str().unwrap().to_string();
+
+    // The token only counts with the access token it was issued for, and only while recent enough
+    let other_session = AuthUtils::create_token(user_id, &format!("{}-other", username), "user", SECRET).unwrap();
+    assert_ne!(other_session, token);
+    let resp = test::call_service(&app, post("/disable-2fa", &other_session, Some(&step_up_token), serde_json::json!({}))).await;
+    assert_eq!(resp.status(), 403);
+    let resp = test::call_service(&app, post("/newer", &token, Some(&step_up_token), serde_json::json!({}))).await;
+    assert_eq!(resp.status(), 403);
+
+    let req = test::TestRequest::get()
+        .uri("/step-up")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .insert_header(("X-Step-Up-Token", step_up_token.clone()))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["elevated"]["method"], "password");
+
+    // Emailed codes work once
+    let resp = test::call_service(&app, post("/step-up/start", &token, None, serde_json::json!({"method": "email"}))).await;
+    assert_eq!(resp.status(), 200);
+    let text: String = sqlx::query_scalar("SELECT text_body FROM email_outbox WHERE recipient = $1 ORDER BY id DESC LIMIT 1")
+        .bind(&email)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    let code = text.split_whitespace().find(|word| word.len() == 6 && word.chars().all(|c| c.is_ascii_digit())).unwrap();
+    let resp = test::call_service(&app, post("/step-up", &token, None, serde_json::json!({"method": "email", "code": code}))).await;
+    assert_eq!(resp.status(), 200);
+    let resp = test::call_service(&app, post("/step-up", &token, None, serde_json::json!({"method": "email", "code": code}))).await;
+    assert_eq!(resp.status(), 401);
+
+    // A TOTP step-up lets 2FA be turned off
+    let resp = test::call_service(&app, post("/step-up", &token, None, serde_json::json!({"method": "totp", "code": common::totp_code(&totp_secret, 0)}))).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let step_up_token = body["step_up_token"].as_str().unwrap();
+    let resp = test::call_service(&app, post("/disable-2fa", &token, Some(step_up_token), serde_json::json!({}))).await;
+    assert_eq!(resp.status(), 200);
+
+    let totp_enabled: Option<bool> = sqlx::query_scalar("SELECT totp_enabled FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(totp_enabled, Some(false));
+
+    sqlx::query("DELETE FROM email_outbox WHERE recipient = $1").bind(&email).execute(&pool).await.unwrap();
+    sqlx::query("DELETE FROM users WHERE id = $1").bind(user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_password_step_up_stops_once_the_account_is_locked() {
+    let pool = common::setup_test_db().await;
+    let username = format!("stepuplock_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &format!("{}@example.com", username), true).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .route("/step-up", web::post().to(step_up).wrap(AuthMiddleware::new()))
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+    let attempt = |password: &str| {
+        test::TestRequest::post()
+            .uri("/step-up")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(serde_json::json!({"method": "password", "password": password}))
+            .to_request()
+    };
+
+    for _ in 0..AccountLockout::MAX_ATTEMPTS {
+        assert_eq!(test::call_service(&app, attempt("wrong")).await.status(), 401);
+    }
+
+    // Even the right password is refused while locked
+    let resp = test::call_service(&app, attempt("Test@1234")).await;
+    assert_eq!(resp.status(), 403);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["locked"], true);
+
+    sqlx::query("DELETE FROM users WHERE id = $1").bind(user_id).execute(&pool).await.unwrap();
+}
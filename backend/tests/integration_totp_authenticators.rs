@@ -0,0 +1,114 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::routes::api::config;
+use backend::services::step_up_service::{StepUpMethod, StepUpService};
+use backend::services::totp_service::TotpConfig;
+use backend::utils::auth::AuthUtils;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use totp_rs::Algorithm;
+
+const SECRET: &str = "test-secret";
+
+// Code for `steps` periods from now, with the parameters configured below
+#[actix_web::test]
+async fn test_codes_are_single_use_and_apps_can_be_added() {
+    let pool = common::setup_test_db().await;
+    let username = format!("totpapps_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &format!("{}@example.com", username), true).await;
+
+    // A wide skew leaves room for several steps, as each one is accepted once
+    let totp_config = TotpConfig {
+        issuer: "Acme".to_string(),
+        algorithm: Algorithm::SHA256,
+        digits: 8,
+        skew: 3,
+        ..TotpConfig::default()
+    };
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .app_data(web::Data::new(totp_config))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+    // Adding and removing apps, and regenerating codes, need a recent step-up
+    let (step_up, _) = StepUpService::issue(user_id, &token, StepUpMethod::Password, SECRET).unwrap();
+    let request = |method: &str, uri: &str, body: serde_json::Value| {
+        let req = match method {
+            "GET" => test::TestRequest::get(),
+            "PUT" => test::TestRequest::put(),
+            "DELETE" => test::TestRequest::delete(),
+            _ => test::TestRequest::post(),
+        };
+        req.uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .insert_header(("X-Step-Up-Token", step_up.clone()))
+            .set_json(body)
+            .to_request()
+    };
+    let regenerate = |code: String| request("POST", "/api/auth/recovery-codes/regenerate", serde_json::json!({ "code": code }));
+
+    let setup: serde_json::Value =
+        test::call_and_read_body_json(&app, request("POST", "/api/auth/setup-2fa", serde_json::json!({}))).await;
+    let first = setup["secret"].as_str().unwrap().to_string();
+    let first_id = setup["authenticator"]["id"].as_i64().unwrap();
+    let qr_code_url = setup["qr_code_url"].as_str().unwrap();
+    assert!(qr_code_url.starts_with(&format!("otpauth://totp/Acme:{}?", username)));
+    assert!(qr_code_url.contains("algorithm=SHA256") && qr_code_url.contains("digits=8"));
+    let png = STANDARD.decode(setup["qr_code_png"].as_str().unwrap()).unwrap();
+    assert!(png.starts_with(b"\x89PNG"));
+
+    // Each code works once, and not after a later one was used
+    let code = common::totp_code_with(Algorithm::SHA256, 8, &first, -3);
+    let resp = test::call_service(&app, request("POST", "/api/auth/verify-2fa", serde_json::json!({ "code": code }))).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(test::call_service(&app, regenerate(code)).await.status(), 401);
+    assert_eq!(test::call_service(&app, regenerate(common::totp_code_with(Algorithm::SHA256, 8, &first, -2))).await.status(), 200);
+    assert_eq!(test::call_service(&app, regenerate(common::totp_code_with(Algorithm::SHA256, 8, &first, -3))).await.status(), 401);
+
+    // A second app needs a code from the first
+    let add = |code: String| request("POST", "/api/auth/totp/authenticators", serde_json::json!({ "label": "Backup phone", "code": code }));
+    assert_eq!(test::call_service(&app, add("00000000".to_string())).await.status(), 401);
+    let resp = test::call_service(&app, add(common::totp_code_with(Algorithm::SHA256, 8, &first, -1))).await;
+    assert_eq!(resp.status(), 201);
+    let enrollment: serde_json::Value = test::read_body_json(resp).await;
+    let second = enrollment["secret"].as_str().unwrap().to_string();
+    let second_id = enrollment["authenticator"]["id"].as_i64().unwrap();
+    assert_eq!(enrollment["authenticator"]["label"], "Backup phone");
+    assert!(enrollment["authenticator"]["confirmed_at"].is_null());
+
+    // Not usable until confirmed
+    assert_eq!(test::call_service(&app, regenerate(common::totp_code_with(Algorithm::SHA256, 8, &second, 0))).await.status(), 401);
+    let confirm = |id: i64, code: String| request("POST", &format!("/api/auth/totp/authenticators/{}/confirm", id), serde_json::json!({ "code": code }));
+    assert_eq!(test::call_service(&app, confirm(second_id, common::totp_code_with(Algorithm::SHA256, 8, &first, 0))).await.status(), 401);
+    assert_eq!(test::call_service(&app, confirm(first_id, common::totp_code_with(Algorithm::SHA256, 8, &first, 0))).await.status(), 404);
+    assert_eq!(test::call_service(&app, confirm(second_id, common::totp_code_with(Algorithm::SHA256, 8, &second, 0))).await.status(), 200);
+
+    // Steps are tracked per app
+    assert_eq!(test::call_service(&app, regenerate(common::totp_code_with(Algorithm::SHA256, 8, &second, 0))).await.status(), 401);
+    assert_eq!(test::call_service(&app, regenerate(common::totp_code_with(Algorithm::SHA256, 8, &first, 0))).await.status(), 200);
+
+    let list: serde_json::Value =
+        test::call_and_read_body_json(&app, request("GET", "/api/auth/totp/authenticators", serde_json::json!({}))).await;
+    let authenticators = list["authenticators"].as_array().unwrap();
+    assert_eq!(authenticators.len(), 2);
+    assert!(authenticators.iter().all(|a| a.get("secret").is_none() && !a["confirmed_at"].is_null()));
+    assert_eq!(authenticators[0]["algorithm"], "SHA256");
+
+    let rename = request("PUT", &format!("/api/auth/totp/authenticators/{}", second_id), serde_json::json!({ "label": "Tablet" }));
+    let body: serde_json::Value = test::call_and_read_body_json(&app, rename).await;
+    assert_eq!(body["authenticator"]["label"], "Tablet");
+
+    // The last one can only go by disabling 2FA
+    let remove = |id: i64| request("DELETE", &format!("/api/auth/totp/authenticators/{}", id), serde_json::json!({}));
+    assert_eq!(test::call_service(&app, remove(first_id)).await.status(), 200);
+    assert_eq!(test::call_service(&app, remove(second_id)).await.status(), 409);
+
+    let activity: serde_json::Value =
+        test::call_and_read_body_json(&app, request("GET", "/api/auth/activity", serde_json::json!({}))).await;
+    let kinds: Vec<&str> = activity["events"].as_array().unwrap().iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert!(kinds.contains(&"authenticator_added") && kinds.contains(&"authenticator_removed"));
+}
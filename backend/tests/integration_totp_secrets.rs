@@ -0,0 +1,135 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::routes::api::config;
+use backend::services::totp_secret_service::{TotpKeyring, TotpSecretError};
+use backend::services::totp_service::TotpService;
+use backend::utils::auth::AuthUtils;
+use backend::utils::totp::generate_totp_secret;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+const SECRET: &str = "test-secret";
+
+// Fixed keys, so rows left behind by earlier runs stay readable
+fn keyring(ids: &[&str]) -> TotpKeyring {
+    let spec: Vec<String> = ids
+        .iter()
+        .map(|id| format!("{}:{}", id, STANDARD.encode([id.bytes().fold(0u8, u8::wrapping_add); 32])))
+        .collect();
+    TotpKeyring::parse(&spec.join(",")).unwrap()
+}
+
+#[actix_web::test]
+async fn test_keyring_parsing_and_envelope() {
+    let key = STANDARD.encode([7u8; 32]);
+    let parsed = TotpKeyring::parse(&format!("# rotated 2025-12\nnew:{}\n\nold:{}", key, key)).unwrap();
+    assert_eq!(parsed.current().unwrap().id, "new");
+    assert!(TotpKeyring::parse("").unwrap().current().is_none());
+    assert!(TotpKeyring::parse(&format!("a:{},a:{}", key, key)).is_err());
+    assert!(TotpKeyring::parse("short:AAAA").is_err());
+    assert!(TotpKeyring::parse(&key).is_err());
+
+    let secret = generate_totp_secret().unwrap();
+    let (stored, key_id) = parsed.encrypt(1, &secret).unwrap();
+    assert_eq!(key_id.as_deref(), Some("new"));
+    assert!(!stored.contains(&secret));
+    assert_eq!(parsed.decrypt(1, &stored, Some("new")).unwrap(), secret);
+
+    // Bound to the user and the key it was written for
+    assert!(matches!(parsed.decrypt(2, &stored, Some("new")), Err(TotpSecretError::Corrupt)));
+    assert!(matches!(parsed.decrypt(1, &stored, Some("old")), Err(TotpSecretError::Corrupt)));
+    assert!(matches!(keyring(&["x"]).decrypt(1, &stored, Some("new")), Err(TotpSecretError::UnknownKey(_))));
+
+    // No key configured: plaintext, as before
+    let (stored, key_id) = TotpKeyring::default().encrypt(1, &secret).unwrap();
+    assert_eq!((stored.as_str(), key_id), (secret.as_str(), None));
+}
+
+#[actix_web::test]
+async fn test_secrets_are_encrypted_at_rest_and_rotated() {
+    let pool = common::setup_test_db().await;
+    let username = format!("totpkey_{}", &uuid::Uuid::new_v4().to_string()[..8]);
+    let (user_id, username, _) = common::create_test_user(&pool, &username, &format!("{}@example.com", username), true).await;
+    let stored_secret = |user_id: i32| {
+        let pool = pool.clone();
+        async move {
+            sqlx::query_as::<_, (String, Option<String>)>("SELECT secret, key_id FROM totp_authenticators WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        }
+    };
+    let load = |keyring: TotpKeyring, user_id: i32| {
+        async move {
+            let (stored, key_id) = stored_secret(user_id).await;
+            keyring.decrypt(user_id, &stored, key_id.as_deref())
+        }
+    };
+
+    let old_keys = keyring(&["test-old"]);
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(SECRET.to_string()))
+            .app_data(web::Data::new(old_keys.clone()))
+            .configure(config)
+    ).await;
+    let token = AuthUtils::create_token(user_id, &username, "user", SECRET).unwrap();
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/setup-2fa")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let secret = body["secret"].as_str().unwrap().to_string();
+
+    let (stored, key_id) = stored_secret(user_id).await;
+    assert_eq!(key_id.as_deref(), Some("test-old"));
+    assert!(!stored.contains(&secret));
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/verify-2fa")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "code": common::totp_code(&secret, 0) }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    // The debug endpoint (admins only) no longer hands out codes
+    #[cfg(feature = "debug-endpoints")]
+    {
+        let admin_token = AuthUtils::create_token(user_id, &username, "admin", SECRET).unwrap();
+        let req = test::TestRequest::get()
+            .uri("/api/auth/debug-2fa")
+            .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["totp_enabled"], true);
+        assert!(body.get("current_valid_code").is_none());
+        assert!(!body.to_string().contains(&secret));
+    }
+
+    // A plaintext secret from before encryption was configured
+    let (legacy_id, _, _) = common::create_test_user(&pool, &format!("{}_l", username), &format!("{}_l@example.com", username), true).await;
+    let legacy_secret = generate_totp_secret().unwrap();
+    sqlx::query("INSERT INTO totp_authenticators (user_id, label, secret, confirmed_at) VALUES ($1, 'Phone', $2, NOW())")
+        .bind(legacy_id)
+        .bind(&legacy_secret)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(load(old_keys.clone(), legacy_id).await.unwrap(), legacy_secret);
+
+    // Rotate: the new key goes first, the old one stays until everything is re-encrypted
+    assert!(matches!(TotpService::reencrypt_all(&pool, &TotpKeyring::default()).await, Err(TotpSecretError::NoMasterKey)));
+    let new_keys = keyring(&["test-new", "test-old"]);
+    assert!(TotpService::reencrypt_all(&pool, &new_keys).await.unwrap() >= 2);
+    assert_eq!(TotpService::reencrypt_all(&pool, &new_keys).await.unwrap(), 0);
+
+    assert_eq!(stored_secret(user_id).await.1.as_deref(), Some("test-new"));
+    let only_new = keyring(&["test-new"]);
+    assert_eq!(load(only_new.clone(), user_id).await.unwrap(), secret);
+    assert_eq!(load(only_new, legacy_id).await.unwrap(), legacy_secret);
+    assert!(matches!(load(old_keys, user_id).await, Err(TotpSecretError::UnknownKey(_))));
+}
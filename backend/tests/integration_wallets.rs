@@ -0,0 +1,362 @@
+mod common;
+
+use actix_web::{test, web, App};
+use backend::routes::api::config;
+use backend::services::siwe::{SiweConfig, SiweMessage};
+use backend::services::step_up_service::{StepUpMethod, StepUpService};
+use ethers::signers::{LocalWallet, Signer};
+
+fn challenge_request(peer: &str, address: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/api/auth/web3/challenge")
+        .peer_addr(peer.parse().unwrap())
+        .set_json(serde_json::json!({ "address": address }))
+}
+
+/// Sign the challenge message from a /web3/challenge response, returns (challenge, signature)
+async fn sign(signer: &LocalWallet, response: serde_json::Value) -> (String, String) {
+    let challenge = response["challenge"].as_str().unwrap().to_string();
+    let signature = signer.sign_message(&challenge).await.unwrap();
+    (challenge, format!("0x{}", signature))
+}
+
+#[actix_web::test]
+async fn test_wallet_login_and_connect_require_signatures() {
+    let pool = common::setup_test_db().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+    let peer = "203.0.113.41:5000";
+    let first = LocalWallet::new(&mut rand::thread_rng());
+    let second = LocalWallet::new(&mut rand::thread_rng());
+    let first_address = format!("{:?}", first.address());
+    let second_address = format!("{:?}", second.address());
+
+    let verify = |address: &str, challenge: &str, signature: &str| {
+        test::TestRequest::post()
+            .uri("/api/auth/web3/verify")
+            .peer_addr(peer.parse().unwrap())
+            .set_json(serde_json::json!({ "address": address, "challenge": challenge, "signature": signature }))
+            .to_request()
+    };
+
+    // Signed by someone else
+    let (challenge, signature) = sign(&second, test::call_and_read_body_json(&app, challenge_request(peer, &first_address).to_request()).await).await;
+    let resp = test::call_service(&app, verify(&first_address, &challenge, &signature)).await;
+    assert_eq!(resp.status(), 400);
+
+    // First sign-in creates the account
+    let (challenge, signature) = sign(&first, test::call_and_read_body_json(&app, challenge_request(peer, &first_address).to_request()).await).await;
+    let body: serde_json::Value = test::call_and_read_body_json(&app, verify(&first_address, &challenge, &signature)).await;
+    let token = body["token"].as_str().unwrap().to_string();
+    // Connecting and removing wallets need a recent step-up
+    let (step_up, _) = StepUpService::issue(body["user"]["id"].as_i64().unwrap() as i32, &token, StepUpMethod::Wallet, "test-secret").unwrap();
+
+    // The challenge is single use
+    let resp = test::call_service(&app, verify(&first_address, &challenge, &signature)).await;
+    assert_eq!(resp.status(), 400);
+
+    let connect = |address: &str, challenge: &str, signature: &str| {
+        test::TestRequest::post()
+            .uri("/api/auth/connect-wallet")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .insert_header(("X-Step-Up-Token", step_up.clone()))
+            .set_json(serde_json::json!({
+                "address": address,
+                "challenge": challenge,
+                "signature": signature,
+                "label": "Hardware wallet"
+            }))
+            .to_request()
+    };
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/connect-wallet")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "address": second_address, "challenge": "", "signature": "" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    let (challenge, signature) = sign(&first, test::call_and_read_body_json(&app, challenge_request(peer, &second_address).to_request()).await).await;
+    assert_eq!(test::call_service(&app, connect(&second_address, &challenge, &signature)).await.status(), 400);
+
+    // Checksummed (mixed case) spelling of the same address
+    let checksummed = ethers::utils::to_checksum(&second.address(), None);
+    let (challenge, signature) = sign(&second, test::call_and_read_body_json(&app, challenge_request(peer, &checksummed).to_request()).await).await;
+    let resp = test::call_service(&app, connect(&checksummed, &challenge, &signature)).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["wallet"]["address"], second_address.as_str());
+    assert_eq!(body["wallet"]["label"], "Hardware wallet");
+    assert_eq!(body["wallet"]["is_primary"], false);
+
+    // Either wallet signs into the same account
+    let (challenge, signature) = sign(&second, test::call_and_read_body_json(&app, challenge_request(peer, &second_address).to_request()).await).await;
+    let body: serde_json::Value = test::call_and_read_body_json(&app, verify(&second_address, &challenge, &signature)).await;
+    let second_token = body["token"].as_str().unwrap().to_string();
+
+    let req = test::TestRequest::get()
+        .uri("/api/auth/wallets")
+        .insert_header(("Authorization", format!("Bearer {}", second_token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let wallets = body["wallets"].as_array().unwrap();
+    assert_eq!(wallets.len(), 2);
+    assert_eq!(wallets[0]["address"], first_address.as_str());
+    assert_eq!(wallets[0]["is_primary"], true);
+    assert!(wallets[1]["last_used_at"].is_string());
+    let user_id = wallets[0]["user_id"].as_i64().unwrap() as i32;
+    let (first_id, second_id) = (wallets[0]["id"].as_i64().unwrap(), wallets[1]["id"].as_i64().unwrap());
+
+    let primary = || async {
+        sqlx::query_scalar!("SELECT wallet_address FROM users WHERE id = $1", user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+    };
+    assert_eq!(primary().await.as_deref(), Some(first_address.as_str()));
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/auth/wallets/{}", second_id))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(serde_json::json!({ "primary": true, "label": "Main" }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["wallet"]["is_primary"], true);
+    assert_eq!(body["wallet"]["label"], "Main");
+    assert_eq!(primary().await.as_deref(), Some(second_address.as_str()));
+
+    let remove = |id: i64| {
+        test::TestRequest::delete()
+            .uri(&format!("/api/auth/wallets/{}", id))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .insert_header(("X-Step-Up-Token", step_up.clone()))
+            .to_request()
+    };
+    assert_eq!(test::call_service(&app, remove(second_id)).await.status(), 200);
+    assert_eq!(primary().await.as_deref(), Some(first_address.as_str()));
+
+    // The remaining wallet is the only way in
+    assert_eq!(test::call_service(&app, remove(first_id)).await.status(), 409);
+
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_wallet_belongs_to_one_account() {
+    let pool = common::setup_test_db().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+    let peer = "203.0.113.42:5000";
+    let suffix = &uuid::Uuid::new_v4().simple().to_string()[..12];
+    let (user_id, username, _) = common::create_test_user(&pool, &format!("wallet_{}", suffix), &format!("wallet_{}@example.com", suffix), true).await;
+    let token = backend::utils::auth::AuthUtils::create_token(user_id, &username, "user", "test-secret").unwrap();
+    let (step_up, _) = StepUpService::issue(user_id, &token, StepUpMethod::Password, "test-secret").unwrap();
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let address = format!("{:?}", wallet.address());
+
+    // Claimed by a wallet sign-in first
+    let (challenge, signature) = sign(&wallet, test::call_and_read_body_json(&app, challenge_request(peer, &address).to_request()).await).await;
+    let req = test::TestRequest::post()
+        .uri("/api/auth/web3/verify")
+        .peer_addr(peer.parse().unwrap())
+        .set_json(serde_json::json!({ "address": address, "challenge": challenge, "signature": signature }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    let (challenge, signature) = sign(&wallet, test::call_and_read_body_json(&app, challenge_request(peer, &address).to_request()).await).await;
+    let req = test::TestRequest::post()
+        .uri("/api/auth/connect-wallet")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .insert_header(("X-Step-Up-Token", step_up.clone()))
+        .set_json(serde_json::json!({ "address": address, "challenge": challenge, "signature": signature }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // Setting an address without a signature is no longer possible
+    let profile = |step_up_token: Option<&str>| {
+        let mut req = test::TestRequest::put()
+            .uri("/api/auth/profile")
+            .insert_header(("Authorization", format!("Bearer {}", token)));
+        if let Some(step_up_token) = step_up_token {
+            req = req.insert_header(("X-Step-Up-Token", step_up_token.to_string()));
+        }
+        req.set_json(serde_json::json!({ "wallet_address": address })).to_request()
+    };
+    assert_eq!(test::call_service(&app, profile(None)).await.status(), 403);
+    assert_eq!(test::call_service(&app, profile(Some(&step_up))).await.status(), 400);
+
+    let owner = sqlx::query_scalar!("SELECT user_id FROM user_wallets WHERE address = $1", address)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_ne!(owner, user_id);
+
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[user_id, owner][..]).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_sign_in_message_must_be_for_this_site_and_chain() {
+    let pool = common::setup_test_db().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .app_data(web::Data::new(SiweConfig {
+                domain: "app.example.com".to_string(),
+                uri: "https://app.example.com".to_string(),
+                chain_ids: vec![11155111, 1],
+                solana_chain_ids: vec!["devnet".to_string()],
+                statement: None,
+                ttl_seconds: 300,
+            }))
+            .configure(config)
+    ).await;
+    let peer = "203.0.113.43:5000";
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let address = format!("{:?}", wallet.address());
+    let verify = |challenge: &str, signature: &str| {
+        test::TestRequest::post()
+            .uri("/api/auth/web3/verify")
+            .peer_addr(peer.parse().unwrap())
+            .set_json(serde_json::json!({ "address": address, "challenge": challenge, "signature": signature }))
+            .to_request()
+    };
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/web3/challenge")
+        .peer_addr(peer.parse().unwrap())
+        .set_json(serde_json::json!({ "address": address, "chain_id": 137 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let body: serde_json::Value = test::call_and_read_body_json(&app, challenge_request(peer, &address).to_request()).await;
+    let message = body["challenge"].as_str().unwrap().to_string();
+    let parsed: SiweMessage = message.parse().unwrap();
+    assert_eq!(parsed.domain, "app.example.com");
+    assert_eq!(parsed.chain_id, "11155111");
+    assert_eq!(parsed.nonce, body["nonce"].as_str().unwrap());
+
+    // Validly signed, but for another site or chain
+    for tampered in [
+        message.replacen("app.example.com", "evil.example.com", 1),
+        message.replace("Chain ID: 11155111", "Chain ID: 137"),
+    ] {
+        let signature = wallet.sign_message(&tampered).await.unwrap();
+        assert_eq!(test::call_service(&app, verify(&tampered, &format!("0x{}", signature))).await.status(), 400);
+    }
+
+    // A nonce we never issued
+    let forged = message.replace(&parsed.nonce, "ForgedNonce12345");
+    let signature = wallet.sign_message(&forged).await.unwrap();
+    assert_eq!(test::call_service(&app, verify(&forged, &format!("0x{}", signature))).await.status(), 400);
+
+    let signature = wallet.sign_message(&message).await.unwrap();
+    assert_eq!(test::call_service(&app, verify(&message, &format!("0x{}", signature))).await.status(), 200);
+
+    let user_id = sqlx::query_scalar!("SELECT user_id FROM user_wallets WHERE address = $1", address)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_solana_wallet_sign_in() {
+    use ed25519_dalek::{Signer as _, SigningKey};
+    use rand::Rng;
+
+    let pool = common::setup_test_db().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+    let peer = "203.0.113.44:5000";
+    let key = SigningKey::from_bytes(&rand::thread_rng().r#gen());
+    let address = bs58::encode(key.verifying_key().as_bytes()).into_string();
+
+    // A Solana address is not an Ethereum one
+    let resp = test::call_service(&app, challenge_request(peer, &address).to_request()).await;
+    assert_eq!(resp.status(), 400);
+
+    let req = test::TestRequest::post()
+        .uri("/api/auth/web3/challenge")
+        .peer_addr(peer.parse().unwrap())
+        .set_json(serde_json::json!({ "address": address, "chain": "solana" }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["chain"], "solana");
+    let message = body["challenge"].as_str().unwrap();
+    assert!(message.contains("wants you to sign in with your Solana account:\n"));
+
+    let signature = bs58::encode(key.sign(message.as_bytes()).to_bytes()).into_string();
+    let req = test::TestRequest::post()
+        .uri("/api/auth/web3/verify")
+        .peer_addr(peer.parse().unwrap())
+        .set_json(serde_json::json!({ "address": address, "challenge": message, "signature": signature }))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let token = body["token"].as_str().unwrap();
+
+    let req = test::TestRequest::get()
+        .uri("/api/auth/wallets")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    // Base58 keeps its case
+    assert_eq!(body["wallets"][0]["address"], address.as_str());
+    assert_eq!(body["wallets"][0]["chain"], "solana");
+
+    let user_id = body["wallets"][0]["user_id"].as_i64().unwrap() as i32;
+    sqlx::query!("DELETE FROM users WHERE id = $1", user_id).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_wallet_login_issues_a_full_session_and_avoids_taken_usernames() {
+    let pool = common::setup_test_db().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new("test-secret".to_string()))
+            .configure(config)
+    ).await;
+    let peer = "203.0.113.45:5000";
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let address = format!("{:?}", wallet.address());
+
+    // Someone already has the username the wallet would get
+    let taken = format!("user_{}", &address[address.len() - 8..]);
+    let (squatter_id, _, _) = common::create_test_user(&pool, &taken, &format!("{}@example.com", taken), true).await;
+
+    let (challenge, signature) = sign(&wallet, test::call_and_read_body_json(&app, challenge_request(peer, &address).to_request()).await).await;
+    let req = test::TestRequest::post()
+        .uri("/api/auth/web3/verify")
+        .peer_addr(peer.parse().unwrap())
+        .set_json(serde_json::json!({ "address": address, "challenge": challenge, "signature": signature }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["token"].is_string());
+    assert!(body["refresh_token"].is_string());
+    let username = body["user"]["username"].as_str().unwrap();
+    assert!(username.starts_with(&format!("{}_", taken)), "{}", username);
+
+    let user_id = body["user"]["id"].as_i64().unwrap() as i32;
+    let sessions = sqlx::query_scalar!("SELECT COUNT(*) FROM sessions WHERE user_id = $1", user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions, Some(1));
+
+    sqlx::query!("DELETE FROM users WHERE id = ANY($1)", &[user_id, squatter_id][..]).execute(&pool).await.unwrap();
+}
@@ -0,0 +1,144 @@
+#[cfg(test)]
+mod tests {
+    use backend::services::siwe::{SiweConfig, SiweError, SiweMessage};
+    use backend::services::wallet_chains::WalletChain;
+    use chrono::{Duration, TimeZone, Utc};
+
+    // The example message from EIP-4361
+    const EXAMPLE: &str = "example.com wants you to sign in with your Ethereum account:
+0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2
+
+I accept the ExampleOrg Terms of Service: https://example.com/tos
+
+URI: https://example.com/login
+Version: 1
+Chain ID: 1
+Nonce: 32891756
+Issued At: 2021-09-30T16:25:24Z
+Resources:
+- ipfs://bafybeiemxf5abjwjbikoz4mc3a3dla6ual3jsgpdr4cjr3oz3evfyavhwq/
+- https://example.com/my-web2-claim.json";
+
+    fn config() -> SiweConfig {
+        SiweConfig {
+            domain: "app.example.com".to_string(),
+            uri: "https://app.example.com".to_string(),
+            chain_ids: vec![1, 11155111],
+            solana_chain_ids: vec!["devnet".to_string()],
+            statement: Some("Sign in to USH with your wallet.".to_string()),
+            ttl_seconds: 300,
+        }
+    }
+
+    fn issued() -> SiweMessage {
+        let address = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+        config().message(WalletChain::Ethereum, address, "11155111", "abcdef1234567890", Utc::now()).unwrap()
+    }
+
+    #[test]
+    fn test_parses_the_spec_example() {
+        let message: SiweMessage = EXAMPLE.parse().unwrap();
+
+        assert_eq!(message.chain, WalletChain::Ethereum);
+        assert_eq!(message.domain, "example.com");
+        assert_eq!(message.address, "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        assert_eq!(message.statement.as_deref(), Some("I accept the ExampleOrg Terms of Service: https://example.com/tos"));
+        assert_eq!(message.uri, "https://example.com/login");
+        assert_eq!(message.chain_id, "1");
+        assert_eq!(message.nonce, "32891756");
+        assert_eq!(message.issued_at, Utc.with_ymd_and_hms(2021, 9, 30, 16, 25, 24).unwrap());
+        assert_eq!(message.expiration_time, None);
+        assert_eq!(message.resources.len(), 2);
+    }
+
+    #[test]
+    fn test_issued_messages_round_trip() {
+        let message = issued();
+        let text = message.to_string();
+
+        assert!(text.starts_with("app.example.com wants you to sign in with your Ethereum account:\n0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2\n\n"));
+        assert!(text.contains("\nChain ID: 11155111\n"));
+        assert!(text.contains("\nExpiration Time: "));
+
+        let parsed: SiweMessage = text.parse().unwrap();
+        assert_eq!(parsed.nonce, message.nonce);
+        assert_eq!(parsed.expiration_time.unwrap().timestamp(), message.expiration_time.unwrap().timestamp());
+        assert!(config().validate(&parsed, Utc::now()).is_ok());
+    }
+
+    #[test]
+    fn test_solana_messages() {
+        let config = config();
+        let address = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+        let message = config.message(WalletChain::Solana, address, "devnet", "abcdef1234567890", Utc::now()).unwrap();
+        let text = message.to_string();
+
+        assert!(text.starts_with("app.example.com wants you to sign in with your Solana account:\n9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM\n"));
+        let parsed: SiweMessage = text.parse().unwrap();
+        assert_eq!(parsed.chain, WalletChain::Solana);
+        assert_eq!(parsed.chain_id, "devnet");
+        assert!(config.validate(&parsed, Utc::now()).is_ok());
+
+        // Clusters are per chain
+        let mut mainnet = parsed.clone();
+        mainnet.chain_id = "mainnet".to_string();
+        assert!(config.validate(&mainnet, Utc::now()).is_err());
+        let mut numbered = parsed;
+        numbered.chain_id = "11155111".to_string();
+        assert!(config.validate(&numbered, Utc::now()).is_err());
+
+        assert!(config.message(WalletChain::Solana, "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", "devnet", "abcdef1234567890", Utc::now()).is_err());
+        assert!(text.replace("Solana account", "Bitcoin account").parse::<SiweMessage>().is_err());
+    }
+
+    #[test]
+    fn test_rejects_malformed_messages() {
+        let malformed = |text: &str| matches!(text.parse::<SiweMessage>(), Err(SiweError::Malformed(_)));
+
+        assert!(malformed("Welcome to USH!\n\nPlease sign this message"));
+        // Lowercase address is not EIP-55
+        assert!(malformed(&EXAMPLE.replace("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2")));
+        assert!(malformed(&EXAMPLE.replace("Chain ID: 1", "Chain ID: one")));
+        assert!(malformed(&EXAMPLE.replace("Issued At: 2021-09-30T16:25:24Z", "Issued At: yesterday")));
+        assert!(malformed(&EXAMPLE.replace("\nNonce: 32891756", "")));
+        assert!(malformed(&format!("{}\nextra", EXAMPLE)));
+    }
+
+    #[test]
+    fn test_validation_checks_domain_chain_and_time() {
+        let config = config();
+        let invalid = |message: &SiweMessage| matches!(config.validate(message, Utc::now()), Err(SiweError::Invalid(_)));
+
+        let mut message = issued();
+        message.domain = "evil.example.com".to_string();
+        assert!(invalid(&message));
+
+        let mut message = issued();
+        message.uri = "https://evil.example.com".to_string();
+        assert!(invalid(&message));
+
+        let mut message = issued();
+        message.chain_id = "137".to_string();
+        assert!(invalid(&message));
+
+        let mut message = issued();
+        message.nonce = "short".to_string();
+        assert!(invalid(&message));
+
+        let mut message = issued();
+        message.expiration_time = Some(Utc::now() - Duration::seconds(1));
+        assert!(invalid(&message));
+
+        let mut message = issued();
+        message.expiration_time = None;
+        assert!(invalid(&message));
+
+        let mut message = issued();
+        message.issued_at = Utc::now() + Duration::minutes(10);
+        assert!(invalid(&message));
+
+        let mut message = issued();
+        message.not_before = Some(Utc::now() + Duration::minutes(10));
+        assert!(invalid(&message));
+    }
+}
@@ -0,0 +1,69 @@
+#[cfg(test)]
+mod tests {
+    use backend::services::wallet_chains::WalletChain;
+    use ed25519_dalek::{Signer as _, SigningKey};
+    use ethers::signers::{LocalWallet, Signer as _};
+    use ethers::utils::hash_message;
+    use rand::Rng;
+
+    const MESSAGE: &str = "app.example.com wants you to sign in with your account";
+
+    fn solana_keypair() -> (SigningKey, String) {
+        let key = SigningKey::from_bytes(&rand::thread_rng().r#gen());
+        let address = bs58::encode(key.verifying_key().as_bytes()).into_string();
+        (key, address)
+    }
+
+    #[test]
+    fn test_ethereum_personal_sign() {
+        let verifier = WalletChain::Ethereum.verifier();
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let other = LocalWallet::new(&mut rand::thread_rng());
+        let address = format!("{:?}", wallet.address());
+        let signature = format!("0x{}", wallet.sign_hash(hash_message(MESSAGE)).unwrap());
+
+        assert!(verifier.verify_signature(&address, MESSAGE, &signature).is_ok());
+        assert!(verifier.verify_signature(&address.to_uppercase().replace("0X", "0x"), MESSAGE, &signature).is_ok());
+        assert!(verifier.verify_signature(&address, "another message", &signature).is_err());
+        assert!(verifier.verify_signature(&format!("{:?}", other.address()), MESSAGE, &signature).is_err());
+        assert!(verifier.verify_signature(&address, MESSAGE, "0x1234").is_err());
+    }
+
+    #[test]
+    fn test_ethereum_addresses() {
+        let verifier = WalletChain::Ethereum.verifier();
+        let checksummed = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+
+        assert_eq!(verifier.normalize_address(checksummed).unwrap(), checksummed.to_lowercase());
+        assert_eq!(verifier.display_address(&checksummed.to_lowercase()).unwrap(), checksummed);
+        assert!(verifier.normalize_address("9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM").is_err());
+    }
+
+    #[test]
+    fn test_solana_sign_message() {
+        let verifier = WalletChain::Solana.verifier();
+        let (key, address) = solana_keypair();
+        let (_, other_address) = solana_keypair();
+        let signature = key.sign(MESSAGE.as_bytes()).to_bytes();
+        let base58_signature = bs58::encode(signature).into_string();
+
+        assert!(verifier.verify_signature(&address, MESSAGE, &base58_signature).is_ok());
+        assert!(verifier.verify_signature(&address, MESSAGE, &format!("0x{}", hex::encode(signature))).is_ok());
+        assert!(verifier.verify_signature(&address, "another message", &base58_signature).is_err());
+        assert!(verifier.verify_signature(&other_address, MESSAGE, &base58_signature).is_err());
+        assert!(verifier.verify_signature(&address, MESSAGE, &bs58::encode(&signature[..32]).into_string()).is_err());
+    }
+
+    #[test]
+    fn test_solana_addresses() {
+        let verifier = WalletChain::Solana.verifier();
+        let address = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+
+        // Base58 is case-sensitive
+        assert_eq!(verifier.normalize_address(address).unwrap(), address);
+        assert!(verifier.normalize_address(&address.to_lowercase()).is_err());
+        assert!(verifier.normalize_address("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").is_err());
+        // 0, O, I and l are not in the base58 alphabet
+        assert!(verifier.normalize_address("0OIl").is_err());
+    }
+}
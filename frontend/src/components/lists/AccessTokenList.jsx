@@ -0,0 +1,188 @@
+import { useEffect, useState } from 'react'
+import accessTokenService from '../../services/accessTokenService'
+import stepUpService from '../../services/stepUpService'
+
+const EXPIRY_OPTIONS = [7, 30, 90, 365]
+
+// Personal access tokens for scripts and bots, on the profile page
+const AccessTokenList = () => {
+  const [tokens, setTokens] = useState([])
+  const [availableScopes, setAvailableScopes] = useState([])
+  const [name, setName] = useState('')
+  const [scopes, setScopes] = useState([])
+  const [expiresInDays, setExpiresInDays] = useState(30)
+  const [newToken, setNewToken] = useState(null)
+  const [error, setError] = useState('')
+  const [loading, setLoading] = useState(false)
+  // Set when creating needs the user to confirm it's them: 'totp' or 'password'
+  const [stepUpMethod, setStepUpMethod] = useState(null)
+  const [stepUpValue, setStepUpValue] = useState('')
+
+  const loadTokens = async () => {
+    try {
+      const data = await accessTokenService.list()
+      setTokens(data.tokens)
+      setAvailableScopes(data.scopes)
+    } catch (err) {
+      console.error('Failed to load access tokens:', err)
+    }
+  }
+
+  useEffect(() => {
+    loadTokens()
+  }, [])
+
+  const toggleScope = (scope) => {
+    setScopes(prev => prev.includes(scope) ? prev.filter(s => s !== scope) : [...prev, scope])
+  }
+
+  const handleCreate = async (e) => {
+    e.preventDefault()
+    setError('')
+    setLoading(true)
+    try {
+      let stepUpToken
+      if (stepUpMethod) {
+        const proof = stepUpMethod === 'totp'
+          ? { method: stepUpMethod, code: stepUpValue }
+          : { method: stepUpMethod, password: stepUpValue }
+        stepUpToken = await stepUpService.stepUp(proof)
+      }
+      const created = await accessTokenService.create({ name, scopes, expires_in_days: expiresInDays }, stepUpToken)
+      setNewToken(created.token)
+      setName('')
+      setScopes([])
+      setStepUpMethod(null)
+      await loadTokens()
+    } catch (err) {
+      if (stepUpService.isRequired(err)) {
+        const methods = err.response.data.methods || []
+        setStepUpMethod(methods.includes('totp') ? 'totp' : 'password')
+      } else {
+        setError(err.response?.data?.error || 'Failed to create token')
+      }
+    } finally {
+      setStepUpValue('')
+      setLoading(false)
+    }
+  }
+
+  const handleRevoke = async (id) => {
+    if (!window.confirm('Revoke this token? Scripts using it will stop working.')) return
+    try {
+      await accessTokenService.revoke(id)
+      await loadTokens()
+    } catch (err) {
+      setError(err.response?.data?.error || 'Failed to revoke token')
+    }
+  }
+
+  return (
+    <div className="border border-black p-6">
+      <h2 className="text-xl font-bold text-black mb-4">Personal Access Tokens</h2>
+      <p className="text-black text-sm mb-4">
+        Tokens let scripts and bots use the API as you, limited to the scopes you choose.
+      </p>
+
+      {error && (
+        <div className="bg-red-100 border border-red-400 text-red-700 px-4 py-2 mb-4" role="alert">
+          {error}
+        </div>
+      )}
+
+      {newToken && (
+        <div className="p-4 border border-green-600 bg-green-50 text-green-700 text-sm mb-4">
+          <p className="font-bold mb-2">Copy your new token now. It won't be shown again.</p>
+          <code className="block break-all bg-white border border-green-600 p-2 text-black">{newToken}</code>
+          <button
+            type="button"
+            onClick={() => setNewToken(null)}
+            className="mt-2 px-3 py-1 border border-black bg-white text-black font-bold hover:bg-gray-100 transition"
+          >
+            Done
+          </button>
+        </div>
+      )}
+
+      {tokens.length > 0 && (
+        <ul className="space-y-2 mb-6">
+          {tokens.map(token => (
+            <li key={token.id} className="flex justify-between items-start border border-black p-3">
+              <div className="text-sm text-black">
+                <p className="font-bold">{token.name} <span className="font-mono font-normal">{token.token_prefix}…</span></p>
+                <p>{token.scopes.join(', ')}</p>
+                <p className="text-gray-600">
+                  Expires {new Date(token.expires_at + 'Z').toLocaleDateString()}
+                  {' · '}
+                  {token.last_used_at ? `Last used ${new Date(token.last_used_at + 'Z').toLocaleString()}` : 'Never used'}
+                </p>
+              </div>
+              <button
+                type="button"
+                onClick={() => handleRevoke(token.id)}
+                className="px-3 py-1 border border-red-600 bg-red-50 text-red-700 font-bold hover:bg-red-600 hover:text-white transition"
+              >
+                Revoke
+              </button>
+            </li>
+          ))}
+        </ul>
+      )}
+
+      <form onSubmit={handleCreate} className="space-y-3">
+        <input
+          type="text"
+          value={name}
+          onChange={(e) => setName(e.target.value)}
+          placeholder="Token name, e.g. CI bot"
+          className="w-full border border-black p-2"
+          maxLength={64}
+        />
+        <div className="flex flex-wrap gap-4">
+          {availableScopes.map(scope => (
+            <label key={scope} className="flex items-center gap-2 text-sm text-black">
+              <input type="checkbox" checked={scopes.includes(scope)} onChange={() => toggleScope(scope)} />
+              {scope}
+            </label>
+          ))}
+        </div>
+        <select
+          value={expiresInDays}
+          onChange={(e) => setExpiresInDays(Number(e.target.value))}
+          className="w-full border border-black p-2"
+        >
+          {EXPIRY_OPTIONS.map(days => (
+            <option key={days} value={days}>Expires in {days} days</option>
+          ))}
+        </select>
+        {stepUpMethod && (
+          <div>
+            <p className="text-black text-sm mb-2">
+              {stepUpMethod === 'totp'
+                ? 'Enter the code from your authenticator app to create this token.'
+                : 'Enter your password to create this token.'}
+            </p>
+            <input
+              type={stepUpMethod === 'totp' ? 'text' : 'password'}
+              value={stepUpValue}
+              onChange={(e) => setStepUpValue(e.target.value)}
+              placeholder={stepUpMethod === 'totp' ? 'Enter 6-digit code' : 'Password'}
+              className="w-full border border-black p-2"
+              maxLength={stepUpMethod === 'totp' ? 6 : undefined}
+              autoFocus
+            />
+          </div>
+        )}
+        <button
+          type="submit"
+          disabled={loading || !name.trim() || scopes.length === 0 || (stepUpMethod && !stepUpValue)}
+          className="w-full px-4 py-2 border border-black bg-white text-black font-bold hover:bg-black hover:text-white transition disabled:opacity-50 disabled:cursor-not-allowed"
+        >
+          {loading ? 'Creating...' : 'Create Token'}
+        </button>
+      </form>
+    </div>
+  )
+}
+
+export default AccessTokenList
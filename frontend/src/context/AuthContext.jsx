@@ -175,10 +175,10 @@ export const AuthProvider = ({ children }) => {
     }
   };
 
-  const getWeb3Challenge = async (address) => {
+  const getWeb3Challenge = async (address, chainId, chain) => {
     try {
       setError(null);
-      const response = await authService.getWeb3Challenge(address);
+      const response = await authService.getWeb3Challenge(address, chainId, chain);
       return response;
     } catch (err) {
       const errorMsg = err.response?.data?.error || err.message;
@@ -192,6 +192,12 @@ export const AuthProvider = ({ children }) => {
       setError(null);
       setLoading(true);
       const response = await authService.verifyWeb3Signature(address, challenge, signature);
+
+      // Check if MFA is required
+      if (response.requires_mfa) {
+        return response;
+      }
+
       setToken(response.token);
       setUser(response.user);
       return response;
@@ -4,9 +4,11 @@ import { useNavigate } from 'react-router-dom';
 import axios from 'axios';
 import { QRCodeSVG } from 'qrcode.react';
 import twoFactorService from '../services/twoFactorService';
+import stepUpService from '../services/stepUpService';
+import AccessTokenList from '../components/lists/AccessTokenList';
 
 const ProfilePage = () => {
-  const { user, setError: setAuthError } = useAuth();
+  const { user, setError: setAuthError, getWeb3Challenge } = useAuth();
   const navigate = useNavigate();
 
   const [username, setUsername] = useState('');
@@ -37,13 +39,17 @@ const ProfilePage = () => {
   const [showPasswordConfirm, setShowPasswordConfirm] = useState(false);
   const [showUnlinkWalletConfirm, setShowUnlinkWalletConfirm] = useState(false);
   const [showDisable2FAConfirm, setShowDisable2FAConfirm] = useState(false);
+  const [disable2FACode, setDisable2FACode] = useState('');
   const [passwordConfirmValue, setPasswordConfirmValue] = useState('');
   const [pendingUpdateData, setPendingUpdateData] = useState(null);
   const [passwordChangeStep, setPasswordChangeStep] = useState(0); // 0: Initial, 1: Verification, 2: New Password
-  const [tempToken, setTempToken] = useState('');
+  const [passwordStepUpToken, setPasswordStepUpToken] = useState('');
   const [countdown, setCountdown] = useState(0);
   const [show2FAConfirm, setShow2FAConfirm] = useState(false);
   const [twoFACode, setTwoFACode] = useState('');
+  // Signed wallet link waiting for a step-up, and the method to confirm with
+  const [pendingWalletLink, setPendingWalletLink] = useState(null);
+  const [walletStepUpValue, setWalletStepUpValue] = useState('');
   const qrCodeRef = useRef(null);
 
   // Countdown timer effect
@@ -102,9 +108,15 @@ const ProfilePage = () => {
     setSendingCode(true);
 
     try {
+      const token = localStorage.getItem('token');
       await axios.post(
-        `${import.meta.env.VITE_API_BASE_URL}/api/auth/email/send-verification`,
-        { email: user.email }
+        `${import.meta.env.VITE_API_BASE_URL}/api/auth/step-up/start`,
+        { method: 'email' },
+        {
+          headers: {
+            Authorization: `Bearer ${token}`,
+          },
+        }
       );
 
       setSuccess('Verification code sent to your email.');
@@ -125,27 +137,11 @@ const ProfilePage = () => {
     setLoading(true);
 
     try {
-      const token = localStorage.getItem('token');
-      const response = await axios.post(
-        `${import.meta.env.VITE_API_BASE_URL}/api/auth/password/verify-code`,
-        {
-          verification_code: verificationCode,
-          verification_method: verificationMethod
-        },
-        {
-          headers: {
-            Authorization: `Bearer ${token}`,
-            'Content-Type': 'application/json',
-          },
-        }
-      );
-
-      if (response.data.success) {
-        setTempToken(response.data.temp_token);
-        setPasswordChangeStep(2);
-        setSuccess('Code verified successfully');
-        setTimeout(() => setSuccess(''), 2000);
-      }
+      const stepUpToken = await stepUpService.stepUp({ method: verificationMethod, code: verificationCode });
+      setPasswordStepUpToken(stepUpToken);
+      setPasswordChangeStep(2);
+      setSuccess('Code verified successfully');
+      setTimeout(() => setSuccess(''), 2000);
     } catch (err) {
       console.error('Verification error:', err);
       const errorMsg = err.response?.data?.error || err.message || 'Failed to verify code';
@@ -165,18 +161,15 @@ const ProfilePage = () => {
     setError('');
 
     try {
-      const token = localStorage.getItem('token');
-      const updateData = {
-        ...pendingUpdateData,
-        verification_code: twoFACode
-      };
+      const updateData = pendingUpdateData;
+      const stepUpToken = await stepUpService.stepUp({ method: 'totp', code: twoFACode });
 
       const response = await axios.put(
         `${import.meta.env.VITE_API_BASE_URL}/api/auth/profile`,
         updateData,
         {
           headers: {
-            Authorization: `Bearer ${token}`,
+            ...stepUpService.headers(stepUpToken),
             'Content-Type': 'application/json',
           },
         }
@@ -290,17 +283,9 @@ const ProfilePage = () => {
         setIsEditingUsername(false);
         setIsEditingEmail(false);
       } catch (err) {
-        // Check if 2FA confirmation is required
-        if (err.response?.data?.require_2fa) {
-          setPendingUpdateData(updateData);
-          setShow2FAConfirm(true);
-          setLoading(false);
-          return;
-        }
-        // Check if password confirmation is required (legacy)
-        if (err.response?.data?.require_password) {
-          setPendingUpdateData(updateData);
-          setShowPasswordConfirm(true);
+        // Check if re-authentication (step-up) is required
+        if (stepUpService.isRequired(err)) {
+          requestProfileStepUp(updateData);
           setLoading(false);
           return;
         }
@@ -316,6 +301,16 @@ const ProfilePage = () => {
     }
   };
 
+  // Profile changes need a recent step-up: a 2FA code when it's on, the password otherwise
+  const requestProfileStepUp = (updateData) => {
+    setPendingUpdateData(updateData);
+    if (is2FAEnabled) {
+      setShow2FAConfirm(true);
+    } else {
+      setShowPasswordConfirm(true);
+    }
+  };
+
   const handleUpdatePassword = async (e) => {
     if (e) e.preventDefault();
     setError('');
@@ -323,8 +318,6 @@ const ProfilePage = () => {
     setLoading(true);
 
     try {
-      const token = localStorage.getItem('token');
-
       if (!newPassword) {
         setError('Please enter a new password');
         setLoading(false);
@@ -357,15 +350,10 @@ const ProfilePage = () => {
       try {
         await axios.post(
           `${import.meta.env.VITE_API_BASE_URL}/api/auth/password/change`,
-          {
-            verification_code: verificationCode,
-            verification_method: verificationMethod,
-            temp_token: tempToken,
-            new_password: newPassword
-          },
+          { new_password: newPassword },
           {
             headers: {
-              Authorization: `Bearer ${token}`,
+              ...stepUpService.headers(passwordStepUpToken),
               'Content-Type': 'application/json',
             },
           }
@@ -374,7 +362,7 @@ const ProfilePage = () => {
         setSuccess('Password updated successfully!');
         // Clear password fields
         setVerificationCode('');
-        setTempToken('');
+        setPasswordStepUpToken('');
         setNewPassword('');
         setConfirmPassword('');
         setPasswordChangeStep(0);
@@ -405,18 +393,15 @@ const ProfilePage = () => {
     setShowPasswordConfirm(false);
 
     try {
-      const token = localStorage.getItem('token');
-      const updateData = {
-        ...pendingUpdateData,
-        current_password: passwordConfirmValue
-      };
+      const updateData = pendingUpdateData;
+      const stepUpToken = await stepUpService.stepUp({ method: 'password', password: passwordConfirmValue });
 
       const response = await axios.put(
         `${import.meta.env.VITE_API_BASE_URL}/api/auth/profile`,
         updateData,
         {
           headers: {
-            Authorization: `Bearer ${token}`,
+            ...stepUpService.headers(stepUpToken),
             'Content-Type': 'application/json',
           },
         }
@@ -541,7 +526,7 @@ const ProfilePage = () => {
     setDisable2FALoading(true);
 
     try {
-      const result = await twoFactorService.disable2FA();
+      const result = await twoFactorService.disable2FA(disable2FACode);
       if (result.success) {
         setSuccess('2FA has been successfully disabled');
         setIs2FAEnabled(false);
@@ -568,6 +553,7 @@ const ProfilePage = () => {
       console.error('Disable 2FA error:', err);
       setError(err.response?.data?.error || 'Failed to disable 2FA. Please try again.');
     } finally {
+      setDisable2FACode('');
       setDisable2FALoading(false);
     }
   };
@@ -600,28 +586,26 @@ const ProfilePage = () => {
       }
 
       const walletAddress = accounts[0];
-      const token = localStorage.getItem('token');
 
-      // Update user profile with wallet address
-      const response = await axios.put(
-        `${import.meta.env.VITE_API_BASE_URL}/api/auth/profile`,
-        { wallet_address: walletAddress },
-        {
-          headers: {
-            Authorization: `Bearer ${token}`,
-            'Content-Type': 'application/json',
-          },
-        }
-      );
-
-      setSuccess(`Wallet linked successfully! ${walletAddress.substring(0, 6)}...${walletAddress.substring(walletAddress.length - 4)}`);
-
-      // Update localStorage
-      localStorage.setItem('user', JSON.stringify(response.data));
+      // Prove ownership the same way as a wallet sign-in: sign a one-time challenge
+      const { challenge } = await getWeb3Challenge(walletAddress);
+      const signature = await window.ethereum.request({
+        method: 'personal_sign',
+        params: [challenge, walletAddress],
+      });
 
-      setTimeout(() => {
-        window.location.reload();
-      }, 2000);
+      const walletLink = { address: walletAddress, challenge, signature };
+      try {
+        await connectWallet(walletLink);
+      } catch (err) {
+        // Adding a sign-in method needs a recent step-up; the challenge is still unused
+        if (stepUpService.isRequired(err)) {
+          const methods = err.response.data.methods || [];
+          setPendingWalletLink({ ...walletLink, method: methods.includes('totp') ? 'totp' : 'password' });
+          return;
+        }
+        throw err;
+      }
     } catch (err) {
       console.error('Link wallet error:', err);
       if (err.message.includes('User rejected')) {
@@ -634,6 +618,48 @@ const ProfilePage = () => {
     }
   };
 
+  const connectWallet = async ({ address, challenge, signature }, stepUpToken) => {
+    const headers = {
+      ...stepUpService.headers(stepUpToken),
+      'Content-Type': 'application/json',
+    };
+
+    await axios.post(
+      `${import.meta.env.VITE_API_BASE_URL}/api/auth/connect-wallet`,
+      { address, challenge, signature },
+      { headers }
+    );
+
+    setSuccess(`Wallet linked successfully! ${address.substring(0, 6)}...${address.substring(address.length - 4)}`);
+
+    // Update localStorage
+    const me = await axios.get(`${import.meta.env.VITE_API_BASE_URL}/api/auth/me`, { headers: stepUpService.headers() });
+    localStorage.setItem('user', JSON.stringify(me.data.user));
+
+    setTimeout(() => {
+      window.location.reload();
+    }, 2000);
+  };
+
+  const confirmWalletLinkStepUp = async () => {
+    const { method, ...walletLink } = pendingWalletLink;
+    setError('');
+
+    try {
+      const proof = method === 'totp' ? { method, code: walletStepUpValue } : { method, password: walletStepUpValue };
+      const stepUpToken = await stepUpService.stepUp(proof);
+      setPendingWalletLink(null);
+      await connectWallet(walletLink, stepUpToken);
+    } catch (err) {
+      console.error('Link wallet error:', err);
+      setError(err.response?.data?.error || err.message || 'Failed to link wallet');
+      setPendingWalletLink(null);
+      setLinkWalletLoading(false);
+    } finally {
+      setWalletStepUpValue('');
+    }
+  };
+
   const handleUnlinkWeb3Wallet = () => {
     setShowUnlinkWalletConfirm(true);
   };
@@ -668,6 +694,10 @@ const ProfilePage = () => {
         window.location.reload();
       }, 2000);
     } catch (err) {
+      if (stepUpService.isRequired(err)) {
+        requestProfileStepUp({ wallet_address: null });
+        return;
+      }
       console.error('Unlink wallet error:', err);
       const errorMsg = err.response?.data?.error || err.message || 'Failed to unlink wallet';
       setError(errorMsg);
@@ -1281,6 +1311,9 @@ const ProfilePage = () => {
               )}
             </div>
 
+            {/* Personal Access Tokens Section */}
+            <AccessTokenList />
+
             {/* Account Info Section */}
             <div className="border border-black p-6">
               <h2 className="text-xl font-bold text-black mb-4">Account Information</h2>
@@ -1391,6 +1424,50 @@ const ProfilePage = () => {
         )
       }
 
+      {/* Step-up Modal for linking a wallet */}
+      {
+        pendingWalletLink && (
+          <div className="fixed inset-0 bg-black bg-opacity-50 flex items-center justify-center z-50">
+            <div className="bg-white p-6 border-2 border-black max-w-md w-full mx-4 shadow-lg">
+              <h3 className="text-xl font-bold text-black mb-4">Confirm It's You</h3>
+              <p className="text-black mb-4">
+                {pendingWalletLink.method === 'totp'
+                  ? 'Please enter the code from your authenticator app to link this wallet.'
+                  : 'Please enter your password to link this wallet.'}
+              </p>
+              <input
+                type={pendingWalletLink.method === 'totp' ? 'text' : 'password'}
+                value={walletStepUpValue}
+                onChange={(e) => setWalletStepUpValue(e.target.value)}
+                placeholder={pendingWalletLink.method === 'totp' ? 'Enter 6-digit code' : 'Password'}
+                className="w-full border border-black p-2 mb-4"
+                autoFocus
+                maxLength={pendingWalletLink.method === 'totp' ? 6 : undefined}
+              />
+              <div className="flex justify-end gap-2">
+                <button
+                  onClick={() => {
+                    setPendingWalletLink(null);
+                    setWalletStepUpValue('');
+                    setLinkWalletLoading(false);
+                  }}
+                  className="px-4 py-2 border border-black bg-white text-black font-bold hover:bg-gray-100 transition"
+                >
+                  Cancel
+                </button>
+                <button
+                  onClick={confirmWalletLinkStepUp}
+                  disabled={!walletStepUpValue}
+                  className="px-4 py-2 border border-black bg-black text-white font-bold hover:bg-white hover:text-black transition disabled:opacity-50"
+                >
+                  Confirm
+                </button>
+              </div>
+            </div>
+          </div>
+        )
+      }
+
       {/* Unlink Wallet Confirmation Modal */}
       {
         showUnlinkWalletConfirm && (
@@ -1428,15 +1505,28 @@ const ProfilePage = () => {
               <p className="text-black mb-6">
                 Are you sure you want to disable Two-Factor Authentication? This will significantly reduce your account security.
               </p>
+              <input
+                type="text"
+                value={disable2FACode}
+                onChange={(e) => setDisable2FACode(e.target.value)}
+                placeholder="Enter 6-digit code to confirm"
+                className="w-full border border-black p-2 mb-4"
+                autoFocus
+                maxLength={6}
+              />
               <div className="flex justify-end gap-2">
                 <button
-                  onClick={() => setShowDisable2FAConfirm(false)}
+                  onClick={() => {
+                    setShowDisable2FAConfirm(false);
+                    setDisable2FACode('');
+                  }}
                   className="px-4 py-2 border border-black bg-white text-black font-bold hover:bg-gray-100 transition"
                 >
                   Cancel
                 </button>
                 <button
                   onClick={confirmDisable2FA}
+                  disabled={!disable2FACode}
                   className="px-4 py-2 border border-black bg-red-600 text-white font-bold hover:bg-red-700 transition"
                 >
                   Disable 2FA
@@ -72,7 +72,10 @@ const TwoFactorVerifyPage = () => {
     setError('')
     setLoading(true)
 
-    if (code.length !== 6 || !/^\d+$/.test(code)) {
+    // Recovery codes (XXXX-XXXX-XXXX) are only accepted when signing in
+    const isRecoveryCode = isLogin && /^[A-Z0-9]{4}(-[A-Z0-9]{4}){1,2}$/.test(code)
+
+    if (!isRecoveryCode && (code.length !== 6 || !/^\d+$/.test(code))) {
       setError('Please enter a valid 6-digit code')
       setLoading(false)
       return
@@ -86,7 +89,7 @@ const TwoFactorVerifyPage = () => {
           `${import.meta.env.VITE_API_BASE_URL}/api/auth/verify-mfa`,
           {
             temp_token,
-            method: 'totp',
+            method: isRecoveryCode ? 'recovery_code' : 'totp',
             code
           }
         )
@@ -289,13 +292,13 @@ const TwoFactorVerifyPage = () => {
               value={code}
               onChange={(e) => {
                 const value = e.target.value.toUpperCase();
-                // Allow either 6 digits OR recovery code format (XXXX-XXXX)
-                if (/^[0-9]{0,6}$/.test(value) || /^[A-Z0-9-]{0,9}$/.test(value)) {
+                // Allow either 6 digits OR recovery code format (XXXX-XXXX-XXXX)
+                if (/^[0-9]{0,6}$/.test(value) || /^[A-Z0-9-]{0,14}$/.test(value)) {
                   setCode(value);
                 }
               }}
-              maxLength="9"
-              placeholder="000000 or XXXX-XXXX"
+              maxLength="14"
+              placeholder="000000 or XXXX-XXXX-XXXX"
               className="w-full border border-black p-3 bg-white text-black text-center text-2xl font-mono tracking-widest"
               disabled={loading}
               autoFocus
@@ -318,7 +321,7 @@ const TwoFactorVerifyPage = () => {
           <p className="text-xs text-black mb-3 font-bold">Troubleshooting:</p>
           <ul className="text-xs text-black space-y-2 mb-4">
             <li>• <strong>Invalid code?</strong> Check if your device clock is correct</li>
-            <li>• <strong>Lost your authenticator app?</strong> Use a recovery code (format: XXXX-XXXX)</li>
+            <li>• <strong>Lost your authenticator app?</strong> Use a recovery code (format: XXXX-XXXX-XXXX)</li>
             <li>• <strong>Codes not matching?</strong> Try the next code after waiting 5 seconds</li>
           </ul>
           <div className="p-3 bg-orange-50 border border-orange-600 text-xs text-black">
@@ -4,8 +4,12 @@ import { useAuth } from '../context/AuthContext';
 import Notification from '../components/Notification';
 import { useNotification } from '../hooks/useNotification';
 
+// Sepolia testnet chain ID is 0xaa36a7 (11155111 in decimal)
+const SEPOLIA_CHAIN_ID = '0xaa36a7';
+
 const Web3AuthPage = () => {
   const [address, setAddress] = useState('');
+  const [wallet, setWallet] = useState('metamask'); // metamask, phantom
   const [challenge, setChallenge] = useState('');
   const [step, setStep] = useState('select'); // select, challenge, verify
   const [signature, setSignature] = useState('');
@@ -32,9 +36,6 @@ const Web3AuthPage = () => {
         method: 'eth_chainId',
       });
 
-      // Sepolia testnet chain ID is 0xaa36a7 (11155111 in decimal)
-      const SEPOLIA_CHAIN_ID = '0xaa36a7';
-
       if (chainId !== SEPOLIA_CHAIN_ID) {
         try {
           await window.ethereum.request({
@@ -72,6 +73,32 @@ const Web3AuthPage = () => {
     }
   };
 
+  // Phantom injects its Solana provider as window.phantom.solana
+  const getPhantom = () => window.phantom?.solana;
+
+  // Connect Phantom and get the base58 public key
+  const connectPhantom = async () => {
+    const provider = getPhantom();
+    if (!provider?.isPhantom) {
+      setError('Phantom is not installed. Please install it to continue.');
+      return;
+    }
+
+    setLoading(true);
+    setError('');
+
+    try {
+      const response = await provider.connect();
+      setAddress(response.publicKey.toString());
+      setWallet('phantom');
+      setStep('challenge');
+    } catch (err) {
+      setError(err.message || 'Failed to connect Phantom');
+    } finally {
+      setLoading(false);
+    }
+  };
+
   // Connect MetaMask and get address
   const connectMetaMask = async () => {
     if (!isMetaMaskAvailable()) {
@@ -110,7 +137,10 @@ const Web3AuthPage = () => {
     setError('');
 
     try {
-      const response = await getWeb3Challenge(address);
+      // The sign-in message names the chain, so ask for the one we switched to
+      const response = wallet === 'phantom'
+        ? await getWeb3Challenge(address, undefined, 'solana')
+        : await getWeb3Challenge(address, parseInt(SEPOLIA_CHAIN_ID, 16));
       setChallenge(response.challenge);
       setStep('verify');
     } catch (err) {
@@ -120,8 +150,30 @@ const Web3AuthPage = () => {
     }
   };
 
+  // Sign challenge with Phantom (ed25519 over the UTF-8 message), sent hex encoded
+  const signChallengeWithPhantom = async () => {
+    setLoading(true);
+    setError('');
+
+    try {
+      const encoded = new TextEncoder().encode(challenge);
+      const { signature: bytes } = await getPhantom().signMessage(encoded, 'utf8');
+      const sig = '0x' + Array.from(bytes, (b) => b.toString(16).padStart(2, '0')).join('');
+      setSignature(sig);
+      await verifySignature(sig);
+    } catch (err) {
+      setError(err.message || 'Failed to sign challenge');
+    } finally {
+      setLoading(false);
+    }
+  };
+
   // Sign challenge with MetaMask
   const signChallenge = async () => {
+    if (wallet === 'phantom') {
+      return signChallengeWithPhantom();
+    }
+
     if (!isMetaMaskAvailable()) {
       setError('MetaMask is not available');
       return;
@@ -153,10 +205,15 @@ const Web3AuthPage = () => {
     try {
       const response = await verifyWeb3Signature(address, challenge, sig);
 
-      // Check if user has 2FA enabled
-      if (response.user && response.user.two_factor_enabled) {
-        // Redirect to 2FA verification page
-        navigate('/2fa-verify', { state: { username: response.user.username } });
+      // Check if MFA is required (user has 2FA enabled)
+      if (response.requires_mfa) {
+        navigate('/2fa-verify', {
+          state: {
+            temp_token: response.temp_token,
+            user: response.user,
+            isLogin: true
+          }
+        });
       } else {
         // No 2FA → navigate to dashboard
         navigate('/dashboard');
@@ -225,6 +282,13 @@ const Web3AuthPage = () => {
                 {loading ? 'Connecting...' : 'Connect MetaMask'}
               </button>
             )}
+            <button
+              onClick={connectPhantom}
+              disabled={loading}
+              className="w-full bg-white border border-black text-black font-bold py-2 px-4 hover:bg-black hover:text-white transition disabled:opacity-50"
+            >
+              {loading ? 'Connecting...' : 'Connect Phantom (Solana)'}
+            </button>
             <p className="text-black text-xs">
               Make sure you're on Sepolia Testnet. Get test ETH at{' '}
               <a
@@ -247,7 +311,7 @@ const Web3AuthPage = () => {
             </div>
             <div className="border border-black p-4 bg-white">
               <p className="text-black text-sm font-bold mb-2">Network:</p>
-              <p className="text-black text-xs">Sepolia Testnet</p>
+              <p className="text-black text-xs">{wallet === 'phantom' ? 'Solana' : 'Sepolia Testnet'}</p>
             </div>
             <p className="text-black text-sm">Click the button below to request a challenge.</p>
             <button
@@ -266,7 +330,9 @@ const Web3AuthPage = () => {
               <p className="text-black text-sm font-bold mb-2">Challenge:</p>
               <p className="text-black text-xs break-all font-mono">{challenge}</p>
             </div>
-            <p className="text-black text-sm">Sign the challenge with MetaMask to complete authentication.</p>
+            <p className="text-black text-sm">
+              Sign the challenge with {wallet === 'phantom' ? 'Phantom' : 'MetaMask'} to complete authentication.
+            </p>
             <button
               onClick={signChallenge}
               disabled={loading}
@@ -0,0 +1,41 @@
+import axios from 'axios'
+import stepUpService from './stepUpService'
+
+const API_BASE_URL = import.meta.env.VITE_API_BASE_URL
+
+const authHeaders = () => ({
+  headers: {
+    Authorization: `Bearer ${localStorage.getItem('token')}`,
+  },
+})
+
+const accessTokenService = {
+  /**
+   * Active personal access tokens and the scopes a new one can have
+   * @returns {Promise<{tokens: Array, scopes: string[]}>}
+   */
+  list: async () => {
+    const response = await axios.get(`${API_BASE_URL}/api/auth/tokens`, authHeaders())
+    return response.data
+  },
+
+  /**
+   * Create a token. The returned `token.token` is the only time its value is shown.
+   * Needs a recent step-up, see stepUpService
+   * @param {{name: string, scopes: string[], expires_in_days?: number, rate_limit_per_minute?: number}} data
+   * @param {string} [stepUpToken]
+   */
+  create: async (data, stepUpToken) => {
+    const response = await axios.post(`${API_BASE_URL}/api/auth/tokens`, data, {
+      headers: stepUpService.headers(stepUpToken),
+    })
+    return response.data.token
+  },
+
+  revoke: async (id) => {
+    const response = await axios.delete(`${API_BASE_URL}/api/auth/tokens/${id}`, authHeaders())
+    return response.data
+  },
+}
+
+export default accessTokenService
@@ -86,9 +86,12 @@ const authService = {
   },
 
   // Get Web3 Challenge
-  getWeb3Challenge: async (address) => {
+  // chain is 'ethereum' (default) or 'solana'
+  getWeb3Challenge: async (address, chainId, chain) => {
     const response = await api.post('/auth/web3/challenge', {
       address,
+      chain,
+      chain_id: chainId,
     });
     return response.data;
   },
@@ -100,6 +103,17 @@ const authService = {
       challenge,
       signature,
     });
+
+    // Check if MFA is required
+    if (response.data.requires_mfa) {
+      return {
+        requires_mfa: true,
+        mfa_methods: response.data.mfa_methods,
+        temp_token: response.data.temp_token,
+        user: response.data.user,
+      };
+    }
+
     if (response.data.token) {
       localStorage.setItem('token', response.data.token);
       // Store user object if available
@@ -114,14 +128,16 @@ const authService = {
 
   // Refresh JWT Token
   refreshToken: async (refreshToken) => {
-    const response = await api.post('/auth/refresh', {}, {
-      headers: {
-        Authorization: `Bearer ${refreshToken}`,
-      },
+    const response = await api.post('/auth/refresh', {
+      refresh_token: refreshToken,
     });
     if (response.data.token) {
       localStorage.setItem('token', response.data.token);
     }
+    // Refresh tokens are single use, keep the rotated one
+    if (response.data.refresh_token) {
+      localStorage.setItem('refresh_token', response.data.refresh_token);
+    }
     return response.data;
   },
 
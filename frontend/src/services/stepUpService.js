@@ -0,0 +1,37 @@
+import axios from 'axios'
+
+const API_BASE_URL = import.meta.env.VITE_API_BASE_URL
+
+const stepUpService = {
+  /**
+   * Whether a failed request needs a step-up first, as answered by routes that require one
+   * @returns {boolean}
+   */
+  isRequired: (error) => error.response?.status === 403 && error.response?.data?.step_up_required === true,
+
+  /**
+   * Confirm it's the user again, for routes that need a recent step-up
+   * @param {{method: string, password?: string, code?: string}} proof
+   * @returns {Promise<string>} token to send in the X-Step-Up-Token header
+   */
+  stepUp: async (proof) => {
+    const token = localStorage.getItem('token')
+    const response = await axios.post(`${API_BASE_URL}/api/auth/step-up`, proof, {
+      headers: {
+        Authorization: `Bearer ${token}`,
+      },
+    })
+    return response.data.step_up_token
+  },
+
+  /**
+   * Request headers for the current session, with the step-up token if there is one
+   * @param {string} [stepUpToken]
+   */
+  headers: (stepUpToken) => ({
+    Authorization: `Bearer ${localStorage.getItem('token')}`,
+    ...(stepUpToken ? { 'X-Step-Up-Token': stepUpToken } : {}),
+  }),
+}
+
+export default stepUpService
@@ -51,18 +51,29 @@ const twoFactorService = {
   },
 
   /**
-   * Disable 2FA
+   * Disable 2FA, after confirming it's the user with a current TOTP code
+   * @param {string} code - 6-digit code from the authenticator app
    * @returns {Promise<{success: boolean, message: string}>}
    */
-  disable2FA: async () => {
+  disable2FA: async (code) => {
     try {
       const token = localStorage.getItem('token')
+      const stepUp = await axios.post(
+        `${API_BASE_URL}/api/auth/step-up`,
+        { method: 'totp', code },
+        {
+          headers: {
+            Authorization: `Bearer ${token}`,
+          },
+        }
+      )
       const response = await axios.post(
         `${API_BASE_URL}/api/auth/disable-2fa`,
         {},
         {
           headers: {
             Authorization: `Bearer ${token}`,
+            'X-Step-Up-Token': stepUp.data.step_up_token,
           },
         }
       )
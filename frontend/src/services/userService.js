@@ -47,10 +47,11 @@ const userService = {
   },
 
   // Ban/Unban user (admin only)
-  banUser: async (id, isBanned, banDays = null) => {
+  banUser: async (id, isBanned, banDays = null, reason = null) => {
     const response = await api.put(`/users/${id}/ban`, {
       is_banned: isBanned,
-      ban_days: banDays
+      ban_days: banDays,
+      reason
     });
     return response.data;
   },